@@ -1,10 +1,12 @@
 use std::cmp;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 use std::time::Duration;
 
 use graph::components::ethereum::{blocks_with_triggers, triggers_in_block};
+use graph::components::subgraph::{is_within_synced_threshold, SUBGRAPH_SYNCED_BLOCK_THRESHOLD};
 use graph::data::subgraph::schema::{
     SubgraphDeploymentEntity, SubgraphEntity, SubgraphVersionEntity,
 };
@@ -109,6 +111,10 @@ struct BlockStreamContext<S, C> {
     metrics: Arc<BlockStreamMetrics>,
     previous_triggers_per_block: f64,
     previous_block_range_size: u64,
+
+    /// Whether this stream has marked the deployment as synced, so that it
+    /// doesn't need to check again while yielding blocks.
+    synced: Arc<AtomicBool>,
 }
 
 impl<S, C> Clone for BlockStreamContext<S, C> {
@@ -129,6 +135,7 @@ impl<S, C> Clone for BlockStreamContext<S, C> {
             metrics: self.metrics.clone(),
             previous_triggers_per_block: self.previous_triggers_per_block,
             previous_block_range_size: self.previous_block_range_size,
+            synced: self.synced.clone(),
         }
     }
 }
@@ -189,6 +196,7 @@ where
                 // A high number here forces a slow start, with a range of 1.
                 previous_triggers_per_block: 1_000_000.0,
                 previous_block_range_size: 1,
+                synced: Arc::new(AtomicBool::new(false)),
             },
         }
     }
@@ -226,6 +234,14 @@ where
                 // Exit loop if done or there are blocks to process.
                 .and_then(move |outcome| match outcome {
                     ReconciliationStepOutcome::YieldBlocks(next_blocks, range_size) => {
+                        // With a threshold, the subgraph may count as synced
+                        // before it has caught up with the chain head
+                        if *SUBGRAPH_SYNCED_BLOCK_THRESHOLD > 0
+                            && !ctx3.synced.load(Ordering::SeqCst)
+                        {
+                            ctx3.update_subgraph_synced_status()?;
+                        }
+
                         Ok(future::Loop::Break(NextBlocks::Blocks(
                             next_blocks.into_iter().collect(),
                             range_size,
@@ -628,12 +644,22 @@ where
     }
 
     /// Set subgraph deployment entity synced flag if and only if the subgraph block pointer is
-    /// caught up to the head block pointer.
+    /// caught up to the head block pointer, or at most `SUBGRAPH_SYNCED_BLOCK_THRESHOLD`
+    /// blocks behind it.
     fn update_subgraph_synced_status(&self) -> Result<(), Error> {
         let head_ptr_opt = self.chain_store.chain_head_ptr()?;
         let subgraph_ptr = self.subgraph_store.block_ptr(self.subgraph_id.clone())?;
 
-        if head_ptr_opt != subgraph_ptr {
+        let within_threshold = match (&head_ptr_opt, &subgraph_ptr) {
+            (Some(head_ptr), Some(subgraph_ptr)) => is_within_synced_threshold(
+                *subgraph_ptr,
+                *head_ptr,
+                *SUBGRAPH_SYNCED_BLOCK_THRESHOLD,
+            ),
+            _ => false,
+        };
+
+        if head_ptr_opt != subgraph_ptr && !within_threshold {
             // Not synced yet
             Ok(())
         } else {
@@ -774,7 +800,9 @@ where
 
             self.subgraph_store
                 .apply_metadata_operations(ops)
-                .map_err(|e| format_err!("Failed to set deployment synced flag: {}", e))
+                .map_err(|e| format_err!("Failed to set deployment synced flag: {}", e))?;
+            self.synced.store(true, Ordering::SeqCst);
+            Ok(())
         }
     }
 
@@ -1,4 +1,5 @@
 use futures01::future;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
@@ -12,6 +13,12 @@ use lazy_static::lazy_static;
 pub struct GraphQlRunner<S> {
     logger: Logger,
     store: Arc<S>,
+    subscription_metrics: Arc<SubscriptionMetrics>,
+    /// Limit how many queries all subscriptions run at the same time.
+    subscription_query_permits: Arc<QueryPermits>,
+    /// Shared by all subscriptions so that identical subscriptions use the
+    /// same store event stream.
+    subscription_resolver: TracingResolver<SharedStreamResolver<StoreResolver<S>>>,
 }
 
 lazy_static! {
@@ -35,6 +42,93 @@ lazy_static! {
         .map(|s| u32::from_str(&s)
             .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_GRAPHQL_MAX_FIRST")))
         .unwrap_or(1000);
+    static ref GRAPHQL_SUBSCRIPTION_QUERY_PERMIT_TIMEOUT: Option<Duration> =
+        env::var("GRAPH_GRAPHQL_SUBSCRIPTION_QUERY_PERMIT_TIMEOUT")
+            .ok()
+            .map(
+                |s| Duration::from_millis(u64::from_str(&s).unwrap_or_else(|_| panic!(
+                    "failed to parse env var GRAPH_GRAPHQL_SUBSCRIPTION_QUERY_PERMIT_TIMEOUT"
+                )))
+            );
+    static ref GRAPHQL_SUBSCRIPTION_QUERY_PERMITS: Option<usize> =
+        env::var("GRAPH_GRAPHQL_SUBSCRIPTION_QUERY_PERMITS")
+            .ok()
+            .map(|s| usize::from_str(&s).unwrap_or_else(|_| panic!(
+                "failed to parse env var GRAPH_GRAPHQL_SUBSCRIPTION_QUERY_PERMITS"
+            )));
+    static ref GRAPHQL_SUBSCRIPTION_SETUP_TIMEOUT: Duration =
+        env::var("GRAPH_GRAPHQL_SUBSCRIPTION_SETUP_TIMEOUT")
+            .ok()
+            .map(
+                |s| Duration::from_secs(u64::from_str(&s).unwrap_or_else(|_| panic!(
+                    "failed to parse env var GRAPH_GRAPHQL_SUBSCRIPTION_SETUP_TIMEOUT"
+                )))
+            )
+            .unwrap_or(DEFAULT_STREAM_SETUP_TIMEOUT);
+    static ref LOG_NORMALIZED_SUBSCRIPTION_QUERIES: bool =
+        env::var_os("GRAPH_LOG_NORMALIZED_SUBSCRIPTION_QUERIES").is_some();
+    static ref TRACE_SUBSCRIPTION_STORE_QUERIES: bool =
+        env::var_os("GRAPH_GRAPHQL_SUBSCRIPTION_TRACE_STORE_QUERIES").is_some();
+    static ref SUBSCRIPTION_FIELD_FILTERS: HashMap<SubgraphDeploymentId, SubscriptionFieldFilter> =
+        parse_subscription_field_filters(
+            env::var("GRAPH_GRAPHQL_SUBSCRIPTION_ALLOWED_FIELDS")
+                .ok()
+                .as_ref()
+                .map(String::as_str),
+            env::var("GRAPH_GRAPHQL_SUBSCRIPTION_DENIED_FIELDS")
+                .ok()
+                .as_ref()
+                .map(String::as_str),
+        )
+        .unwrap_or_else(|e| panic!("invalid subscription field filters: {}", e));
+}
+
+/// Parses the subscription field filters for deployments from the values of
+/// `GRAPH_GRAPHQL_SUBSCRIPTION_ALLOWED_FIELDS` and
+/// `GRAPH_GRAPHQL_SUBSCRIPTION_DENIED_FIELDS`. Both have the form
+/// `<deployment>:<field>,<field>;<deployment>:<field>`. A deployment can
+/// either have allowed or denied fields, but not both.
+fn parse_subscription_field_filters(
+    allowed: Option<&str>,
+    denied: Option<&str>,
+) -> Result<HashMap<SubgraphDeploymentId, SubscriptionFieldFilter>, String> {
+    fn parse(value: &str) -> Result<Vec<(SubgraphDeploymentId, HashSet<String>)>, String> {
+        value
+            .split(';')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let mut parts = entry.splitn(2, ':');
+                let deployment = parts.next().unwrap().trim();
+                let fields = parts
+                    .next()
+                    .ok_or_else(|| format!("missing fields for deployment `{}`", deployment))?;
+                let deployment = SubgraphDeploymentId::new(deployment)
+                    .map_err(|()| format!("invalid deployment id `{}`", deployment))?;
+                let fields = fields
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|field| !field.is_empty())
+                    .map(String::from)
+                    .collect();
+                Ok((deployment, fields))
+            })
+            .collect()
+    }
+
+    let mut filters = HashMap::new();
+    for (deployment, fields) in parse(allowed.unwrap_or(""))? {
+        filters.insert(deployment, SubscriptionFieldFilter::Allow(fields));
+    }
+    for (deployment, fields) in parse(denied.unwrap_or(""))? {
+        if filters.contains_key(&deployment) {
+            return Err(format!(
+                "deployment `{}` has both allowed and denied fields",
+                deployment
+            ));
+        }
+        filters.insert(deployment, SubscriptionFieldFilter::Deny(fields));
+    }
+    Ok(filters)
 }
 
 impl<S> GraphQlRunner<S>
@@ -42,12 +136,39 @@ where
     S: Store,
 {
     /// Creates a new query runner.
-    pub fn new(logger: &Logger, store: Arc<S>) -> Self {
+    pub fn new(
+        logger: &Logger,
+        store: Arc<S>,
+        metrics_registry: Arc<impl MetricsRegistry>,
+    ) -> Self {
+        let logger = logger.new(o!("component" => "GraphQlRunner"));
+
+        // Reject invalid field filters at startup rather than on the first
+        // subscription
+        lazy_static::initialize(&SUBSCRIPTION_FIELD_FILTERS);
+
+        let subscription_resolver = TracingResolver::new(
+            SharedStreamResolver::new(StoreResolver::new(&logger, store.clone())),
+            *TRACE_SUBSCRIPTION_STORE_QUERIES,
+        );
+        let subscription_query_permits = match *GRAPHQL_SUBSCRIPTION_QUERY_PERMITS {
+            Some(permits) => Arc::new(QueryPermits::new(permits)),
+            None => shared_subscription_query_permits(),
+        };
         GraphQlRunner {
-            logger: logger.new(o!("component" => "GraphQlRunner")),
+            logger,
             store,
+            subscription_metrics: Arc::new(SubscriptionMetrics::new(metrics_registry)),
+            subscription_query_permits,
+            subscription_resolver,
         }
     }
+
+    /// Returns the number of query permits that subscriptions can currently
+    /// get without waiting.
+    pub fn available_subscription_query_permits(&self) -> usize {
+        self.subscription_query_permits.available_permits()
+    }
 }
 
 impl<S> GraphQlRunnerTrait for GraphQlRunner<S>
@@ -91,18 +212,82 @@ where
     }
 
     fn run_subscription(&self, subscription: Subscription) -> SubscriptionResultFuture {
-        let result = execute_subscription(
-            subscription,
-            SubscriptionExecutionOptions {
-                logger: self.logger.clone(),
-                resolver: StoreResolver::new(&self.logger, self.store.clone()),
-                timeout: GRAPHQL_QUERY_TIMEOUT.clone(),
-                max_complexity: *GRAPHQL_MAX_COMPLEXITY,
-                max_depth: *GRAPHQL_MAX_DEPTH,
-                max_first: *GRAPHQL_MAX_FIRST,
-            },
+        let mut options = SubscriptionExecutionOptions::builder(
+            self.logger.clone(),
+            self.subscription_resolver.clone(),
+        )
+        .max_depth(*GRAPHQL_MAX_DEPTH)
+        .max_first(*GRAPHQL_MAX_FIRST)
+        .metrics(self.subscription_metrics.clone())
+        .query_permits(self.subscription_query_permits.clone())
+        .stream_setup_timeout(*GRAPHQL_SUBSCRIPTION_SETUP_TIMEOUT)
+        .log_normalized_query(*LOG_NORMALIZED_SUBSCRIPTION_QUERIES);
+        if let Some(timeout) = *GRAPHQL_QUERY_TIMEOUT {
+            options = options.timeout(timeout);
+        }
+        if let Some(max_complexity) = *GRAPHQL_MAX_COMPLEXITY {
+            options = options.max_complexity(max_complexity);
+        }
+        if let Some(query_permit_timeout) = *GRAPHQL_SUBSCRIPTION_QUERY_PERMIT_TIMEOUT {
+            options = options.query_permit_timeout(query_permit_timeout);
+        }
+        if let Some(field_filter) = SUBSCRIPTION_FIELD_FILTERS.get(&subscription.query.schema.id) {
+            options = options.field_filter(field_filter.clone());
+        }
+
+        // Opening the event streams waits for the store, which must not hold
+        // up the runtime
+        let options = options.build();
+        Box::new(
+            async move {
+                graph::spawn_blocking_allow_panic(async move {
+                    execute_subscription(subscription, options)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    Err(SubscriptionError::from(QueryExecutionError::Panic(
+                        e.to_string(),
+                    )))
+                })
+            }
+            .boxed()
+            .compat(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_subscription_field_filters() {
+        let filters = parse_subscription_field_filters(
+            Some("QmA:tokens, pairs;QmB:swaps"),
+            Some("QmC:mints"),
+        )
+        .unwrap();
+
+        let fields = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        let deployment = |id| SubgraphDeploymentId::new(id).unwrap();
+        assert_eq!(filters.len(), 3);
+        assert_eq!(
+            filters[&deployment("QmA")],
+            SubscriptionFieldFilter::Allow(fields(&["tokens", "pairs"]))
         );
+        assert_eq!(
+            filters[&deployment("QmB")],
+            SubscriptionFieldFilter::Allow(fields(&["swaps"]))
+        );
+        assert_eq!(
+            filters[&deployment("QmC")],
+            SubscriptionFieldFilter::Deny(fields(&["mints"]))
+        );
+    }
 
-        Box::new(future::result(result))
+    #[test]
+    fn rejects_deployments_with_allowed_and_denied_fields() {
+        assert!(parse_subscription_field_filters(Some("QmA:tokens"), Some("QmA:pairs")).is_err());
+        assert!(parse_subscription_field_filters(Some("QmA"), None).is_err());
     }
 }
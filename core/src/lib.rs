@@ -8,5 +8,6 @@ pub use crate::graphql::GraphQlRunner;
 pub use crate::link_resolver::LinkResolver;
 pub use crate::metrics::MetricsRegistry;
 pub use crate::subgraph::{
-    DataSourceLoader, SubgraphAssignmentProvider, SubgraphInstanceManager, SubgraphRegistrar,
+    DataSourceFileCache, DataSourceLoader, FetchRetryPolicy, LruFileCache,
+    SubgraphAssignmentProvider, SubgraphInstanceManager, SubgraphRegistrar,
 };
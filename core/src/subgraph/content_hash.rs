@@ -0,0 +1,262 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+
+use graph::prelude::*;
+
+/// The size of the chunks that IPFS splits files into by default.
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// The maximum number of links of a node in a balanced IPFS DAG.
+const LINKS_PER_NODE: usize = 174;
+
+/// The multihash prefix for SHA2-256 digests, which all CIDv0 use.
+const SHA2_256_PREFIX: [u8; 2] = [0x12, 0x20];
+
+/// UnixFS node types.
+const UNIXFS_RAW: u64 = 0;
+const UNIXFS_FILE: u64 = 2;
+
+fn encode_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn encode_uint_field(buf: &mut Vec<u8>, field: u64, n: u64) {
+    encode_varint(buf, field << 3);
+    encode_varint(buf, n);
+}
+
+fn encode_bytes_field(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    encode_varint(buf, (field << 3) | 2);
+    encode_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// A node of the DAG that IPFS builds for a file.
+struct DagNode {
+    multihash: Vec<u8>,
+    /// The size of the encoded node together with all its descendants.
+    cumulative_size: u64,
+    /// The number of file bytes below the node.
+    file_size: u64,
+}
+
+impl DagNode {
+    fn from_block(block: Vec<u8>, cumulative_size: u64, file_size: u64) -> Self {
+        let mut multihash = SHA2_256_PREFIX.to_vec();
+        multihash.extend_from_slice(&Sha256::digest(&block));
+        DagNode {
+            multihash,
+            cumulative_size: cumulative_size + block.len() as u64,
+            file_size,
+        }
+    }
+
+    fn leaf(chunk: &[u8], leaf_type: u64) -> Self {
+        let mut unixfs = vec![];
+        encode_uint_field(&mut unixfs, 1, leaf_type);
+        if !chunk.is_empty() {
+            encode_bytes_field(&mut unixfs, 2, chunk);
+        }
+        encode_uint_field(&mut unixfs, 3, chunk.len() as u64);
+
+        let mut block = vec![];
+        encode_bytes_field(&mut block, 1, &unixfs);
+        Self::from_block(block, 0, chunk.len() as u64)
+    }
+
+    fn parent(children: &[DagNode]) -> Self {
+        let file_size = children.iter().map(|child| child.file_size).sum();
+        let mut unixfs = vec![];
+        encode_uint_field(&mut unixfs, 1, UNIXFS_FILE);
+        encode_uint_field(&mut unixfs, 3, file_size);
+        for child in children {
+            encode_uint_field(&mut unixfs, 4, child.file_size);
+        }
+
+        // Links come before the data in the canonical encoding
+        let mut block = vec![];
+        for child in children {
+            let mut link = vec![];
+            encode_bytes_field(&mut link, 1, &child.multihash);
+            encode_bytes_field(&mut link, 2, &[]);
+            encode_uint_field(&mut link, 3, child.cumulative_size);
+            encode_bytes_field(&mut block, 2, &link);
+        }
+        encode_bytes_field(&mut block, 1, &unixfs);
+
+        let cumulative_size = children.iter().map(|child| child.cumulative_size).sum();
+        Self::from_block(block, cumulative_size, file_size)
+    }
+}
+
+/// Returns the CIDv0 multihash that adding `data` to IPFS with the default
+/// settings produces: a balanced DAG of 256KiB chunks. Leaves of files with
+/// more than one chunk are encoded with `leaf_type`, which differs between
+/// IPFS versions.
+fn cid_v0_multihash(data: &[u8], leaf_type: u64) -> Vec<u8> {
+    if data.len() <= CHUNK_SIZE {
+        return DagNode::leaf(data, UNIXFS_FILE).multihash;
+    }
+
+    let mut nodes: Vec<_> = data
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| DagNode::leaf(chunk, leaf_type))
+        .collect();
+    while nodes.len() > 1 {
+        nodes = nodes.chunks(LINKS_PER_NODE).map(DagNode::parent).collect();
+    }
+    nodes.pop().unwrap().multihash
+}
+
+/// Checks that `data` is the content of the file with the CIDv0 `cid`.
+fn verify_content(link: &Link, cid: &str, data: &[u8]) -> Result<(), Error> {
+    let expected = bs58::decode(cid)
+        .into_vec()
+        .ok()
+        .filter(|multihash| multihash.len() == 34 && multihash.starts_with(&SHA2_256_PREFIX))
+        .ok_or_else(|| {
+            format_err!(
+                "Cannot verify the content of `{}`, only CIDv0 links are supported",
+                link.link
+            )
+        })?;
+
+    let actual = cid_v0_multihash(data, UNIXFS_FILE);
+    if actual == expected || cid_v0_multihash(data, UNIXFS_RAW) == expected {
+        Ok(())
+    } else {
+        Err(ContentHashMismatch {
+            link: link.link.clone(),
+            actual: bs58::encode(actual).into_string(),
+        }
+        .into())
+    }
+}
+
+/// A link resolver that checks that the files returned by the wrapped
+/// resolver hash to the content hash of their link, so that a compromised
+/// IPFS node or gateway can't serve tampered files.
+pub(crate) struct VerifyingLinkResolver<L> {
+    pub inner: L,
+}
+
+#[async_trait]
+impl<L> LinkResolver for VerifyingLinkResolver<L>
+where
+    L: LinkResolver,
+{
+    // The wrapped resolver is shared, so its configuration is left untouched
+    fn with_timeout(self, _timeout: Duration) -> Self {
+        self
+    }
+
+    fn with_retries(self) -> Self {
+        self
+    }
+
+    async fn cat(&self, logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        // Discard the `/ipfs/` prefix (if present) to get the hash.
+        let cid = link.link.trim_start_matches("/ipfs/");
+
+        let data = self.inner.cat(logger, link).await?;
+        verify_content(link, cid, &data)?;
+        Ok(data)
+    }
+
+    async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        self.inner.json_stream(logger, link).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the same bytes for every link.
+    struct FixedLinkResolver(&'static [u8]);
+
+    #[async_trait]
+    impl LinkResolver for FixedLinkResolver {
+        fn with_timeout(self, _timeout: Duration) -> Self {
+            self
+        }
+
+        fn with_retries(self) -> Self {
+            self
+        }
+
+        async fn cat(&self, _logger: &Logger, _link: &Link) -> Result<Vec<u8>, Error> {
+            Ok(self.0.to_vec())
+        }
+
+        async fn json_stream(
+            &self,
+            _logger: &Logger,
+            _link: &Link,
+        ) -> Result<JsonValueStream, Error> {
+            unimplemented!()
+        }
+    }
+
+    const HELLO_WORLD_CID: &str = "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o";
+
+    fn link(cid: &str) -> Link {
+        Link {
+            link: format!("/ipfs/{}", cid),
+        }
+    }
+
+    #[test]
+    fn computes_cids_of_small_files() {
+        let cid = |data: &[u8]| bs58::encode(cid_v0_multihash(data, UNIXFS_FILE)).into_string();
+
+        assert_eq!(cid(b"hello world\n"), HELLO_WORLD_CID);
+        assert_eq!(cid(b""), "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH");
+    }
+
+    #[tokio::test]
+    async fn accepts_files_that_match_their_hash() {
+        let logger = Logger::root(slog::Discard, o!());
+        let resolver = VerifyingLinkResolver {
+            inner: FixedLinkResolver(b"hello world\n"),
+        };
+
+        let data = resolver.cat(&logger, &link(HELLO_WORLD_CID)).await;
+
+        assert_eq!(data.unwrap(), b"hello world\n".to_vec());
+    }
+
+    #[tokio::test]
+    async fn rejects_files_that_do_not_match_their_hash() {
+        let logger = Logger::root(slog::Discard, o!());
+        let resolver = VerifyingLinkResolver {
+            inner: FixedLinkResolver(b"tampered\n"),
+        };
+
+        let err = resolver
+            .cat(&logger, &link(HELLO_WORLD_CID))
+            .await
+            .unwrap_err();
+
+        let mismatch = err.downcast_ref::<ContentHashMismatch>().unwrap();
+        assert_eq!(mismatch.link, link(HELLO_WORLD_CID).link);
+    }
+
+    #[tokio::test]
+    async fn rejects_links_that_can_not_be_verified() {
+        let logger = Logger::root(slog::Discard, o!());
+        let resolver = VerifyingLinkResolver {
+            inner: FixedLinkResolver(b"hello world\n"),
+        };
+
+        let err = resolver.cat(&logger, &link("not-a-cid")).await.unwrap_err();
+
+        assert!(err.downcast_ref::<ContentHashMismatch>().is_none());
+    }
+}
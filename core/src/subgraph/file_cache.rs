@@ -0,0 +1,277 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+
+use graph::prelude::*;
+
+lazy_static! {
+    /// The default number of files kept by `LruFileCache::default()`.
+    static ref FILE_CACHE_ENTRIES: usize = std::env::var("GRAPH_DATA_SOURCE_FILE_CACHE_ENTRIES")
+        .unwrap_or("1000".into())
+        .parse::<usize>()
+        .expect("invalid GRAPH_DATA_SOURCE_FILE_CACHE_ENTRIES");
+
+    /// The default total size in bytes of the files kept by
+    /// `LruFileCache::default()`.
+    static ref FILE_CACHE_BYTES: usize = std::env::var("GRAPH_DATA_SOURCE_FILE_CACHE_BYTES")
+        .unwrap_or((100 * 1024 * 1024).to_string())
+        .parse::<usize>()
+        .expect("invalid GRAPH_DATA_SOURCE_FILE_CACHE_BYTES");
+}
+
+struct LruState {
+    files: HashMap<String, Vec<u8>>,
+    /// Content hashes from least to most recently used.
+    order: VecDeque<String>,
+    bytes: usize,
+}
+
+/// An in-memory file cache that evicts the least recently used files once
+/// either the number of files or their total size exceeds its limits.
+pub struct LruFileCache {
+    max_entries: usize,
+    max_bytes: usize,
+    state: Mutex<LruState>,
+}
+
+impl LruFileCache {
+    pub fn new(max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            max_entries,
+            max_bytes,
+            state: Mutex::new(LruState {
+                files: HashMap::new(),
+                order: VecDeque::new(),
+                bytes: 0,
+            }),
+        }
+    }
+}
+
+impl Default for LruFileCache {
+    fn default() -> Self {
+        Self::new(*FILE_CACHE_ENTRIES, *FILE_CACHE_BYTES)
+    }
+}
+
+impl FileCache for LruFileCache {
+    fn get(&self, cid: &str) -> Option<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        let data = state.files.get(cid)?.clone();
+
+        // Mark the file as most recently used
+        if let Some(pos) = state.order.iter().position(|key| key == cid) {
+            let key = state.order.remove(pos).unwrap();
+            state.order.push_back(key);
+        }
+
+        Some(data)
+    }
+
+    fn insert(&self, cid: &str, data: &[u8]) {
+        if data.len() > self.max_bytes || self.max_entries == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.files.contains_key(cid) {
+            return;
+        }
+
+        state.files.insert(cid.to_owned(), data.to_vec());
+        state.order.push_back(cid.to_owned());
+        state.bytes += data.len();
+
+        while state.files.len() > self.max_entries || state.bytes > self.max_bytes {
+            let evicted = match state.order.pop_front() {
+                Some(key) => key,
+                None => break,
+            };
+            if let Some(data) = state.files.remove(&evicted) {
+                state.bytes -= data.len();
+            }
+        }
+    }
+}
+
+/// A file cache shared by all data source loaders, together with metrics
+/// about how effective it is.
+pub struct DataSourceFileCache {
+    cache: Arc<dyn FileCache>,
+    hits: Box<Counter>,
+    misses: Box<Counter>,
+}
+
+impl DataSourceFileCache {
+    pub fn new(cache: Arc<dyn FileCache>, registry: Arc<impl MetricsRegistry>) -> Self {
+        let hits = registry
+            .new_counter(
+                String::from("data_source_file_cache_hits"),
+                String::from("Number of data source files that were found in the cache"),
+                HashMap::new(),
+            )
+            .expect("failed to create `data_source_file_cache_hits` counter");
+        let misses = registry
+            .new_counter(
+                String::from("data_source_file_cache_misses"),
+                String::from("Number of data source files that had to be fetched"),
+                HashMap::new(),
+            )
+            .expect("failed to create `data_source_file_cache_misses` counter");
+
+        Self {
+            cache,
+            hits,
+            misses,
+        }
+    }
+
+    fn get(&self, cid: &str) -> Option<Vec<u8>> {
+        let data = self.cache.get(cid);
+        match data {
+            Some(_) => self.hits.inc(),
+            None => self.misses.inc(),
+        }
+        data
+    }
+}
+
+/// A link resolver that looks up files in a `DataSourceFileCache` before
+/// fetching them with the wrapped resolver.
+pub(crate) struct CachingLinkResolver<L> {
+    pub inner: L,
+    pub cache: Option<Arc<DataSourceFileCache>>,
+}
+
+#[async_trait]
+impl<L> LinkResolver for CachingLinkResolver<L>
+where
+    L: LinkResolver,
+{
+    // The wrapped resolver is shared, so its configuration is left untouched
+    fn with_timeout(self, _timeout: Duration) -> Self {
+        self
+    }
+
+    fn with_retries(self) -> Self {
+        self
+    }
+
+    async fn cat(&self, logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return self.inner.cat(logger, link).await,
+        };
+
+        // Discard the `/ipfs/` prefix (if present) to get the hash.
+        let cid = link.link.trim_start_matches("/ipfs/");
+
+        if let Some(data) = cache.get(cid) {
+            trace!(logger, "Data source file cache hit"; "hash" => cid);
+            return Ok(data);
+        }
+
+        let data = self.inner.cat(logger, link).await?;
+        cache.cache.insert(cid, &data);
+        Ok(data)
+    }
+
+    async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        self.inner.json_stream(logger, link).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_mock::MockMetricsRegistry;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Returns the link as the file contents and counts the calls to `cat`.
+    #[derive(Default)]
+    struct CountingLinkResolver {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl LinkResolver for CountingLinkResolver {
+        fn with_timeout(self, _timeout: Duration) -> Self {
+            self
+        }
+
+        fn with_retries(self) -> Self {
+            self
+        }
+
+        async fn cat(&self, _logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(link.link.as_bytes().to_vec())
+        }
+
+        async fn json_stream(
+            &self,
+            _logger: &Logger,
+            _link: &Link,
+        ) -> Result<JsonValueStream, Error> {
+            unimplemented!()
+        }
+    }
+
+    fn link(cid: &str) -> Link {
+        Link {
+            link: format!("/ipfs/{}", cid),
+        }
+    }
+
+    #[tokio::test]
+    async fn second_load_of_same_file_hits_cache() {
+        let logger = Logger::root(slog::Discard, o!());
+        let inner = CountingLinkResolver::default();
+        let calls = inner.calls.clone();
+        let resolver = CachingLinkResolver {
+            inner,
+            cache: Some(Arc::new(DataSourceFileCache::new(
+                Arc::new(LruFileCache::new(10, 1024)),
+                Arc::new(MockMetricsRegistry::new()),
+            ))),
+        };
+
+        let first = resolver.cat(&logger, &link("QmA")).await.unwrap();
+        let second = resolver.cat(&logger, &link("QmA")).await.unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_files() {
+        let cache = LruFileCache::new(2, 1024);
+        cache.insert("a", b"a");
+        cache.insert("b", b"b");
+
+        // Using `a` makes `b` the least recently used file
+        assert!(cache.get("a").is_some());
+        cache.insert("c", b"c");
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn limits_total_size_of_files() {
+        let cache = LruFileCache::new(10, 4);
+        cache.insert("a", b"aa");
+        cache.insert("b", b"bb");
+        cache.insert("c", b"cc");
+        cache.insert("huge", b"too large");
+
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("c").is_some());
+        assert!(cache.get("huge").is_none());
+    }
+}
@@ -2,12 +2,14 @@ use async_trait::async_trait;
 use futures01::sync::mpsc::Sender;
 use lazy_static::lazy_static;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::str::FromStr;
+use std::sync::Mutex;
 
 use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
-use web3::types::Log;
+use graph::util::ethereum::{contract_event_with_signature, contract_function_with_signature};
+use web3::types::{Log, H256};
 
 lazy_static! {
     static ref MAX_DATA_SOURCES: Option<usize> = env::var("GRAPH_SUBGRAPH_MAX_DATA_SOURCES")
@@ -16,6 +18,26 @@ lazy_static! {
             .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_SUBGRAPH_MAX_DATA_SOURCES")));
 }
 
+/// Which data sources of a subgraph are run, to debug a data source by
+/// resyncing with only some of them. Data sources that are not run produce
+/// non-canonical data, and therefore a different proof of indexing.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DataSourceSelection {
+    /// If set, only these data sources are run.
+    pub enabled: Option<HashSet<String>>,
+    /// Data sources that are never run.
+    pub disabled: HashSet<String>,
+}
+
+impl DataSourceSelection {
+    pub fn is_active(&self, name: &str) -> bool {
+        self.enabled
+            .as_ref()
+            .map_or(true, |enabled| enabled.contains(name))
+            && !self.disabled.contains(name)
+    }
+}
+
 pub struct SubgraphInstance<T: RuntimeHostBuilder> {
     subgraph_id: SubgraphDeploymentId,
     network: String,
@@ -30,6 +52,128 @@ pub struct SubgraphInstance<T: RuntimeHostBuilder> {
 
     /// Maps a serialized module to a channel to the thread in which the module is instantiated.
     module_cache: HashMap<Vec<u8>, Sender<T::Req>>,
+
+    /// Names of handlers that are skipped, for debugging. Skipping handlers
+    /// produces non-canonical data, and therefore a different proof of
+    /// indexing.
+    disabled_handlers: Arc<HashSet<String>>,
+
+    /// Runtime hosts with only the disabled handlers of a data source, and
+    /// the names of those handlers. They are never run and only tell us
+    /// when a disabled handler would have fired.
+    disabled_hosts: Vec<(Arc<T::Host>, Vec<String>)>,
+
+    /// The block in which skipped handlers were last logged, and the
+    /// handlers that were logged for it.
+    logged_skipped_handlers: Mutex<(Option<H256>, HashSet<String>)>,
+
+    /// All templates of the manifest, to validate the data sources that
+    /// mappings create against.
+    templates: Vec<DataSourceTemplate>,
+
+    /// The data sources that are run; the others, including data sources
+    /// created from templates, get no runtime host.
+    data_source_selection: Arc<DataSourceSelection>,
+}
+
+/// Whether `host` has a handler for `trigger`.
+fn host_matches(host: &impl RuntimeHost, trigger: &EthereumTrigger) -> bool {
+    match trigger {
+        EthereumTrigger::Log(log) => host.matches_log(log),
+        EthereumTrigger::Call(call) => host.matches_call(call),
+        EthereumTrigger::Block(ptr, trigger_type) => host.matches_block(trigger_type, ptr.number),
+    }
+}
+
+/// Arranges the triggers of a block, which must be sorted, in the order in
+/// which they are processed according to `ordering`. Each trigger is paired
+/// with the hosts, in order, that process it at that point.
+pub(crate) fn order_triggers<'a, H: RuntimeHost>(
+    ordering: TriggerOrdering,
+    hosts: &'a [Arc<H>],
+    triggers: Vec<EthereumTrigger>,
+) -> Vec<(&'a [Arc<H>], EthereumTrigger)> {
+    match ordering {
+        TriggerOrdering::Block => triggers
+            .into_iter()
+            .map(|trigger| (hosts, trigger))
+            .collect(),
+        TriggerOrdering::DataSource => hosts
+            .iter()
+            .flat_map(|host| {
+                triggers
+                    .iter()
+                    .filter(move |trigger| host_matches(host.as_ref(), trigger))
+                    .map(move |trigger| (std::slice::from_ref(host), trigger.clone()))
+            })
+            .collect(),
+    }
+}
+
+/// Checks `info` with `DataSourceTemplateInfo::validate`, and that the
+/// template it refers to is one of `templates`. Returns the first error.
+fn check_template_info(
+    templates: &[DataSourceTemplate],
+    info: &DataSourceTemplateInfo,
+) -> Result<(), DataSourceTemplateInfoValidationError> {
+    match info.validate().into_iter().next() {
+        Some(e) => Err(e),
+        None => validate_template_references(templates, info),
+    }
+}
+
+/// Checks that the template of `info` is one of `templates`, and that the
+/// events and functions of its handlers are part of its source ABI. Returns
+/// the first reference that doesn't resolve.
+fn validate_template_references(
+    templates: &[DataSourceTemplate],
+    info: &DataSourceTemplateInfo,
+) -> Result<(), DataSourceTemplateInfoValidationError> {
+    use DataSourceTemplateInfoValidationError::*;
+
+    let name = &info.template.name;
+    if !templates.iter().any(|template| &template.name == name) {
+        return Err(UnknownTemplate {
+            template: name.clone(),
+            known: templates
+                .iter()
+                .map(|template| template.name.clone())
+                .collect(),
+        });
+    }
+
+    // A missing source ABI is reported by `DataSourceTemplateInfo::validate`
+    let mapping = &info.template.mapping;
+    let abi_name = &info.template.source.abi;
+    let abi = match mapping.abis.iter().find(|abi| &abi.name == abi_name) {
+        Some(abi) => abi,
+        None => return Ok(()),
+    };
+
+    if let Some(handler) = mapping
+        .event_handlers
+        .iter()
+        .find(|handler| contract_event_with_signature(&abi.contract, &handler.event).is_none())
+    {
+        return Err(UnknownEvent {
+            template: name.clone(),
+            abi: abi_name.clone(),
+            event: handler.event.clone(),
+            handler: handler.handler.clone(),
+        });
+    }
+    if let Some(handler) = mapping.call_handlers.iter().find(|handler| {
+        contract_function_with_signature(&abi.contract, &handler.function).is_none()
+    }) {
+        return Err(UnknownFunction {
+            template: name.clone(),
+            abi: abi_name.clone(),
+            function: handler.function.clone(),
+            handler: handler.handler.clone(),
+        });
+    }
+
+    Ok(())
 }
 
 impl<T> SubgraphInstance<T>
@@ -41,9 +185,12 @@ where
         manifest: SubgraphManifest,
         host_builder: T,
         host_metrics: Arc<HostMetrics>,
+        disabled_handlers: Arc<HashSet<String>>,
+        data_source_selection: Arc<DataSourceSelection>,
     ) -> Result<Self, Error> {
         let subgraph_id = manifest.id.clone();
         let network = manifest.network_name();
+        let all_templates = manifest.all_templates().cloned().collect();
         let templates = Arc::new(manifest.templates);
 
         let mut this = SubgraphInstance {
@@ -52,15 +199,55 @@ where
             network,
             hosts: Vec::new(),
             module_cache: HashMap::new(),
+            disabled_handlers,
+            disabled_hosts: Vec::new(),
+            logged_skipped_handlers: Mutex::new((None, HashSet::new())),
+            templates: all_templates,
+            data_source_selection,
         };
 
+        if !this.disabled_handlers.is_empty() {
+            warn!(
+                logger,
+                "Handlers are disabled, the subgraph will produce non-canonical data \
+                 and proofs of indexing";
+                "handlers" => this.disabled_handlers.iter().cloned().collect::<Vec<_>>().join(", "),
+            );
+        }
+
+        let selection = this.data_source_selection.clone();
+        let (data_sources, skipped): (Vec<_>, Vec<_>) = manifest
+            .data_sources
+            .into_iter()
+            .partition(|data_source| selection.is_active(&data_source.name));
+        info!(
+            logger,
+            "Active data sources";
+            "data_sources" => data_sources
+                .iter()
+                .map(|data_source| data_source.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+        if !skipped.is_empty() {
+            warn!(
+                logger,
+                "Data sources are disabled, the subgraph will produce non-canonical data \
+                 and proofs of indexing";
+                "disabled" => skipped
+                    .iter()
+                    .map(|data_source| data_source.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+        }
+
         // Create a new runtime host for each data source in the subgraph manifest;
         // we use the same order here as in the subgraph manifest to make the
         // event processing behavior predictable
-        let (hosts, errors): (_, Vec<_>) = manifest
-            .data_sources
+        let (hosts, errors): (_, Vec<_>) = data_sources
             .into_iter()
-            .map(|d| this.new_host(logger.clone(), d, templates.clone(), host_metrics.clone()))
+            .map(|d| this.new_hosts(logger.clone(), d, templates.clone(), host_metrics.clone()))
             .partition(|res| res.is_ok());
 
         if !errors.is_empty() {
@@ -85,6 +272,139 @@ where
         Ok(this)
     }
 
+    /// Checks that a data source can be created from `info` before it is
+    /// instantiated, so that a misconfigured template fails deterministically
+    /// instead of while its data source is being indexed.
+    pub(crate) fn validate_template_info(
+        &self,
+        info: &DataSourceTemplateInfo,
+    ) -> Result<(), DataSourceTemplateInfoValidationError> {
+        check_template_info(&self.templates, info)
+    }
+
+    /// Splits the disabled handlers off a data source, returning the data
+    /// source without them and, if it has any, a copy of the data source that
+    /// only has the disabled handlers.
+    fn split_disabled_handlers(
+        &self,
+        mut data_source: DataSource,
+    ) -> (DataSource, Option<(DataSource, Vec<String>)>) {
+        let disabled = &self.disabled_handlers;
+        let mapping = &mut data_source.mapping;
+        let (disabled_events, events): (Vec<_>, Vec<_>) = mapping
+            .event_handlers
+            .drain(..)
+            .partition(|handler| disabled.contains(&handler.handler));
+        let (disabled_calls, calls): (Vec<_>, Vec<_>) = mapping
+            .call_handlers
+            .drain(..)
+            .partition(|handler| disabled.contains(&handler.handler));
+        let (disabled_blocks, blocks): (Vec<_>, Vec<_>) = mapping
+            .block_handlers
+            .drain(..)
+            .partition(|handler| disabled.contains(&handler.handler));
+
+        let names: Vec<_> = disabled_events
+            .iter()
+            .map(|handler| handler.handler.clone())
+            .chain(disabled_calls.iter().map(|handler| handler.handler.clone()))
+            .chain(
+                disabled_blocks
+                    .iter()
+                    .map(|handler| handler.handler.clone()),
+            )
+            .collect();
+
+        let disabled = if names.is_empty() {
+            None
+        } else {
+            let mut disabled = data_source.clone();
+            disabled.mapping.event_handlers = disabled_events;
+            disabled.mapping.call_handlers = disabled_calls;
+            disabled.mapping.block_handlers = disabled_blocks;
+            Some((disabled, names))
+        };
+
+        data_source.mapping.event_handlers = events;
+        data_source.mapping.call_handlers = calls;
+        data_source.mapping.block_handlers = blocks;
+        (data_source, disabled)
+    }
+
+    /// Creates the runtime host for a data source, and one for its disabled
+    /// handlers if it has any.
+    fn new_hosts(
+        &mut self,
+        logger: Logger,
+        data_source: DataSource,
+        top_level_templates: Arc<Vec<DataSourceTemplate>>,
+        host_metrics: Arc<HostMetrics>,
+    ) -> Result<T::Host, Error> {
+        let (data_source, disabled) = self.split_disabled_handlers(data_source);
+        if let Some((disabled, names)) = disabled {
+            let host = self.new_host(
+                logger.clone(),
+                disabled,
+                top_level_templates.clone(),
+                host_metrics.clone(),
+            )?;
+            self.disabled_hosts.push((Arc::new(host), names));
+        }
+        self.new_host(logger, data_source, top_level_templates, host_metrics)
+    }
+
+    /// The runtime hosts of the data sources that are run, in the order in
+    /// which they process triggers.
+    pub(crate) fn hosts(&self) -> &[Arc<T::Host>] {
+        &self.hosts
+    }
+
+    /// Processes `trigger` in those of `hosts` that handle it, logging the
+    /// disabled handlers that would have handled it.
+    pub(crate) async fn process_trigger_in_hosts(
+        &self,
+        logger: &Logger,
+        hosts: &[Arc<T::Host>],
+        block: &Arc<LightEthereumBlock>,
+        trigger: EthereumTrigger,
+        state: BlockState,
+    ) -> Result<BlockState, Error> {
+        if !self.disabled_hosts.is_empty() {
+            self.log_skipped_handlers(logger, block, &trigger);
+        }
+        Self::process_trigger_in_runtime_hosts(logger, hosts, block, trigger, state).await
+    }
+
+    /// Logs the disabled handlers that would have processed `trigger`, once
+    /// per block and handler.
+    fn log_skipped_handlers(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        trigger: &EthereumTrigger,
+    ) {
+        let skipped = self
+            .disabled_hosts
+            .iter()
+            .filter(|(host, _)| host_matches(host.as_ref(), trigger))
+            .flat_map(|(_, names)| names);
+
+        let mut logged = self.logged_skipped_handlers.lock().unwrap();
+        if logged.0 != block.hash {
+            *logged = (block.hash, HashSet::new());
+        }
+        for name in skipped {
+            if logged.1.insert(name.clone()) {
+                info!(
+                    logger,
+                    "Skipping disabled handler";
+                    "handler" => name,
+                    "block_number" => format!("{:?}", block.number),
+                );
+            }
+        }
+    }
+
     fn new_host(
         &mut self,
         logger: Logger,
@@ -135,7 +455,8 @@ where
         trigger: EthereumTrigger,
         state: BlockState,
     ) -> Result<BlockState, Error> {
-        Self::process_trigger_in_runtime_hosts(logger, &self.hosts, block, trigger, state).await
+        self.process_trigger_in_hosts(logger, &self.hosts, block, trigger, state)
+            .await
     }
 
     async fn process_trigger_in_runtime_hosts(
@@ -161,6 +482,7 @@ where
                     state = host
                         .process_log(logger, block, &transaction, &log, state)
                         .await?;
+                    state.handler_yield.handler_invoked().await;
                 }
             }
             EthereumTrigger::Call(call) => {
@@ -176,6 +498,7 @@ where
                     state = host
                         .process_call(logger, block, &transaction, &call, state)
                         .await?;
+                    state.handler_yield.handler_invoked().await;
                 }
             }
             EthereumTrigger::Block(ptr, trigger_type) => {
@@ -186,6 +509,7 @@ where
                     state = host
                         .process_block(logger, block, &trigger_type, state)
                         .await?;
+                    state.handler_yield.handler_invoked().await;
                 }
             }
         }
@@ -198,7 +522,16 @@ where
         data_source: DataSource,
         top_level_templates: Arc<Vec<DataSourceTemplate>>,
         metrics: Arc<HostMetrics>,
-    ) -> Result<Arc<T::Host>, Error> {
+    ) -> Result<Option<Arc<T::Host>>, Error> {
+        if !self.data_source_selection.is_active(&data_source.name) {
+            debug!(
+                logger,
+                "Skipping disabled data source";
+                "data_source" => &data_source.name,
+            );
+            return Ok(None);
+        }
+
         // Protect against creating more than the allowed maximum number of data sources
         if let Some(max_data_sources) = *MAX_DATA_SOURCES {
             if self.hosts.len() >= max_data_sources {
@@ -209,13 +542,234 @@ where
             }
         }
 
-        let host = Arc::new(self.new_host(
+        let host = Arc::new(self.new_hosts(
             logger.clone(),
             data_source,
             top_level_templates,
             metrics.clone(),
         )?);
         self.hosts.push(host.clone());
-        Ok(host)
+        Ok(Some(host))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::data::subgraph::{Mapping, TemplateSource};
+    use graph::prelude::ethabi::Contract;
+    use web3::types::{Address, Bytes, Transaction, U64};
+
+    const ABI: &str = r#"[{
+        "anonymous": false,
+        "inputs": [{ "indexed": false, "name": "trader", "type": "address" }],
+        "name": "Trade",
+        "type": "event"
+    }]"#;
+
+    fn template(event: &str) -> DataSourceTemplate {
+        DataSourceTemplate {
+            kind: String::from("ethereum/contract"),
+            network: Some(String::from("mainnet")),
+            name: String::from("Exchange"),
+            source: TemplateSource {
+                abi: String::from("Exchange"),
+            },
+            mapping: Mapping {
+                kind: String::from("ethereum/events"),
+                api_version: String::from("0.0.4"),
+                language: String::from("wasm/assemblyscript"),
+                entities: vec![],
+                abis: vec![MappingABI {
+                    name: String::from("Exchange"),
+                    contract: Contract::load(ABI.as_bytes()).unwrap(),
+                    link: Link::from(String::from("/ipfs/Qmabi")),
+                }],
+                block_handlers: vec![],
+                call_handlers: vec![],
+                event_handlers: vec![MappingEventHandler {
+                    event: event.to_owned(),
+                    topic0: None,
+                    handler: String::from("handleTrade"),
+                }],
+                runtime: Arc::new(Default::default()),
+                link: Link::from(String::from("/ipfs/Qmmapping")),
+            },
+        }
+    }
+
+    fn info(template: DataSourceTemplate) -> DataSourceTemplateInfo {
+        DataSourceTemplateInfo {
+            data_source: String::from("Factory"),
+            template,
+            params: vec![String::from("0x22843e74c59580b3eaf6c233fa67d8b7c561a835")],
+            context: None,
+        }
+    }
+
+    #[test]
+    fn valid_template_references_resolve() {
+        let templates = vec![template("Trade(address)")];
+
+        assert_eq!(
+            Ok(()),
+            check_template_info(&templates, &info(templates[0].clone()))
+        );
+    }
+
+    #[test]
+    fn dangling_references_are_named() {
+        let templates = vec![template("Trade(address)")];
+
+        let mut dangling_abi = templates[0].clone();
+        dangling_abi.source.abi = String::from("Token");
+        assert_eq!(
+            Err(DataSourceTemplateInfoValidationError::UnknownSourceAbi {
+                template: String::from("Exchange"),
+                abi: String::from("Token"),
+            }),
+            check_template_info(&templates, &info(dangling_abi))
+        );
+
+        let dangling_event = template("Transfer(address,address)");
+        assert_eq!(
+            Err(DataSourceTemplateInfoValidationError::UnknownEvent {
+                template: String::from("Exchange"),
+                abi: String::from("Exchange"),
+                event: String::from("Transfer(address,address)"),
+                handler: String::from("handleTrade"),
+            }),
+            check_template_info(&templates, &info(dangling_event))
+        );
+    }
+
+    #[test]
+    fn data_source_selection() {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+
+        let all = DataSourceSelection::default();
+        assert!(all.is_active("Factory"));
+
+        let only = DataSourceSelection {
+            enabled: Some(names(&["Factory", "Exchange"])),
+            disabled: names(&["Exchange"]),
+        };
+        assert!(only.is_active("Factory"));
+        assert!(!only.is_active("Exchange"));
+        assert!(!only.is_active("Token"));
+    }
+
+    /// Handles the events of one contract.
+    #[derive(Debug)]
+    struct ContractHost(Address);
+
+    #[async_trait]
+    impl RuntimeHost for ContractHost {
+        fn matches_log(&self, log: &Log) -> bool {
+            log.address == self.0
+        }
+
+        fn matches_call(&self, _: &EthereumCall) -> bool {
+            false
+        }
+
+        fn matches_block(&self, _: &EthereumBlockTriggerType, _: u64) -> bool {
+            false
+        }
+
+        async fn process_log(
+            &self,
+            _: &Logger,
+            _: &Arc<LightEthereumBlock>,
+            _: &Arc<Transaction>,
+            _: &Arc<Log>,
+            _: BlockState,
+        ) -> Result<BlockState, Error> {
+            unimplemented!()
+        }
+
+        async fn process_call(
+            &self,
+            _: &Logger,
+            _: &Arc<LightEthereumBlock>,
+            _: &Arc<Transaction>,
+            _: &Arc<EthereumCall>,
+            _: BlockState,
+        ) -> Result<BlockState, Error> {
+            unimplemented!()
+        }
+
+        async fn process_block(
+            &self,
+            _: &Logger,
+            _: &Arc<LightEthereumBlock>,
+            _: &EthereumBlockTriggerType,
+            _: BlockState,
+        ) -> Result<BlockState, Error> {
+            unimplemented!()
+        }
+    }
+
+    fn log(address: Address, log_index: u64) -> EthereumTrigger {
+        EthereumTrigger::Log(Log {
+            address,
+            topics: vec![],
+            data: Bytes::default(),
+            block_hash: Some(H256::zero()),
+            block_number: Some(U64::zero()),
+            transaction_hash: Some(H256::zero()),
+            transaction_index: Some(log_index.into()),
+            log_index: Some(log_index.into()),
+            transaction_log_index: Some(log_index.into()),
+            log_type: None,
+            removed: Some(false),
+        })
+    }
+
+    /// The hosts, by index, and log indexes in the order in which the logs
+    /// are processed.
+    fn handler_order(
+        ordering: TriggerOrdering,
+        hosts: &[Arc<ContractHost>],
+        mut triggers: Vec<EthereumTrigger>,
+    ) -> Vec<(usize, u64)> {
+        triggers.sort();
+        order_triggers(ordering, hosts, triggers)
+            .into_iter()
+            .flat_map(|(matching, trigger)| {
+                let log = match trigger {
+                    EthereumTrigger::Log(log) => log,
+                    _ => unreachable!(),
+                };
+                matching
+                    .iter()
+                    .filter(|host| host.matches_log(&log))
+                    .map(|host| {
+                        let index = hosts.iter().position(|h| Arc::ptr_eq(h, host)).unwrap();
+                        (index, log.log_index.unwrap().as_u64())
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn triggers_are_processed_in_a_deterministic_order() {
+        let (a, b) = (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        let hosts = vec![Arc::new(ContractHost(a)), Arc::new(ContractHost(b))];
+        let triggers = vec![log(a, 0), log(b, 1), log(a, 2), log(b, 3)];
+        let mut shuffled = triggers.clone();
+        shuffled.reverse();
+
+        for triggers in vec![triggers, shuffled] {
+            assert_eq!(
+                vec![(0, 0), (1, 1), (0, 2), (1, 3)],
+                handler_order(TriggerOrdering::Block, &hosts, triggers.clone())
+            );
+            assert_eq!(
+                vec![(0, 0), (0, 2), (1, 1), (1, 3)],
+                handler_order(TriggerOrdering::DataSource, &hosts, triggers)
+            );
+        }
     }
 }
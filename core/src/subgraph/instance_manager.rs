@@ -1,18 +1,27 @@
 use futures01::sync::mpsc::{channel, Receiver, Sender};
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::RwLock;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 
 use graph::components::ethereum::triggers_in_block;
 use graph::components::store::ModificationsAndCache;
-use graph::components::subgraph::{ProofOfIndexing, ProofOfIndexingDigest};
+use graph::components::subgraph::{
+    ProofOfIndexing, ProofOfIndexingDigest, ProofOfIndexingSubscriber, SubgraphPauseSubscriber,
+    SubgraphSyncPolicy, SubgraphSyncSubscriber, SubgraphSyncTracker,
+    SUBGRAPH_SYNCED_BLOCK_THRESHOLD,
+};
 use graph::data::subgraph::schema::{
     DynamicEthereumContractDataSourceEntity, SubgraphDeploymentEntity, POI_OBJECT,
 };
-use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
+use graph::prelude::tokio::sync::{watch, Semaphore};
+use graph::prelude::{
+    SubgraphInstance as SubgraphInstanceTrait,
+    SubgraphInstanceManager as SubgraphInstanceManagerTrait, *,
+};
 use graph::util::lfu_cache::LfuCache;
 
+use super::instance::{order_triggers, DataSourceSelection};
 use super::SubgraphInstance;
 
 lazy_static! {
@@ -23,12 +32,275 @@ lazy_static! {
             .unwrap_or("10000".into())
             .parse::<u64>()
             .expect("invalid GRAPH_ENTITY_CACHE_SIZE");
+
+    /// Size of the entity changes made while processing a single block above
+    /// which a warning is logged, in bytes.
+    // Multiplied by 1000 because the env var is in KB.
+    static ref BLOCK_STATE_SIZE_WARNING_THRESHOLD: u64 = 1000
+        * std::env::var("GRAPH_BLOCK_STATE_SIZE_WARNING_THRESHOLD")
+            .unwrap_or("100000".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_BLOCK_STATE_SIZE_WARNING_THRESHOLD");
+
+    /// Names of mapping handlers that are skipped, to find out which handler
+    /// produces wrong data by resyncing without it. Subgraphs indexed with
+    /// disabled handlers have non-canonical data and proofs of indexing.
+    static ref DISABLED_HANDLERS: Arc<HashSet<String>> =
+        Arc::new(env_names("GRAPH_DEBUG_DISABLED_HANDLERS").unwrap_or_default());
+
+    /// Data sources, including those created from templates, that are run
+    /// or skipped, to debug a data source by resyncing with only some of
+    /// them. This has the same caveats as `DISABLED_HANDLERS`.
+    static ref DATA_SOURCE_SELECTION: Arc<DataSourceSelection> = Arc::new(DataSourceSelection {
+        enabled: env_names("GRAPH_DEBUG_ENABLED_DATA_SOURCES"),
+        disabled: env_names("GRAPH_DEBUG_DISABLED_DATA_SOURCES").unwrap_or_default(),
+    });
+
+    /// The order in which the triggers of a block are processed. Changing it
+    /// changes the data and proofs of indexing of subgraphs whose handlers
+    /// depend on each other's changes.
+    static ref TRIGGER_ORDERING: TriggerOrdering = std::env::var("GRAPH_SUBGRAPH_TRIGGER_ORDERING")
+        .map(|ordering| TriggerOrdering::parse(&ordering))
+        .unwrap_or_default();
+
+    /// Whether subgraphs copy the pending entity operations of the block they
+    /// are processing after each handler, so that they can be inspected with
+    /// `pending_block_operations`. This is expensive for blocks with many
+    /// changes, so it is only meant for debugging.
+    static ref CAPTURE_PENDING_OPERATIONS: bool = std::env::var("GRAPH_DEBUG_PENDING_BLOCK_OPERATIONS")
+        .map(|value| value == "true")
+        .unwrap_or(false);
+
+    /// Maximum amount of entity data a subgraph may hold in memory while it
+    /// processes a block, i.e., its entity cache and the changes of the
+    /// block, in bytes. Subgraphs that exceed it fail instead of exhausting
+    /// the memory of the node. Multiplied by 1000 because the env var is in
+    /// KB.
+    static ref SUBGRAPH_MEMORY_BUDGET: Option<u64> = std::env::var("GRAPH_SUBGRAPH_MEMORY_BUDGET")
+        .ok()
+        .map(|budget| {
+            1000 * budget
+                .parse::<u64>()
+                .expect("invalid GRAPH_SUBGRAPH_MEMORY_BUDGET")
+        });
+
+    /// How often processing a block yields to other tasks between handlers.
+    /// By default, it yields every 100ms, which is rare enough to not affect
+    /// indexing throughput.
+    static ref HANDLER_YIELD_POLICY: HandlerYieldPolicy = HandlerYieldPolicy {
+        handlers: std::env::var("GRAPH_SUBGRAPH_HANDLER_YIELD_COUNT")
+            .ok()
+            .map(|count| {
+                count
+                    .parse::<usize>()
+                    .expect("invalid GRAPH_SUBGRAPH_HANDLER_YIELD_COUNT")
+            }),
+        interval: Some(Duration::from_millis(
+            std::env::var("GRAPH_SUBGRAPH_HANDLER_YIELD_INTERVAL")
+                .unwrap_or("100".into())
+                .parse::<u64>()
+                .expect("invalid GRAPH_SUBGRAPH_HANDLER_YIELD_INTERVAL"),
+        ))
+        .filter(|interval| *interval > Duration::from_millis(0)),
+    };
+
+    /// When subgraphs count as synced or unsynced for `SubgraphSyncEvent`s.
+    static ref SYNC_POLICY: SubgraphSyncPolicy = SubgraphSyncPolicy {
+        synced_threshold: *SUBGRAPH_SYNCED_BLOCK_THRESHOLD,
+        unsynced_threshold: std::env::var("GRAPH_SUBGRAPH_UNSYNCED_BLOCK_THRESHOLD")
+            .unwrap_or("50".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_SUBGRAPH_UNSYNCED_BLOCK_THRESHOLD"),
+        debounce: Duration::from_secs(
+            std::env::var("GRAPH_SUBGRAPH_UNSYNCED_DEBOUNCE")
+                .unwrap_or("60".into())
+                .parse::<u64>()
+                .expect("invalid GRAPH_SUBGRAPH_UNSYNCED_DEBOUNCE"),
+        ),
+    };
+
+    /// How subgraphs that fail with non-deterministic errors are restarted.
+    static ref RESTART_POLICY: RestartPolicy = RestartPolicy {
+        max_retries: std::env::var("GRAPH_SUBGRAPH_MAX_RESTARTS")
+            .unwrap_or("10".into())
+            .parse::<u32>()
+            .expect("invalid GRAPH_SUBGRAPH_MAX_RESTARTS"),
+        base_delay: Duration::from_secs(
+            std::env::var("GRAPH_SUBGRAPH_RESTART_BASE_DELAY")
+                .unwrap_or("5".into())
+                .parse::<u64>()
+                .expect("invalid GRAPH_SUBGRAPH_RESTART_BASE_DELAY"),
+        ),
+        max_delay: Duration::from_secs(
+            std::env::var("GRAPH_SUBGRAPH_RESTART_MAX_DELAY")
+                .unwrap_or("600".into())
+                .parse::<u64>()
+                .expect("invalid GRAPH_SUBGRAPH_RESTART_MAX_DELAY"),
+        ),
+    };
+
+    /// Limits how many subgraphs start up at the same time, so that starting
+    /// many subgraphs at once does not exhaust the store connection pool or
+    /// overwhelm IPFS and Ethereum nodes.
+    static ref SUBGRAPH_STARTUP_SEMAPHORE: Semaphore = {
+        // This is duplicating the logic in main.rs to get the connection pool size.
+        // See also 82d5dad6-b633-4350-86d9-70c8b2e65805
+        let db_conn_pool_size = std::env::var("STORE_CONNECTION_POOL_SIZE")
+            .unwrap_or("10".into())
+            .parse::<usize>()
+            .expect("invalid STORE_CONNECTION_POOL_SIZE");
+
+        let permits = std::env::var("GRAPH_SUBGRAPH_STARTUP_CONCURRENCY")
+            .map(|s| {
+                s.parse::<usize>()
+                    .expect("invalid GRAPH_SUBGRAPH_STARTUP_CONCURRENCY")
+            })
+            .unwrap_or((0.5 * db_conn_pool_size as f64).ceil() as usize);
+        Semaphore::new(permits.max(1))
+    };
 }
 
+/// Reads a comma-separated list of names from the environment variable `var`,
+/// or `None` if it is not set.
+fn env_names(var: &str) -> Option<HashSet<String>> {
+    std::env::var(var).ok().map(|names| {
+        names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect()
+    })
+}
+
+/// How often `SubgraphInstanceManager::shutdown` checks whether all subgraphs
+/// have drained.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 type SharedInstanceKeepAliveMap = Arc<RwLock<HashMap<SubgraphDeploymentId, CancelGuard>>>;
 
-struct IndexingInputs<B, S> {
+/// Channels through which running subgraphs are told whether they are paused.
+type SharedInstancePauseMap =
+    Arc<RwLock<HashMap<SubgraphDeploymentId, (watch::Sender<bool>, watch::Receiver<bool>)>>>;
+
+/// What the manager knows about the subgraphs that have been started and not
+/// stopped yet.
+type SharedInstanceStatusMap = Arc<RwLock<HashMap<SubgraphDeploymentId, InstanceStatus>>>;
+
+#[derive(Clone, Debug, Default)]
+struct InstanceStatus {
+    restart: SubgraphRestartStatus,
+    block: Option<EthereumBlockPointer>,
+    synced: bool,
+    failed: bool,
+    last_error: Option<String>,
+}
+
+/// The subgraphs whose indexing task has not finished yet.
+type SharedInstanceRunningSet = Arc<RwLock<HashSet<SubgraphDeploymentId>>>;
+
+/// The subscriber, if any, that receives the proof of indexing events of all
+/// subgraphs.
+type SharedProofOfIndexingSubscriber = Arc<RwLock<Option<Arc<dyn ProofOfIndexingSubscriber>>>>;
+
+/// The subscriber, if any, that receives the sync events of all subgraphs.
+type SharedSubgraphSyncSubscriber = Arc<RwLock<Option<Arc<dyn SubgraphSyncSubscriber>>>>;
+
+/// The subscriber, if any, that receives the pause events of all subgraphs.
+type SharedSubgraphPauseSubscriber = Arc<RwLock<Option<Arc<dyn SubgraphPauseSubscriber>>>>;
+
+/// Copies of the entity operations of the blocks that subgraphs are
+/// processing, if `CAPTURE_PENDING_OPERATIONS` is set.
+type SharedInstancePendingMap = Arc<RwLock<HashMap<SubgraphDeploymentId, PendingBlockOperations>>>;
+
+/// How often a subgraph that is processing blocks compares its progress to
+/// the chain head.
+const SYNC_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Exponential backoff for restarting subgraphs after non-deterministic
+/// failures.
+struct RestartPolicy {
+    max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RestartPolicy {
+    /// The delay before restarting a subgraph that has already been
+    /// restarted `retries` times.
+    fn delay(&self, retries: u32) -> Duration {
+        self.base_delay
+            .checked_mul(2u32.saturating_pow(retries))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+}
+
+/// The ways in which processing a block can fail.
+enum BlockProcessingError {
+    /// The error will occur again whenever the block is processed, e.g. a
+    /// failing mapping. The subgraph is marked as failed.
+    Deterministic(Error),
+
+    /// The error may go away when the block is processed again, e.g. when
+    /// the Ethereum node or the store was unavailable. The subgraph is
+    /// restarted according to the `RESTART_POLICY`.
+    NonDeterministic(Error),
+
+    /// A handler failed non-deterministically more often than its
+    /// `HandlerErrorBudget` allows. The subgraph is marked as failed without
+    /// being restarted.
+    ErrorBudgetExceeded(Error),
+
+    /// The block stream was canceled because the subgraph was stopped.
+    Canceled,
+}
+
+impl BlockProcessingError {
+    /// Whether the error will occur again when the block is processed again.
+    /// This is what the subgraph is marked as failed with.
+    fn is_deterministic(&self) -> bool {
+        match self {
+            BlockProcessingError::Deterministic(_) => true,
+            BlockProcessingError::NonDeterministic(_)
+            | BlockProcessingError::ErrorBudgetExceeded(_)
+            | BlockProcessingError::Canceled => false,
+        }
+    }
+
+    fn into_inner(self) -> Error {
+        match self {
+            BlockProcessingError::Deterministic(e)
+            | BlockProcessingError::NonDeterministic(e)
+            | BlockProcessingError::ErrorBudgetExceeded(e) => e,
+            BlockProcessingError::Canceled => format_err!("Subgraph block stream was canceled"),
+        }
+    }
+}
+
+/// Returns how an error from processing a trigger in a mapping is handled.
+/// Failures of host functions that depend on the Ethereum node, IPFS or the
+/// store, and handler timeouts that are configured to be non-deterministic,
+/// are non-deterministic; all other errors are deterministic.
+fn trigger_error_kind(e: &Error) -> fn(Error) -> BlockProcessingError {
+    if e.downcast_ref::<HandlerErrorBudgetExceeded>().is_some() {
+        BlockProcessingError::ErrorBudgetExceeded
+    } else if is_deterministic_handler_error(e) {
+        BlockProcessingError::Deterministic
+    } else {
+        BlockProcessingError::NonDeterministic
+    }
+}
+
+struct IndexingInputs<B, T, S> {
     deployment_id: SubgraphDeploymentId,
+
+    /// The manifest the subgraph was started with, including the dynamic
+    /// data sources that existed at that point, and the host builder used to
+    /// create its instance. Both are used to recreate the instance when the
+    /// subgraph is restarted after a failure.
+    manifest: SubgraphManifest,
+    host_builder: T,
+
     network_name: String,
     start_blocks: Vec<u64>,
     store: Arc<S>,
@@ -36,6 +308,10 @@ struct IndexingInputs<B, S> {
     stream_builder: B,
     templates_use_calls: bool,
     top_level_templates: Arc<Vec<DataSourceTemplate>>,
+    poi_subscriber: SharedProofOfIndexingSubscriber,
+    sync_subscriber: SharedSubgraphSyncSubscriber,
+    pause_subscriber: SharedSubgraphPauseSubscriber,
+    pending_operations: SharedInstancePendingMap,
 }
 
 struct IndexingState<T: RuntimeHostBuilder> {
@@ -47,11 +323,32 @@ struct IndexingState<T: RuntimeHostBuilder> {
     block_filter: EthereumBlockFilter,
     restarts: u64,
     entity_lfu_cache: LfuCache<EntityKey, Option<Entity>>,
+
+    /// Whether the subgraph is currently paused. The sender is dropped when
+    /// the subgraph is stopped.
+    paused: watch::Receiver<bool>,
+
+    /// Dynamic data sources created, and committed to the store, since the
+    /// subgraph was started.
+    dynamic_data_sources: Vec<DataSource>,
+
+    /// Progress, restarts and failures of the subgraph, shared with the
+    /// manager so that they can be reported.
+    instance_status: SharedInstanceStatusMap,
+    retries: u32,
+
+    /// Set to `true` when the manager shuts down, after which no new blocks
+    /// are processed.
+    shutdown: watch::Receiver<bool>,
+
+    /// Whether the subgraph is synced, and when that was last checked.
+    sync_tracker: SubgraphSyncTracker,
+    sync_checked_at: Option<Instant>,
 }
 
 struct IndexingContext<B, T: RuntimeHostBuilder, S> {
     /// Read only inputs that are needed while indexing a subgraph.
-    pub inputs: IndexingInputs<B, S>,
+    pub inputs: IndexingInputs<B, T, S>,
 
     /// Mutable state that may be modified while indexing a subgraph.
     pub state: IndexingState<T>,
@@ -71,6 +368,15 @@ struct IndexingContext<B, T: RuntimeHostBuilder, S> {
 pub struct SubgraphInstanceManager {
     logger: Logger,
     input: Sender<SubgraphAssignmentProviderEvent>,
+    instances: SharedInstanceKeepAliveMap,
+    paused: SharedInstancePauseMap,
+    instance_status: SharedInstanceStatusMap,
+    running: SharedInstanceRunningSet,
+    poi_subscriber: SharedProofOfIndexingSubscriber,
+    sync_subscriber: SharedSubgraphSyncSubscriber,
+    pause_subscriber: SharedSubgraphPauseSubscriber,
+    pending_operations: SharedInstancePendingMap,
+    shutdown: watch::Sender<bool>,
 }
 
 struct SubgraphInstanceManagerMetrics {
@@ -112,6 +418,9 @@ struct SubgraphInstanceMetrics {
     pub block_trigger_count: Box<Histogram>,
     pub block_processing_duration: Box<Histogram>,
     pub block_ops_transaction_duration: Box<Histogram>,
+    pub block_state_entity_count: Box<Histogram>,
+    pub block_state_size: Box<Histogram>,
+    pub memory_usage: Box<Gauge>,
 
     trigger_processing_duration: Box<HistogramVec>,
 }
@@ -153,12 +462,44 @@ impl SubgraphInstanceMetrics {
                 vec![0.01, 0.05, 0.1, 0.3, 0.7, 2.0],
             )
             .expect("failed to create `subgraph_transact_block_operations_duration_{}");
+        let block_state_entity_count = registry
+            .new_histogram(
+                format!("subgraph_block_state_entity_count_{}", subgraph_hash),
+                String::from(
+                    "Measures the number of entities changed in each block for a subgraph deployment",
+                ),
+                HashMap::new(),
+                vec![1.0, 10.0, 100.0, 1_000.0, 10_000.0, 100_000.0],
+            )
+            .expect("failed to create `subgraph_block_state_entity_count` histogram");
+        let block_state_size = registry
+            .new_histogram(
+                format!("subgraph_block_state_size_{}", subgraph_hash),
+                String::from(
+                    "Measures the approximate size in bytes of the entities changed in each block for a subgraph deployment",
+                ),
+                HashMap::new(),
+                vec![1_000.0, 10_000.0, 100_000.0, 1_000_000.0, 10_000_000.0, 100_000_000.0],
+            )
+            .expect("failed to create `subgraph_block_state_size` histogram");
+        let memory_usage = registry
+            .new_gauge(
+                format!("subgraph_memory_usage_{}", subgraph_hash),
+                String::from(
+                    "Estimates the bytes of entity data a subgraph deployment holds in memory",
+                ),
+                HashMap::new(),
+            )
+            .expect("failed to create `subgraph_memory_usage` gauge");
 
         Self {
             block_trigger_count,
             block_processing_duration,
             trigger_processing_duration,
             block_ops_transaction_duration,
+            block_state_entity_count,
+            block_state_size,
+            memory_usage,
         }
     }
 
@@ -173,6 +514,9 @@ impl SubgraphInstanceMetrics {
         registry.unregister(self.block_trigger_count.clone());
         registry.unregister(self.trigger_processing_duration.clone());
         registry.unregister(self.block_ops_transaction_duration.clone());
+        registry.unregister(self.block_state_entity_count.clone());
+        registry.unregister(self.block_state_size.clone());
+        registry.unregister(self.memory_usage.clone());
     }
 }
 
@@ -197,10 +541,30 @@ impl SubgraphInstanceManager {
         // Create channel for receiving subgraph provider events.
         let (subgraph_sender, subgraph_receiver) = channel(100);
 
+        // Subgraph instance shutdown senders
+        let instances: SharedInstanceKeepAliveMap = Default::default();
+        let paused: SharedInstancePauseMap = Default::default();
+        let instance_status: SharedInstanceStatusMap = Default::default();
+        let running: SharedInstanceRunningSet = Default::default();
+        let poi_subscriber: SharedProofOfIndexingSubscriber = Default::default();
+        let sync_subscriber: SharedSubgraphSyncSubscriber = Default::default();
+        let pause_subscriber: SharedSubgraphPauseSubscriber = Default::default();
+        let pending_operations: SharedInstancePendingMap = Default::default();
+        let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+
         // Handle incoming events from the subgraph provider.
         Self::handle_subgraph_events(
             logger_factory,
             subgraph_receiver,
+            instances.clone(),
+            paused.clone(),
+            instance_status.clone(),
+            running.clone(),
+            poi_subscriber.clone(),
+            sync_subscriber.clone(),
+            pause_subscriber.clone(),
+            pending_operations.clone(),
+            shutdown_receiver,
             stores,
             eth_adapters,
             host_builder,
@@ -211,13 +575,122 @@ impl SubgraphInstanceManager {
         SubgraphInstanceManager {
             logger,
             input: subgraph_sender,
+            instances,
+            paused,
+            instance_status,
+            running,
+            poi_subscriber,
+            sync_subscriber,
+            pause_subscriber,
+            pending_operations,
+            shutdown: shutdown_sender,
+        }
+    }
+
+    /// Stops indexing gracefully. No new subgraphs are started and no new
+    /// blocks are processed, but blocks that are being processed are
+    /// finished and committed. Subgraphs that are still processing a block
+    /// after `timeout` are stopped without waiting for it.
+    pub async fn shutdown(&self, timeout: Duration) {
+        info!(
+            self.logger,
+            "Draining subgraphs";
+            "timeout" => format!("{:?}", timeout),
+        );
+
+        // Running subgraphs notice this before processing their next block
+        if self.shutdown.broadcast(true).is_err() {
+            return;
+        }
+
+        // Subgraphs that are paused or waiting to be restarted have no work
+        // in flight and can be stopped right away
+        let idle: Vec<_> = self
+            .instance_status
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(id, status)| {
+                status.restart.next_retry_at.is_some() || self.is_subgraph_paused(id)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in idle {
+            let logger = self.logger.new(o!("subgraph_id" => id.to_string()));
+            Self::stop_subgraph(
+                self.instances.clone(),
+                self.paused.clone(),
+                self.instance_status.clone(),
+                id,
+            );
+            info!(logger, "Subgraph drained"; "code" => LogCode::SubgraphDrained);
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let running: Vec<_> = self.running.read().unwrap().iter().cloned().collect();
+            if running.is_empty() {
+                info!(self.logger, "All subgraphs drained");
+                return;
+            }
+
+            if Instant::now() >= deadline {
+                for id in running {
+                    warn!(
+                        self.logger,
+                        "Subgraph did not drain in time, stopping it";
+                        "subgraph_id" => id.to_string(),
+                        "timeout" => format!("{:?}", timeout),
+                    );
+                    Self::stop_subgraph(
+                        self.instances.clone(),
+                        self.paused.clone(),
+                        self.instance_status.clone(),
+                        id,
+                    );
+                }
+                return;
+            }
+
+            tokio::time::delay_for(DRAIN_POLL_INTERVAL).await;
         }
     }
 
+    /// Attaches a subscriber that receives every proof of indexing event
+    /// recorded by any subgraph, or detaches it with `None`.
+    pub fn set_proof_of_indexing_subscriber(
+        &self,
+        subscriber: Option<Arc<dyn ProofOfIndexingSubscriber>>,
+    ) {
+        *self.poi_subscriber.write().unwrap() = subscriber;
+    }
+
+    /// Attaches a subscriber that is told whenever a subgraph becomes synced
+    /// or unsynced, or detaches it with `None`.
+    pub fn set_sync_subscriber(&self, subscriber: Option<Arc<dyn SubgraphSyncSubscriber>>) {
+        *self.sync_subscriber.write().unwrap() = subscriber;
+    }
+
+    /// Attaches a subscriber that is told whenever a subgraph stops or
+    /// continues processing blocks because it was paused or resumed, or
+    /// detaches it with `None`.
+    pub fn set_pause_subscriber(&self, subscriber: Option<Arc<dyn SubgraphPauseSubscriber>>) {
+        *self.pause_subscriber.write().unwrap() = subscriber;
+    }
+
     /// Handle incoming events from subgraph providers.
     fn handle_subgraph_events<B, S, M>(
         logger_factory: LoggerFactory,
         receiver: Receiver<SubgraphAssignmentProviderEvent>,
+        instances: SharedInstanceKeepAliveMap,
+        paused: SharedInstancePauseMap,
+        instance_status: SharedInstanceStatusMap,
+        running: SharedInstanceRunningSet,
+        poi_subscriber: SharedProofOfIndexingSubscriber,
+        sync_subscriber: SharedSubgraphSyncSubscriber,
+        pause_subscriber: SharedSubgraphPauseSubscriber,
+        pending_operations: SharedInstancePendingMap,
+        shutdown: watch::Receiver<bool>,
         stores: HashMap<String, Arc<S>>,
         eth_adapters: HashMap<String, Arc<dyn EthereumAdapter>>,
         host_builder: impl RuntimeHostBuilder,
@@ -232,9 +705,6 @@ impl SubgraphInstanceManager {
         let metrics_registry_for_subgraph = metrics_registry.clone();
         let manager_metrics = SubgraphInstanceManagerMetrics::new(metrics_registry_for_manager);
 
-        // Subgraph instance shutdown senders
-        let instances: SharedInstanceKeepAliveMap = Default::default();
-
         // Blocking due to store interactions. Won't be blocking after #905.
         graph::spawn_blocking(receiver.compat().try_for_each(move |event| {
             use self::SubgraphAssignmentProviderEvent::*;
@@ -242,6 +712,10 @@ impl SubgraphInstanceManager {
             match event {
                 SubgraphStart(manifest) => {
                     let logger = logger_factory.subgraph_logger(&manifest.id);
+                    if *shutdown.borrow() {
+                        info!(logger, "Not starting subgraph, indexing is shutting down");
+                        return futures03::future::ok(());
+                    }
                     info!(
                         logger,
                         "Start subgraph";
@@ -251,6 +725,14 @@ impl SubgraphInstanceManager {
                     Self::start_subgraph(
                         logger.clone(),
                         instances.clone(),
+                        paused.clone(),
+                        instance_status.clone(),
+                        running.clone(),
+                        poi_subscriber.clone(),
+                        sync_subscriber.clone(),
+                        pause_subscriber.clone(),
+                        pending_operations.clone(),
+                        shutdown.clone(),
                         host_builder.clone(),
                         block_stream_builder.clone(),
                         stores
@@ -288,7 +770,12 @@ impl SubgraphInstanceManager {
                     let logger = logger_factory.subgraph_logger(&id);
                     info!(logger, "Stop subgraph");
 
-                    Self::stop_subgraph(instances.clone(), id);
+                    Self::stop_subgraph(
+                        instances.clone(),
+                        paused.clone(),
+                        instance_status.clone(),
+                        id,
+                    );
                     manager_metrics.subgraph_count.dec();
                 }
             };
@@ -300,6 +787,14 @@ impl SubgraphInstanceManager {
     fn start_subgraph<B, S, M>(
         logger: Logger,
         instances: SharedInstanceKeepAliveMap,
+        paused: SharedInstancePauseMap,
+        instance_status: SharedInstanceStatusMap,
+        running: SharedInstanceRunningSet,
+        poi_subscriber: SharedProofOfIndexingSubscriber,
+        sync_subscriber: SharedSubgraphSyncSubscriber,
+        pause_subscriber: SharedSubgraphPauseSubscriber,
+        pending_operations: SharedInstancePendingMap,
+        shutdown: watch::Receiver<bool>,
         host_builder: impl RuntimeHostBuilder,
         stream_builder: B,
         store: Arc<S>,
@@ -345,6 +840,8 @@ impl SubgraphInstanceManager {
         });
 
         let top_level_templates = Arc::new(manifest.templates.clone());
+        let manifest_for_restart = manifest.clone();
+        let host_builder_for_restart = host_builder.clone();
 
         // Create a subgraph instance from the manifest; this moves
         // ownership of the manifest and host builder into the new instance
@@ -370,13 +867,35 @@ impl SubgraphInstanceManager {
             deployment_id.clone(),
             stopwatch_metrics,
         ));
-        let instance =
-            SubgraphInstance::from_manifest(&logger, manifest, host_builder, host_metrics.clone())?;
+        let instance = SubgraphInstance::from_manifest(
+            &logger,
+            manifest,
+            host_builder,
+            host_metrics.clone(),
+            DISABLED_HANDLERS.clone(),
+            DATA_SOURCE_SELECTION.clone(),
+        )?;
+
+        let (pause_sender, pause_receiver) = watch::channel(false);
+        paused.write().unwrap().insert(
+            deployment_id.clone(),
+            (pause_sender, pause_receiver.clone()),
+        );
+        instance_status.write().unwrap().insert(
+            deployment_id.clone(),
+            InstanceStatus {
+                block: store.block_ptr(deployment_id.clone())?,
+                ..Default::default()
+            },
+        );
+        running.write().unwrap().insert(deployment_id.clone());
 
         // The subgraph state tracks the state of the subgraph instance over time
         let ctx = IndexingContext {
             inputs: IndexingInputs {
                 deployment_id: deployment_id.clone(),
+                manifest: manifest_for_restart,
+                host_builder: host_builder_for_restart,
                 network_name,
                 start_blocks,
                 store,
@@ -384,6 +903,10 @@ impl SubgraphInstanceManager {
                 stream_builder,
                 templates_use_calls,
                 top_level_templates,
+                poi_subscriber,
+                sync_subscriber,
+                pause_subscriber,
+                pending_operations,
             },
             state: IndexingState {
                 logger,
@@ -394,6 +917,13 @@ impl SubgraphInstanceManager {
                 block_filter,
                 restarts: 0,
                 entity_lfu_cache: LfuCache::new(),
+                paused: pause_receiver,
+                dynamic_data_sources: vec![],
+                instance_status,
+                retries: 0,
+                shutdown,
+                sync_tracker: SubgraphSyncTracker::new(deployment_id.clone(), *SYNC_POLICY),
+                sync_checked_at: None,
             },
             subgraph_metrics,
             host_metrics,
@@ -411,17 +941,90 @@ impl SubgraphInstanceManager {
         graph::spawn_blocking(async move {
             let res = run_subgraph(ctx).await;
             subgraph_metrics_unregister.unregister(registry);
+            running.write().unwrap().remove(&deployment_id);
             res
         });
 
         Ok(())
     }
 
-    fn stop_subgraph(instances: SharedInstanceKeepAliveMap, id: SubgraphDeploymentId) {
+    fn stop_subgraph(
+        instances: SharedInstanceKeepAliveMap,
+        paused: SharedInstancePauseMap,
+        instance_status: SharedInstanceStatusMap,
+        id: SubgraphDeploymentId,
+    ) {
+        // Drop the pause sender so that a paused subgraph, or one waiting to
+        // be restarted, wakes up and notices that it was stopped
+        paused.write().unwrap().remove(&id);
+        instance_status.write().unwrap().remove(&id);
+
         // Drop the cancel guard to shut down the subgraph now
         let mut instances = instances.write().unwrap();
         instances.remove(&id);
     }
+
+    fn set_paused(&self, id: &SubgraphDeploymentId, paused: bool) -> Result<(), Error> {
+        match self.paused.read().unwrap().get(id) {
+            Some((sender, _)) => sender
+                .broadcast(paused)
+                .map_err(|_| format_err!("subgraph `{}` is not running", id)),
+            None => Err(format_err!("subgraph `{}` is not running", id)),
+        }
+    }
+}
+
+impl SubgraphInstanceManagerTrait for SubgraphInstanceManager {
+    fn pause_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), Error> {
+        self.set_paused(id, true)
+    }
+
+    fn resume_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), Error> {
+        self.set_paused(id, false)
+    }
+
+    fn is_subgraph_paused(&self, id: &SubgraphDeploymentId) -> bool {
+        self.paused
+            .read()
+            .unwrap()
+            .get(id)
+            .map_or(false, |(_, receiver)| *receiver.borrow())
+    }
+
+    fn subgraph_restart_status(&self, id: &SubgraphDeploymentId) -> Option<SubgraphRestartStatus> {
+        self.instance_status
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|status| status.restart.clone())
+    }
+
+    fn running_subgraphs(&self) -> Vec<RunningSubgraph> {
+        let mut running: Vec<_> = self
+            .instance_status
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, status)| RunningSubgraph {
+                deployment_id: id.clone(),
+                block: status.block,
+                synced: status.synced,
+                paused: self.is_subgraph_paused(id),
+                failed: status.failed,
+                restart_status: status.restart.clone(),
+                last_error: status.last_error.clone(),
+            })
+            .collect();
+        running.sort_by(|a, b| a.deployment_id.cmp(&b.deployment_id));
+        running
+    }
+
+    fn pending_block_operations(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Option<PendingBlockOperations> {
+        self.pending_operations.read().unwrap().get(id).cloned()
+    }
 }
 
 impl EventConsumer<SubgraphAssignmentProviderEvent> for SubgraphInstanceManager {
@@ -448,6 +1051,33 @@ where
     let logger = ctx.state.logger.cheap_clone();
     let id_for_err = ctx.inputs.deployment_id.clone();
 
+    // Wait for our turn to start up. The permit is held until the block
+    // stream produces its first event, so that only the initial requests of
+    // the subgraph are limited and not steady-state indexing
+    debug!(logger, "Waiting for startup permit");
+    let mut startup_permit = Some(SUBGRAPH_STARTUP_SEMAPHORE.acquire().await);
+
+    // The subgraph may have been stopped while it was waiting
+    if !ctx
+        .state
+        .instance_status
+        .read()
+        .unwrap()
+        .contains_key(&ctx.inputs.deployment_id)
+    {
+        debug!(
+            &logger,
+            "Subgraph shut down cleanly before starting up";
+            "id" => id_for_err.to_string(),
+        );
+        return Err(());
+    }
+
+    if *ctx.state.shutdown.borrow() {
+        drain_subgraph(&ctx);
+        return Err(());
+    }
+
     loop {
         debug!(logger, "Starting or restarting subgraph");
 
@@ -483,7 +1113,60 @@ where
 
         // Process events from the stream as long as no restart is needed
         loop {
-            let block = match block_stream.next().await {
+            // While the subgraph is paused, the block stream is not polled so
+            // that it neither advances nor makes any requests
+            if *ctx.state.paused.borrow() {
+                // Let other subgraphs start up while this one is paused
+                startup_permit.take();
+
+                let block = ctx
+                    .state
+                    .instance_status
+                    .read()
+                    .unwrap()
+                    .get(&ctx.inputs.deployment_id)
+                    .and_then(|status| status.block);
+                let resumed = wait_while_paused(
+                    &logger,
+                    &ctx.inputs.deployment_id,
+                    block,
+                    &mut ctx.state.paused,
+                    &ctx.inputs.pause_subscriber,
+                )
+                .await;
+                if !resumed {
+                    debug!(
+                        &logger,
+                        "Paused subgraph shut down cleanly";
+                        "id" => id_for_err.to_string(),
+                    );
+                    return Err(());
+                }
+            }
+
+            // Wait for the next block unless indexing is shutting down;
+            // blocks that are already being processed are always finished
+            let event = {
+                let shutdown = shutdown_requested(&mut ctx.state.shutdown);
+                futures03::pin_mut!(shutdown);
+                match futures03::future::select(block_stream.next(), shutdown).await {
+                    futures03::future::Either::Left((event, _)) => Some(event),
+                    futures03::future::Either::Right(_) => None,
+                }
+            };
+            let event = match event {
+                Some(event) => event,
+                None => {
+                    drain_subgraph(&ctx);
+                    return Err(());
+                }
+            };
+
+            // The subgraph has started up once its block stream produces
+            // anything
+            startup_permit.take();
+
+            let block = match event {
                 Some(Ok(BlockStreamEvent::Block(block))) => block,
                 Some(Ok(BlockStreamEvent::Revert)) => {
                     // On revert, clear the entity cache.
@@ -503,6 +1186,8 @@ where
                 None => unreachable!("The block stream stopped producing blocks"),
             };
 
+            let block_ptr = EthereumBlockPointer::from(&block.ethereum_block);
+
             if block.triggers.len() > 0 {
                 subgraph_metrics
                     .block_trigger_count
@@ -514,18 +1199,34 @@ where
             let res = process_block(
                 &logger,
                 ctx.inputs.eth_adapter.cheap_clone(),
-                ctx,
+                &mut ctx,
                 block_stream_cancel_handle.clone(),
                 block,
             )
             .await;
 
+            // Whether the block was committed or not, its operations are no
+            // longer pending
+            ctx.inputs
+                .pending_operations
+                .write()
+                .unwrap()
+                .remove(&ctx.inputs.deployment_id);
+
             let elapsed = start.elapsed().as_secs_f64();
             subgraph_metrics.block_processing_duration.observe(elapsed);
 
             match res {
-                Ok((c, needs_restart)) => {
-                    ctx = c;
+                Ok(needs_restart) => {
+                    // The subgraph made progress, so failures before this
+                    // block no longer count towards the restart limit
+                    if ctx.state.retries > 0 {
+                        ctx.state.retries = 0;
+                        set_restart_status(&ctx, SubgraphRestartStatus::default());
+                    }
+
+                    update_sync_status(&logger, &mut ctx, block_ptr);
+
                     if needs_restart {
                         // Increase the restart counter
                         ctx.state.restarts += 1;
@@ -541,7 +1242,7 @@ where
                         break;
                     }
                 }
-                Err(CancelableError::Cancel) => {
+                Err(BlockProcessingError::Canceled) => {
                     debug!(
                         &logger,
                         "Subgraph block stream shut down cleanly";
@@ -549,26 +1250,87 @@ where
                     );
                     return Err(());
                 }
-                // Handle unexpected stream errors by marking the subgraph as failed.
-                Err(CancelableError::Error(e)) => {
-                    error!(
+                // Restart the subgraph after a delay, starting over with the
+                // block that failed
+                Err(BlockProcessingError::NonDeterministic(e))
+                    if ctx.state.retries < RESTART_POLICY.max_retries =>
+                {
+                    let delay = RESTART_POLICY.delay(ctx.state.retries);
+                    ctx.state.retries += 1;
+                    let error = e.to_string();
+                    update_instance_status(&ctx, |status| status.last_error = Some(error));
+
+                    warn!(
                         &logger,
-                        "Subgraph instance failed to run: {}", e;
+                        "Subgraph instance failed to run, restarting: {}", e;
                         "id" => id_for_err.to_string(),
+                        "retries" => ctx.state.retries,
+                        "delay" => format!("{:?}", delay),
                         "code" => LogCode::SubgraphSyncingFailure
                     );
 
-                    // Set subgraph status to Failed
-                    let status_ops =
-                        SubgraphDeploymentEntity::update_failed_operations(&id_for_err, true);
-                    if let Err(e) = store_for_err.apply_metadata_operations(status_ops) {
+                    // Cancel the stream for real
+                    ctx.state
+                        .instances
+                        .write()
+                        .unwrap()
+                        .remove(&ctx.inputs.deployment_id);
+
+                    set_restart_status(
+                        &ctx,
+                        SubgraphRestartStatus {
+                            retries: ctx.state.retries,
+                            next_retry_at: Some(SystemTime::now() + delay),
+                        },
+                    );
+
+                    if !wait_for_restart(&mut ctx.state.paused, delay).await {
+                        debug!(
+                            &logger,
+                            "Subgraph shut down cleanly while waiting to be restarted";
+                            "id" => id_for_err.to_string(),
+                        );
+                        return Err(());
+                    }
+
+                    set_restart_status(
+                        &ctx,
+                        SubgraphRestartStatus {
+                            retries: ctx.state.retries,
+                            next_retry_at: None,
+                        },
+                    );
+
+                    // The instance may contain hosts for data sources that
+                    // were created in the failed block, so recreate it
+                    if let Err(e) = reset_instance(&mut ctx) {
                         error!(
                             &logger,
-                            "Failed to set subgraph status to Failed: {}", e;
+                            "Failed to recreate subgraph instance: {}", e;
                             "id" => id_for_err.to_string(),
-                            "code" => LogCode::SubgraphSyncingFailureNotRecorded
+                            "code" => LogCode::SubgraphSyncingFailure
                         );
+                        return Err(());
                     }
+
+                    // And restart the subgraph
+                    ctx.state.restarts += 1;
+                    break;
+                }
+                // Handle deterministic errors, and non-deterministic errors
+                // that persist after all restarts, by marking the subgraph as
+                // failed.
+                Err(error) => {
+                    let deterministic = error.is_deterministic();
+                    let e = error.into_inner();
+                    set_failed(&ctx, &e);
+                    mark_subgraph_failed(
+                        &logger,
+                        store_for_err.as_ref(),
+                        &id_for_err,
+                        e,
+                        deterministic,
+                    );
                     return Err(());
                 }
             }
@@ -576,15 +1338,245 @@ where
     }
 }
 
-/// Processes a block and returns the updated context and a boolean flag indicating
-/// whether new dynamic data sources have been added to the subgraph.
+/// Resolves once the manager starts shutting down.
+async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) {
+    while !*shutdown.borrow() {
+        // The manager, and with it the sender, is gone; there is nobody left
+        // to request a shutdown
+        if shutdown.recv().await.is_none() {
+            futures03::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Stops a subgraph that finished its last block after the manager started
+/// shutting down.
+fn drain_subgraph<B, T: RuntimeHostBuilder, S>(ctx: &IndexingContext<B, T, S>) {
+    ctx.state
+        .instances
+        .write()
+        .unwrap()
+        .remove(&ctx.inputs.deployment_id);
+    ctx.state
+        .instance_status
+        .write()
+        .unwrap()
+        .remove(&ctx.inputs.deployment_id);
+
+    info!(
+        ctx.state.logger,
+        "Subgraph drained";
+        "id" => ctx.inputs.deployment_id.to_string(),
+        "code" => LogCode::SubgraphDrained
+    );
+}
+
+/// Sets the status of the subgraph to failed, recording whether the failure
+/// will happen again when the failed block is processed again.
+fn mark_subgraph_failed(
+    logger: &Logger,
+    store: &impl Store,
+    id: &SubgraphDeploymentId,
+    e: Error,
+    deterministic: bool,
+) {
+    error!(
+        logger,
+        "Subgraph instance failed to run: {}", e;
+        "id" => id.to_string(),
+        "deterministic" => deterministic,
+        "code" => LogCode::SubgraphSyncingFailure
+    );
+
+    let status_ops = SubgraphDeploymentEntity::update_failure_operations(id, deterministic);
+    if let Err(e) = store.apply_metadata_operations(status_ops) {
+        error!(
+            logger,
+            "Failed to set subgraph status to Failed: {}", e;
+            "id" => id.to_string(),
+            "code" => LogCode::SubgraphSyncingFailureNotRecorded
+        );
+    }
+}
+
+/// Waits until a paused subgraph is resumed, and tells the pause subscriber
+/// when the subgraph pauses at `block` and when it resumes. Returns `false`
+/// if the subgraph was stopped while it was paused.
+async fn wait_while_paused(
+    logger: &Logger,
+    deployment_id: &SubgraphDeploymentId,
+    block: Option<EthereumBlockPointer>,
+    paused: &mut watch::Receiver<bool>,
+    subscriber: &SharedSubgraphPauseSubscriber,
+) -> bool {
+    let notify = |event: SubgraphPauseEvent| {
+        if let Some(subscriber) = subscriber.read().unwrap().clone() {
+            subscriber.event(&event);
+        }
+    };
+
+    info!(logger, "Subgraph paused"; "code" => LogCode::SubgraphPaused);
+    notify(SubgraphPauseEvent::Paused {
+        deployment_id: deployment_id.clone(),
+        block,
+        timestamp: SystemTime::now(),
+    });
+
+    loop {
+        match paused.recv().await {
+            Some(true) => continue,
+            Some(false) => break,
+            None => return false,
+        }
+    }
+
+    info!(logger, "Subgraph resumed"; "code" => LogCode::SubgraphResumed);
+    notify(SubgraphPauseEvent::Resumed {
+        deployment_id: deployment_id.clone(),
+        block,
+        timestamp: SystemTime::now(),
+    });
+    true
+}
+
+/// Compares the progress of the subgraph to the chain head, at most every
+/// `SYNC_CHECK_INTERVAL`, and emits a `SubgraphSyncEvent` if the subgraph
+/// became synced or unsynced. This is called for every block the subgraph
+/// processes, whether the block has triggers or not. Blocks that the block
+/// stream skips because they have no triggers for the subgraph are never
+/// processed, so the status is not updated while the stream skips blocks.
+fn update_sync_status<B, T: RuntimeHostBuilder, S: ChainStore>(
+    logger: &Logger,
+    ctx: &mut IndexingContext<B, T, S>,
+    block_ptr: EthereumBlockPointer,
+) {
+    let now = Instant::now();
+    if ctx.state.sync_checked_at.map_or(false, |checked_at| {
+        now.duration_since(checked_at) < SYNC_CHECK_INTERVAL
+    }) {
+        return;
+    }
+    ctx.state.sync_checked_at = Some(now);
+
+    let head = match ctx.inputs.store.chain_head_ptr() {
+        Ok(Some(head)) => head,
+        Ok(None) => return,
+        Err(e) => {
+            debug!(
+                logger,
+                "Failed to check whether the subgraph is synced: {}", e
+            );
+            return;
+        }
+    };
+
+    let event = match ctx.state.sync_tracker.update(block_ptr, head, now) {
+        Some(event) => event,
+        None => return,
+    };
+    match &event {
+        SubgraphSyncEvent::Synced { head, .. } => info!(
+            logger,
+            "Subgraph synced";
+            "head_block" => head.number,
+            "code" => LogCode::SubgraphSynced,
+        ),
+        SubgraphSyncEvent::Unsynced {
+            subgraph_ptr, head, ..
+        } => warn!(
+            logger,
+            "Subgraph fell behind the chain head";
+            "block_number" => subgraph_ptr.number,
+            "head_block" => head.number,
+            "code" => LogCode::SubgraphUnsynced,
+        ),
+    }
+    let synced = ctx.state.sync_tracker.is_synced();
+    update_instance_status(ctx, |status| status.synced = synced);
+    if let Some(subscriber) = ctx.inputs.sync_subscriber.read().unwrap().clone() {
+        subscriber.event(&event);
+    }
+}
+
+fn set_restart_status<B, T: RuntimeHostBuilder, S>(
+    ctx: &IndexingContext<B, T, S>,
+    status: SubgraphRestartStatus,
+) {
+    update_instance_status(ctx, |current| current.restart = status);
+}
+
+fn set_failed<B, T: RuntimeHostBuilder, S>(ctx: &IndexingContext<B, T, S>, e: &Error) {
+    let error = e.to_string();
+    update_instance_status(ctx, |status| {
+        status.failed = true;
+        status.last_error = Some(error);
+    });
+}
+
+fn update_instance_status<B, T: RuntimeHostBuilder, S>(
+    ctx: &IndexingContext<B, T, S>,
+    update: impl FnOnce(&mut InstanceStatus),
+) {
+    let mut instance_status = ctx.state.instance_status.write().unwrap();
+
+    // Only update the status of subgraphs that have not been stopped
+    if let Some(current) = instance_status.get_mut(&ctx.inputs.deployment_id) {
+        update(current);
+    }
+}
+
+/// Waits for `delay` before a failed subgraph is restarted. Returns `false`
+/// if the subgraph was stopped in the meantime.
+async fn wait_for_restart(paused: &mut watch::Receiver<bool>, delay: Duration) -> bool {
+    // The pause sender is dropped when the subgraph is stopped
+    let stopped = async { while paused.recv().await.is_some() {} };
+    let delay = tokio::time::delay_for(delay);
+    futures03::pin_mut!(stopped);
+    futures03::pin_mut!(delay);
+
+    match futures03::future::select(delay, stopped).await {
+        futures03::future::Either::Left(_) => true,
+        futures03::future::Either::Right(_) => false,
+    }
+}
+
+/// Recreates the subgraph instance and filters from the manifest and the
+/// dynamic data sources that have been committed since the subgraph was
+/// started, discarding anything that was added while processing a block
+/// that failed.
+fn reset_instance<B, T: RuntimeHostBuilder, S>(
+    ctx: &mut IndexingContext<B, T, S>,
+) -> Result<(), Error> {
+    let mut manifest = ctx.inputs.manifest.clone();
+    manifest
+        .data_sources
+        .extend(ctx.state.dynamic_data_sources.iter().cloned());
+
+    ctx.state.log_filter = EthereumLogFilter::from_data_sources(&manifest.data_sources);
+    ctx.state.call_filter = EthereumCallFilter::from_data_sources(&manifest.data_sources);
+    ctx.state.block_filter = EthereumBlockFilter::from_data_sources(&manifest.data_sources);
+    ctx.state.instance = SubgraphInstance::from_manifest(
+        &ctx.state.logger,
+        manifest,
+        ctx.inputs.host_builder.clone(),
+        ctx.host_metrics.clone(),
+        DISABLED_HANDLERS.clone(),
+        DATA_SOURCE_SELECTION.clone(),
+    )?;
+    ctx.state.entity_lfu_cache = LfuCache::new();
+
+    Ok(())
+}
+
+/// Processes a block and returns a boolean flag indicating whether new
+/// dynamic data sources have been added to the subgraph.
 async fn process_block<B, T: RuntimeHostBuilder, S>(
     logger: &Logger,
     eth_adapter: Arc<dyn EthereumAdapter>,
-    mut ctx: IndexingContext<B, T, S>,
+    ctx: &mut IndexingContext<B, T, S>,
     block_stream_cancel_handle: CancelHandle,
     block: EthereumBlockWithTriggers,
-) -> Result<(IndexingContext<B, T, S>, bool), CancelableError<Error>>
+) -> Result<bool, BlockProcessingError>
 where
     B: BlockStreamBuilder,
     S: ChainStore + Store + EthereumCallCache + SubgraphDeploymentStore,
@@ -598,6 +1590,17 @@ where
         "block_hash" => format!("{:?}", block_ptr.hash)
     ));
 
+    // A block without triggers runs no handlers, and therefore changes
+    // neither entities nor the proof of indexing; only the block pointer
+    // needs to be advanced
+    if triggers.is_empty() {
+        if block_stream_cancel_handle.is_canceled() {
+            return Err(BlockProcessingError::Canceled);
+        }
+        transact_block(&logger, ctx, block_ptr, vec![])?;
+        return Ok(false);
+    }
+
     if triggers.len() == 1 {
         info!(&logger, "1 trigger found in this block for this subgraph");
     } else if triggers.len() > 1 {
@@ -615,16 +1618,24 @@ where
 
     let metrics = ctx.subgraph_metrics.clone();
 
+    let mut block_state = BlockState::with_cache(std::mem::take(&mut ctx.state.entity_lfu_cache))
+        .with_handler_yield(*HANDLER_YIELD_POLICY);
+    if let Some(subscriber) = ctx.inputs.poi_subscriber.read().unwrap().clone() {
+        block_state.proof_of_indexing = ProofOfIndexing::with_subscriber(
+            ctx.inputs.deployment_id.clone(),
+            block_ptr.clone(),
+            subscriber,
+        );
+    }
+
     // Process events one after the other, passing in entity operations
     // collected previously to every new event being processed
-    let (mut ctx, mut block_state) = process_triggers(
-        &logger,
-        BlockState::with_cache(std::mem::take(&mut ctx.state.entity_lfu_cache)),
-        ctx,
-        &light_block,
-        triggers,
-    )
-    .await?;
+    let mut block_state =
+        process_triggers(&logger, block_state, ctx, &light_block, triggers).await?;
+
+    // Dynamic data sources created in this block, which are only remembered
+    // for restarts once the block has been committed
+    let mut block_data_sources = vec![];
 
     // If new data sources have been created, restart the subgraph after this block.
     // This is necessary to re-create the block stream.
@@ -643,10 +1654,11 @@ where
         // Instantiate dynamic data sources, removing them from the block state.
         let (data_sources, runtime_hosts) = create_dynamic_data_sources(
             logger.clone(),
-            &mut ctx,
+            ctx,
             host_metrics.clone(),
             block_state.created_data_sources.drain(..),
-        )?;
+        )
+        .map_err(BlockProcessingError::Deterministic)?;
 
         // Reprocess the triggers from this block that match the new data sources
         let block_with_triggers = triggers_in_block(
@@ -659,7 +1671,8 @@ where
             EthereumBlockFilter::from_data_sources(data_sources.iter()),
             block.clone(),
         )
-        .await?;
+        .await
+        .map_err(BlockProcessingError::NonDeterministic)?;
 
         let triggers = block_with_triggers.triggers;
 
@@ -678,9 +1691,10 @@ where
 
         // Add entity operations for the new data sources to the block state
         // and add runtimes for the data sources to the subgraph instance.
+        block_data_sources.extend(data_sources.iter().cloned());
         persist_dynamic_data_sources(
             logger.clone(),
-            &mut ctx,
+            ctx,
             &mut block_state.entity_cache,
             data_sources,
             block_ptr_for_new_data_sources,
@@ -688,16 +1702,18 @@ where
 
         // Process the triggers in each host in the same order the
         // corresponding data sources have been created.
-
-        for trigger in triggers.into_iter() {
+        for (hosts, trigger) in order_triggers(*TRIGGER_ORDERING, &runtime_hosts, triggers) {
             block_state = SubgraphInstance::<T>::process_trigger_in_runtime_hosts(
                 &logger,
-                &runtime_hosts,
+                hosts,
                 &light_block,
                 trigger,
                 block_state,
             )
-            .await?;
+            .await
+            .map_err(|e| trigger_error_kind(&e)(e))?;
+            capture_pending_operations(ctx, block_ptr, &block_state);
+            check_memory_budget(ctx, block_ptr, &block_state)?;
         }
     }
 
@@ -705,7 +1721,7 @@ where
 
     // Avoid writing to store if block stream has been canceled
     if block_stream_cancel_handle.is_canceled() {
-        return Err(CancelableError::Cancel);
+        return Err(BlockProcessingError::Canceled);
     }
 
     update_proof_of_indexing(
@@ -715,7 +1731,25 @@ where
         &ctx.inputs.deployment_id,
         &mut block_state.entity_cache,
     )
-    .await?;
+    .await
+    .map_err(BlockProcessingError::NonDeterministic)?;
+
+    let entity_count = block_state.entity_cache.update_count();
+    let size = block_state.entity_cache.updates_weight();
+    metrics
+        .block_state_entity_count
+        .observe(entity_count as f64);
+    metrics.block_state_size.observe(size as f64);
+    if size > *BLOCK_STATE_SIZE_WARNING_THRESHOLD {
+        warn!(
+            logger,
+            "Block changes an unusually large amount of entity data";
+            "block_number" => block_ptr_after.number,
+            "entities" => entity_count,
+            "size" => size,
+            "threshold" => *BLOCK_STATE_SIZE_WARNING_THRESHOLD,
+        );
+    }
 
     let section = ctx.host_metrics.stopwatch.start_section("as_modifications");
     let ModificationsAndCache {
@@ -725,7 +1759,7 @@ where
         .entity_cache
         .as_modifications(ctx.inputs.store.as_ref())
         .map_err(|e| {
-            CancelableError::from(format_err!(
+            BlockProcessingError::NonDeterministic(format_err!(
                 "Error while processing block stream for a subgraph: {}",
                 e
             ))
@@ -740,15 +1774,31 @@ where
     section.end();
 
     // Put the cache back in the ctx, asserting that the placeholder cache was not used.
+    // No changes are pending anymore, so the cache is all the memory usage
     assert!(ctx.state.entity_lfu_cache.is_empty());
+    metrics.memory_usage.set(cache.weight() as f64);
     ctx.state.entity_lfu_cache = cache;
 
     if !mods.is_empty() {
         info!(&logger, "Applying {} entity operation(s)", mods.len());
     }
 
-    // Transact entity operations into the store and update the
-    // subgraph's block stream pointer
+    transact_block(&logger, ctx, block_ptr_after, mods)?;
+    ctx.state.dynamic_data_sources.extend(block_data_sources);
+    Ok(needs_restart)
+}
+
+/// Transacts the entity operations of a block into the store and updates the
+/// subgraph's block stream pointer.
+fn transact_block<B, T: RuntimeHostBuilder, S>(
+    logger: &Logger,
+    ctx: &mut IndexingContext<B, T, S>,
+    block_ptr_after: EthereumBlockPointer,
+    mods: Vec<EntityModification>,
+) -> Result<(), BlockProcessingError>
+where
+    S: Store + SubgraphDeploymentStore,
+{
     let _section = ctx.host_metrics.stopwatch.start_section("transact_block");
     let subgraph_id = ctx.inputs.deployment_id.clone();
     let stopwatch = ctx.host_metrics.stopwatch.clone();
@@ -761,7 +1811,9 @@ where
     {
         Ok(should_migrate) => {
             let elapsed = start.elapsed().as_secs_f64();
-            metrics.block_ops_transaction_duration.observe(elapsed);
+            ctx.subgraph_metrics
+                .block_ops_transaction_duration
+                .observe(elapsed);
             if should_migrate {
                 ctx.inputs.store.migrate_subgraph_deployment(
                     &logger,
@@ -769,11 +1821,13 @@ where
                     &block_ptr_after,
                 );
             }
-            Ok((ctx, needs_restart))
-        }
-        Err(e) => {
-            Err(format_err!("Error while processing block stream for a subgraph: {}", e).into())
+            update_instance_status(ctx, |status| status.block = Some(block_ptr_after));
+            Ok(())
         }
+        Err(e) => Err(BlockProcessingError::NonDeterministic(format_err!(
+            "Error while processing block stream for a subgraph: {}",
+            e
+        ))),
     }
 }
 
@@ -837,11 +1891,12 @@ async fn update_proof_of_indexing(
 async fn process_triggers<B: BlockStreamBuilder, T: RuntimeHostBuilder, S: Send + Sync>(
     logger: &Logger,
     mut block_state: BlockState,
-    ctx: IndexingContext<B, T, S>,
+    ctx: &mut IndexingContext<B, T, S>,
     block: &Arc<LightEthereumBlock>,
     triggers: Vec<EthereumTrigger>,
-) -> Result<(IndexingContext<B, T, S>, BlockState), CancelableError<Error>> {
-    for trigger in triggers.into_iter() {
+) -> Result<BlockState, BlockProcessingError> {
+    let instance = &ctx.state.instance;
+    for (hosts, trigger) in order_triggers(*TRIGGER_ORDERING, instance.hosts(), triggers) {
         let block_ptr = EthereumBlockPointer::from(block.as_ref());
         let subgraph_metrics = ctx.subgraph_metrics.clone();
         let trigger_type = match trigger {
@@ -855,24 +1910,86 @@ async fn process_triggers<B: BlockStreamBuilder, T: RuntimeHostBuilder, S: Send
             EthereumTrigger::Block(..) => None,
         };
         let start = Instant::now();
-        block_state = ctx
-            .state
-            .instance
-            .process_trigger(&logger, &block, trigger, block_state)
+        block_state = instance
+            .process_trigger_in_hosts(&logger, hosts, &block, trigger, block_state)
             .await
-            .map_err(move |e| match transaction_id {
-                Some(tx_hash) => format_err!(
-                    "Failed to process trigger in block {}, transaction {:x}: {}",
-                    block_ptr,
-                    tx_hash,
-                    e
-                ),
-                None => format_err!("Failed to process trigger: {}", e),
+            .map_err(move |e| {
+                let kind = trigger_error_kind(&e);
+                let e = match transaction_id {
+                    Some(tx_hash) => format_err!(
+                        "Failed to process trigger in block {}, transaction {:x}: {}",
+                        block_ptr,
+                        tx_hash,
+                        e
+                    ),
+                    None => format_err!("Failed to process trigger: {}", e),
+                };
+                kind(e)
             })?;
         let elapsed = start.elapsed().as_secs_f64();
         subgraph_metrics.observe_trigger_processing_duration(elapsed, trigger_type);
+        capture_pending_operations(ctx, block_ptr, &block_state);
+        check_memory_budget(ctx, block_ptr, &block_state)?;
+    }
+    Ok(block_state)
+}
+
+/// Makes a copy of the entity operations the subgraph has made so far in the
+/// block `block_ptr` available through `pending_block_operations`. The block
+/// state itself is never shared with other threads.
+fn capture_pending_operations<B, T: RuntimeHostBuilder, S>(
+    ctx: &IndexingContext<B, T, S>,
+    block_ptr: EthereumBlockPointer,
+    block_state: &BlockState,
+) {
+    if !*CAPTURE_PENDING_OPERATIONS {
+        return;
+    }
+    let pending = PendingBlockOperations {
+        block: block_ptr,
+        operations: block_state.pending_entity_operations(),
+    };
+    ctx.inputs
+        .pending_operations
+        .write()
+        .unwrap()
+        .insert(ctx.inputs.deployment_id.clone(), pending);
+}
+
+/// Updates the memory usage of the subgraph, i.e., its entity cache plus the
+/// changes of the block so far, and fails the block if that exceeds
+/// `SUBGRAPH_MEMORY_BUDGET`.
+fn check_memory_budget<B, T: RuntimeHostBuilder, S>(
+    ctx: &IndexingContext<B, T, S>,
+    block_ptr: EthereumBlockPointer,
+    block_state: &BlockState,
+) -> Result<(), BlockProcessingError> {
+    let usage = block_state.entity_cache.weight();
+    ctx.subgraph_metrics.memory_usage.set(usage as f64);
+
+    enforce_memory_budget(*SUBGRAPH_MEMORY_BUDGET, usage, block_ptr)
+}
+
+/// Fails the block if the memory `usage` of the subgraph exceeds `budget`.
+/// What the entity cache holds depends on the blocks processed before, e.g.
+/// since the subgraph was last restarted, so the failure is
+/// non-deterministic; the subgraph is restarted instead of being marked as
+/// failed right away.
+fn enforce_memory_budget(
+    budget: Option<u64>,
+    usage: u64,
+    block_ptr: EthereumBlockPointer,
+) -> Result<(), BlockProcessingError> {
+    match budget {
+        Some(budget) if usage > budget => Err(BlockProcessingError::NonDeterministic(format_err!(
+            "Subgraph exceeded its memory budget of {} bytes with {} bytes of entity data \
+             in block {}",
+            budget,
+            usage,
+            block_ptr
+        ))),
+        _ => Ok(()),
     }
-    Ok((ctx, block_state))
 }
 
 fn create_dynamic_data_sources<B, T: RuntimeHostBuilder, S>(
@@ -889,6 +2006,10 @@ where
     let mut runtime_hosts = vec![];
 
     for info in created_data_sources {
+        // Fail with a clear error if the template or the ABI and handlers it
+        // refers to are not part of the subgraph
+        ctx.state.instance.validate_template_info(&info)?;
+
         // Try to instantiate a data source from the template
         let data_source = DataSource::try_from(info)?;
 
@@ -900,8 +2021,10 @@ where
             host_metrics.clone(),
         )?;
 
+        // Disabled data sources are still persisted so that they are run
+        // once they are enabled again
         data_sources.push(data_source);
-        runtime_hosts.push(host);
+        runtime_hosts.extend(host);
     }
 
     Ok((data_sources, runtime_hosts))
@@ -953,3 +2076,137 @@ fn persist_dynamic_data_sources<B, T: RuntimeHostBuilder, S>(
         .block_filter
         .extend(EthereumBlockFilter::from_data_sources(&data_sources));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::types::H256;
+
+    #[test]
+    fn restart_delay_doubles_up_to_max_delay() {
+        let policy = RestartPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(60),
+        };
+
+        assert_eq!(policy.delay(0), Duration::from_secs(5));
+        assert_eq!(policy.delay(1), Duration::from_secs(10));
+        assert_eq!(policy.delay(3), Duration::from_secs(40));
+        assert_eq!(policy.delay(4), Duration::from_secs(60));
+        assert_eq!(policy.delay(100), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn provider_errors_are_restarted_but_mapping_aborts_are_not() {
+        let provider_error: Error = NonDeterministicHostError {
+            message: "Failed to handle Ethereum event with handler \"handleTransfer\": \
+                      Ethereum node could not be reached"
+                .to_owned(),
+        }
+        .into();
+        let abort = format_err!(
+            "Failed to handle Ethereum event with handler \"handleTransfer\": \
+             Mapping aborted at src/mapping.ts, line 12, column 4, with message: boom"
+        );
+
+        match trigger_error_kind(&provider_error)(provider_error) {
+            BlockProcessingError::NonDeterministic(_) => (),
+            _ => panic!("provider errors must be restarted"),
+        }
+        match trigger_error_kind(&abort)(abort) {
+            BlockProcessingError::Deterministic(_) => (),
+            _ => panic!("mapping aborts must not be restarted"),
+        }
+    }
+
+    #[test]
+    fn transient_failures_are_not_reported_as_deterministic() {
+        let transient: Error = NonDeterministicHostError {
+            message: "Failed to load entity from the store: connection refused".to_owned(),
+        }
+        .into();
+        let budget_exceeded: Error = HandlerErrorBudgetExceeded {
+            handler: "handleTransfer".to_owned(),
+            data_source: "Token".to_owned(),
+            failures: 4,
+            window: Duration::from_secs(3600),
+            error: "Handler timed out".to_owned(),
+        }
+        .into();
+        let abort = format_err!("Mapping aborted at src/mapping.ts, line 12, column 4");
+
+        assert!(!trigger_error_kind(&transient)(transient).is_deterministic());
+        assert!(!trigger_error_kind(&budget_exceeded)(budget_exceeded).is_deterministic());
+        assert!(trigger_error_kind(&abort)(abort).is_deterministic());
+    }
+
+    #[tokio::test]
+    async fn pausing_and_resuming_are_reported_to_the_pause_subscriber() {
+        let events = Arc::new(std::sync::Mutex::new(vec![]));
+        let subscriber: SharedSubgraphPauseSubscriber = Default::default();
+        let recorded = events.clone();
+        *subscriber.write().unwrap() = Some(Arc::new(move |event: &SubgraphPauseEvent| {
+            recorded.lock().unwrap().push(event.clone())
+        }));
+        let block = Some(EthereumBlockPointer::from((H256::zero(), 7u64)));
+        let kinds = || {
+            events
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|event| match event {
+                    SubgraphPauseEvent::Paused { block, .. } => ("paused", *block),
+                    SubgraphPauseEvent::Resumed { block, .. } => ("resumed", *block),
+                })
+                .collect::<Vec<_>>()
+        };
+        let wait = |mut paused: watch::Receiver<bool>| {
+            let subscriber = subscriber.clone();
+            tokio::spawn(async move {
+                let logger = Logger::root(slog::Discard, o!());
+                let id = SubgraphDeploymentId::new("paused").unwrap();
+                wait_while_paused(&logger, &id, block, &mut paused, &subscriber).await
+            })
+        };
+
+        // The subgraph reports that it paused right away, and that it
+        // resumed only once it is resumed
+        let (sender, receiver) = watch::channel(true);
+        let waiting = wait(receiver);
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        assert_eq!(kinds(), vec![("paused", block)]);
+        sender.broadcast(false).unwrap();
+        assert!(waiting.await.unwrap());
+        assert_eq!(kinds(), vec![("paused", block), ("resumed", block)]);
+
+        // A subgraph that is stopped while it is paused never resumes
+        events.lock().unwrap().clear();
+        let (sender, receiver) = watch::channel(true);
+        let waiting = wait(receiver);
+        drop(sender);
+        assert!(!waiting.await.unwrap());
+        assert_eq!(kinds(), vec![("paused", block)]);
+    }
+
+    #[test]
+    fn blocks_over_the_memory_budget_fail() {
+        let block_ptr = EthereumBlockPointer::from((H256::zero(), 7u64));
+
+        match enforce_memory_budget(Some(1000), 1001, block_ptr) {
+            Err(BlockProcessingError::NonDeterministic(e)) => assert!(e
+                .to_string()
+                .contains("exceeded its memory budget of 1000 bytes with 1001 bytes")),
+            _ => panic!("blocks over the memory budget must fail non-deterministically"),
+        }
+    }
+
+    #[test]
+    fn blocks_within_the_memory_budget_succeed() {
+        let block_ptr = EthereumBlockPointer::from((H256::zero(), 7u64));
+
+        assert!(enforce_memory_budget(Some(1000), 1000, block_ptr).is_ok());
+        assert!(enforce_memory_budget(Some(1000), 0, block_ptr).is_ok());
+        assert!(enforce_memory_budget(None, u64::max_value(), block_ptr).is_ok());
+    }
+}
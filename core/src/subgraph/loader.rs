@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 use std::iter::FromIterator;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 
@@ -9,12 +9,102 @@ use graph::data::subgraph::UnresolvedDataSource;
 use graph::prelude::{DataSourceLoader as DataSourceLoaderTrait, GraphQlRunner, *};
 use graph_graphql::graphql_parser::{parse_query, query as q};
 
+use super::content_hash::VerifyingLinkResolver;
+use super::file_cache::{CachingLinkResolver, DataSourceFileCache};
+use super::local_files::LocalFileLinkResolver;
+
+/// Controls how often and how quickly failed link fetches are retried
+/// while resolving dynamic data sources.
+#[derive(Clone, Debug)]
+pub struct FetchRetryPolicy {
+    /// The maximum number of attempts, including the first one.
+    pub max_attempts: usize,
+    /// The delay before the first retry; it doubles with every retry.
+    pub base_delay: Duration,
+    /// The upper bound for the delay between retries.
+    pub max_delay: Duration,
+}
+
+impl Default for FetchRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Returns `true` for fetch errors that will not go away when retrying,
+/// e.g. because the file does not exist or exceeds the size limit.
+fn is_permanent_fetch_error(e: &Error) -> bool {
+    let message = e.to_string();
+    message.contains("not found") || message.contains("too large")
+}
+
+/// A link resolver that retries failed `cat` calls of the wrapped resolver
+/// with exponential backoff.
+struct RetryingLinkResolver<L> {
+    inner: Arc<L>,
+    policy: FetchRetryPolicy,
+}
+
+#[async_trait]
+impl<L> LinkResolver for RetryingLinkResolver<L>
+where
+    L: LinkResolver,
+{
+    // The wrapped resolver is shared, so its configuration is left untouched
+    fn with_timeout(self, _timeout: Duration) -> Self {
+        self
+    }
+
+    fn with_retries(self) -> Self {
+        self
+    }
+
+    async fn cat(&self, logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        let mut delay = self.policy.base_delay;
+        let mut attempt = 1;
+        loop {
+            match self.inner.cat(logger, link).await {
+                Ok(data) => return Ok(data),
+                Err(e) if attempt >= self.policy.max_attempts || is_permanent_fetch_error(&e) => {
+                    return Err(e)
+                }
+                Err(e) => {
+                    warn!(
+                        logger,
+                        "Failed to fetch file, retrying";
+                        "link" => &link.link,
+                        "attempt" => attempt,
+                        "error" => e.to_string(),
+                    );
+                    tokio::time::delay_for(delay).await;
+                    delay = (delay * 2).min(self.policy.max_delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        self.inner.json_stream(logger, link).await
+    }
+}
+
 pub struct DataSourceLoader<L, Q, S> {
     store: Arc<S>,
     link_resolver: Arc<L>,
     graphql_runner: Arc<Q>,
+    retry_policy: FetchRetryPolicy,
+    max_concurrent_fetches: usize,
+    file_cache: Option<Arc<DataSourceFileCache>>,
 }
 
+/// The default number of data sources that are resolved at the same time.
+const DEFAULT_MAX_CONCURRENT_FETCHES: usize = 10;
+
 impl<L, Q, S> DataSourceLoader<L, Q, S>
 where
     L: LinkResolver,
@@ -26,9 +116,30 @@ where
             store,
             link_resolver,
             graphql_runner,
+            retry_policy: FetchRetryPolicy::default(),
+            max_concurrent_fetches: DEFAULT_MAX_CONCURRENT_FETCHES,
+            file_cache: None,
         }
     }
 
+    /// Overrides the policy for retrying failed file fetches.
+    pub fn with_retry_policy(mut self, retry_policy: FetchRetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Overrides how many data sources are resolved concurrently.
+    pub fn with_max_concurrent_fetches(mut self, max_concurrent_fetches: usize) -> Self {
+        self.max_concurrent_fetches = max_concurrent_fetches.max(1);
+        self
+    }
+
+    /// Looks up files in `file_cache` before fetching them.
+    pub fn with_file_cache(mut self, file_cache: Arc<DataSourceFileCache>) -> Self {
+        self.file_cache = Some(file_cache);
+        self
+    }
+
     fn dynamic_data_sources_query(
         &self,
         deployment: &SubgraphDeploymentId,
@@ -191,13 +302,28 @@ where
         unresolved_data_sources: Vec<UnresolvedDataSource>,
         logger: &Logger,
     ) -> Result<Vec<DataSource>, Error> {
-        // Resolve the data sources and return them
-        let mut result = Vec::new();
-        for item in unresolved_data_sources.into_iter() {
-            let resolved = item.resolve(&*self.link_resolver, logger).await?;
-            result.push(resolved);
-        }
-        Ok(result)
+        // Local files are read before the cache is consulted, since they may
+        // change between loads. Files are verified before they are cached.
+        let resolver = LocalFileLinkResolver::new(Arc::new(CachingLinkResolver {
+            inner: VerifyingLinkResolver {
+                inner: RetryingLinkResolver {
+                    inner: self.link_resolver.clone(),
+                    policy: self.retry_policy.clone(),
+                },
+            },
+            cache: self.file_cache.clone(),
+        }));
+
+        // Resolve the data sources concurrently and return them in their
+        // original order; the first error drops all pending fetches
+        futures03::stream::iter(
+            unresolved_data_sources
+                .into_iter()
+                .map(|item| item.resolve(&resolver, logger)),
+        )
+        .buffered(self.max_concurrent_fetches)
+        .try_collect()
+        .await
     }
 }
 
@@ -244,3 +370,109 @@ where
         Ok(data_sources)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Fails the first `failures` calls to `cat` with `error`.
+    struct FlakyLinkResolver {
+        failures: usize,
+        error: &'static str,
+        attempts: AtomicUsize,
+    }
+
+    impl FlakyLinkResolver {
+        fn new(failures: usize, error: &'static str) -> Self {
+            Self {
+                failures,
+                error,
+                attempts: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl LinkResolver for FlakyLinkResolver {
+        fn with_timeout(self, _timeout: Duration) -> Self {
+            self
+        }
+
+        fn with_retries(self) -> Self {
+            self
+        }
+
+        async fn cat(&self, _logger: &Logger, _link: &Link) -> Result<Vec<u8>, Error> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+                Err(format_err!("{}", self.error))
+            } else {
+                Ok(b"data".to_vec())
+            }
+        }
+
+        async fn json_stream(
+            &self,
+            _logger: &Logger,
+            _link: &Link,
+        ) -> Result<JsonValueStream, Error> {
+            unimplemented!()
+        }
+    }
+
+    fn retrying(inner: Arc<FlakyLinkResolver>) -> RetryingLinkResolver<FlakyLinkResolver> {
+        RetryingLinkResolver {
+            inner,
+            policy: FetchRetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(2),
+            },
+        }
+    }
+
+    fn link() -> Link {
+        Link {
+            link: "/ipfs/QmTest".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_timeouts_until_fetch_succeeds() {
+        let inner = Arc::new(FlakyLinkResolver::new(2, "operation timed out"));
+        let resolver = retrying(inner.clone());
+
+        let data = resolver
+            .cat(&Logger::root(slog::Discard, o!()), &link())
+            .await;
+
+        assert_eq!(data.unwrap(), b"data".to_vec());
+        assert_eq!(inner.attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let inner = Arc::new(FlakyLinkResolver::new(5, "connection refused"));
+        let resolver = retrying(inner.clone());
+
+        let data = resolver
+            .cat(&Logger::root(slog::Discard, o!()), &link())
+            .await;
+
+        assert!(data.is_err());
+        assert_eq!(inner.attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_missing_files() {
+        let inner = Arc::new(FlakyLinkResolver::new(1, "merkledag: not found"));
+        let resolver = retrying(inner.clone());
+
+        let data = resolver
+            .cat(&Logger::root(slog::Discard, o!()), &link())
+            .await;
+
+        assert!(data.is_err());
+        assert_eq!(inner.attempts.load(Ordering::SeqCst), 1);
+    }
+}
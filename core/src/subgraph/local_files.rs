@@ -0,0 +1,199 @@
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+
+use graph::prelude::*;
+
+lazy_static! {
+    /// Whether links of the form `file://<path>` are read from the local
+    /// filesystem. This is meant for local subgraph development only:
+    /// anyone who can deploy a subgraph can make the node read any file it
+    /// has access to, and files can change after a subgraph was deployed.
+    static ref ALLOW_LOCAL_FILE_LINKS: bool = std::env::var("GRAPH_ALLOW_LOCAL_FILE_LINKS")
+        .map_or(false, |s| s == "true");
+}
+
+const LOCAL_FILE_SCHEME: &str = "file://";
+
+/// Returns the local path that `link` refers to, or `None` if it is not a
+/// `file://` link.
+fn local_path(link: &Link) -> Option<&Path> {
+    if link.link.starts_with(LOCAL_FILE_SCHEME) {
+        Some(Path::new(&link.link[LOCAL_FILE_SCHEME.len()..]))
+    } else {
+        None
+    }
+}
+
+/// A link resolver that reads `file://` links from the local filesystem if
+/// that is allowed, and fetches all other links with the wrapped resolver.
+pub(crate) struct LocalFileLinkResolver<L> {
+    pub inner: Arc<L>,
+    pub allow_local_files: bool,
+}
+
+impl<L> LocalFileLinkResolver<L> {
+    /// Allows local files if `GRAPH_ALLOW_LOCAL_FILE_LINKS` is `true`.
+    pub fn new(inner: Arc<L>) -> Self {
+        Self {
+            inner,
+            allow_local_files: *ALLOW_LOCAL_FILE_LINKS,
+        }
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        if !self.allow_local_files {
+            return Err(format_err!(
+                "Link `{}{}` refers to a local file, but local file links are disabled; \
+                 set GRAPH_ALLOW_LOCAL_FILE_LINKS=true to allow them during development",
+                LOCAL_FILE_SCHEME,
+                path.display()
+            ));
+        }
+
+        // Local files are small and only read during development, so blocking
+        // the executor briefly is acceptable
+        std::fs::read(path).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => format_err!("Local file `{}` not found", path.display()),
+            _ => format_err!("Failed to read local file `{}`: {}", path.display(), e),
+        })
+    }
+}
+
+#[async_trait]
+impl<L> LinkResolver for LocalFileLinkResolver<L>
+where
+    L: LinkResolver,
+{
+    // The wrapped resolver is shared, so its configuration is left untouched
+    fn with_timeout(self, _timeout: Duration) -> Self {
+        self
+    }
+
+    fn with_retries(self) -> Self {
+        self
+    }
+
+    async fn cat(&self, logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        match local_path(link) {
+            Some(path) => {
+                debug!(logger, "Read local file"; "path" => path.display().to_string());
+                self.read(path)
+            }
+            None => self.inner.cat(logger, link).await,
+        }
+    }
+
+    async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        let path = match local_path(link) {
+            Some(path) => path,
+            None => return self.inner.json_stream(logger, link).await,
+        };
+
+        let data = String::from_utf8(self.read(path)?)?;
+        let values: Vec<_> = data
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(line, value)| {
+                serde_json::from_str(value)
+                    .map(|value| JsonStreamValue { value, line })
+                    .map_err(Error::from)
+            })
+            .collect();
+        Ok(Box::pin(futures03::stream::iter(values)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fails all calls, so that tests notice when a link is not read from
+    /// the local filesystem.
+    struct UnreachableLinkResolver;
+
+    #[async_trait]
+    impl LinkResolver for UnreachableLinkResolver {
+        fn with_timeout(self, _timeout: Duration) -> Self {
+            self
+        }
+
+        fn with_retries(self) -> Self {
+            self
+        }
+
+        async fn cat(&self, _logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+            Err(format_err!("fetched `{}` remotely", link.link))
+        }
+
+        async fn json_stream(
+            &self,
+            _logger: &Logger,
+            _link: &Link,
+        ) -> Result<JsonValueStream, Error> {
+            unimplemented!()
+        }
+    }
+
+    fn resolver(allow_local_files: bool) -> LocalFileLinkResolver<UnreachableLinkResolver> {
+        LocalFileLinkResolver {
+            inner: Arc::new(UnreachableLinkResolver),
+            allow_local_files,
+        }
+    }
+
+    fn file_link(path: &Path) -> Link {
+        Link {
+            link: format!("{}{}", LOCAL_FILE_SCHEME, path.display()),
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_local_files_when_allowed() {
+        let logger = Logger::root(slog::Discard, o!());
+        let path = std::env::temp_dir().join(format!("local-file-link-{}", std::process::id()));
+        std::fs::write(&path, b"abi").unwrap();
+
+        let allowed = resolver(true).cat(&logger, &file_link(&path)).await;
+        let denied = resolver(false).cat(&logger, &file_link(&path)).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(allowed.unwrap(), b"abi".to_vec());
+        assert!(denied
+            .unwrap_err()
+            .to_string()
+            .contains("local file links are disabled"));
+    }
+
+    #[tokio::test]
+    async fn reports_missing_local_files() {
+        let logger = Logger::root(slog::Discard, o!());
+        let path = std::env::temp_dir().join("local-file-link-that-does-not-exist");
+
+        let err = resolver(true)
+            .cat(&logger, &file_link(&path))
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!("Local file `{}` not found", path.display())
+        );
+    }
+
+    #[tokio::test]
+    async fn fetches_other_links_with_inner_resolver() {
+        let logger = Logger::root(slog::Discard, o!());
+        let link = Link {
+            link: "/ipfs/QmTest".to_owned(),
+        };
+
+        let err = resolver(true).cat(&logger, &link).await.unwrap_err();
+
+        assert_eq!(err.to_string(), "fetched `/ipfs/QmTest` remotely");
+    }
+}
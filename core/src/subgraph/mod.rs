@@ -1,11 +1,15 @@
+mod content_hash;
+mod file_cache;
 mod instance;
 mod instance_manager;
 mod loader;
+mod local_files;
 mod provider;
 mod registrar;
 
+pub use self::file_cache::{DataSourceFileCache, LruFileCache};
 pub use self::instance::SubgraphInstance;
 pub use self::instance_manager::SubgraphInstanceManager;
-pub use self::loader::DataSourceLoader;
+pub use self::loader::{DataSourceLoader, FetchRetryPolicy};
 pub use self::provider::SubgraphAssignmentProvider;
 pub use self::registrar::SubgraphRegistrar;
@@ -1,5 +1,4 @@
 use std::collections::HashSet;
-use std::ops::Deref as _;
 use std::sync::Mutex;
 
 use async_trait::async_trait;
@@ -11,8 +10,9 @@ use graph::prelude::{
     SubgraphAssignmentProvider as SubgraphAssignmentProviderTrait, *,
 };
 
+use crate::subgraph::local_files::LocalFileLinkResolver;
 use crate::subgraph::registrar::IPFS_SUBGRAPH_LOADING_TIMEOUT;
-use crate::DataSourceLoader;
+use crate::{DataSourceFileCache, DataSourceLoader};
 
 pub struct SubgraphAssignmentProvider<L, Q, S> {
     logger_factory: LoggerFactory,
@@ -22,6 +22,7 @@ pub struct SubgraphAssignmentProvider<L, Q, S> {
     subgraphs_running: Arc<Mutex<HashSet<SubgraphDeploymentId>>>,
     store: Arc<S>,
     graphql_runner: Arc<Q>,
+    file_cache: Option<Arc<DataSourceFileCache>>,
 }
 
 impl<L, Q, S> SubgraphAssignmentProvider<L, Q, S>
@@ -56,9 +57,17 @@ where
             subgraphs_running: Arc::new(Mutex::new(HashSet::new())),
             store,
             graphql_runner,
+            file_cache: None,
         }
     }
 
+    /// Shares `file_cache` between the data source loaders of all subgraphs,
+    /// so that files don't have to be fetched again on restarts.
+    pub fn with_file_cache(mut self, file_cache: Arc<DataSourceFileCache>) -> Self {
+        self.file_cache = Some(file_cache);
+        self
+    }
+
     /// Clones but forcing receivers to `None`.
     fn clone_no_receivers(&self) -> Self {
         SubgraphAssignmentProvider {
@@ -69,6 +78,7 @@ where
             store: self.store.clone(),
             graphql_runner: self.graphql_runner.clone(),
             logger_factory: self.logger_factory.clone(),
+            file_cache: self.file_cache.clone(),
         }
     }
 }
@@ -88,11 +98,15 @@ where
         let store = self.store.clone();
         let subgraph_id = id.clone();
 
-        let loader = Arc::new(DataSourceLoader::new(
+        let mut loader = DataSourceLoader::new(
             store.clone(),
             self.resolver.clone(),
             self.graphql_runner.clone(),
-        ));
+        );
+        if let Some(file_cache) = &self.file_cache {
+            loader = loader.with_file_cache(file_cache.clone());
+        }
+        let loader = Arc::new(loader);
 
         let link = format!("/ipfs/{}", id);
 
@@ -105,7 +119,7 @@ where
         async move {
             let mut subgraph = SubgraphManifest::resolve(
                 Link { link },
-                self.resolver.deref(),
+                &LocalFileLinkResolver::new(self.resolver.clone()),
                 &logger_for_resolve,
             )
             .map_err(SubgraphAssignmentProviderError::ResolveError)
@@ -14,6 +14,8 @@ use graph::prelude::{
     SubgraphRegistrar as SubgraphRegistrarTrait, *,
 };
 
+use crate::subgraph::local_files::LocalFileLinkResolver;
+
 lazy_static! {
     // The timeout for IPFS requests in seconds
     pub static ref IPFS_SUBGRAPH_LOADING_TIMEOUT: Duration = Duration::from_secs(
@@ -302,7 +304,7 @@ where
 
         let unvalidated = UnvalidatedSubgraphManifest::resolve(
             hash.to_ipfs_link(),
-            self.resolver.clone(),
+            Arc::new(LocalFileLinkResolver::new(self.resolver.clone())),
             &logger,
         )
         .map_err(SubgraphRegistrarError::ResolveError)
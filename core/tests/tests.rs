@@ -13,7 +13,7 @@ use graph::mock::MockEthereumAdapter;
 use graph::prelude::*;
 
 use graph_core::LinkResolver;
-use graph_mock::MockStore;
+use graph_mock::{MockMetricsRegistry, MockStore};
 
 use test_store::LOGGER;
 
@@ -256,7 +256,11 @@ async fn subgraph_provider_events() {
         .into_iter()
         .map(|e| ("mainnet".to_string(), e))
         .collect();
-    let graphql_runner = Arc::new(graph_core::GraphQlRunner::new(&logger, store.clone()));
+    let graphql_runner = Arc::new(graph_core::GraphQlRunner::new(
+        &logger,
+        store.clone(),
+        Arc::new(MockMetricsRegistry::new()),
+    ));
     let mut provider = graph_core::SubgraphAssignmentProvider::new(
         &logger_factory,
         resolver.clone(),
@@ -2,7 +2,7 @@ use std::pin::Pin;
 use std::time::Duration;
 
 use async_trait::async_trait;
-use failure::Error;
+use failure::{Error, Fail};
 use futures03::prelude::Stream;
 use serde_json::Value;
 use slog::Logger;
@@ -20,6 +20,18 @@ pub struct JsonStreamValue {
 pub type JsonValueStream =
     Pin<Box<dyn Stream<Item = Result<JsonStreamValue, Error>> + Send + 'static>>;
 
+/// The file that a resolver returned for a link does not have the content
+/// hash of the link.
+#[derive(Fail, Debug)]
+#[fail(
+    display = "Content of `{}` does not match its hash, got content with hash `{}`",
+    link, actual
+)]
+pub struct ContentHashMismatch {
+    pub link: String,
+    pub actual: String,
+}
+
 /// Resolves links to subgraph manifests and resources referenced by them.
 #[async_trait]
 pub trait LinkResolver: Send + Sync + 'static {
@@ -42,3 +54,14 @@ pub trait LinkResolver: Send + Sync + 'static {
     /// separately.
     async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error>;
 }
+
+/// A cache for the contents of files addressed by their content hash. Since
+/// such files are immutable, entries never need to be invalidated; an
+/// implementation may keep them in memory or persist them.
+pub trait FileCache: Send + Sync + 'static {
+    /// Returns the cached contents of the file with the given content hash.
+    fn get(&self, cid: &str) -> Option<Vec<u8>>;
+
+    /// Adds the contents of the file with the given content hash.
+    fn insert(&self, cid: &str, data: &[u8]);
+}
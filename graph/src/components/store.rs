@@ -15,11 +15,11 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 use web3::types::H256;
 
-use crate::components::subgraph::ProofOfIndexingDigest;
+use crate::components::subgraph::{ProofOfIndexingDigest, ProofOfIndexingHealth};
 use crate::data::store::*;
 use crate::data::subgraph::schema::*;
 use crate::prelude::*;
-use crate::util::lfu_cache::LfuCache;
+use crate::util::lfu_cache::{CacheWeight, LfuCache};
 
 lazy_static! {
     pub static ref SUBSCRIPTION_THROTTLE_INTERVAL: Duration =
@@ -440,6 +440,12 @@ pub struct StoreEvent {
     // logs as they flow through the system
     pub tag: usize,
     pub changes: HashSet<EntityChange>,
+    /// The number of the block whose processing or revert caused these
+    /// changes. After a revert, this is the block that was reverted to.
+    /// Events that are not related to a block, like the ones for the
+    /// subgraph of subgraphs, don't have one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block: Option<BlockNumber>,
 }
 
 impl From<Vec<MetadataOperation>> for StoreEvent {
@@ -475,7 +481,17 @@ impl StoreEvent {
 
         let tag = NEXT_TAG.fetch_add(1, Ordering::Relaxed);
         let changes = changes.into_iter().collect();
-        StoreEvent { tag, changes }
+        StoreEvent {
+            tag,
+            changes,
+            block: None,
+        }
+    }
+
+    /// Marks the event as caused by the block with number `block`.
+    pub fn with_block(mut self, block: BlockNumber) -> Self {
+        self.block = Some(block);
+        self
     }
 
     /// Extend `ev1` with `ev2`. If `ev1` is `None`, just set it to `ev2`
@@ -484,6 +500,7 @@ impl StoreEvent {
             trace!(logger, "Adding changes to event";
                            "from" => ev2.tag, "to" => e.tag);
             e.changes.extend(ev2.changes);
+            e.block = e.block.max(ev2.block);
         } else {
             *ev1 = Some(ev2);
         }
@@ -491,6 +508,7 @@ impl StoreEvent {
 
     pub fn extend(mut self, other: StoreEvent) -> Self {
         self.changes.extend(other.changes);
+        self.block = self.block.max(other.block);
         self
     }
 }
@@ -561,6 +579,27 @@ where
         StoreEventStream::new(Box::new(source))
     }
 
+    /// Filter a `StoreEventStream` by the IDs of entities of a single type.
+    /// Events whose changes all affect entities of `entity_type` in
+    /// `subgraph_id` with an ID other than one of `entity_ids` are dropped;
+    /// events that contain any other change are delivered unchanged.
+    pub fn filter_by_entity_ids(
+        self,
+        subgraph_id: SubgraphDeploymentId,
+        entity_type: String,
+        entity_ids: Vec<String>,
+    ) -> StoreEventStreamBox {
+        let source = self.source.filter(move |event| {
+            event.changes.iter().any(|change| {
+                change.subgraph_id != subgraph_id
+                    || change.entity_type != entity_type
+                    || entity_ids.contains(&change.entity_id)
+            })
+        });
+
+        StoreEventStream::new(Box::new(source))
+    }
+
     /// Reduce the frequency with which events are generated while a
     /// subgraph deployment is syncing. While the given `deployment` is not
     /// synced yet, events from `source` are reported at most every
@@ -755,6 +794,34 @@ pub enum StoreError {
     InvalidIdentifier(String),
 }
 
+/// Why the proof of indexing of a subgraph cannot be computed for a block.
+#[derive(Fail, Debug)]
+pub enum ProofOfIndexingError {
+    #[fail(
+        display = "subgraph `{}` has not indexed block {} yet (latest block: {:?})",
+        subgraph_id, block_number, latest_block
+    )]
+    BlockNotIndexed {
+        subgraph_id: SubgraphDeploymentId,
+        block_number: u64,
+        latest_block: Option<u64>,
+    },
+
+    /// The store does not have the history of the subgraph for the block,
+    /// e.g. because it was pruned or because the subgraph started indexing
+    /// at a later block.
+    #[fail(
+        display = "the history of subgraph `{}` starts at block {}, \
+                   block {} has been pruned",
+        subgraph_id, earliest_block, block_number
+    )]
+    BlockPruned {
+        subgraph_id: SubgraphDeploymentId,
+        block_number: u64,
+        earliest_block: u64,
+    },
+}
+
 impl From<TransactionAbortError> for StoreError {
     fn from(e: TransactionAbortError) -> Self {
         StoreError::Aborted(e)
@@ -813,17 +880,33 @@ pub trait Store: Send + Sync + 'static {
         subgraph_id: &'a SubgraphDeploymentId,
     ) -> DynTryFuture<'a, bool>;
 
+    /// Returns the proof of indexing of the subgraph as of `block_number`,
+    /// computed from the history of the PoI entities in the store.
+    ///
     /// A value of None indicates that the table is not available. Re-deploying
     /// the subgraph fixes this. It is undesirable to force everything to
     /// re-sync from scratch, so existing deployments will continue without a
     /// Proof of Indexing. Once all subgraphs have been re-deployed the Option
     /// can be removed.
+    ///
+    /// Fails with a `ProofOfIndexingError` if the subgraph has not reached
+    /// the block yet, or if its history for the block is no longer available.
     fn get_proof_of_indexing<'a>(
         &'a self,
         subgraph_id: &'a SubgraphDeploymentId,
         block_number: u64,
     ) -> DynTryFuture<'a, Option<ProofOfIndexingDigest>>;
 
+    /// Checks that the proof of indexing the subgraph stores for its current
+    /// head block matches the one computed from the history of the PoI
+    /// entities as of that block; see `ProofOfIndexingHealth` for what that
+    /// detects. Gives up with `ProofOfIndexingHealth::Advancing` if the
+    /// subgraph keeps processing blocks while it is checked.
+    fn check_proof_of_indexing<'a>(
+        &'a self,
+        subgraph_id: &'a SubgraphDeploymentId,
+    ) -> DynTryFuture<'a, ProofOfIndexingHealth>;
+
     /// Looks up an entity using the given store key at the latest block.
     fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError>;
 
@@ -1245,6 +1328,13 @@ impl Store for MockStore {
         unimplemented!();
     }
 
+    fn check_proof_of_indexing<'a>(
+        &'a self,
+        _subgraph_id: &'a SubgraphDeploymentId,
+    ) -> DynTryFuture<'a, ProofOfIndexingHealth> {
+        unimplemented!();
+    }
+
     fn get(&self, _key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
         unimplemented!()
     }
@@ -1508,6 +1598,8 @@ pub struct EntityCache {
     /// The accumulated changes to an entity. An entry of `None`
     /// means that the entity should be deleted
     updates: HashMap<EntityKey, Option<Entity>>,
+    /// The weight of `updates`, kept up to date by `set` and `remove`
+    updates_weight: u64,
 }
 
 pub struct ModificationsAndCache {
@@ -1524,6 +1616,7 @@ impl EntityCache {
         EntityCache {
             current,
             updates: HashMap::new(),
+            updates_weight: 0,
         }
     }
 
@@ -1557,22 +1650,82 @@ impl EntityCache {
     }
 
     pub fn remove(&mut self, key: EntityKey) {
-        self.updates.insert(key, None);
+        let key_weight = Self::key_weight(&key);
+        if let Some(previous) = self.updates.insert(key, None) {
+            self.updates_weight -= key_weight + previous.weight();
+        }
+        self.updates_weight += key_weight;
+    }
+
+    /// The changes that have been made via `set` and `remove` so far, ordered
+    /// by entity key. Unlike `as_modifications`, this neither consumes the
+    /// cache nor consults the store, so the operations are not merged with
+    /// the current state of the entities.
+    pub fn pending_operations(&self) -> Vec<EntityOperation> {
+        let mut updates: Vec<_> = self.updates.iter().collect();
+        updates.sort_by(|(a, _), (b, _)| a.cmp(b));
+        updates
+            .into_iter()
+            .map(|(key, update)| match update {
+                Some(data) => EntityOperation::Set {
+                    key: key.clone(),
+                    data: data.clone(),
+                },
+                None => EntityOperation::Remove { key: key.clone() },
+            })
+            .collect()
+    }
+
+    /// The number of entities that have been changed via `set` and `remove`.
+    pub fn update_count(&self) -> usize {
+        self.updates.len()
+    }
+
+    /// The approximate amount of bytes occupied by the changes made via
+    /// `set` and `remove`.
+    pub fn updates_weight(&self) -> u64 {
+        self.updates_weight
+    }
+
+    /// The approximate amount of bytes occupied by the entities in the cache
+    /// and the changes made via `set` and `remove`.
+    pub fn weight(&self) -> u64 {
+        self.current.weight() + self.updates_weight
+    }
+
+    fn key_weight(key: &EntityKey) -> u64 {
+        std::mem::size_of_val(key) as u64
+            + key.entity_type.len() as u64
+            + key.entity_id.len() as u64
     }
 
     pub fn set(&mut self, key: EntityKey, entity: Entity) {
-        let update = self.updates.entry(key).or_insert(None);
+        use std::collections::hash_map::Entry;
+
+        let key_weight = Self::key_weight(&key);
+        let mut entry = match self.updates.entry(key) {
+            Entry::Occupied(entry) => entry,
+            Entry::Vacant(entry) => {
+                // Never changed
+                self.updates_weight += key_weight + entity.weight();
+                entry.insert(Some(entity));
+                return;
+            }
+        };
 
+        let update = entry.get_mut();
+        self.updates_weight -= update.weight();
         match update {
             Some(update) => {
                 // Previously changed
                 update.merge(entity);
             }
             None => {
-                // Previously removed or never changed
+                // Previously removed
                 *update = Some(entity);
             }
         }
+        self.updates_weight += update.weight();
     }
 
     pub fn append(&mut self, operations: Vec<EntityOperation>) {
@@ -1,12 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use failure::Error;
 use futures::sync::mpsc;
 
-use crate::components::metrics::HistogramVec;
+use crate::components::metrics::{CounterVec, HistogramVec};
 use crate::prelude::*;
 use web3::types::{Log, Transaction};
 
@@ -52,9 +53,108 @@ pub trait RuntimeHost: Send + Sync + Debug + 'static {
     ) -> Result<BlockState, Error>;
 }
 
+/// A mapping handler ran for longer than the handler timeout.
+#[derive(Fail, Debug)]
+#[fail(
+    display = "Handler `{}` of data source `{}` timed out after {:?} in block {}",
+    handler, data_source, timeout, block
+)]
+pub struct HandlerTimeoutError {
+    pub handler: String,
+    pub data_source: String,
+    pub block: EthereumBlockPointer,
+    pub timeout: Duration,
+
+    /// Whether the handler is expected to time out again when the block is
+    /// processed again. If it is not, the subgraph is restarted instead of
+    /// being marked as failed.
+    pub deterministic: bool,
+}
+
+/// A host function failed for a reason that has nothing to do with the
+/// mapping, e.g. because the Ethereum node, IPFS or the store was
+/// unavailable. The handler that called it may succeed when its trigger is
+/// processed again.
+#[derive(Fail, Debug)]
+#[fail(display = "{}", message)]
+pub struct NonDeterministicHostError {
+    pub message: String,
+}
+
+/// Whether a mapping handler that failed with `e` will fail again when its
+/// trigger is processed again. That is the case for all errors except
+/// failures of host functions that raise a `NonDeterministicHostError`, and
+/// handler timeouts that are configured to be non-deterministic.
+pub fn is_deterministic_handler_error(e: &Error) -> bool {
+    if e.downcast_ref::<NonDeterministicHostError>().is_some() {
+        return false;
+    }
+    e.downcast_ref::<HandlerTimeoutError>()
+        .map_or(true, |e| e.deterministic)
+}
+
+/// How many non-deterministic failures a single handler may have within
+/// `window` before the subgraph is marked as failed instead of restarted.
+/// Deterministic failures always fail the subgraph.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HandlerErrorBudget {
+    pub failures: usize,
+    pub window: Duration,
+}
+
+/// A mapping handler failed non-deterministically more often than its
+/// `HandlerErrorBudget` allows.
+#[derive(Fail, Debug)]
+#[fail(
+    display = "Handler `{}` of data source `{}` failed {} times within {:?}, last error: {}",
+    handler, data_source, failures, window, error
+)]
+pub struct HandlerErrorBudgetExceeded {
+    pub handler: String,
+    pub data_source: String,
+    pub failures: usize,
+    pub window: Duration,
+    pub error: String,
+}
+
+/// The times of the recent non-deterministic failures of each handler, by
+/// handler and data source name.
+#[derive(Default)]
+struct RecentHandlerFailures(HashMap<(String, String), VecDeque<Instant>>);
+
+impl RecentHandlerFailures {
+    /// Records a failure at `now` and returns the number of failures of the
+    /// handler within `window`, including this one.
+    fn record(
+        &mut self,
+        handler: &str,
+        data_source: &str,
+        now: Instant,
+        window: Duration,
+    ) -> usize {
+        let failures = self
+            .0
+            .entry((handler.to_owned(), data_source.to_owned()))
+            .or_default();
+        failures.push_back(now);
+        while failures
+            .front()
+            .map_or(false, |failed_at| now.duration_since(*failed_at) > window)
+        {
+            failures.pop_front();
+        }
+        failures.len()
+    }
+}
+
 pub struct HostMetrics {
     handler_execution_time: Box<HistogramVec>,
+    handler_gas_used: Box<HistogramVec>,
+    handler_invocations: Box<CounterVec>,
+    handler_timeouts: Box<CounterVec>,
+    handler_failures: Box<CounterVec>,
     host_fn_execution_time: Box<HistogramVec>,
+    recent_handler_failures: Mutex<RecentHandlerFailures>,
     pub stopwatch: StopwatchMetrics,
 }
 
@@ -76,10 +176,43 @@ impl HostMetrics {
                 format!("subgraph_handler_execution_time_{}", subgraph_hash),
                 String::from("Measures the execution time for handlers"),
                 HashMap::new(),
-                vec![String::from("handler")],
+                vec![String::from("handler"), String::from("data_source")],
                 vec![0.1, 0.5, 1.0, 10.0, 100.0],
             )
             .expect("failed to create `subgraph_handler_execution_time` histogram");
+        let handler_gas_used = registry
+            .new_histogram_vec(
+                format!("subgraph_handler_gas_used_{}", subgraph_hash),
+                String::from("Measures the gas used by handlers"),
+                HashMap::new(),
+                vec![String::from("handler"), String::from("data_source")],
+                vec![1e4, 1e5, 1e6, 1e7, 1e8, 1e9],
+            )
+            .expect("failed to create `subgraph_handler_gas_used` histogram");
+        let handler_invocations = registry
+            .new_counter_vec(
+                format!("subgraph_handler_invocations_{}", subgraph_hash),
+                String::from("Counts the invocations of handlers"),
+                HashMap::new(),
+                vec![String::from("handler"), String::from("data_source")],
+            )
+            .expect("failed to create `subgraph_handler_invocations` counter");
+        let handler_timeouts = registry
+            .new_counter_vec(
+                format!("subgraph_handler_timeouts_{}", subgraph_hash),
+                String::from("Counts the handler invocations that timed out"),
+                HashMap::new(),
+                vec![String::from("handler"), String::from("data_source")],
+            )
+            .expect("failed to create `subgraph_handler_timeouts` counter");
+        let handler_failures = registry
+            .new_counter_vec(
+                format!("subgraph_handler_failures_{}", subgraph_hash),
+                String::from("Counts the handler invocations that failed non-deterministically"),
+                HashMap::new(),
+                vec![String::from("handler"), String::from("data_source")],
+            )
+            .expect("failed to create `subgraph_handler_failures` counter");
         let host_fn_execution_time = registry
             .new_histogram_vec(
                 format!("subgraph_host_fn_execution_time_{}", subgraph_hash),
@@ -91,17 +224,64 @@ impl HostMetrics {
             .expect("failed to create `subgraph_host_fn_execution_time` histogram");
         Self {
             handler_execution_time,
+            handler_gas_used,
+            handler_invocations,
+            handler_timeouts,
+            handler_failures,
             host_fn_execution_time,
+            recent_handler_failures: Mutex::new(RecentHandlerFailures::default()),
             stopwatch,
         }
     }
 
-    pub fn observe_handler_execution_time(&self, duration: f64, handler: &str) {
+    pub fn observe_handler_execution_time(&self, duration: f64, handler: &str, data_source: &str) {
         self.handler_execution_time
-            .with_label_values(vec![handler].as_slice())
+            .with_label_values(vec![handler, data_source].as_slice())
             .observe(duration);
     }
 
+    pub fn observe_handler_gas_used(&self, gas: u64, handler: &str, data_source: &str) {
+        self.handler_gas_used
+            .with_label_values(vec![handler, data_source].as_slice())
+            .observe(gas as f64);
+    }
+
+    pub fn observe_handler_invocation(&self, handler: &str, data_source: &str) {
+        self.handler_invocations
+            .with_label_values(vec![handler, data_source].as_slice())
+            .inc();
+    }
+
+    pub fn observe_handler_timeout(&self, handler: &str, data_source: &str) {
+        self.handler_timeouts
+            .with_label_values(vec![handler, data_source].as_slice())
+            .inc();
+    }
+
+    pub fn observe_handler_failure(&self, handler: &str, data_source: &str) {
+        self.handler_failures
+            .with_label_values(vec![handler, data_source].as_slice())
+            .inc();
+    }
+
+    /// Remembers a non-deterministic failure of a handler for `window` and
+    /// returns how often the handler has failed within `window`. Since the
+    /// metrics of a subgraph are kept when it is restarted, this includes
+    /// failures before earlier restarts.
+    pub fn record_recent_handler_failure(
+        &self,
+        handler: &str,
+        data_source: &str,
+        window: Duration,
+    ) -> usize {
+        self.recent_handler_failures.lock().unwrap().record(
+            handler,
+            data_source,
+            Instant::now(),
+            window,
+        )
+    }
+
     pub fn observe_host_fn_execution_time(&self, duration: f64, fn_name: &str) {
         self.host_fn_execution_time
             .with_label_values(vec![fn_name].as_slice())
@@ -133,3 +313,33 @@ pub trait RuntimeHostBuilder: Clone + Send + Sync + 'static {
         metrics: Arc<HostMetrics>,
     ) -> Result<mpsc::Sender<Self::Req>, Error>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_handler_failures_are_counted_per_handler_within_window() {
+        let mut failures = RecentHandlerFailures::default();
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+
+        assert_eq!(failures.record("handleA", "ds", start, window), 1);
+        assert_eq!(failures.record("handleB", "ds", start, window), 1);
+        assert_eq!(failures.record("handleA", "other", start, window), 1);
+        assert_eq!(
+            failures.record("handleA", "ds", start + Duration::from_secs(30), window),
+            2
+        );
+
+        // The first failure of `handleA` is now outside of the window
+        assert_eq!(
+            failures.record("handleA", "ds", start + Duration::from_secs(61), window),
+            2
+        );
+        assert_eq!(
+            failures.record("handleA", "ds", start + Duration::from_secs(200), window),
+            1
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use std::time::{Duration, Instant};
 use web3::types::Log;
 
 use super::ProofOfIndexing;
@@ -13,11 +14,113 @@ pub struct DataSourceTemplateInfo {
     pub context: Option<DataSourceContext>,
 }
 
+/// The order in which the triggers of a block are processed by the data
+/// sources of a subgraph. Since handlers see the entity changes of the
+/// handlers before them, the ordering affects the data and the proof of
+/// indexing of a subgraph.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TriggerOrdering {
+    /// Triggers are processed in the order in which they happened in the
+    /// block: events by log index and calls by transaction index, with the
+    /// events of a transaction before its calls, followed by block triggers.
+    /// Each trigger is processed by the data sources that handle it in the
+    /// order in which they are declared in the manifest, followed by the data
+    /// sources created from templates in the order in which they were
+    /// created. This is the default.
+    Block,
+
+    /// Each data source, in the order described for `Block`, processes all
+    /// the triggers it handles in block order before the next data source
+    /// processes any.
+    DataSource,
+}
+
+impl TriggerOrdering {
+    pub fn parse(ordering: &str) -> Self {
+        match ordering.to_ascii_lowercase().as_str() {
+            "block" => TriggerOrdering::Block,
+            "data_source" => TriggerOrdering::DataSource,
+            _ => panic!("invalid trigger ordering: {:?}", ordering),
+        }
+    }
+}
+
+impl Default for TriggerOrdering {
+    fn default() -> Self {
+        TriggerOrdering::Block
+    }
+}
+
+/// How often processing a block yields to the async runtime between handler
+/// invocations, so that blocks with many triggers don't keep other subgraphs
+/// and queries from running on the same runtime thread. Processing yields
+/// once either limit is reached; without limits, it never yields.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HandlerYieldPolicy {
+    /// Yield after this many handler invocations.
+    pub handlers: Option<usize>,
+    /// Yield once this much time has passed since the last yield.
+    pub interval: Option<Duration>,
+}
+
+/// Tracks the handler invocations of a block since processing last yielded.
+#[derive(Debug)]
+pub struct HandlerYield {
+    policy: HandlerYieldPolicy,
+    handlers: usize,
+    since: Instant,
+    yields: usize,
+}
+
+impl HandlerYield {
+    pub fn new(policy: HandlerYieldPolicy) -> Self {
+        HandlerYield {
+            policy,
+            handlers: 0,
+            since: Instant::now(),
+            yields: 0,
+        }
+    }
+
+    /// Records a handler invocation and yields to the runtime if the policy
+    /// says so.
+    pub async fn handler_invoked(&mut self) {
+        self.handlers += 1;
+        let handlers_reached = self
+            .policy
+            .handlers
+            .map_or(false, |handlers| self.handlers >= handlers);
+        let interval_reached = self
+            .policy
+            .interval
+            .map_or(false, |interval| self.since.elapsed() >= interval);
+
+        if handlers_reached || interval_reached {
+            tokio::task::yield_now().await;
+            self.handlers = 0;
+            self.since = Instant::now();
+            self.yields += 1;
+        }
+    }
+
+    /// The number of times processing has yielded.
+    pub fn yields(&self) -> usize {
+        self.yields
+    }
+}
+
+impl Default for HandlerYield {
+    fn default() -> Self {
+        HandlerYield::new(HandlerYieldPolicy::default())
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct BlockState {
     pub entity_cache: EntityCache,
     pub created_data_sources: Vec<DataSourceTemplateInfo>,
     pub proof_of_indexing: ProofOfIndexing,
+    pub handler_yield: HandlerYield,
 }
 
 impl BlockState {
@@ -26,8 +129,20 @@ impl BlockState {
             entity_cache: EntityCache::with_current(lfu_cache),
             created_data_sources: Vec::new(),
             proof_of_indexing: Default::default(),
+            handler_yield: Default::default(),
         }
     }
+
+    pub fn with_handler_yield(mut self, policy: HandlerYieldPolicy) -> Self {
+        self.handler_yield = HandlerYield::new(policy);
+        self
+    }
+
+    /// A copy of the entity operations that the handlers for the block have
+    /// made so far and that will be written when the block is committed.
+    pub fn pending_entity_operations(&self) -> Vec<EntityOperation> {
+        self.entity_cache.pending_operations()
+    }
 }
 
 /// Represents a loaded instance of a subgraph.
@@ -54,12 +169,118 @@ pub trait SubgraphInstance<H: RuntimeHost> {
         state: BlockState,
     ) -> Result<BlockState, Error>;
 
-    /// Adds dynamic data sources to the subgraph.
+    /// Adds dynamic data sources to the subgraph. Returns `None` if the data
+    /// source is disabled and therefore has no runtime host.
     fn add_dynamic_data_source(
         &mut self,
         logger: &Logger,
         data_source: DataSource,
         top_level_templates: Arc<Vec<DataSourceTemplate>>,
         metrics: Arc<HostMetrics>,
-    ) -> Result<Arc<H>, Error>;
+    ) -> Result<Option<Arc<H>>, Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn yields(policy: HandlerYieldPolicy, invocations: usize) -> usize {
+        let mut runtime = tokio::runtime::Builder::new().enable_all().build().unwrap();
+        runtime.block_on(async move {
+            let mut handler_yield = HandlerYield::new(policy);
+            for _ in 0..invocations {
+                handler_yield.handler_invoked().await;
+            }
+            handler_yield.yields()
+        })
+    }
+
+    #[test]
+    fn yields_after_handlers() {
+        let policy = HandlerYieldPolicy {
+            handlers: Some(10),
+            interval: None,
+        };
+        assert_eq!(0, yields(policy, 9));
+        assert_eq!(1, yields(policy, 10));
+        assert_eq!(10, yields(policy, 105));
+    }
+
+    #[test]
+    fn yields_after_interval() {
+        let policy = HandlerYieldPolicy {
+            handlers: None,
+            interval: Some(Duration::from_secs(0)),
+        };
+        assert_eq!(5, yields(policy, 5));
+
+        let policy = HandlerYieldPolicy {
+            handlers: None,
+            interval: Some(Duration::from_secs(3600)),
+        };
+        assert_eq!(0, yields(policy, 100));
+    }
+
+    #[test]
+    fn never_yields_without_limits() {
+        assert_eq!(0, yields(HandlerYieldPolicy::default(), 1000));
+    }
+
+    /// Acts like a block with `handlers` handlers that each update the same
+    /// entity, recording the order in which the handlers run.
+    async fn process_block(
+        policy: HandlerYieldPolicy,
+        id: &'static str,
+        handlers: i32,
+        order: Arc<Mutex<Vec<&'static str>>>,
+    ) -> BlockState {
+        let mut state = BlockState::default().with_handler_yield(policy);
+        for count in 0..handlers {
+            let key = EntityKey {
+                subgraph_id: SubgraphDeploymentId::new("yielding").unwrap(),
+                entity_type: String::from("Counter"),
+                entity_id: String::from(id),
+            };
+            let entity = Entity::from(vec![("id", Value::from(id)), ("count", Value::from(count))]);
+            state.entity_cache.set(key, entity);
+            order.lock().unwrap().push(id);
+            state.handler_yield.handler_invoked().await;
+        }
+        state
+    }
+
+    #[test]
+    fn yielding_preserves_block_state() {
+        let mut runtime = tokio::runtime::Builder::new().enable_all().build().unwrap();
+        let order = Arc::new(Mutex::new(vec![]));
+        let every_handler = HandlerYieldPolicy {
+            handlers: Some(1),
+            interval: None,
+        };
+
+        let (a, b) = runtime.block_on(futures03::future::join(
+            process_block(every_handler, "a", 3, order.clone()),
+            process_block(every_handler, "b", 3, order.clone()),
+        ));
+
+        // Both blocks were processed at the same time
+        assert_eq!(vec!["a", "b", "a", "b", "a", "b"], *order.lock().unwrap());
+        assert_eq!(3, a.handler_yield.yields());
+
+        // And resulted in the same changes as processing them without yielding
+        for (state, id) in vec![(a, "a"), (b, "b")] {
+            let sequential = runtime.block_on(process_block(
+                HandlerYieldPolicy::default(),
+                id,
+                3,
+                Arc::new(Mutex::new(vec![])),
+            ));
+            assert_eq!(0, sequential.handler_yield.yields());
+            assert_eq!(
+                format!("{:?}", sequential.entity_cache),
+                format!("{:?}", state.entity_cache)
+            );
+        }
+    }
 }
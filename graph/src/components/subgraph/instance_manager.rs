@@ -1,6 +1,242 @@
+use failure::Error;
+use lazy_static::lazy_static;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::components::ethereum::EthereumBlockPointer;
+use crate::components::store::EntityOperation;
 use crate::components::EventConsumer;
 
-use crate::data::subgraph::SubgraphAssignmentProviderEvent;
+use crate::data::subgraph::{SubgraphAssignmentProviderEvent, SubgraphDeploymentId};
+
+lazy_static! {
+    /// How many blocks a subgraph may be behind the chain head and still be
+    /// considered synced. This is used both for the synced flag of its
+    /// deployment, which pending versions wait for, and as the
+    /// `synced_threshold` of the `SubgraphSyncPolicy` subgraphs run with.
+    pub static ref SUBGRAPH_SYNCED_BLOCK_THRESHOLD: u64 =
+        std::env::var("GRAPH_SUBGRAPH_SYNCED_BLOCK_THRESHOLD")
+            .unwrap_or("0".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_SUBGRAPH_SYNCED_BLOCK_THRESHOLD");
+}
+
+/// Whether a subgraph that has processed `subgraph_ptr` is at most
+/// `threshold` blocks behind the chain `head`.
+pub fn is_within_synced_threshold(
+    subgraph_ptr: EthereumBlockPointer,
+    head: EthereumBlockPointer,
+    threshold: u64,
+) -> bool {
+    head.number.saturating_sub(subgraph_ptr.number) <= threshold
+}
+
+/// Restarts of a subgraph after non-deterministic failures, e.g. when an
+/// Ethereum node or the store was temporarily unavailable.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SubgraphRestartStatus {
+    /// The number of restarts since the subgraph last processed a block
+    /// successfully.
+    pub retries: u32,
+
+    /// When the subgraph will be restarted next, if it is currently waiting
+    /// to be restarted.
+    pub next_retry_at: Option<SystemTime>,
+}
+
+/// A subgraph that is assigned to and started by this node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunningSubgraph {
+    pub deployment_id: SubgraphDeploymentId,
+
+    /// The last block the subgraph has processed, or `None` if it hasn't
+    /// processed any block yet.
+    pub block: Option<EthereumBlockPointer>,
+
+    /// Whether the subgraph is synced, as reported by `SubgraphSyncEvent`s.
+    pub synced: bool,
+
+    /// Whether the subgraph was paused and not resumed yet. The subgraph
+    /// emits `SubgraphPauseEvent`s once it actually stops and continues
+    /// processing blocks.
+    pub paused: bool,
+
+    /// Whether indexing stopped because the subgraph failed. Failed
+    /// subgraphs are listed until they are stopped.
+    pub failed: bool,
+
+    pub restart_status: SubgraphRestartStatus,
+
+    /// The error that last made the subgraph restart or fail.
+    pub last_error: Option<String>,
+}
+
+/// The entity operations of the block that a subgraph is processing, as of
+/// the last handler that finished.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingBlockOperations {
+    pub block: EthereumBlockPointer,
+    pub operations: Vec<EntityOperation>,
+}
+
+/// Signals that a subgraph started or stopped tracking the chain head.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SubgraphSyncEvent {
+    /// The subgraph caught up with the chain head, either for the first
+    /// time since it was started or after it was `Unsynced`.
+    Synced {
+        deployment_id: SubgraphDeploymentId,
+        head: EthereumBlockPointer,
+        timestamp: SystemTime,
+    },
+
+    /// The synced subgraph fell behind the chain head by more than the
+    /// unsynced threshold, and has stayed behind for the debounce period.
+    Unsynced {
+        deployment_id: SubgraphDeploymentId,
+        subgraph_ptr: EthereumBlockPointer,
+        head: EthereumBlockPointer,
+        timestamp: SystemTime,
+    },
+}
+
+/// Receives the `SubgraphSyncEvent`s of all subgraphs.
+pub trait SubgraphSyncSubscriber: Send + Sync + 'static {
+    fn event(&self, event: &SubgraphSyncEvent);
+}
+
+impl<F> SubgraphSyncSubscriber for F
+where
+    F: Fn(&SubgraphSyncEvent) + Send + Sync + 'static,
+{
+    fn event(&self, event: &SubgraphSyncEvent) {
+        self(event)
+    }
+}
+
+/// Signals that a subgraph stopped or continued processing blocks because it
+/// was paused or resumed with `SubgraphInstanceManager::pause_subgraph` and
+/// `SubgraphInstanceManager::resume_subgraph`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SubgraphPauseEvent {
+    /// The subgraph stopped processing blocks. `block` is the last block it
+    /// processed, or `None` if it hasn't processed any block yet.
+    Paused {
+        deployment_id: SubgraphDeploymentId,
+        block: Option<EthereumBlockPointer>,
+        timestamp: SystemTime,
+    },
+
+    /// The subgraph continues processing blocks after `block`, the block at
+    /// which it was paused.
+    Resumed {
+        deployment_id: SubgraphDeploymentId,
+        block: Option<EthereumBlockPointer>,
+        timestamp: SystemTime,
+    },
+}
+
+/// Receives the `SubgraphPauseEvent`s of all subgraphs.
+pub trait SubgraphPauseSubscriber: Send + Sync + 'static {
+    fn event(&self, event: &SubgraphPauseEvent);
+}
+
+impl<F> SubgraphPauseSubscriber for F
+where
+    F: Fn(&SubgraphPauseEvent) + Send + Sync + 'static,
+{
+    fn event(&self, event: &SubgraphPauseEvent) {
+        self(event)
+    }
+}
+
+/// When a subgraph counts as synced or unsynced.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SubgraphSyncPolicy {
+    /// How many blocks a subgraph may be behind the chain head and become
+    /// synced.
+    pub synced_threshold: u64,
+
+    /// How many blocks a synced subgraph may fall behind the chain head
+    /// before it becomes unsynced. Thresholds below `synced_threshold` are
+    /// treated as `synced_threshold`.
+    pub unsynced_threshold: u64,
+
+    /// How long a synced subgraph has to stay behind by more than
+    /// `unsynced_threshold` before it becomes unsynced, so that a slow block
+    /// doesn't make it flap between synced and unsynced.
+    pub debounce: Duration,
+}
+
+/// Tracks whether a subgraph is synced, deciding when `SubgraphSyncEvent`s
+/// are emitted.
+#[derive(Debug)]
+pub struct SubgraphSyncTracker {
+    deployment_id: SubgraphDeploymentId,
+    policy: SubgraphSyncPolicy,
+    synced: bool,
+
+    /// Since when a synced subgraph has been behind by more than the
+    /// unsynced threshold.
+    behind_since: Option<Instant>,
+}
+
+impl SubgraphSyncTracker {
+    pub fn new(deployment_id: SubgraphDeploymentId, policy: SubgraphSyncPolicy) -> Self {
+        SubgraphSyncTracker {
+            deployment_id,
+            policy,
+            synced: false,
+            behind_since: None,
+        }
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Updates the state with the block the subgraph has processed and the
+    /// chain head at time `now`, returning the event for a change of state.
+    pub fn update(
+        &mut self,
+        subgraph_ptr: EthereumBlockPointer,
+        head: EthereumBlockPointer,
+        now: Instant,
+    ) -> Option<SubgraphSyncEvent> {
+        if !self.synced {
+            if !is_within_synced_threshold(subgraph_ptr, head, self.policy.synced_threshold) {
+                return None;
+            }
+            self.synced = true;
+            return Some(SubgraphSyncEvent::Synced {
+                deployment_id: self.deployment_id.clone(),
+                head,
+                timestamp: SystemTime::now(),
+            });
+        }
+
+        let unsynced_threshold = self
+            .policy
+            .unsynced_threshold
+            .max(self.policy.synced_threshold);
+        if is_within_synced_threshold(subgraph_ptr, head, unsynced_threshold) {
+            self.behind_since = None;
+            return None;
+        }
+
+        let behind_since = *self.behind_since.get_or_insert(now);
+        if now.duration_since(behind_since) < self.policy.debounce {
+            return None;
+        }
+        self.synced = false;
+        self.behind_since = None;
+        Some(SubgraphSyncEvent::Unsynced {
+            deployment_id: self.deployment_id.clone(),
+            subgraph_ptr,
+            head,
+            timestamp: SystemTime::now(),
+        })
+    }
+}
 
 /// A `SubgraphInstanceManager` loads and manages subgraph instances.
 ///
@@ -8,4 +244,143 @@ use crate::data::subgraph::SubgraphAssignmentProviderEvent;
 /// When a subgraph is added, the subgraph instance manager creates and starts
 /// a subgraph instances for the subgraph. When a subgraph is removed, the
 /// subgraph instance manager stops and removes the corresponding instance.
-pub trait SubgraphInstanceManager: EventConsumer<SubgraphAssignmentProviderEvent> {}
+pub trait SubgraphInstanceManager: EventConsumer<SubgraphAssignmentProviderEvent> {
+    /// Stops processing blocks for a running subgraph while keeping its
+    /// instance and in-memory state around. Fails if the subgraph is not
+    /// running.
+    fn pause_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), Error>;
+
+    /// Continues processing blocks for a paused subgraph, starting with the
+    /// block at which it was paused. Fails if the subgraph is not running.
+    fn resume_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), Error>;
+
+    /// Returns `true` if the subgraph is running and paused.
+    fn is_subgraph_paused(&self, id: &SubgraphDeploymentId) -> bool;
+
+    /// Returns the restart status of a running subgraph, or `None` if the
+    /// subgraph is not running.
+    fn subgraph_restart_status(&self, id: &SubgraphDeploymentId) -> Option<SubgraphRestartStatus>;
+
+    /// Returns all subgraphs that have been started and not stopped, ordered
+    /// by deployment id. This only reads in-memory state and is cheap.
+    fn running_subgraphs(&self) -> Vec<RunningSubgraph>;
+
+    /// Returns what the block a running subgraph is processing is about to
+    /// write, or `None` if it is not processing a block or capturing pending
+    /// operations is disabled.
+    ///
+    /// Indexing is not interrupted for this: the operations are a copy that
+    /// the subgraph takes after each handler, and that may be behind the
+    /// handler that is currently running.
+    fn pending_block_operations(&self, id: &SubgraphDeploymentId)
+        -> Option<PendingBlockOperations>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ptr(number: u64) -> EthereumBlockPointer {
+        EthereumBlockPointer {
+            hash: Default::default(),
+            number,
+        }
+    }
+
+    fn tracker() -> SubgraphSyncTracker {
+        SubgraphSyncTracker::new(
+            SubgraphDeploymentId::new("syncing").unwrap(),
+            SubgraphSyncPolicy {
+                synced_threshold: 1,
+                unsynced_threshold: 10,
+                debounce: Duration::from_secs(60),
+            },
+        )
+    }
+
+    fn is_synced(event: Option<SubgraphSyncEvent>) -> Option<bool> {
+        event.map(|event| match event {
+            SubgraphSyncEvent::Synced { .. } => true,
+            SubgraphSyncEvent::Unsynced { .. } => false,
+        })
+    }
+
+    #[test]
+    fn synced_threshold_allows_falling_behind_the_head() {
+        assert!(is_within_synced_threshold(ptr(100), ptr(100), 0));
+        assert!(!is_within_synced_threshold(ptr(99), ptr(100), 0));
+        assert!(is_within_synced_threshold(ptr(95), ptr(100), 5));
+        assert!(!is_within_synced_threshold(ptr(94), ptr(100), 5));
+
+        // The head may lag behind the block the subgraph has processed
+        assert!(is_within_synced_threshold(ptr(101), ptr(100), 0));
+    }
+
+    #[test]
+    fn synced_once_caught_up() {
+        let mut tracker = tracker();
+        let now = Instant::now();
+
+        assert_eq!(None, is_synced(tracker.update(ptr(10), ptr(100), now)));
+        assert_eq!(None, is_synced(tracker.update(ptr(98), ptr(100), now)));
+        assert_eq!(
+            Some(true),
+            is_synced(tracker.update(ptr(99), ptr(100), now))
+        );
+        assert!(tracker.is_synced());
+
+        // Only once
+        assert_eq!(None, is_synced(tracker.update(ptr(100), ptr(100), now)));
+        assert_eq!(None, is_synced(tracker.update(ptr(101), ptr(101), now)));
+    }
+
+    #[test]
+    fn unsynced_after_debounce() {
+        let mut tracker = tracker();
+        let now = Instant::now();
+        let later = |secs| now + Duration::from_secs(secs);
+
+        tracker.update(ptr(100), ptr(100), now);
+
+        // Falling behind by less than the threshold, or only briefly, doesn't
+        // count as unsynced
+        assert_eq!(
+            None,
+            is_synced(tracker.update(ptr(100), ptr(110), later(0)))
+        );
+        assert_eq!(
+            None,
+            is_synced(tracker.update(ptr(100), ptr(111), later(0)))
+        );
+        assert_eq!(
+            None,
+            is_synced(tracker.update(ptr(100), ptr(120), later(59)))
+        );
+        assert_eq!(
+            None,
+            is_synced(tracker.update(ptr(115), ptr(120), later(60)))
+        );
+        assert_eq!(
+            None,
+            is_synced(tracker.update(ptr(115), ptr(130), later(61)))
+        );
+        assert!(tracker.is_synced());
+
+        // Staying behind does
+        assert_eq!(
+            Some(false),
+            is_synced(tracker.update(ptr(115), ptr(140), later(121)))
+        );
+        assert!(!tracker.is_synced());
+        assert_eq!(
+            None,
+            is_synced(tracker.update(ptr(120), ptr(140), later(200)))
+        );
+
+        // And catching up again makes it synced again
+        assert_eq!(
+            Some(true),
+            is_synced(tracker.update(ptr(139), ptr(140), later(201)))
+        );
+    }
+}
@@ -8,12 +8,27 @@ mod registrar;
 
 pub use crate::prelude::Entity;
 
-pub use self::host::{HostMetrics, RuntimeHost, RuntimeHostBuilder};
-pub use self::instance::{BlockState, DataSourceTemplateInfo, SubgraphInstance};
-pub use self::instance_manager::SubgraphInstanceManager;
+pub use self::host::{
+    is_deterministic_handler_error, HandlerErrorBudget, HandlerErrorBudgetExceeded,
+    HandlerTimeoutError, HostMetrics, NonDeterministicHostError, RuntimeHost, RuntimeHostBuilder,
+};
+pub use self::instance::{
+    BlockState, DataSourceTemplateInfo, HandlerYield, HandlerYieldPolicy, SubgraphInstance,
+    TriggerOrdering,
+};
+pub use self::instance_manager::{
+    is_within_synced_threshold, PendingBlockOperations, RunningSubgraph, SubgraphInstanceManager,
+    SubgraphPauseEvent, SubgraphPauseSubscriber, SubgraphRestartStatus, SubgraphSyncEvent,
+    SubgraphSyncPolicy, SubgraphSyncSubscriber, SubgraphSyncTracker,
+    SUBGRAPH_SYNCED_BLOCK_THRESHOLD,
+};
 pub use self::loader::DataSourceLoader;
 pub use self::proof_of_indexing::{
-    ProofOfIndexing, ProofOfIndexingDigest, ProofOfIndexingEvent, ProofOfIndexingStream,
+    combine_proofs_of_indexing, compare_poi, proof_of_indexing_checkpoints, ProofOfIndexing,
+    ProofOfIndexingCheckpoint, ProofOfIndexingDigest, ProofOfIndexingDivergence,
+    ProofOfIndexingEvent, ProofOfIndexingFold, ProofOfIndexingHealth, ProofOfIndexingRecord,
+    ProofOfIndexingRecorder, ProofOfIndexingStream, ProofOfIndexingSubscriber,
+    ProofOfIndexingVersion, RecordedProofOfIndexingEvent,
 };
 pub use self::provider::SubgraphAssignmentProvider;
 pub use self::registrar::{SubgraphRegistrar, SubgraphVersionSwitchingMode};
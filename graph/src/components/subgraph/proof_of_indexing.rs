@@ -1,20 +1,120 @@
-use crate::prelude::Value;
-use stable_hash::{prelude::*, utils::StableHasherWrapper, SequenceNumberInt};
+use crate::prelude::{EthereumBlockPointer, SubgraphDeploymentId, Value};
+use stable_hash::{
+    prelude::*,
+    utils::{stable_hash_with_hasher, StableHasherWrapper},
+    SequenceNumberInt,
+};
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::{Arc, Mutex};
 use strum::AsStaticRef as _;
 use strum_macros::AsStaticStr;
 use twox_hash::XxHash64;
 
-#[derive(Debug)]
+/// The format in which a proof of indexing digest is computed. Digests in
+/// any format other than `Legacy` are prefixed with their version tag, e.g.
+/// `v1:`, so that the format can be recovered from the digest itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProofOfIndexingVersion {
+    /// The original, untagged format.
+    Legacy,
+    /// Like `Legacy`, but the version is part of the hashed tuple and the
+    /// digest is tagged with it.
+    V1,
+}
+
+impl ProofOfIndexingVersion {
+    /// The version used for subgraphs that have no proof of indexing yet.
+    pub const CURRENT: ProofOfIndexingVersion = ProofOfIndexingVersion::V1;
+
+    fn tag(self) -> Option<&'static str> {
+        match self {
+            ProofOfIndexingVersion::Legacy => None,
+            ProofOfIndexingVersion::V1 => Some("v1"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ProofOfIndexingDigest(pub String);
 
+impl ProofOfIndexingDigest {
+    /// The format version the digest was computed in.
+    pub fn version(&self) -> ProofOfIndexingVersion {
+        if self.0.starts_with("v1:") {
+            ProofOfIndexingVersion::V1
+        } else {
+            ProofOfIndexingVersion::Legacy
+        }
+    }
+}
+
 impl StableHash for ProofOfIndexingDigest {
     fn stable_hash(&self, sequence_number: impl SequenceNumber, state: &mut impl StableHasher) {
         self.0.stable_hash(sequence_number, state)
     }
 }
 
+/// Whether the proof of indexing a subgraph has stored for its current head
+/// block is consistent with the one computed from its history.
+///
+/// Both digests are read from the PoI entities: the stored one from their
+/// latest versions, the computed one from the versions visible as of the
+/// head block. The check therefore detects PoI entities whose block ranges
+/// disagree with the head block of the subgraph, e.g. after an incomplete
+/// revert, but not digests that are wrong for the events that were indexed,
+/// since the events themselves are not stored.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProofOfIndexingHealth {
+    /// The subgraph has not processed any block yet, so there is nothing to
+    /// verify.
+    NotStarted,
+
+    /// The subgraph was deployed without a proof of indexing.
+    Unsupported,
+
+    /// The stored and the computed digest for the head block agree.
+    Healthy {
+        block_number: u64,
+        digest: ProofOfIndexingDigest,
+    },
+
+    /// The digests disagree: the latest PoI entities are not the ones of
+    /// the head block, which indicates corruption of the store or a bug in
+    /// reverting blocks.
+    Mismatch {
+        block_number: u64,
+        stored: ProofOfIndexingDigest,
+        computed: ProofOfIndexingDigest,
+    },
+
+    /// The subgraph processed blocks while every attempt to check it was
+    /// running, so the digests could not be compared. `block_number` is the
+    /// last head block that was seen.
+    Advancing { block_number: u64 },
+}
+
+impl ProofOfIndexingHealth {
+    /// Whether nothing is known to be wrong. Subgraphs that have not started
+    /// or that have no proof of indexing are considered healthy.
+    pub fn is_healthy(&self) -> bool {
+        match self {
+            ProofOfIndexingHealth::Mismatch { .. } => false,
+            _ => true,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProofOfIndexingHealth::NotStarted => "not_started",
+            ProofOfIndexingHealth::Unsupported => "unsupported",
+            ProofOfIndexingHealth::Healthy { .. } => "healthy",
+            ProofOfIndexingHealth::Mismatch { .. } => "mismatch",
+            ProofOfIndexingHealth::Advancing { .. } => "advancing",
+        }
+    }
+}
+
 #[derive(AsStaticStr)]
 pub enum ProofOfIndexingEvent<'a> {
     RemoveEntity {
@@ -51,26 +151,220 @@ impl StableHash for ProofOfIndexingEvent<'_> {
     }
 }
 
+/// Receives every event that is written to a proof of indexing, without
+/// affecting the digest. This allows tooling to reconstruct which entity
+/// changes contributed to the proof of indexing of a block.
+pub trait ProofOfIndexingSubscriber: Send + Sync + 'static {
+    fn event(
+        &self,
+        deployment_id: &SubgraphDeploymentId,
+        block: &EthereumBlockPointer,
+        causality_region: &str,
+        event: &ProofOfIndexingEvent<'_>,
+    );
+}
+
+impl<F> ProofOfIndexingSubscriber for F
+where
+    F: Fn(&SubgraphDeploymentId, &EthereumBlockPointer, &str, &ProofOfIndexingEvent<'_>)
+        + Send
+        + Sync
+        + 'static,
+{
+    fn event(
+        &self,
+        deployment_id: &SubgraphDeploymentId,
+        block: &EthereumBlockPointer,
+        causality_region: &str,
+        event: &ProofOfIndexingEvent<'_>,
+    ) {
+        self(deployment_id, block, causality_region, event)
+    }
+}
+
+/// An owned copy of a `ProofOfIndexingEvent`, together with the block and
+/// causality region it was recorded for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProofOfIndexingRecord {
+    pub block: EthereumBlockPointer,
+    pub causality_region: String,
+    pub event: RecordedProofOfIndexingEvent,
+}
+
+#[derive(Clone, Debug, PartialEq, AsStaticStr)]
+pub enum RecordedProofOfIndexingEvent {
+    RemoveEntity {
+        entity_type: String,
+        id: String,
+    },
+    SetEntity {
+        entity_type: String,
+        id: String,
+        data: HashMap<String, Value>,
+    },
+}
+
+impl RecordedProofOfIndexingEvent {
+    pub fn entity_type(&self) -> &str {
+        match self {
+            Self::RemoveEntity { entity_type, .. } | Self::SetEntity { entity_type, .. } => {
+                entity_type
+            }
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        match self {
+            Self::RemoveEntity { id, .. } | Self::SetEntity { id, .. } => id,
+        }
+    }
+
+    /// The event as it is written to a proof of indexing.
+    pub fn as_event(&self) -> ProofOfIndexingEvent<'_> {
+        match self {
+            Self::RemoveEntity { entity_type, id } => {
+                ProofOfIndexingEvent::RemoveEntity { entity_type, id }
+            }
+            Self::SetEntity {
+                entity_type,
+                id,
+                data,
+            } => ProofOfIndexingEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            },
+        }
+    }
+}
+
+impl From<&ProofOfIndexingEvent<'_>> for RecordedProofOfIndexingEvent {
+    fn from(event: &ProofOfIndexingEvent<'_>) -> Self {
+        match event {
+            ProofOfIndexingEvent::RemoveEntity { entity_type, id } => Self::RemoveEntity {
+                entity_type: entity_type.to_string(),
+                id: id.to_string(),
+            },
+            ProofOfIndexingEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            } => Self::SetEntity {
+                entity_type: entity_type.to_string(),
+                id: id.to_string(),
+                data: (*data).clone(),
+            },
+        }
+    }
+}
+
+/// A subscriber that keeps a record of all events it receives, in order.
+#[derive(Default)]
+pub struct ProofOfIndexingRecorder {
+    records: Mutex<Vec<ProofOfIndexingRecord>>,
+}
+
+impl ProofOfIndexingRecorder {
+    /// Returns the events recorded so far and clears the recorder.
+    pub fn take(&self) -> Vec<ProofOfIndexingRecord> {
+        std::mem::replace(&mut *self.records.lock().unwrap(), vec![])
+    }
+}
+
+impl ProofOfIndexingSubscriber for ProofOfIndexingRecorder {
+    fn event(
+        &self,
+        _deployment_id: &SubgraphDeploymentId,
+        block: &EthereumBlockPointer,
+        causality_region: &str,
+        event: &ProofOfIndexingEvent<'_>,
+    ) {
+        self.records.lock().unwrap().push(ProofOfIndexingRecord {
+            block: *block,
+            causality_region: causality_region.to_owned(),
+            event: event.into(),
+        });
+    }
+}
+
+/// The first position at which two sequences of proof of indexing events
+/// differ.
+#[derive(Debug, PartialEq)]
+pub struct ProofOfIndexingDivergence {
+    /// The position of the events in both sequences.
+    pub index: usize,
+    pub left: ProofOfIndexingRecord,
+    pub right: ProofOfIndexingRecord,
+}
+
+impl fmt::Display for ProofOfIndexingDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "entity `{}` with id `{}` diverged at block {} in causality region `{}`, \
+             event type {} (other side: {} of `{}` with id `{}` at block {})",
+            self.left.event.entity_type(),
+            self.left.event.id(),
+            self.left.block.number,
+            self.left.causality_region,
+            self.left.event.as_static(),
+            self.right.event.as_static(),
+            self.right.event.entity_type(),
+            self.right.event.id(),
+            self.right.block.number,
+        )
+    }
+}
+
+/// Compares two sequences of recorded proof of indexing events, e.g. from
+/// two nodes that disagree on a digest, and returns the first event at which
+/// they differ. Returns `None` if the sequences are identical up to the
+/// length of the shorter one.
+pub fn compare_poi(
+    left: impl IntoIterator<Item = ProofOfIndexingRecord>,
+    right: impl IntoIterator<Item = ProofOfIndexingRecord>,
+) -> Option<ProofOfIndexingDivergence> {
+    left.into_iter()
+        .zip(right)
+        .enumerate()
+        .find(|(_, (left, right))| left != right)
+        .map(|(index, (left, right))| ProofOfIndexingDivergence { index, left, right })
+}
+
+/// A subscriber together with the block whose events it receives.
+struct ProofOfIndexingTap {
+    deployment_id: SubgraphDeploymentId,
+    block: EthereumBlockPointer,
+    subscriber: Arc<dyn ProofOfIndexingSubscriber>,
+}
+
 /// The POI is the StableHash of:
 /// (Vec<ProofOfIndexingEvent>, PreviousDigest)
+/// or, from `ProofOfIndexingVersion::V1` on:
+/// (Vec<ProofOfIndexingEvent>, PreviousDigest, Version)
 /// This struct contains the necessary state to construct that value in a streaming manner
 pub struct ProofOfIndexingStream {
     previous_digest_sequence_number: SequenceNumberInt<u64>,
+    version_sequence_number: SequenceNumberInt<u64>,
     vec_sequence_number: SequenceNumberInt<u64>,
     vec_length: usize,
     digest: StableHasherWrapper<XxHash64>,
+    version: Option<ProofOfIndexingVersion>,
 }
 
 impl ProofOfIndexingStream {
-    fn new() -> Self {
+    fn new(version: Option<ProofOfIndexingVersion>) -> Self {
         let mut tuple_sequence_number = SequenceNumberInt::<u64>::root();
         let vec_sequence_number = tuple_sequence_number.next_child();
         let previous_digest_sequence_number = tuple_sequence_number.next_child();
+        let version_sequence_number = tuple_sequence_number.next_child();
         Self {
             previous_digest_sequence_number,
+            version_sequence_number,
             vec_sequence_number,
             vec_length: 0,
             digest: Default::default(),
+            version,
         }
     }
 
@@ -79,23 +373,155 @@ impl ProofOfIndexingStream {
         self.vec_length += 1;
     }
 
+    /// Computes the digest in the version the stream was created with. If no
+    /// version was given, the digest continues in the version of `previous`,
+    /// so that existing subgraphs keep the format they started with.
     pub fn finish(self, previous: &Option<ProofOfIndexingDigest>) -> ProofOfIndexingDigest {
         let Self {
             previous_digest_sequence_number,
+            version_sequence_number,
             vec_sequence_number,
             vec_length,
             mut digest,
+            version,
         } = self;
 
+        let version = version.unwrap_or_else(|| {
+            previous
+                .as_ref()
+                .map_or(ProofOfIndexingVersion::CURRENT, |previous| {
+                    previous.version()
+                })
+        });
+
         // Finish out the vec digest
         vec_length.stable_hash(vec_sequence_number, &mut digest);
 
         // Add the previous digest to the end of the tuple
         previous.stable_hash(previous_digest_sequence_number, &mut digest);
 
-        let hash = format!("{:x}", digest.finish());
-        ProofOfIndexingDigest(hash)
+        match version.tag() {
+            None => ProofOfIndexingDigest(format!("{:x}", digest.finish())),
+            Some(tag) => {
+                tag.stable_hash(version_sequence_number, &mut digest);
+                ProofOfIndexingDigest(format!("{}:{:x}", tag, digest.finish()))
+            }
+        }
+    }
+}
+
+/// Combines the digests of the causality regions of a subgraph into the
+/// proof of indexing of the subgraph.
+pub fn combine_proofs_of_indexing(
+    by_causality_region: &HashMap<String, ProofOfIndexingDigest>,
+) -> ProofOfIndexingDigest {
+    let hash = stable_hash_with_hasher::<XxHash64, _>(by_causality_region);
+    ProofOfIndexingDigest(format!("{:x}", hash))
+}
+
+/// The digests of all causality regions of a subgraph after a block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProofOfIndexingCheckpoint {
+    pub block: EthereumBlockPointer,
+    pub digests: HashMap<String, ProofOfIndexingDigest>,
+}
+
+impl ProofOfIndexingCheckpoint {
+    /// The proof of indexing of the subgraph as of `block`.
+    pub fn digest(&self) -> ProofOfIndexingDigest {
+        combine_proofs_of_indexing(&self.digests)
+    }
+}
+
+/// Computes the proof of indexing of a subgraph from its recorded events,
+/// e.g. to verify its entire history. Events are folded into the digests one
+/// at a time, so that memory use depends on the number of causality regions
+/// but not on the number of events.
+///
+/// Events must be written in the order in which they were recorded. Since
+/// the digests of a block are finished once the first event of a different
+/// block is written, events of a block must not be interleaved with those of
+/// other blocks.
+#[derive(Default)]
+pub struct ProofOfIndexingFold {
+    /// The digests as of the last finished block.
+    digests: HashMap<String, ProofOfIndexingDigest>,
+    /// The block whose events are being written, if any.
+    block: Option<EthereumBlockPointer>,
+    pending: HashMap<String, ProofOfIndexingStream>,
+    version: Option<ProofOfIndexingVersion>,
+}
+
+impl ProofOfIndexingFold {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Continues from the digests of a checkpoint instead of from scratch.
+    pub fn resume(checkpoint: ProofOfIndexingCheckpoint) -> Self {
+        Self {
+            digests: checkpoint.digests,
+            ..Self::default()
+        }
+    }
+
+    /// Computes all digests in `version`; see
+    /// `ProofOfIndexing::with_version`.
+    pub fn with_version(mut self, version: ProofOfIndexingVersion) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Folds `record` into the digest of its causality region. Returns the
+    /// checkpoint of the previous block if `record` is the first event of a
+    /// new block.
+    pub fn write(&mut self, record: &ProofOfIndexingRecord) -> Option<ProofOfIndexingCheckpoint> {
+        let checkpoint = match self.block {
+            Some(block) if block != record.block => self.end_block(),
+            _ => None,
+        };
+        self.block = Some(record.block);
+
+        let version = self.version;
+        self.pending
+            .entry(record.causality_region.clone())
+            .or_insert_with(|| ProofOfIndexingStream::new(version))
+            .write(&record.event.as_event());
+
+        checkpoint
     }
+
+    /// Finishes the digests of the current block and returns its checkpoint,
+    /// or `None` if no events were written since the last checkpoint.
+    pub fn end_block(&mut self) -> Option<ProofOfIndexingCheckpoint> {
+        let block = self.block.take()?;
+        for (causality_region, stream) in self.pending.drain() {
+            let previous = self.digests.remove(&causality_region);
+            self.digests
+                .insert(causality_region, stream.finish(&previous));
+        }
+        Some(ProofOfIndexingCheckpoint {
+            block,
+            digests: self.digests.clone(),
+        })
+    }
+}
+
+/// Lazily folds `records` and yields a checkpoint for every block that has
+/// events, without buffering more than one event.
+pub fn proof_of_indexing_checkpoints(
+    records: impl IntoIterator<Item = ProofOfIndexingRecord>,
+) -> impl Iterator<Item = ProofOfIndexingCheckpoint> {
+    let mut records = records.into_iter().fuse();
+    let mut fold = ProofOfIndexingFold::new();
+    std::iter::from_fn(move || {
+        for record in &mut records {
+            if let Some(checkpoint) = fold.write(&record) {
+                return Some(checkpoint);
+            }
+        }
+        fold.end_block()
+    })
 }
 
 #[derive(Default)]
@@ -105,6 +531,14 @@ pub struct ProofOfIndexing {
     /// state with other data sources. This may also give us some freedom to change
     /// the order of triggers in the future.
     per_causality_region: HashMap<String, ProofOfIndexingStream>,
+
+    /// Receives a copy of every event that is written; `None` unless a
+    /// subscriber is attached.
+    tap: Option<ProofOfIndexingTap>,
+
+    /// The format version to compute digests in; see
+    /// `ProofOfIndexingStream::finish` for what `None` means.
+    version: Option<ProofOfIndexingVersion>,
 }
 
 impl fmt::Debug for ProofOfIndexing {
@@ -114,13 +548,43 @@ impl fmt::Debug for ProofOfIndexing {
 }
 
 impl ProofOfIndexing {
+    /// Creates a proof of indexing for `block` that passes every event
+    /// written to it on to `subscriber`.
+    pub fn with_subscriber(
+        deployment_id: SubgraphDeploymentId,
+        block: EthereumBlockPointer,
+        subscriber: Arc<dyn ProofOfIndexingSubscriber>,
+    ) -> Self {
+        Self {
+            per_causality_region: HashMap::new(),
+            tap: Some(ProofOfIndexingTap {
+                deployment_id,
+                block,
+                subscriber,
+            }),
+            version: None,
+        }
+    }
+
+    /// Computes all digests in `version`, e.g. to verify the proof of
+    /// indexing of a subgraph that was indexed with an older version.
+    pub fn with_version(mut self, version: ProofOfIndexingVersion) -> Self {
+        self.version = Some(version);
+        self
+    }
+
     /// Adds an event to the digest of the ProofOfIndexingStream local to the DataSource
     pub fn write(&mut self, causality_region: &str, event: &ProofOfIndexingEvent<'_>) {
+        if let Some(tap) = &self.tap {
+            tap.subscriber
+                .event(&tap.deployment_id, &tap.block, causality_region, event);
+        }
+
         // This may be better with the raw_entry API, once that is stabilized
         if let Some(data_source) = self.per_causality_region.get_mut(causality_region) {
             data_source.write(event);
         } else {
-            let mut entry = ProofOfIndexingStream::new();
+            let mut entry = ProofOfIndexingStream::new(self.version);
             entry.write(event);
             self.per_causality_region
                 .insert(causality_region.to_owned(), entry);
@@ -140,3 +604,238 @@ impl ProofOfIndexing {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::types::H256;
+
+    fn digest(mut proof_of_indexing: ProofOfIndexing) -> String {
+        let data = HashMap::new();
+        proof_of_indexing.write(
+            "region",
+            &ProofOfIndexingEvent::SetEntity {
+                entity_type: "Thing",
+                id: "one",
+                data: &data,
+            },
+        );
+        proof_of_indexing.write(
+            "region",
+            &ProofOfIndexingEvent::RemoveEntity {
+                entity_type: "Thing",
+                id: "two",
+            },
+        );
+        let stream = proof_of_indexing.take().unwrap().remove("region").unwrap();
+        stream.finish(&None).0
+    }
+
+    #[test]
+    fn subscriber_receives_events_without_changing_digest() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_by_subscriber = received.clone();
+        let subscriber = move |_: &SubgraphDeploymentId,
+                               block: &EthereumBlockPointer,
+                               causality_region: &str,
+                               event: &ProofOfIndexingEvent<'_>| {
+            received_by_subscriber.lock().unwrap().push(format!(
+                "{} {} {}",
+                block.number,
+                causality_region,
+                event.as_static()
+            ));
+        };
+
+        let tapped = ProofOfIndexing::with_subscriber(
+            SubgraphDeploymentId::new("poi").unwrap(),
+            EthereumBlockPointer::from((H256::zero(), 7u64)),
+            Arc::new(subscriber),
+        );
+
+        assert_eq!(digest(tapped), digest(ProofOfIndexing::default()));
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec!["7 region SetEntity", "7 region RemoveEntity"]
+        );
+    }
+
+    fn record(names: &[&str]) -> Vec<ProofOfIndexingRecord> {
+        let recorder = Arc::new(ProofOfIndexingRecorder::default());
+        for (number, name) in names.iter().enumerate() {
+            let mut proof_of_indexing = ProofOfIndexing::with_subscriber(
+                SubgraphDeploymentId::new("poi").unwrap(),
+                EthereumBlockPointer::from((H256::zero(), number as u64)),
+                recorder.clone(),
+            );
+            let mut data = HashMap::new();
+            data.insert("name".to_owned(), Value::from(*name));
+            proof_of_indexing.write(
+                "region",
+                &ProofOfIndexingEvent::SetEntity {
+                    entity_type: "Thing",
+                    id: "one",
+                    data: &data,
+                },
+            );
+        }
+        recorder.take()
+    }
+
+    #[test]
+    fn compare_poi_reports_first_divergent_event() {
+        let left = record(&["a", "b", "c", "d"]);
+        let right = record(&["a", "b", "x", "d", "e"]);
+
+        let divergence = compare_poi(left.clone(), right).expect("sequences diverge");
+        assert_eq!(divergence.index, 2);
+        assert_eq!(divergence.left, left[2]);
+        assert_eq!(divergence.left.block.number, 2);
+        assert_eq!(
+            divergence.to_string(),
+            "entity `Thing` with id `one` diverged at block 2 in causality region `region`, \
+             event type SetEntity (other side: SetEntity of `Thing` with id `one` at block 2)"
+        );
+
+        // Sequences that only differ in length do not diverge
+        assert_eq!(compare_poi(left.clone(), left[..3].to_vec()), None);
+    }
+
+    #[test]
+    fn versions_produce_distinguishable_tagged_digests() {
+        let legacy =
+            digest(ProofOfIndexing::default().with_version(ProofOfIndexingVersion::Legacy));
+        let v1 = digest(ProofOfIndexing::default().with_version(ProofOfIndexingVersion::V1));
+
+        assert!(!legacy.contains(':'));
+        assert!(v1.starts_with("v1:"));
+        assert_ne!(&v1["v1:".len()..], legacy);
+        assert_eq!(
+            ProofOfIndexingDigest(legacy).version(),
+            ProofOfIndexingVersion::Legacy
+        );
+        assert_eq!(
+            ProofOfIndexingDigest(v1).version(),
+            ProofOfIndexingVersion::V1
+        );
+    }
+
+    #[test]
+    fn only_mismatched_digests_are_unhealthy() {
+        let digest = ProofOfIndexingDigest("v1:0123abcd".to_owned());
+        let mismatch = ProofOfIndexingHealth::Mismatch {
+            block_number: 3,
+            stored: digest.clone(),
+            computed: ProofOfIndexingDigest("v1:4567cdef".to_owned()),
+        };
+
+        assert!(ProofOfIndexingHealth::NotStarted.is_healthy());
+        assert!(ProofOfIndexingHealth::Unsupported.is_healthy());
+        assert!(ProofOfIndexingHealth::Advancing { block_number: 3 }.is_healthy());
+        assert!(ProofOfIndexingHealth::Healthy {
+            block_number: 3,
+            digest,
+        }
+        .is_healthy());
+        assert!(!mismatch.is_healthy());
+        assert_eq!(mismatch.as_str(), "mismatch");
+    }
+
+    /// Writes the events of every block to a fresh `ProofOfIndexing`, like
+    /// indexing does, and returns the digests after the last block.
+    fn index(records: &[ProofOfIndexingRecord]) -> HashMap<String, ProofOfIndexingDigest> {
+        let mut digests: HashMap<String, ProofOfIndexingDigest> = HashMap::new();
+        let mut blocks: Vec<Vec<&ProofOfIndexingRecord>> = vec![];
+        for record in records {
+            match blocks.last_mut() {
+                Some(block) if block[0].block == record.block => block.push(record),
+                _ => blocks.push(vec![record]),
+            }
+        }
+        for block in blocks {
+            let mut proof_of_indexing = ProofOfIndexing::default();
+            for record in block {
+                proof_of_indexing.write(&record.causality_region, &record.event.as_event());
+            }
+            for (causality_region, stream) in proof_of_indexing.take().unwrap() {
+                let previous = digests.remove(&causality_region);
+                digests.insert(causality_region, stream.finish(&previous));
+            }
+        }
+        digests
+    }
+
+    fn long_history(
+        blocks: u64,
+        events_per_block: u64,
+    ) -> impl Iterator<Item = ProofOfIndexingRecord> {
+        (0..blocks).flat_map(move |number| {
+            (0..events_per_block).map(move |n| ProofOfIndexingRecord {
+                block: EthereumBlockPointer::from((H256::zero(), number)),
+                causality_region: format!("region{}", n % 2),
+                event: RecordedProofOfIndexingEvent::RemoveEntity {
+                    entity_type: "Thing".to_owned(),
+                    id: format!("{}-{}", number, n),
+                },
+            })
+        })
+    }
+
+    #[test]
+    fn fold_matches_the_digests_of_indexing() {
+        let records: Vec<_> = long_history(5, 3).collect();
+        let checkpoints: Vec<_> = proof_of_indexing_checkpoints(records.clone()).collect();
+
+        assert_eq!(checkpoints.len(), 5);
+        assert_eq!(checkpoints[2].block.number, 2);
+        assert_eq!(checkpoints[4].digests, index(&records));
+        assert_eq!(checkpoints[2].digests, index(&records[..3 * 3]));
+
+        // Resuming from a checkpoint gives the same result as starting over
+        let mut fold = ProofOfIndexingFold::resume(checkpoints[2].clone());
+        for record in &records[3 * 3..] {
+            fold.write(record);
+        }
+        assert_eq!(fold.end_block(), Some(checkpoints[4].clone()));
+        assert_eq!(fold.end_block(), None);
+    }
+
+    #[test]
+    fn fold_does_not_buffer_events() {
+        const EVENTS_PER_BLOCK: u64 = 10;
+
+        let pulled = std::cell::Cell::new(0u64);
+        let records =
+            long_history(20_000, EVENTS_PER_BLOCK).inspect(|_| pulled.set(pulled.get() + 1));
+
+        let mut last = None;
+        for checkpoint in proof_of_indexing_checkpoints(records) {
+            // At most the first event of the next block has been read ahead
+            let number = checkpoint.block.number;
+            assert!(pulled.get() <= (number + 1) * EVENTS_PER_BLOCK + 1);
+            assert_eq!(checkpoint.digests.len(), 2);
+            last = Some(checkpoint);
+        }
+        assert_eq!(pulled.get(), 20_000 * EVENTS_PER_BLOCK);
+        assert_eq!(last.unwrap().block.number, 19_999);
+    }
+
+    #[test]
+    fn digests_continue_in_the_version_of_the_previous_digest() {
+        let previous = Some(ProofOfIndexingDigest("0123abcd".to_owned()));
+        let mut proof_of_indexing = ProofOfIndexing::default();
+        proof_of_indexing.write(
+            "region",
+            &ProofOfIndexingEvent::RemoveEntity {
+                entity_type: "Thing",
+                id: "one",
+            },
+        );
+        let stream = proof_of_indexing.take().unwrap().remove("region").unwrap();
+
+        assert_eq!(
+            stream.finish(&previous).version(),
+            ProofOfIndexingVersion::Legacy
+        );
+    }
+}
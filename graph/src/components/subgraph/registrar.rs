@@ -4,7 +4,11 @@ use crate::prelude::*;
 
 #[derive(Clone, Copy, Debug)]
 pub enum SubgraphVersionSwitchingMode {
+    /// New versions become current as soon as they are deployed.
     Instant,
+    /// New versions stay pending, while queries go to the current version,
+    /// until their deployment is synced, i.e. at most
+    /// `GRAPH_SUBGRAPH_SYNCED_BLOCK_THRESHOLD` blocks behind the chain head.
     Synced,
 }
 
@@ -7,6 +7,7 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::string::FromUtf8Error;
+use std::time::Duration;
 
 use crate::components::store::StoreError;
 use crate::data::graphql::SerializableValue;
@@ -19,7 +20,10 @@ pub enum QueryExecutionError {
     OperationNotFound(String),
     NotSupported(String),
     NoRootQueryObjectType,
-    NoRootSubscriptionObjectType,
+    NoRootSubscriptionObjectType {
+        deployment: SubgraphDeploymentId,
+        schema_version: String,
+    },
     NonNullError(Pos, String),
     ListValueError(Pos, String),
     NamedTypeError(String),
@@ -28,6 +32,7 @@ pub enum QueryExecutionError {
     MissingArgumentError(Pos, String),
     InvalidVariableTypeError(Pos, String),
     MissingVariableError(Pos, String),
+    InvalidVariableError(Pos, String, String, q::Value),
     ResolveEntityError(SubgraphDeploymentId, String, String, String),
     ResolveEntitiesError(String),
     OrderByNotSupportedError(String, String),
@@ -36,6 +41,7 @@ pub enum QueryExecutionError {
     UnknownField(Pos, String, String),
     EmptyQuery,
     MultipleSubscriptionFields,
+    IntrospectionDisabled(Pos, String),
     SubgraphDeploymentIdError(String),
     RangeArgumentsError(Vec<&'static str>, u32),
     InvalidFilterError,
@@ -47,18 +53,37 @@ pub enum QueryExecutionError {
     EntityParseError(String),
     StoreError(failure::Error),
     Timeout,
+    SubscriptionTimeout {
+        elapsed: Duration,
+    },
+    Overloaded {
+        waited: Duration,
+    },
+    ExecutionBudgetExhausted {
+        spent: Duration,
+        budget: Duration,
+    },
+    FieldTimeout(Pos, String, Duration),
+    Canceled,
     EmptySelectionSet(String),
     AmbiguousDerivedFromResult(Pos, String, String, String),
     Unimplemented(String),
     EnumCoercionError(Pos, String, q::Value, String, Vec<String>),
     ScalarCoercionError(Pos, String, q::Value, String),
-    TooComplex(u64, u64), // (complexity, max_complexity)
-    TooDeep(u8),          // max_depth
+    TooComplex(u64, u64),         // (complexity, max_complexity)
+    TooDeep(u8),                  // max_depth
+    ResultTooLarge(usize, usize), // (size, max_size)
     UndefinedFragment(String),
+    FragmentCycle(Vec<String>),
     // Using slow and prefetch query resolution yield different results
-    IncorrectPrefetchResult { slow: q::Value, prefetch: q::Value },
+    IncorrectPrefetchResult {
+        slow: q::Value,
+        prefetch: q::Value,
+    },
     Panic(String),
+    FieldPanic(String, String),
     EventStreamError,
+    SubscriptionSchemaChanged(String),
     FulltextQueryRequiresFilter,
 }
 
@@ -85,9 +110,12 @@ impl fmt::Display for QueryExecutionError {
             NoRootQueryObjectType => {
                 write!(f, "No root Query type defined in the schema")
             }
-            NoRootSubscriptionObjectType => {
-                write!(f, "No root Subscription type defined in the schema")
-            }
+            NoRootSubscriptionObjectType { deployment, schema_version } => write!(
+                f,
+                "No root Subscription type defined in the schema of deployment `{}` \
+                 (schema version `{}`)",
+                deployment, schema_version
+            ),
             NonNullError(_, s) => {
                 write!(f, "Null value resolved for non-null field `{}`", s)
             }
@@ -112,6 +140,11 @@ impl fmt::Display for QueryExecutionError {
             MissingVariableError(_, s) => {
                 write!(f, "No value provided for required variable `{}`", s)
             }
+            InvalidVariableError(_, s, t, v) => write!(
+                f,
+                "Invalid value provided for variable `{}`: expected a value of type `{}`, got {}",
+                s, t, v
+            ),
             ResolveEntityError(_, entity, id, e) => {
                 write!(f, "Failed to get `{}` entity with ID `{}` from store: {}", entity, id, e)
             }
@@ -135,6 +168,11 @@ impl fmt::Display for QueryExecutionError {
                 f,
                 "Only a single top-level field is allowed in subscriptions"
             ),
+            IntrospectionDisabled(_, field) => write!(
+                f,
+                "Introspection is disabled, the field `{}` is not allowed",
+                field
+            ),
             SubgraphDeploymentIdError(s) => {
                 write!(f, "Failed to get subgraph ID from type: `{}`", s)
             }
@@ -175,6 +213,22 @@ impl fmt::Display for QueryExecutionError {
                 write!(f, "Store error: {}", e)
             }
             Timeout => write!(f, "Query timed out"),
+            SubscriptionTimeout { elapsed } => {
+                write!(f, "Subscription query timed out after {}ms", elapsed.as_millis())
+            }
+            Overloaded { waited } => {
+                write!(f, "Server is overloaded, no capacity to run the query became \
+                           available within {}ms; try again later", waited.as_millis())
+            }
+            ExecutionBudgetExhausted { spent, budget } => {
+                write!(f, "Subscription ended after its queries ran for {}ms in total, \
+                           which exceeds its execution budget of {}ms",
+                       spent.as_millis(), budget.as_millis())
+            }
+            FieldTimeout(_, field, timeout) => {
+                write!(f, "Field `{}` timed out after {}ms", field, timeout.as_millis())
+            }
+            Canceled => write!(f, "Query execution was canceled"),
             EmptySelectionSet(entity_type) => {
                 write!(f, "Selection set for type `{}` is empty", entity_type)
             }
@@ -199,13 +253,20 @@ impl fmt::Display for QueryExecutionError {
                            return smaller collections", complexity, max_complexity)
             }
             TooDeep(max_depth) => write!(f, "query has a depth that exceeds the limit of `{}`", max_depth),
+            ResultTooLarge(size, max_size) => write!(f, "query result has a size of {} bytes which exceeds the limit of {} bytes", size, max_size),
             UndefinedFragment(frag_name) => write!(f, "fragment `{}` is not defined", frag_name),
+            FragmentCycle(path) => write!(f, "fragments must not form cycles, but `{}` do", path.join("` -> `")),
             IncorrectPrefetchResult{ .. } => write!(f, "Running query with prefetch \
                            and slow query resolution yielded different results. \
                            This is a bug. Please open an issue at \
                            https://github.com/graphprotocol/graph-node"),
             Panic(msg) => write!(f, "panic processing query: {}", msg),
+            FieldPanic(field, msg) => {
+                write!(f, "panic processing field `{}`: {}", field, msg)
+            }
             EventStreamError => write!(f, "error in the subscription event stream"),
+            SubscriptionSchemaChanged(deployment) => write!(f, "the subgraph has been deployed with a new schema in deployment `{}`, \
+                           and the subscription is not valid against it", deployment),
             FulltextQueryRequiresFilter => write!(f, "fulltext search queries can only use EntityFilter::Equal"),
         }
     }
@@ -353,10 +414,13 @@ impl Serialize for QueryError {
             | QueryError::ExecutionError(MissingArgumentError(pos, _))
             | QueryError::ExecutionError(InvalidVariableTypeError(pos, _))
             | QueryError::ExecutionError(MissingVariableError(pos, _))
+            | QueryError::ExecutionError(InvalidVariableError(pos, _, _, _))
+            | QueryError::ExecutionError(FieldTimeout(pos, _, _))
             | QueryError::ExecutionError(AmbiguousDerivedFromResult(pos, _, _, _))
             | QueryError::ExecutionError(EnumCoercionError(pos, _, _, _, _))
             | QueryError::ExecutionError(ScalarCoercionError(pos, _, _, _))
-            | QueryError::ExecutionError(UnknownField(pos, _, _)) => {
+            | QueryError::ExecutionError(UnknownField(pos, _, _))
+            | QueryError::ExecutionError(IntrospectionDisabled(pos, _)) => {
                 let mut location = HashMap::new();
                 location.insert("line", pos.line);
                 location.insert("column", pos.column);
@@ -4,4 +4,6 @@ mod result;
 
 pub use self::error::{QueryError, QueryExecutionError};
 pub use self::query::{Query, QueryVariables};
-pub use self::result::QueryResult;
+pub use self::result::{
+    QueryResult, QueryResultCompression, QueryResultEncoding, QueryResultEvent, QueryTraceEntry,
+};
@@ -109,6 +109,9 @@ pub struct Query {
     pub schema: Arc<Schema>,
     pub document: q::Document,
     pub variables: Option<QueryVariables>,
+    /// The name of the operation to execute, required if the document has
+    /// more than one operation.
+    pub operation_name: Option<String>,
     _force_use_of_new: (),
 }
 
@@ -122,7 +125,14 @@ impl Query {
             schema,
             document,
             variables,
+            operation_name: None,
             _force_use_of_new: (),
         }
     }
+
+    /// Selects the operation of the document to execute by its name.
+    pub fn with_operation_name(mut self, operation_name: Option<String>) -> Self {
+        self.operation_name = operation_name;
+        self
+    }
 }
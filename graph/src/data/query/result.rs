@@ -3,29 +3,238 @@ use crate::data::graphql::SerializableValue;
 use graphql_parser::query as q;
 use serde::ser::*;
 use serde::Serialize;
+use std::collections::BTreeMap;
 
-fn serialize_data<S>(data: &Option<q::Value>, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    SerializableValue(data.as_ref().unwrap_or(&q::Value::Null)).serialize(serializer)
+/// Describes the store event a subscription result was produced for.
+/// Serialized as `"extensions": { "event": { ... } }`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryResultEvent {
+    /// The tag of the event; the initial result of a subscription has tag 0.
+    pub tag: usize,
+    /// The number of entity changes in the event.
+    pub changes: usize,
+    /// The number of entity changes in the event for each entity type.
+    pub entity_types: BTreeMap<String, usize>,
+}
+
+/// A store operation that a resolver performed while executing a query.
+/// Serialized as one of the entries of `"extensions": { "trace": [ ... ] }`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryTraceEntry {
+    /// The resolver method that performed the operation, e.g. `resolve_objects`.
+    pub operation: &'static str,
+    /// The response key of the field the operation resolved, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// How long the operation took, in microseconds.
+    pub duration_us: u64,
+}
+
+/// A content encoding that transports can compress serialized results with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueryResultEncoding {
+    Gzip,
+    Deflate,
+}
+
+impl QueryResultEncoding {
+    /// The name of the encoding as used in `Content-Encoding` headers.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QueryResultEncoding::Gzip => "gzip",
+            QueryResultEncoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Tells the transport that a subscription result is large enough to be
+/// worth compressing, and possibly carries the compressed result already.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryResultCompression {
+    /// The encoding to compress the result with.
+    pub encoding: QueryResultEncoding,
+    /// The size of the serialized result before compression, in bytes.
+    pub uncompressed_size: usize,
+    /// The serialized result compressed with `encoding`, if the subscription
+    /// compressed it already. Transports can send these bytes as they are
+    /// instead of serializing the result again.
+    pub bytes: Option<Vec<u8>>,
 }
 
 /// The result of running a query, if successful.
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub struct QueryResult {
-    #[serde(
-        skip_serializing_if = "Option::is_none",
-        serialize_with = "serialize_data"
-    )]
     pub data: Option<q::Value>,
-    #[serde(skip_serializing_if = "Option::is_none")]
     pub errors: Option<Vec<QueryError>>,
+    /// Errors of a subscription result that its subscription doesn't
+    /// consider errors of the result, e.g. because later results may well
+    /// succeed. Serialized as `"extensions": { "warnings": [ ... ] }`.
+    pub warnings: Option<Vec<QueryError>>,
+    /// Set on the result that marks the graceful end of a subscription.
+    /// Serialized as `"extensions": { "completed": true }`.
+    pub completed: bool,
+    /// Set on the result that ends a subscription because the schema of its
+    /// subgraph changed. Serialized as `"extensions": { "schemaChanged": true }`.
+    pub schema_changed: bool,
+    /// Set on the result that ends a subscription because its client
+    /// unsubscribed. Serialized as `"extensions": { "unsubscribed": true }`.
+    pub unsubscribed: bool,
+    /// Set on subscription results if the subscription reports the event
+    /// that caused them.
+    pub event: Option<QueryResultEvent>,
+    /// Set on subscription results if the resolver traces the store
+    /// operations of the query, in the order they were performed.
+    pub trace: Option<Vec<QueryTraceEntry>>,
+    /// Set on subscription results that exceed the compression threshold of
+    /// the subscription. It is not part of the serialized result.
+    pub compression: Option<QueryResultCompression>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+#[derive(Serialize)]
+struct Extensions<'a> {
+    #[serde(skip_serializing_if = "is_false")]
+    completed: bool,
+    #[serde(rename = "schemaChanged", skip_serializing_if = "is_false")]
+    schema_changed: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    unsubscribed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warnings: &'a Option<Vec<QueryError>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: &'a Option<QueryResultEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace: &'a Option<Vec<QueryTraceEntry>>,
+}
+
+impl Serialize for QueryResult {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        if let Some(data) = &self.data {
+            map.serialize_entry("data", &SerializableValue(data))?;
+        }
+        if let Some(errors) = &self.errors {
+            map.serialize_entry("errors", errors)?;
+        }
+        if self.completed
+            || self.schema_changed
+            || self.unsubscribed
+            || self.warnings.is_some()
+            || self.event.is_some()
+            || self.trace.is_some()
+        {
+            map.serialize_entry(
+                "extensions",
+                &Extensions {
+                    completed: self.completed,
+                    schema_changed: self.schema_changed,
+                    unsubscribed: self.unsubscribed,
+                    warnings: &self.warnings,
+                    event: &self.event,
+                    trace: &self.trace,
+                },
+            )?;
+        }
+        map.end()
+    }
 }
 
 impl QueryResult {
     pub fn new(data: Option<q::Value>) -> Self {
-        QueryResult { data, errors: None }
+        QueryResult {
+            data,
+            errors: None,
+            warnings: None,
+            completed: false,
+            schema_changed: false,
+            unsubscribed: false,
+            event: None,
+            trace: None,
+            compression: None,
+        }
+    }
+
+    /// Creates a result with neither data nor errors, used to keep otherwise
+    /// idle subscriptions alive.
+    pub fn keep_alive() -> Self {
+        QueryResult {
+            data: None,
+            errors: None,
+            warnings: None,
+            completed: false,
+            schema_changed: false,
+            unsubscribed: false,
+            event: None,
+            trace: None,
+            compression: None,
+        }
+    }
+
+    /// Whether this is a keep-alive result, i.e. it has neither data nor
+    /// errors nor warnings and doesn't mark the end of a subscription.
+    pub fn is_keep_alive(&self) -> bool {
+        self.data.is_none()
+            && self.errors.is_none()
+            && self.warnings.is_none()
+            && !self.completed
+            && !self.schema_changed
+            && !self.unsubscribed
+    }
+
+    /// Creates the last result of a subscription whose events ended
+    /// gracefully. It has neither data nor errors.
+    pub fn completed() -> Self {
+        QueryResult {
+            data: None,
+            errors: None,
+            warnings: None,
+            completed: true,
+            schema_changed: false,
+            unsubscribed: false,
+            event: None,
+            trace: None,
+            compression: None,
+        }
+    }
+
+    /// Creates the last result of a subscription whose client unsubscribed.
+    /// It has neither data nor errors.
+    pub fn unsubscribed() -> Self {
+        QueryResult {
+            data: None,
+            errors: None,
+            warnings: None,
+            completed: false,
+            schema_changed: false,
+            unsubscribed: true,
+            event: None,
+            trace: None,
+            compression: None,
+        }
+    }
+
+    /// Creates the last result of a subscription that ended because the
+    /// schema of its subgraph changed, with the errors that explain why.
+    pub fn schema_changed(errors: Vec<QueryExecutionError>) -> Self {
+        QueryResult {
+            data: None,
+            errors: Some(errors.into_iter().map(QueryError::from).collect()),
+            warnings: None,
+            completed: false,
+            schema_changed: true,
+            unsubscribed: false,
+            event: None,
+            trace: None,
+            compression: None,
+        }
     }
 }
 
@@ -42,6 +251,13 @@ impl From<Vec<QueryExecutionError>> for QueryResult {
         QueryResult {
             data: None,
             errors: Some(e.into_iter().map(QueryError::from).collect()),
+            warnings: None,
+            completed: false,
+            schema_changed: false,
+            unsubscribed: false,
+            event: None,
+            trace: None,
+            compression: None,
         }
     }
 }
@@ -13,11 +13,12 @@ use graphql_parser::{
 };
 use inflector::Inflector;
 use serde::{Deserialize, Serialize};
+use twox_hash::XxHash64;
 
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
 use std::iter::FromIterator;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -108,6 +109,25 @@ pub enum SchemaValidationError {
     FulltextIncludedFieldMissingRequiredProperty,
     #[fail(display = "Fulltext entity field, {}, not found or not a string", _0)]
     FulltextIncludedFieldInvalid(String),
+    #[fail(display = "@maxFirst directive on `{}` is invalid: {}", _0, _1)]
+    MaxFirstDirectiveInvalid(String, String), // (type or field, reason)
+}
+
+/// Parses the value of a `@maxFirst(value: Int)` directive. The directive
+/// limits the `first` argument for collections of a type, or for a list
+/// field, instead of the global limit.
+pub fn max_first_directive_value(directive: &Directive) -> Result<u32, String> {
+    match directive.argument("value") {
+        Some(Value::Int(n)) => match n.as_i64() {
+            Some(n) if n > 0 && n <= u32::max_value() as i64 => Ok(n as u32),
+            _ => Err(format!(
+                "`value` must be between 1 and {}",
+                u32::max_value()
+            )),
+        },
+        Some(_) => Err("`value` must be an Int".to_owned()),
+        None => Err("the `value` argument is missing".to_owned()),
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -381,6 +401,14 @@ impl Schema {
         }
     }
 
+    /// A short fingerprint of the schema document that tells different
+    /// revisions of a deployment's schema apart, e.g. in error messages.
+    pub fn version(&self) -> String {
+        let mut hasher = XxHash64::with_seed(0);
+        hasher.write(self.document.to_string().as_bytes());
+        format!("{:016x}", hasher.finish())
+    }
+
     pub fn resolve_schema_references<S: Store + SubgraphDeploymentStore>(
         &self,
         store: Arc<S>,
@@ -616,6 +644,7 @@ impl Schema {
         errors.append(&mut self.validate_fields());
         errors.append(&mut self.validate_import_directives());
         errors.append(&mut self.validate_fulltext_directives());
+        errors.append(&mut self.validate_max_first_directives());
         errors.append(&mut self.validate_imported_types(schemas));
         if errors.is_empty() {
             Ok(())
@@ -767,6 +796,55 @@ impl Schema {
             })
     }
 
+    fn validate_max_first_directives(&self) -> Vec<SchemaValidationError> {
+        fn is_list(field_type: &Type) -> bool {
+            match field_type {
+                Type::ListType(_) => true,
+                Type::NonNullType(inner) => is_list(inner),
+                Type::NamedType(_) => false,
+            }
+        }
+
+        let mut errors = vec![];
+        for definition in &self.document.definitions {
+            let (type_name, directives, fields) = match definition {
+                Definition::TypeDefinition(TypeDefinition::Object(t)) => {
+                    (&t.name, &t.directives, &t.fields)
+                }
+                Definition::TypeDefinition(TypeDefinition::Interface(t)) => {
+                    (&t.name, &t.directives, &t.fields)
+                }
+                _ => continue,
+            };
+
+            for directive in directives.iter().filter(|d| d.name == "maxFirst") {
+                if let Err(reason) = max_first_directive_value(directive) {
+                    errors.push(SchemaValidationError::MaxFirstDirectiveInvalid(
+                        type_name.clone(),
+                        reason,
+                    ));
+                }
+            }
+
+            for field in fields {
+                for directive in field.directives.iter().filter(|d| d.name == "maxFirst") {
+                    let location = format!("{}.{}", type_name, field.name);
+                    let result = if is_list(&field.field_type) {
+                        max_first_directive_value(directive).map(|_| ())
+                    } else {
+                        Err("only list fields can have a @maxFirst directive".to_owned())
+                    };
+                    if let Err(reason) = result {
+                        errors.push(SchemaValidationError::MaxFirstDirectiveInvalid(
+                            location, reason,
+                        ));
+                    }
+                }
+            }
+        }
+        errors
+    }
+
     fn validate_fulltext_directive_name(&self, fulltext: &Directive) -> Vec<SchemaValidationError> {
         let name = match fulltext.argument("name") {
             Some(Value::String(name)) => name,
@@ -1419,6 +1497,42 @@ type A @entity {
     assert_eq!(schema.validate_fields().len(), 0);
 }
 
+#[test]
+fn test_max_first_directive_validation() {
+    const ROOT_SCHEMA: &str = r#"
+type A @entity @maxFirst(value: 5000) {
+  id: ID!
+  bs: [B!]! @maxFirst(value: 10)
+  b: B @maxFirst(value: 10)
+}
+
+type B @entity @maxFirst(value: 0) {
+  id: ID!
+  as: [A!]! @maxFirst(value: "ten")
+}"#;
+
+    let document = graphql_parser::parse_schema(ROOT_SCHEMA).expect("Failed to parse root schema");
+    let schema = Schema::new(SubgraphDeploymentId::new("id").unwrap(), document);
+    let errors = schema.validate_max_first_directives();
+    assert_eq!(
+        errors,
+        vec![
+            SchemaValidationError::MaxFirstDirectiveInvalid(
+                "A.b".to_owned(),
+                "only list fields can have a @maxFirst directive".to_owned()
+            ),
+            SchemaValidationError::MaxFirstDirectiveInvalid(
+                "B".to_owned(),
+                format!("`value` must be between 1 and {}", u32::max_value())
+            ),
+            SchemaValidationError::MaxFirstDirectiveInvalid(
+                "B.as".to_owned(),
+                "`value` must be an Int".to_owned()
+            ),
+        ]
+    );
+}
+
 #[test]
 fn test_recursively_imported_type_validates() {
     const ROOT_SCHEMA: &str = r#"
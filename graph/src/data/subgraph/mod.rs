@@ -753,6 +753,122 @@ impl TryFrom<DataSourceTemplateInfo> for DataSource {
     }
 }
 
+/// A reason why no data source can be created from a `DataSourceTemplateInfo`.
+/// Each error names the field it is about.
+#[derive(Fail, Debug, PartialEq)]
+pub enum DataSourceTemplateInfoValidationError {
+    #[fail(
+        display = "`params`: the address parameter for template `{}` is missing",
+        _0
+    )]
+    AddressMissing(String),
+    #[fail(
+        display = "`params[0]`: invalid address `{}` for template `{}`: {}",
+        address, template, error
+    )]
+    InvalidAddress {
+        template: String,
+        address: String,
+        error: String,
+    },
+    #[fail(
+        display = "`template.source.abi`: ABI `{}` of template `{}` is not one of the mapping ABIs",
+        abi, template
+    )]
+    UnknownSourceAbi { template: String, abi: String },
+    #[fail(
+        display = "`template.mapping`: template `{}` has no event, call or block handlers",
+        _0
+    )]
+    NoHandlers(String),
+    #[fail(
+        display = "`template.name`: the subgraph has no template `{}`, its templates are {:?}",
+        template, known
+    )]
+    UnknownTemplate {
+        template: String,
+        known: Vec<String>,
+    },
+    #[fail(
+        display = "`template.mapping.eventHandlers`: event `{}` of handler `{}` is not in ABI `{}` of template `{}`",
+        event, handler, abi, template
+    )]
+    UnknownEvent {
+        template: String,
+        abi: String,
+        event: String,
+        handler: String,
+    },
+    #[fail(
+        display = "`template.mapping.callHandlers`: function `{}` of handler `{}` is not in ABI `{}` of template `{}`",
+        function, handler, abi, template
+    )]
+    UnknownFunction {
+        template: String,
+        abi: String,
+        function: String,
+        handler: String,
+    },
+}
+
+impl DataSourceTemplateInfo {
+    /// Checks that a data source can be created from the template with the
+    /// given parameters and that the data source can be indexed.
+    pub fn validate(&self) -> Vec<DataSourceTemplateInfoValidationError> {
+        use DataSourceTemplateInfoValidationError::*;
+
+        let template = &self.template;
+        let mut errors = vec![];
+
+        match self.params.get(0) {
+            None => errors.push(AddressMissing(template.name.clone())),
+            Some(address) => {
+                if let Err(e) = Address::from_str(address.trim_start_matches("0x")) {
+                    errors.push(InvalidAddress {
+                        template: template.name.clone(),
+                        address: address.clone(),
+                        error: e.to_string(),
+                    })
+                }
+            }
+        }
+
+        if !template
+            .mapping
+            .abis
+            .iter()
+            .any(|abi| abi.name == template.source.abi)
+        {
+            errors.push(UnknownSourceAbi {
+                template: template.name.clone(),
+                abi: template.source.abi.clone(),
+            });
+        }
+
+        let mapping = &template.mapping;
+        if mapping.event_handlers.is_empty()
+            && mapping.call_handlers.is_empty()
+            && mapping.block_handlers.is_empty()
+        {
+            errors.push(NoHandlers(template.name.clone()));
+        }
+
+        errors
+    }
+
+    /// Creates the data source that indexing would create from the template,
+    /// without adding it to any running subgraph.
+    pub fn dry_run(self) -> Result<DataSource, Vec<DataSourceTemplateInfoValidationError>> {
+        let errors = self.validate();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        // Everything that makes the conversion fail has been validated
+        Ok(DataSource::try_from(self).expect("failed to create data source from valid template"))
+    }
+}
+
 impl TryFromValue for UnresolvedDataSource {
     fn try_from_value(value: &q::Value) -> Result<Self, Error> {
         let map = match value {
@@ -830,7 +946,56 @@ impl UnresolvedDataSourceTemplate {
     }
 }
 
+/// A parameter that mappings pass when they create a data source from a
+/// template.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataSourceTemplateParam {
+    /// The position of the parameter in `DataSourceTemplateInfo::params`.
+    pub index: usize,
+    pub name: &'static str,
+    /// The type the parameter is parsed as.
+    pub kind: &'static str,
+}
+
+/// What tooling needs to know about a template to create data sources
+/// from it, without its mapping code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataSourceTemplateSummary {
+    pub name: String,
+    pub kind: String,
+    pub network: Option<String>,
+    /// The ABI of the contracts the data sources are created for.
+    pub abi: String,
+    pub params: Vec<DataSourceTemplateParam>,
+    pub event_handlers: Vec<MappingEventHandler>,
+    pub call_handlers: Vec<MappingCallHandler>,
+    pub block_handlers: Vec<MappingBlockHandler>,
+}
+
 impl DataSourceTemplate {
+    /// The parameters data sources are created from. All kinds of templates
+    /// take the address of the contract as their only parameter.
+    pub fn params(&self) -> Vec<DataSourceTemplateParam> {
+        vec![DataSourceTemplateParam {
+            index: 0,
+            name: "address",
+            kind: "Address",
+        }]
+    }
+
+    pub fn summary(&self) -> DataSourceTemplateSummary {
+        DataSourceTemplateSummary {
+            name: self.name.clone(),
+            kind: self.kind.clone(),
+            network: self.network.clone(),
+            abi: self.source.abi.clone(),
+            params: self.params(),
+            event_handlers: self.mapping.event_handlers.clone(),
+            call_handlers: self.mapping.call_handlers.clone(),
+            block_handlers: self.mapping.block_handlers.clone(),
+        }
+    }
+
     pub fn has_call_handler(&self) -> bool {
         !self.mapping.call_handlers.is_empty()
     }
@@ -1088,6 +1253,52 @@ impl SubgraphManifest {
             .map(|data_source| data_source.source.start_block)
             .collect()
     }
+
+    /// All templates data sources can be created from: the top-level
+    /// templates, followed by those declared in data sources, which is
+    /// deprecated.
+    pub fn all_templates(&self) -> impl Iterator<Item = &DataSourceTemplate> {
+        self.templates.iter().chain(
+            self.data_sources
+                .iter()
+                .flat_map(|data_source| data_source.templates.iter()),
+        )
+    }
+
+    /// Describes all templates, see `all_templates`.
+    pub fn template_summaries(&self) -> Vec<DataSourceTemplateSummary> {
+        self.all_templates()
+            .map(DataSourceTemplate::summary)
+            .collect()
+    }
+
+    /// Looks up the template named `name`.
+    pub fn find_template(
+        &self,
+        name: &str,
+    ) -> Result<&DataSourceTemplate, DataSourceTemplateInfoValidationError> {
+        self.all_templates()
+            .find(|template| template.name == name)
+            .ok_or_else(|| DataSourceTemplateInfoValidationError::UnknownTemplate {
+                template: name.to_owned(),
+                known: self
+                    .all_templates()
+                    .map(|template| template.name.clone())
+                    .collect(),
+            })
+    }
+
+    /// Like `DataSourceTemplateInfo::validate`, but also checks that the
+    /// template of `info` is one of the templates of the subgraph.
+    pub fn validate_template_info(
+        &self,
+        info: &DataSourceTemplateInfo,
+    ) -> Vec<DataSourceTemplateInfoValidationError> {
+        match self.find_template(&info.template.name) {
+            Ok(_) => info.validate(),
+            Err(e) => vec![e],
+        }
+    }
 }
 
 impl UnresolvedSubgraphManifest {
@@ -365,9 +365,35 @@ impl SubgraphDeploymentEntity {
     pub fn update_failed_operations(
         id: &SubgraphDeploymentId,
         failed: bool,
+    ) -> Vec<MetadataOperation> {
+        // Clearing the failure also clears its cause
+        let entity = if failed {
+            entity! {
+                failed: failed
+            }
+        } else {
+            entity! {
+                failed: failed,
+                deterministicFailure: Value::Null,
+            }
+        };
+
+        vec![update_metadata_operation(
+            Self::TYPENAME,
+            id.as_str(),
+            entity,
+        )]
+    }
+
+    /// Marks the subgraph as failed and records whether the failure will
+    /// happen again when the failed block is processed again.
+    pub fn update_failure_operations(
+        id: &SubgraphDeploymentId,
+        deterministic: bool,
     ) -> Vec<MetadataOperation> {
         let entity = entity! {
-            failed: failed
+            failed: true,
+            deterministicFailure: deterministic,
         };
 
         vec![update_metadata_operation(
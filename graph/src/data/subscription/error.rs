@@ -1,4 +1,5 @@
 use serde::ser::*;
+use std::time::Duration;
 
 use crate::prelude::{Fail, QueryExecutionError};
 
@@ -7,6 +8,42 @@ use crate::prelude::{Fail, QueryExecutionError};
 pub enum SubscriptionError {
     #[fail(display = "GraphQL error: {:?}", _0)]
     GraphQLError(Vec<QueryExecutionError>),
+
+    #[fail(
+        display = "Connection `{}` already has the maximum of {} active subscriptions",
+        connection_id, limit
+    )]
+    TooManySubscriptions { connection_id: String, limit: usize },
+
+    #[fail(
+        display = "Invalid block range: `from` ({}) must not be greater than `to` ({})",
+        from, to
+    )]
+    InvalidBlockRange { from: i32, to: i32 },
+
+    #[fail(display = "Subscriptions to the field `{}` are disabled", field)]
+    FieldNotPermitted { field: String },
+
+    #[fail(display = "The field `{}` is not subscribable", field)]
+    FieldNotSubscribable { field: String },
+
+    #[fail(
+        display = "Subscription reads more than the maximum of {} entity types, also: {:?}",
+        limit, entity_types
+    )]
+    TooManyEntityTypes {
+        limit: usize,
+        entity_types: Vec<String>,
+    },
+
+    #[fail(
+        display = "Opening the event stream for the field `{}` took longer than {:?}",
+        field, timeout
+    )]
+    StreamSetupTimeout { field: String, timeout: Duration },
+
+    #[fail(display = "Setting up the subscription took longer than {:?}", timeout)]
+    SetupTimeout { timeout: Duration },
 }
 
 impl From<QueryExecutionError> for SubscriptionError {
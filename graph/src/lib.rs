@@ -84,7 +84,9 @@ pub mod prelude {
     pub use crate::components::graphql::{
         GraphQlRunner, QueryResultFuture, SubscriptionResultFuture,
     };
-    pub use crate::components::link_resolver::{JsonStreamValue, JsonValueStream, LinkResolver};
+    pub use crate::components::link_resolver::{
+        ContentHashMismatch, FileCache, JsonStreamValue, JsonValueStream, LinkResolver,
+    };
     pub use crate::components::metrics::{
         aggregate::Aggregate, stopwatch::StopwatchMetrics, Collector, Counter, CounterVec, Gauge,
         GaugeVec, Histogram, HistogramOpts, HistogramVec, MetricsRegistry, Opts, PrometheusError,
@@ -99,21 +101,25 @@ pub mod prelude {
         AttributeIndexDefinition, BlockNumber, ChainStore, EntityCache, EntityChange,
         EntityChangeOperation, EntityCollection, EntityFilter, EntityKey, EntityLink,
         EntityModification, EntityOperation, EntityOrder, EntityQuery, EntityRange, EntityWindow,
-        EthereumCallCache, MetadataOperation, ParentLink, Store, StoreError, StoreEvent,
-        StoreEventStream, StoreEventStreamBox, SubgraphDeploymentStore, TransactionAbortError,
-        WindowAttribute, BLOCK_NUMBER_MAX, SUBSCRIPTION_THROTTLE_INTERVAL,
+        EthereumCallCache, MetadataOperation, ParentLink, ProofOfIndexingError, Store, StoreError,
+        StoreEvent, StoreEventStream, StoreEventStreamBox, SubgraphDeploymentStore,
+        TransactionAbortError, WindowAttribute, BLOCK_NUMBER_MAX, SUBSCRIPTION_THROTTLE_INTERVAL,
     };
     pub use crate::components::subgraph::{
-        BlockState, DataSourceLoader, DataSourceTemplateInfo, HostMetrics, RuntimeHost,
-        RuntimeHostBuilder, SubgraphAssignmentProvider, SubgraphInstance, SubgraphInstanceManager,
-        SubgraphRegistrar, SubgraphVersionSwitchingMode,
+        is_deterministic_handler_error, BlockState, DataSourceLoader, DataSourceTemplateInfo,
+        HandlerErrorBudget, HandlerErrorBudgetExceeded, HandlerTimeoutError, HandlerYieldPolicy,
+        HostMetrics, NonDeterministicHostError, PendingBlockOperations, RunningSubgraph,
+        RuntimeHost, RuntimeHostBuilder, SubgraphAssignmentProvider, SubgraphInstance,
+        SubgraphInstanceManager, SubgraphPauseEvent, SubgraphRegistrar, SubgraphRestartStatus,
+        SubgraphSyncEvent, SubgraphVersionSwitchingMode, TriggerOrdering,
     };
     pub use crate::components::{EventConsumer, EventProducer};
 
     pub use crate::cheap_clone::CheapClone;
     pub use crate::data::graphql::{SerializableValue, TryFromValue, ValueMap};
     pub use crate::data::query::{
-        Query, QueryError, QueryExecutionError, QueryResult, QueryVariables,
+        Query, QueryError, QueryExecutionError, QueryResult, QueryResultCompression,
+        QueryResultEncoding, QueryResultEvent, QueryTraceEntry, QueryVariables,
     };
     pub use crate::data::schema::Schema;
     pub use crate::data::store::ethereum::*;
@@ -125,7 +131,8 @@ pub mod prelude {
     pub use crate::data::subgraph::schema::{SubgraphDeploymentEntity, TypedEntity};
     pub use crate::data::subgraph::{
         BlockHandlerFilter, CreateSubgraphResult, DataSource, DataSourceContext,
-        DataSourceTemplate, Link, MappingABI, MappingBlockHandler, MappingCallHandler,
+        DataSourceTemplate, DataSourceTemplateInfoValidationError, DataSourceTemplateParam,
+        DataSourceTemplateSummary, Link, MappingABI, MappingBlockHandler, MappingCallHandler,
         MappingEventHandler, SubgraphAssignmentProviderError, SubgraphAssignmentProviderEvent,
         SubgraphDeploymentId, SubgraphManifest, SubgraphManifestResolveError,
         SubgraphManifestValidationError, SubgraphName, SubgraphRegistrarError,
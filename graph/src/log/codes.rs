@@ -4,6 +4,11 @@ pub enum LogCode {
     SubgraphStartFailure,
     SubgraphSyncingFailure,
     SubgraphSyncingFailureNotRecorded,
+    SubgraphPaused,
+    SubgraphResumed,
+    SubgraphDrained,
+    SubgraphSynced,
+    SubgraphUnsynced,
     BlockIngestionStatus,
     BlockIngestionLagging,
     GraphQlQuerySuccess,
@@ -17,6 +22,11 @@ impl Display for LogCode {
             LogCode::SubgraphStartFailure => "SubgraphStartFailure",
             LogCode::SubgraphSyncingFailure => "SubgraphSyncingFailure",
             LogCode::SubgraphSyncingFailureNotRecorded => "SubgraphSyncingFailureNotRecorded",
+            LogCode::SubgraphPaused => "SubgraphPaused",
+            LogCode::SubgraphResumed => "SubgraphResumed",
+            LogCode::SubgraphDrained => "SubgraphDrained",
+            LogCode::SubgraphSynced => "SubgraphSynced",
+            LogCode::SubgraphUnsynced => "SubgraphUnsynced",
             LogCode::BlockIngestionStatus => "BlockIngestionStatus",
             LogCode::BlockIngestionLagging => "BlockIngestionLagging",
             LogCode::GraphQlQuerySuccess => "GraphQLQuerySuccess",
@@ -2,7 +2,8 @@ use std::collections::BTreeMap;
 
 use graph::mock::MockStore;
 use graph::prelude::{
-    Entity, EntityCache, EntityKey, EntityModification, SubgraphDeploymentId, Value,
+    Entity, EntityCache, EntityKey, EntityModification, EntityOperation, SubgraphDeploymentId,
+    Value,
 };
 
 fn make_band(id: &'static str, data: Vec<(&str, Value)>) -> (EntityKey, Entity) {
@@ -199,3 +200,73 @@ fn consecutive_modifications() {
         },])
     );
 }
+
+#[test]
+fn counts_and_weighs_updates() {
+    let mut cache = EntityCache::new();
+    assert_eq!(cache.update_count(), 0);
+    assert_eq!(cache.updates_weight(), 0);
+
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+    );
+    cache.set(mogwai_key.clone(), mogwai_data);
+    let weight = cache.updates_weight();
+    assert_eq!(cache.update_count(), 1);
+    assert!(weight > 0);
+
+    // Changing the same entity again does not count as another update
+    let (_, founded) = make_band("mogwai", vec![("founded", 1995.into())]);
+    cache.set(mogwai_key, founded);
+    assert_eq!(cache.update_count(), 1);
+    assert!(cache.updates_weight() > weight);
+
+    let (sigurros_key, sigurros_data) = make_band(
+        "sigurros",
+        vec![("id", "sigurros".into()), ("name", "Sigur Ros".into())],
+    );
+    cache.remove(sigurros_key.clone());
+    assert_eq!(cache.update_count(), 2);
+
+    // The weight is kept track of as the changes are made
+    let weight = cache.updates_weight();
+    cache.set(sigurros_key.clone(), sigurros_data);
+    assert!(cache.updates_weight() > weight);
+    cache.remove(sigurros_key);
+    assert_eq!(cache.updates_weight(), weight);
+}
+
+#[test]
+fn pending_operations() {
+    let mut cache = EntityCache::new();
+
+    let (sigurros_key, sigurros_data) = make_band(
+        "sigurros",
+        vec![("id", "sigurros".into()), ("name", "Sigur Ros".into())],
+    );
+    cache.set(sigurros_key.clone(), sigurros_data.clone());
+
+    let (mogwai_key, _) = make_band("mogwai", vec![]);
+    cache.remove(mogwai_key.clone());
+
+    let (_, founded) = make_band("sigurros", vec![("founded", 1994.into())]);
+    cache.set(sigurros_key.clone(), founded);
+
+    let mut sigurros_data = sigurros_data;
+    sigurros_data.set("founded", 1994);
+
+    // Pending operations don't need the store and leave the cache intact
+    let expected = vec![
+        EntityOperation::Remove {
+            key: mogwai_key.clone(),
+        },
+        EntityOperation::Set {
+            key: sigurros_key.clone(),
+            data: sigurros_data,
+        },
+    ];
+    assert_eq!(cache.pending_operations(), expected);
+    assert_eq!(cache.pending_operations(), expected);
+    assert_eq!(cache.update_count(), 2);
+}
@@ -6,7 +6,8 @@ use std::time::Duration;
 
 use graph::components::link_resolver::{JsonValueStream, LinkResolver as LinkResolverTrait};
 use graph::prelude::{
-    Entity, Link, SubgraphDeploymentId, SubgraphManifest, SubgraphManifestValidationError,
+    DataSourceTemplateInfo, DataSourceTemplateInfoValidationError, DataSourceTemplateParam, Entity,
+    Link, SubgraphDeploymentId, SubgraphManifest, SubgraphManifestValidationError,
     UnvalidatedSubgraphManifest,
 };
 
@@ -51,12 +52,17 @@ impl LinkResolverTrait for TextResolver {
 
 const GQL_SCHEMA: &str = "type Thing @entity { id: ID! }";
 
+/// An empty WASM module.
+const MAPPING: &str = "\0asm\x01\0\0\0";
+
 async fn resolve_manifest(text: &str) -> SubgraphManifest {
     let mut resolver = TextResolver::default();
     let link = Link::from("/ipfs/Qmmanifest".to_owned());
 
     resolver.add(link.link.as_str(), text);
     resolver.add("/ipfs/Qmschema", GQL_SCHEMA);
+    resolver.add("/ipfs/Qmabi", "[]");
+    resolver.add("/ipfs/Qmmapping", MAPPING);
 
     SubgraphManifest::resolve(link, &resolver, &LOGGER)
         .await
@@ -172,3 +178,160 @@ specVersion: 0.0.1
         );
     })
 }
+
+#[tokio::test]
+async fn dry_run_template_info() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+templates:
+  - kind: ethereum/contract
+    name: Exchange
+    network: mainnet
+    source:
+      abi: Exchange
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.1
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      abis:
+        - name: Exchange
+          file:
+            /: /ipfs/Qmabi
+      eventHandlers:
+        - event: Trade(address)
+          handler: handleTrade
+      file:
+        /: /ipfs/Qmmapping
+specVersion: 0.0.2
+";
+
+    let manifest = resolve_manifest(YAML).await;
+    let template = manifest.templates[0].clone();
+    let info = |params: Vec<&str>| DataSourceTemplateInfo {
+        data_source: "Factory".to_owned(),
+        template: template.clone(),
+        params: params.into_iter().map(String::from).collect(),
+        context: None,
+    };
+
+    let data_source = info(vec!["0x22843e74c59580b3eaf6c233fa67d8b7c561a835"])
+        .dry_run()
+        .expect("template info is valid");
+    assert_eq!("Exchange", data_source.name);
+    assert!(data_source.source.address.is_some());
+    assert_eq!("handleTrade", data_source.mapping.event_handlers[0].handler);
+
+    assert_eq!(
+        vec![DataSourceTemplateInfoValidationError::AddressMissing(
+            "Exchange".to_owned()
+        )],
+        info(vec![]).validate()
+    );
+
+    // Remove the handlers and refer to an ABI the mapping does not have
+    let mut template = manifest.templates[0].clone();
+    template.source.abi = "Token".to_owned();
+    template.mapping.event_handlers.clear();
+    let errors = DataSourceTemplateInfo {
+        data_source: "Factory".to_owned(),
+        template,
+        params: vec!["not an address".to_owned()],
+        context: None,
+    }
+    .dry_run()
+    .expect_err("template info is invalid");
+
+    assert_eq!(3, errors.len());
+    match &errors[0] {
+        DataSourceTemplateInfoValidationError::InvalidAddress { address, .. } => {
+            assert_eq!("not an address", address)
+        }
+        e => panic!("unexpected error: {}", e),
+    }
+    assert_eq!(
+        DataSourceTemplateInfoValidationError::UnknownSourceAbi {
+            template: "Exchange".to_owned(),
+            abi: "Token".to_owned(),
+        },
+        errors[1]
+    );
+    assert_eq!(
+        DataSourceTemplateInfoValidationError::NoHandlers("Exchange".to_owned()),
+        errors[2]
+    );
+}
+
+#[tokio::test]
+async fn template_summaries() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+templates:
+  - kind: ethereum/contract
+    name: Exchange
+    network: mainnet
+    source:
+      abi: Exchange
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.1
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      abis:
+        - name: Exchange
+          file:
+            /: /ipfs/Qmabi
+      eventHandlers:
+        - event: Trade(address)
+          handler: handleTrade
+      file:
+        /: /ipfs/Qmmapping
+specVersion: 0.0.2
+";
+
+    let manifest = resolve_manifest(YAML).await;
+
+    let summaries = manifest.template_summaries();
+    assert_eq!(1, summaries.len());
+    assert_eq!("Exchange", summaries[0].name);
+    assert_eq!("ethereum/contract", summaries[0].kind);
+    assert_eq!("Exchange", summaries[0].abi);
+    assert_eq!("handleTrade", summaries[0].event_handlers[0].handler);
+    assert_eq!(
+        vec![DataSourceTemplateParam {
+            index: 0,
+            name: "address",
+            kind: "Address",
+        }],
+        summaries[0].params
+    );
+
+    let mut template = manifest.templates[0].clone();
+    template.name = "Token".to_owned();
+    let info = DataSourceTemplateInfo {
+        data_source: "Factory".to_owned(),
+        template,
+        params: vec!["0x22843e74c59580b3eaf6c233fa67d8b7c561a835".to_owned()],
+        context: None,
+    };
+    let errors = manifest.validate_template_info(&info);
+    assert_eq!(
+        vec![DataSourceTemplateInfoValidationError::UnknownTemplate {
+            template: "Token".to_owned(),
+            known: vec!["Exchange".to_owned()],
+        }],
+        errors
+    );
+    assert_eq!(
+        "`template.name`: the subgraph has no template `Token`, its templates are [\"Exchange\"]",
+        errors[0].to_string()
+    );
+}
@@ -0,0 +1,47 @@
+use futures::{stream, Future, Stream};
+
+use graph::prelude::{
+    EntityChange, EntityChangeOperation, StoreEvent, StoreEventStream, StoreEventStreamBox,
+    SubgraphDeploymentId,
+};
+
+fn change(entity_type: &str, entity_id: &str) -> EntityChange {
+    EntityChange {
+        subgraph_id: SubgraphDeploymentId::new("store_events").unwrap(),
+        entity_type: entity_type.into(),
+        entity_id: entity_id.into(),
+        operation: EntityChangeOperation::Set,
+    }
+}
+
+fn event_stream(events: Vec<StoreEvent>) -> StoreEventStreamBox {
+    let source: Box<dyn Stream<Item = StoreEvent, Error = ()> + Send> =
+        Box::new(stream::iter_ok(events));
+    StoreEventStream::new(source)
+}
+
+#[test]
+fn filter_by_entity_ids_drops_events_for_other_ids() {
+    let events = vec![
+        StoreEvent::new(vec![change("Musician", "m1")]),
+        StoreEvent::new(vec![change("Musician", "m2")]),
+        StoreEvent::new(vec![change("Musician", "m2"), change("Band", "b1")]),
+    ];
+
+    let subgraph_id = SubgraphDeploymentId::new("store_events").unwrap();
+    let received = event_stream(events)
+        .filter_by_entity_ids(subgraph_id, "Musician".into(), vec!["m1".into()])
+        .collect()
+        .wait()
+        .unwrap();
+
+    // The event that only changes `m2` is dropped; the event that also
+    // changes a band is passed through
+    assert_eq!(
+        received,
+        vec![
+            StoreEvent::new(vec![change("Musician", "m1")]),
+            StoreEvent::new(vec![change("Musician", "m2"), change("Band", "b1")]),
+        ]
+    );
+}
@@ -2,10 +2,12 @@ use graphql_parser::query as q;
 use graphql_parser::schema as s;
 use indexmap::IndexMap;
 use lazy_static::lazy_static;
+use std::cell::RefCell;
 use std::cmp;
 use std::collections::{BTreeMap, HashMap, HashSet};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use graph::data::schema::max_first_directive_value;
 use graph::prelude::*;
 
 use crate::introspection::{
@@ -21,6 +23,23 @@ lazy_static! {
     static ref NO_PREFETCH: bool = std::env::var_os("GRAPH_GRAPHQL_NO_PREFETCH").is_some();
 }
 
+thread_local! {
+    /// The response key of the top-level field that this thread is executing.
+    static CURRENT_ROOT_FIELD: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Returns the response key of the top-level field that this thread was
+/// executing, or `None` if it wasn't executing any. This is only meant for
+/// attributing a panic during execution to a field, since a panic leaves the
+/// field that was being executed behind.
+pub(crate) fn current_root_field() -> Option<String> {
+    CURRENT_ROOT_FIELD.with(|field| field.borrow().clone())
+}
+
+fn set_current_root_field(response_key: Option<&str>) {
+    CURRENT_ROOT_FIELD.with(|field| *field.borrow_mut() = response_key.map(String::from));
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ExecutionMode {
     Prefetch,
@@ -48,6 +67,15 @@ where
     /// Time at which the query times out.
     pub deadline: Option<Instant>,
 
+    /// Timeouts for individual fields, keyed by `<type name>.<field name>`.
+    /// A field that runs out of time fails with a `FieldTimeout` error, but
+    /// its sibling fields are still executed. Timeouts are checked between
+    /// fields, the same way as `deadline`, and never extend `deadline`.
+    pub field_timeouts: Arc<HashMap<String, Duration>>,
+
+    /// If set, execution stops once the corresponding guard is canceled.
+    pub cancel_handle: Option<CancelHandle>,
+
     /// Max value for `first`.
     pub max_first: u32,
 
@@ -83,6 +111,28 @@ impl<R> ExecutionContext<R>
 where
     R: Resolver,
 {
+    /// The maximum value for `first` when resolving the list field
+    /// `field_definition` with items of type `item_type`. A `@maxFirst`
+    /// directive on the field takes precedence over one on the item type,
+    /// which takes precedence over the global `max_first`. Non-list fields
+    /// always use the global `max_first`.
+    pub fn max_first_for_field(
+        &self,
+        field_definition: &s::Field,
+        item_type: ObjectOrInterface<'_>,
+    ) -> u32 {
+        if !sast::is_list_or_non_null_list_field(field_definition) {
+            return self.max_first;
+        }
+        field_definition
+            .directives
+            .iter()
+            .chain(item_type.directives())
+            .find(|directive| directive.name == "maxFirst")
+            .and_then(|directive| max_first_directive_value(directive).ok())
+            .unwrap_or(self.max_first)
+    }
+
     /// Creates a derived context for a new field (added to the top of the field stack).
     pub fn for_field<'a>(
         &self,
@@ -106,6 +156,8 @@ where
             query: self.query.as_introspection_query(),
             fields: vec![],
             deadline: self.deadline,
+            field_timeouts: Arc::new(HashMap::new()),
+            cancel_handle: self.cancel_handle.clone(),
             max_first: std::u32::MAX,
             block: self.block,
             mode: ExecutionMode::Prefetch,
@@ -203,12 +255,60 @@ pub fn execute_selection_set(
     )?))
 }
 
+/// Executes a selection set like `execute_selection_set`, but instead of
+/// failing as a whole when some of its fields fail, returns the values of the
+/// fields that succeeded together with the errors of those that failed. The
+/// value is `None` if no field succeeded.
+pub fn execute_selection_set_partially(
+    ctx: &ExecutionContext<impl Resolver>,
+    selection_set: &q::SelectionSet,
+    object_type: &s::ObjectType,
+    object_value: &Option<q::Value>,
+) -> (Option<q::Value>, Vec<QueryExecutionError>) {
+    let (result_map, mut errors) =
+        execute_fields_to_map(ctx, selection_set, object_type, object_value);
+
+    if result_map.is_empty() {
+        if errors.is_empty() {
+            errors.push(QueryExecutionError::EmptySelectionSet(
+                object_type.name.clone(),
+            ));
+        }
+        (None, errors)
+    } else {
+        (Some(q::Value::Object(result_map)), errors)
+    }
+}
+
 fn execute_selection_set_to_map(
     ctx: &ExecutionContext<impl Resolver>,
     selection_set: &q::SelectionSet,
     object_type: &s::ObjectType,
     object_value: &Option<q::Value>,
 ) -> Result<BTreeMap<String, q::Value>, Vec<QueryExecutionError>> {
+    let (result_map, mut errors) =
+        execute_fields_to_map(ctx, selection_set, object_type, object_value);
+
+    if errors.is_empty() && !result_map.is_empty() {
+        Ok(result_map)
+    } else {
+        if errors.is_empty() {
+            errors.push(QueryExecutionError::EmptySelectionSet(
+                object_type.name.clone(),
+            ));
+        }
+        Err(errors)
+    }
+}
+
+/// Executes the fields of a selection set, returning the values of the
+/// fields that succeeded and the errors of the fields that failed.
+fn execute_fields_to_map(
+    ctx: &ExecutionContext<impl Resolver>,
+    selection_set: &q::SelectionSet,
+    object_type: &s::ObjectType,
+    object_value: &Option<q::Value>,
+) -> (BTreeMap<String, q::Value>, Vec<QueryExecutionError>) {
     let mut errors: Vec<QueryExecutionError> = Vec::new();
     let mut result_map: BTreeMap<String, q::Value> = BTreeMap::new();
 
@@ -216,7 +316,12 @@ fn execute_selection_set_to_map(
     let grouped_field_set = collect_fields(ctx, object_type, selection_set, None);
 
     // Process all field groups in order
+    let is_root = ctx.fields.is_empty();
     for (response_key, fields) in grouped_field_set {
+        if is_root {
+            set_current_root_field(Some(response_key));
+        }
+
         match ctx.deadline {
             Some(deadline) if deadline < Instant::now() => {
                 errors.push(QueryExecutionError::Timeout);
@@ -225,19 +330,55 @@ fn execute_selection_set_to_map(
             _ => (),
         }
 
+        if ctx
+            .cancel_handle
+            .as_ref()
+            .map_or(false, |handle| handle.is_canceled())
+        {
+            errors.push(QueryExecutionError::Canceled);
+            break;
+        }
+
         // If the field exists on the object, execute it and add its result to the result map
         if let Some(ref field) = sast::get_field(object_type, &fields[0].name) {
+            let field_timeout = ctx
+                .field_timeouts
+                .get(&format!("{}.{}", object_type.name, field.name))
+                .cloned();
+
             // Push the new field onto the context's field stack
             match ctx.for_field(&fields[0], object_type) {
-                Ok(ctx) => {
-                    match execute_field(&ctx, object_type, object_value, &fields[0], field, fields)
-                    {
+                Ok(mut field_ctx) => {
+                    if let Some(timeout) = field_timeout {
+                        let field_deadline = Instant::now() + timeout;
+                        field_ctx.deadline = Some(
+                            ctx.deadline
+                                .map_or(field_deadline, |deadline| deadline.min(field_deadline)),
+                        );
+                    }
+
+                    match execute_field(
+                        &field_ctx,
+                        object_type,
+                        object_value,
+                        &fields[0],
+                        field,
+                        fields,
+                    ) {
                         Ok(v) => {
                             result_map.insert(response_key.to_owned(), v);
                         }
-                        Err(mut e) => {
-                            errors.append(&mut e);
-                        }
+                        Err(e) => match field_timeout {
+                            Some(timeout) if !deadline_passed(ctx.deadline) => {
+                                errors.extend(field_timeout_errors(
+                                    e,
+                                    &fields[0],
+                                    response_key,
+                                    timeout,
+                                ));
+                            }
+                            _ => errors.extend(e),
+                        },
                     };
                 }
                 Err(e) => errors.push(e),
@@ -251,16 +392,36 @@ fn execute_selection_set_to_map(
         }
     }
 
-    if errors.is_empty() && !result_map.is_empty() {
-        Ok(result_map)
-    } else {
-        if errors.is_empty() {
-            errors.push(QueryExecutionError::EmptySelectionSet(
-                object_type.name.clone(),
-            ));
-        }
-        Err(errors)
+    if is_root {
+        set_current_root_field(None);
+    }
+
+    (result_map, errors)
+}
+
+fn deadline_passed(deadline: Option<Instant>) -> bool {
+    deadline.map_or(false, |deadline| deadline < Instant::now())
+}
+
+/// Replaces the `Timeout` errors of a field that ran out of its own time
+/// with a single `FieldTimeout` error for the field.
+fn field_timeout_errors(
+    errors: Vec<QueryExecutionError>,
+    field: &q::Field,
+    response_key: &str,
+    timeout: Duration,
+) -> Vec<QueryExecutionError> {
+    let (timeouts, mut errors): (Vec<_>, Vec<_>) = errors
+        .into_iter()
+        .partition(|e| matches!(e, QueryExecutionError::Timeout));
+    if !timeouts.is_empty() {
+        errors.push(QueryExecutionError::FieldTimeout(
+            field.position,
+            response_key.to_owned(),
+            timeout,
+        ));
     }
+    errors
 }
 
 /// Collects fields of a selection set.
@@ -577,7 +738,7 @@ fn resolve_field_value_for_list_type(
                         argument_values,
                         ctx.query.schema.types_for_interface(),
                         ctx.block,
-                        ctx.max_first,
+                        ctx.max_first_for_field(field_definition, t.into()),
                     )
                     .map_err(|e| vec![e]),
 
@@ -611,7 +772,7 @@ fn resolve_field_value_for_list_type(
                         argument_values,
                         ctx.query.schema.types_for_interface(),
                         ctx.block,
-                        ctx.max_first,
+                        ctx.max_first_for_field(field_definition, t.into()),
                     )
                     .map_err(|e| vec![e]),
 
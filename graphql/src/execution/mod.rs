@@ -7,5 +7,5 @@ mod resolver;
 mod query;
 
 pub use self::execution::*;
-pub use self::query::Query;
+pub use self::query::{normalized_query_text, query_id, Query};
 pub use self::resolver::{ObjectOrInterface, Resolver};
@@ -1,12 +1,13 @@
 use graphql_parser::query as q;
 use graphql_parser::schema as s;
-use std::collections::HashMap;
+use graphql_parser::Style;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use graph::data::graphql::ext::TypeExt;
 use graph::data::query::{Query as GraphDataQuery, QueryVariables};
 use graph::data::schema::Schema;
-use graph::prelude::QueryExecutionError;
+use graph::prelude::{tiny_keccak, QueryExecutionError};
 
 use crate::execution::{get_field, get_named_type};
 use crate::introspection::introspection_schema;
@@ -42,6 +43,80 @@ pub struct Query {
     /// execution modes, and the results of the two executions should be
     /// checked against each other
     pub verify: bool,
+    /// A short identifier for the query document, see `query_id`
+    pub query_id: String,
+}
+
+/// Returns a short identifier for `document` that is the same for documents
+/// that only differ cosmetically, e.g., in whitespace, commas or comments.
+/// Only the document is hashed; executing it with different variables does
+/// not change the id.
+pub fn query_id(document: &q::Document) -> String {
+    // Formatting the parsed document discards everything cosmetic
+    let normalized = document.format(&Style::default().indent(0));
+    tiny_keccak::keccak256(normalized.as_bytes())[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Returns a readable form of `document` for logging. Selection sets are
+/// spread over indented lines, and fields, arguments and fragments are sorted
+/// so that documents that only differ in formatting or in the order of those
+/// have the same form. Since sorting changes the order of fields in results,
+/// the returned text is only meant to be read, not executed.
+pub fn normalized_query_text(document: &q::Document) -> String {
+    fn sort_selection_set(selection_set: &mut q::SelectionSet) {
+        for selection in selection_set.items.iter_mut() {
+            match selection {
+                q::Selection::Field(field) => {
+                    field.arguments.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    sort_selection_set(&mut field.selection_set);
+                }
+                q::Selection::InlineFragment(fragment) => {
+                    sort_selection_set(&mut fragment.selection_set)
+                }
+                q::Selection::FragmentSpread(_) => (),
+            }
+        }
+        selection_set
+            .items
+            .sort_by_key(|selection| match selection {
+                q::Selection::Field(field) => {
+                    (0, field.alias.as_ref().unwrap_or(&field.name).clone())
+                }
+                q::Selection::FragmentSpread(spread) => (1, spread.fragment_name.clone()),
+                q::Selection::InlineFragment(fragment) => match &fragment.type_condition {
+                    Some(q::TypeCondition::On(name)) => (2, name.clone()),
+                    None => (2, String::new()),
+                },
+            });
+    }
+
+    let mut document = document.clone();
+    for definition in document.definitions.iter_mut() {
+        match definition {
+            q::Definition::Operation(operation) => sort_selection_set(match operation {
+                q::OperationDefinition::SelectionSet(selection_set) => selection_set,
+                q::OperationDefinition::Query(query) => &mut query.selection_set,
+                q::OperationDefinition::Mutation(mutation) => &mut mutation.selection_set,
+                q::OperationDefinition::Subscription(subscription) => {
+                    &mut subscription.selection_set
+                }
+            }),
+            q::Definition::Fragment(fragment) => sort_selection_set(&mut fragment.selection_set),
+        }
+    }
+
+    // Operations come first, followed by fragments in order of their names
+    document
+        .definitions
+        .sort_by_key(|definition| match definition {
+            q::Definition::Operation(_) => None,
+            q::Definition::Fragment(fragment) => Some(fragment.name.clone()),
+        });
+
+    document.format(&Style::default().indent(2))
 }
 
 impl Query {
@@ -54,20 +129,16 @@ impl Query {
         max_complexity: Option<u64>,
         max_depth: u8,
     ) -> Result<Arc<Self>, Vec<QueryExecutionError>> {
-        let mut operation = None;
+        let query_id = query_id(&query.document);
+
+        let operation =
+            qast::get_operation(&query.document, query.operation_name.as_deref())?.clone();
         let mut fragments = HashMap::new();
         for defn in query.document.definitions.into_iter() {
-            match defn {
-                q::Definition::Operation(op) => match operation {
-                    None => operation = Some(op),
-                    Some(_) => return Err(vec![QueryExecutionError::OperationNameRequired]),
-                },
-                q::Definition::Fragment(frag) => {
-                    fragments.insert(frag.name.clone(), frag);
-                }
+            if let q::Definition::Fragment(frag) = defn {
+                fragments.insert(frag.name.clone(), frag);
             }
         }
-        let operation = operation.ok_or(QueryExecutionError::OperationNameRequired)?;
 
         let verify = if let q::OperationDefinition::Query(query) = &operation {
             query.directives.iter().any(|dir| dir.name == "verify")
@@ -99,8 +170,10 @@ impl Query {
             selection_set,
             kind,
             verify,
+            query_id,
         });
 
+        query.check_fragment_cycles()?;
         query.validate_fields()?;
         query.check_complexity(max_complexity, max_depth)?;
 
@@ -118,6 +191,7 @@ impl Query {
             selection_set: self.selection_set.clone(),
             kind: self.kind,
             verify: self.verify,
+            query_id: self.query_id.clone(),
         })
     }
 
@@ -176,6 +250,66 @@ impl Query {
         }
     }
 
+    /// Checks that no fragment spreads itself, directly or through other
+    /// fragments. Validating or executing such a query would recurse until
+    /// the stack overflows.
+    fn check_fragment_cycles(&self) -> Result<(), Vec<QueryExecutionError>> {
+        fn spreads<'a>(selection_set: &'a q::SelectionSet, names: &mut Vec<&'a q::Name>) {
+            for selection in selection_set.items.iter() {
+                match selection {
+                    q::Selection::Field(field) => spreads(&field.selection_set, names),
+                    q::Selection::FragmentSpread(spread) => names.push(&spread.fragment_name),
+                    q::Selection::InlineFragment(fragment) => {
+                        spreads(&fragment.selection_set, names)
+                    }
+                }
+            }
+        }
+
+        // Returns the cycle if `name` is already on `path`. Fragments that
+        // are visited a second time without being on the path are part of
+        // no cycle, or were already found to be
+        fn visit<'a>(
+            query: &'a Query,
+            name: &'a q::Name,
+            path: &mut Vec<&'a q::Name>,
+            visited: &mut HashSet<&'a q::Name>,
+        ) -> Option<Vec<String>> {
+            if let Some(start) = path.iter().position(|other| *other == name) {
+                let mut cycle: Vec<_> = path[start..].iter().map(|name| name.to_string()).collect();
+                cycle.push(name.clone());
+                return Some(cycle);
+            }
+            if !visited.insert(name) {
+                return None;
+            }
+
+            // Undefined fragments are reported by `validate_fields`
+            let fragment = query.fragments.get(name)?;
+            let mut names = vec![];
+            spreads(&fragment.selection_set, &mut names);
+
+            path.push(name);
+            for next in names {
+                if let Some(cycle) = visit(query, next, path, visited) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            None
+        }
+
+        let mut names: Vec<_> = self.fragments.keys().collect();
+        names.sort();
+        let mut visited = HashSet::new();
+        for name in names {
+            if let Some(cycle) = visit(self, name, &mut vec![], &mut visited) {
+                return Err(vec![QueryExecutionError::FragmentCycle(cycle)]);
+            }
+        }
+        Ok(())
+    }
+
     fn validate_fields(&self) -> Result<(), Vec<QueryExecutionError>> {
         let root_type = sast::get_root_query_type_def(&self.schema.document).unwrap();
 
@@ -408,11 +542,14 @@ pub fn coerce_variables(
         };
 
         // We have a variable value, attempt to coerce it to the value type
-        // of the variable definition
-        coerced_values.insert(
-            variable_def.name.to_owned(),
-            coerce_variable(schema, variable_def, &value)?,
-        );
+        // of the variable definition; keep going on failure so that all
+        // invalid variables are reported at once
+        match coerce_variable(schema, variable_def, &value) {
+            Ok(value) => {
+                coerced_values.insert(variable_def.name.to_owned(), value);
+            }
+            Err(e) => errors.push(e),
+        }
     }
 
     if errors.is_empty() {
@@ -426,16 +563,17 @@ fn coerce_variable(
     schema: &Schema,
     variable_def: &q::VariableDefinition,
     value: &q::Value,
-) -> Result<q::Value, Vec<QueryExecutionError>> {
+) -> Result<q::Value, QueryExecutionError> {
     use crate::values::coercion::coerce_value;
 
     let resolver = |name: &q::Name| sast::get_named_type(&schema.document, name);
 
     coerce_value(&value, &variable_def.var_type, &resolver, &HashMap::new()).ok_or_else(|| {
-        vec![QueryExecutionError::InvalidArgumentError(
+        QueryExecutionError::InvalidVariableError(
             variable_def.position,
             variable_def.name.to_owned(),
+            variable_def.var_type.to_string(),
             value.clone(),
-        )]
+        )
     })
 }
@@ -4,7 +4,10 @@ use std::collections::{BTreeMap, HashMap};
 use crate::prelude::*;
 use crate::query::ext::BlockConstraint;
 use crate::schema::ast::get_named_type;
-use graph::prelude::{BlockNumber, QueryExecutionError, Schema, StoreEventStreamBox};
+use graph::prelude::{
+    BlockNumber, QueryExecutionError, QueryTraceEntry, Schema, StoreEventStreamBox,
+    SubgraphDeploymentId,
+};
 
 #[derive(Copy, Clone, Debug)]
 pub enum ObjectOrInterface<'a> {
@@ -171,15 +174,63 @@ pub trait Resolver: Clone + Send + Sync {
         }
     }
 
-    // Resolves a change stream for a given field.
+    // Resolves a change stream for a given field. The coerced `arguments`
+    // of the field allow resolvers to filter the stream at the source.
     fn resolve_field_stream<'a, 'b>(
         &self,
         _schema: &'a s::Document,
         _object_type: &'a s::ObjectType,
         _field: &'b q::Field,
+        _arguments: &HashMap<&q::Name, q::Value>,
     ) -> Result<StoreEventStreamBox, QueryExecutionError> {
         Err(QueryExecutionError::NotSupported(String::from(
             "Resolving field streams is not supported by this resolver",
         )))
     }
+
+    /// Whether `resolve_field_stream` can open a stream for `field`.
+    /// Subscriptions ask this for each of their top-level fields before
+    /// coercing arguments or opening any streams, so that subscriptions to
+    /// fields that can't be streamed fail early with a clear error.
+    fn supports_field_stream(
+        &self,
+        _schema: &s::Document,
+        _object_type: &s::ObjectType,
+        _field: &q::Field,
+    ) -> bool {
+        true
+    }
+
+    /// Whether resolving fields may block the thread, e.g. because it waits
+    /// for the database. Subscriptions that allow it execute their queries
+    /// for resolvers that don't block inline on the async runtime instead of
+    /// on the blocking thread pool, see
+    /// `SubscriptionExecutionOptions::execute_inline`.
+    fn is_blocking(&self) -> bool {
+        true
+    }
+
+    /// Returns the number of the latest block that `subgraph` has been
+    /// indexed up to, or `None` if it hasn't indexed any blocks yet.
+    fn latest_block(
+        &self,
+        _subgraph: &SubgraphDeploymentId,
+    ) -> Result<Option<BlockNumber>, QueryExecutionError> {
+        Err(QueryExecutionError::NotSupported(String::from(
+            "Looking up the latest block is not supported by this resolver",
+        )))
+    }
+
+    /// Returns a resolver for executing a single query. Resolvers that
+    /// trace the store operations of queries return one with an empty
+    /// trace; all others return a clone of themselves.
+    fn for_query(&self) -> Self {
+        self.clone()
+    }
+
+    /// Returns the store operations that this resolver performed so far, or
+    /// `None` if it doesn't trace them.
+    fn trace(&self) -> Option<Vec<QueryTraceEntry>> {
+        None
+    }
 }
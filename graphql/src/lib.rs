@@ -25,14 +25,25 @@ mod store;
 
 /// Prelude that exports the most important traits and types.
 pub mod prelude {
-    pub use super::execution::{ExecutionContext, ObjectOrInterface, Resolver};
+    pub use super::execution::{ExecutionContext, ExecutionMode, ObjectOrInterface, Resolver};
     pub use super::introspection::{introspection_schema, IntrospectionResolver};
     pub use super::query::{
         execute_query, ext::BlockConstraint, ext::BlockLocator, QueryExecutionOptions,
     };
     pub use super::schema::{api_schema, ast::validate_entity, APISchemaError};
     pub use super::store::{build_query, StoreResolver};
-    pub use super::subscription::{execute_subscription, SubscriptionExecutionOptions};
+    pub use super::subscription::{
+        execute_subscription, execute_subscription_snapshot, is_subscription_document,
+        shared_subscription_query_permits, validate_subscription, QueryPermits,
+        SharedStreamResolver, SubscriptionBlockRange, SubscriptionBufferPolicy, SubscriptionClock,
+        SubscriptionCompression, SubscriptionDeliveryMode, SubscriptionErrorClassifier,
+        SubscriptionErrorPolicy, SubscriptionErrorSeverity, SubscriptionEventBuffer,
+        SubscriptionExecutionOptions, SubscriptionExecutionOptionsBuilder, SubscriptionFieldFilter,
+        SubscriptionLogContext, SubscriptionMetrics, SubscriptionPriority,
+        SubscriptionResultTransform, SubscriptionSchemaChangePolicy, SubscriptionSchemaSource,
+        SubscriptionValidationLimits, SystemClock, TracingResolver, UnsubscribeSignal,
+        Unsubscriber, DEFAULT_STREAM_SETUP_TIMEOUT, DEFAULT_TIMER_JITTER,
+    };
     pub use super::values::{object_value, IntoValue, MaybeCoercible};
 
     pub use super::graphql_parser::{query::Name, schema::ObjectType};
@@ -1,5 +1,6 @@
 use graph::prelude::{Query as GraphDataQuery, *};
 use graphql_parser::{query as q, Style};
+use std::collections::HashMap;
 use std::time::Instant;
 use uuid::Uuid;
 
@@ -87,6 +88,8 @@ where
         query: query.clone(),
         fields: vec![],
         deadline: options.deadline,
+        field_timeouts: Arc::new(HashMap::new()),
+        cancel_handle: None,
         max_first: options.max_first,
         block: BLOCK_NUMBER_MAX,
         mode,
@@ -835,7 +835,7 @@ fn execute_field(
         &argument_values,
         ctx.query.schema.types_for_interface(),
         ctx.block,
-        ctx.max_first,
+        ctx.max_first_for_field(field_definition, join.child_type),
     )
     .map_err(|e| vec![e])
 }
@@ -53,7 +53,8 @@ fn build_range(
                 Err("first")
             }
         }
-        Some(q::Value::Null) => Ok(100),
+        // Don't let the default exceed the limit
+        Some(q::Value::Null) => Ok(100.min(max_first)),
         _ => unreachable!("first is an Int with a default value"),
     };
 
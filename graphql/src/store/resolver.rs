@@ -4,6 +4,7 @@ use std::result;
 use std::sync::Arc;
 
 use graph::components::store::*;
+use graph::data::graphql::ext::TypeExt;
 use graph::prelude::*;
 
 use crate::prelude::*;
@@ -432,26 +433,75 @@ where
         schema: &'a s::Document,
         object_type: &'a s::ObjectType,
         field: &'b q::Field,
+        arguments: &HashMap<&q::Name, q::Value>,
     ) -> result::Result<StoreEventStreamBox, QueryExecutionError> {
         // Fail if the field does not exist on the object type
-        if sast::get_field(object_type, &field.name).is_none() {
-            return Err(QueryExecutionError::UnknownField(
-                field.position,
-                object_type.name.clone(),
-                field.name.clone(),
-            ));
-        }
+        let field_definition = match sast::get_field(object_type, &field.name) {
+            Some(field_definition) => field_definition,
+            None => {
+                return Err(QueryExecutionError::UnknownField(
+                    field.position,
+                    object_type.name.clone(),
+                    field.name.clone(),
+                ))
+            }
+        };
 
         // Collect all entities involved in the query field
         let entities = collect_entities_from_query_field(schema, object_type, field);
 
-        // Subscribe to the store and return the entity change stream
+        // Subscribe to the store and, if the field's arguments select specific
+        // entities, only pass on events that may affect those entities
         let deployment_id = parse_subgraph_id(object_type)?;
-        Ok(self.store.subscribe(entities).throttle_while_syncing(
+        let stream = match entity_ids_from_arguments(arguments) {
+            Some(entity_ids) => self.store.subscribe(entities).filter_by_entity_ids(
+                deployment_id.clone(),
+                field_definition.field_type.get_base_type().to_owned(),
+                entity_ids,
+            ),
+            None => self.store.subscribe(entities),
+        };
+
+        // Return the entity change stream
+        Ok(stream.throttle_while_syncing(
             &self.logger,
             self.store.clone(),
             deployment_id,
             *SUBSCRIPTION_THROTTLE_INTERVAL,
         ))
     }
+
+    fn latest_block(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+    ) -> result::Result<Option<BlockNumber>, QueryExecutionError> {
+        self.store
+            .block_ptr(subgraph.clone())
+            .map(|ptr| ptr.map(|ptr| ptr.number as BlockNumber))
+            .map_err(|e| StoreError::from(e).into())
+    }
+}
+
+/// Returns the IDs of the entities that a field is restricted to through
+/// its `id` argument or through `id` and `id_in` filters in its `where`
+/// argument. Returns `None` if the field is not restricted to specific IDs.
+fn entity_ids_from_arguments(arguments: &HashMap<&q::Name, q::Value>) -> Option<Vec<String>> {
+    if let Some(q::Value::String(id)) = arguments.get(&"id".to_string()) {
+        return Some(vec![id.clone()]);
+    }
+
+    match arguments.get(&"where".to_string()) {
+        Some(q::Value::Object(filter)) => match (filter.get("id"), filter.get("id_in")) {
+            (Some(q::Value::String(id)), _) => Some(vec![id.clone()]),
+            (None, Some(q::Value::List(ids))) => ids
+                .iter()
+                .map(|id| match id {
+                    q::Value::String(id) => Some(id.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        },
+        _ => None,
+    }
 }
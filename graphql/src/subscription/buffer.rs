@@ -0,0 +1,201 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+
+use graph::prelude::*;
+
+use super::SubscriptionMetrics;
+
+/// What happens to events that arrive while the buffer between the source
+/// stream of a subscription and the execution of its query is full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubscriptionBufferPolicy {
+    /// Stop reading events from the source stream until there is space.
+    Block,
+
+    /// Discard the oldest buffered event to make space for the new one. This
+    /// keeps subscriptions that can't keep up close to the latest events.
+    DropOldest,
+
+    /// Discard the new event.
+    DropNewest,
+}
+
+impl SubscriptionBufferPolicy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionBufferPolicy::Block => "block",
+            SubscriptionBufferPolicy::DropOldest => "drop_oldest",
+            SubscriptionBufferPolicy::DropNewest => "drop_newest",
+        }
+    }
+}
+
+/// A bounded buffer for the events of a subscription.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SubscriptionEventBuffer {
+    /// The maximum number of buffered events; buffers hold at least one.
+    pub size: usize,
+    pub policy: SubscriptionBufferPolicy,
+}
+
+#[derive(Default)]
+struct BufferState {
+    events: VecDeque<Result<StoreEvent, ()>>,
+    /// Whether the source stream has ended.
+    ended: bool,
+    /// Whether the consumer of the buffer is gone.
+    closed: bool,
+    consumer: Option<Waker>,
+    producer: Option<Waker>,
+}
+
+struct Buffer {
+    state: Mutex<BufferState>,
+    size: usize,
+    policy: SubscriptionBufferPolicy,
+    metrics: Option<Arc<SubscriptionMetrics>>,
+    query_id: String,
+}
+
+impl Buffer {
+    /// Whether an event can be added without dropping any, or the buffer
+    /// doesn't need events anymore. Only used with `Block`.
+    fn poll_space(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.events.len() < self.size || state.closed {
+            Poll::Ready(())
+        } else {
+            state.producer = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    fn push(&self, event: Result<StoreEvent, ()>) {
+        let mut state = self.state.lock().unwrap();
+
+        // Errors end the subscription and are never dropped
+        if state.events.len() >= self.size && event.is_ok() {
+            match self.policy {
+                // The buffer is only full here if its consumer is gone
+                SubscriptionBufferPolicy::Block => (),
+                SubscriptionBufferPolicy::DropOldest => {
+                    state.events.pop_front();
+                    self.event_dropped();
+                }
+                SubscriptionBufferPolicy::DropNewest => {
+                    self.event_dropped();
+                    return;
+                }
+            }
+        }
+
+        state.events.push_back(event);
+        if let Some(consumer) = state.consumer.take() {
+            consumer.wake();
+        }
+    }
+
+    fn event_dropped(&self) {
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.event_dropped(&self.query_id, self.policy.as_str());
+        }
+    }
+
+    fn end(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.ended = true;
+        if let Some(consumer) = state.consumer.take() {
+            consumer.wake();
+        }
+    }
+}
+
+/// The receiving end of the buffer. Dropping it stops reading events from
+/// the source stream.
+struct BufferedEvents {
+    buffer: Arc<Buffer>,
+    _guard: CancelGuard,
+}
+
+impl futures03::Stream for BufferedEvents {
+    type Item = Result<StoreEvent, ()>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.buffer.state.lock().unwrap();
+        match state.events.pop_front() {
+            Some(event) => {
+                if let Some(producer) = state.producer.take() {
+                    producer.wake();
+                }
+                Poll::Ready(Some(event))
+            }
+            None if state.ended => Poll::Ready(None),
+            None => {
+                state.consumer = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for BufferedEvents {
+    fn drop(&mut self) {
+        let mut state = self.buffer.state.lock().unwrap();
+        state.closed = true;
+        if let Some(producer) = state.producer.take() {
+            producer.wake();
+        }
+    }
+}
+
+/// Reads the events of `source` in the background into a bounded buffer
+/// and returns a stream of the buffered events. Once the buffer is full,
+/// events are handled according to the policy of `options`, and every
+/// dropped event is counted in `metrics`.
+pub(crate) fn buffered_events(
+    source: StoreEventStreamBox,
+    options: SubscriptionEventBuffer,
+    metrics: Option<Arc<SubscriptionMetrics>>,
+    query_id: String,
+) -> StoreEventStreamBox {
+    let buffer = Arc::new(Buffer {
+        state: Mutex::new(BufferState::default()),
+        size: options.size.max(1),
+        policy: options.policy,
+        metrics,
+        query_id,
+    });
+
+    // Stops the source stream as soon as the buffered stream is dropped,
+    // without waiting for the next event
+    let guard = CancelGuard::new();
+    let source = source.cancelable(&guard, || ());
+
+    // The reading task only holds a weak reference so that it doesn't keep
+    // the buffer alive
+    let weak_buffer: Weak<Buffer> = Arc::downgrade(&buffer);
+    graph::spawn(async move {
+        let mut source = source.compat();
+        while let Some(event) = source.next().await {
+            let buffer = match weak_buffer.upgrade() {
+                Some(buffer) => buffer,
+                None => return,
+            };
+            if buffer.policy == SubscriptionBufferPolicy::Block {
+                futures03::future::poll_fn(|cx| buffer.poll_space(cx)).await;
+            }
+            buffer.push(event);
+        }
+        if let Some(buffer) = weak_buffer.upgrade() {
+            buffer.end();
+        }
+    });
+
+    let events = BufferedEvents {
+        buffer,
+        _guard: guard,
+    };
+    StoreEventStream::new(Box::new(events.compat()))
+}
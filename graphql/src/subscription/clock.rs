@@ -0,0 +1,34 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Instant;
+
+use graph::prelude::tokio;
+
+/// A future that completes once a clock reaches a point in time.
+pub type ClockDelay = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Where the timers of a subscription get the time from: the keep-alive
+/// interval, the debounce window, the minimum update interval and the query
+/// permit timeout. The timeout of the query for each event is always in real
+/// time, since the query itself runs in real time. Subscriptions use
+/// `SystemClock` unless tests inject a different clock to control time.
+pub trait SubscriptionClock: Send + Sync + 'static {
+    fn now(&self) -> Instant;
+
+    /// Returns a future that completes once `now` has reached `deadline`.
+    fn delay_until(&self, deadline: Instant) -> ClockDelay;
+}
+
+/// The real time, with timers driven by tokio.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl SubscriptionClock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn delay_until(&self, deadline: Instant) -> ClockDelay {
+        Box::pin(tokio::time::delay_until(deadline.into()))
+    }
+}
@@ -0,0 +1,141 @@
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
+use std::io::Write;
+
+use graph::prelude::*;
+
+/// How a subscription marks, and possibly compresses, large results.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SubscriptionCompression {
+    pub encoding: QueryResultEncoding,
+    /// Results whose serialization is at most this many bytes are left alone.
+    pub threshold_bytes: usize,
+    /// Whether to compress results right away instead of only telling the
+    /// transport that they are worth compressing.
+    pub precompress: bool,
+}
+
+fn compress(encoding: QueryResultEncoding, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        QueryResultEncoding::Gzip => {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(bytes)?;
+            encoder.finish()
+        }
+        QueryResultEncoding::Deflate => {
+            let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+            encoder.write_all(bytes)?;
+            encoder.finish()
+        }
+    }
+}
+
+/// Sets `QueryResult::compression` on `result` if its serialization is
+/// larger than the threshold of `compression`. Keep-alive results are never
+/// compressed, and results that can't be serialized or compressed are
+/// passed on unchanged.
+pub(crate) fn compress_result(
+    mut result: QueryResult,
+    compression: &SubscriptionCompression,
+) -> QueryResult {
+    if result.is_keep_alive() {
+        return result;
+    }
+
+    let serialized = match serde_json::to_vec(&result) {
+        Ok(serialized) => serialized,
+        Err(_) => return result,
+    };
+    if serialized.len() <= compression.threshold_bytes {
+        return result;
+    }
+
+    let bytes = if compression.precompress {
+        match compress(compression.encoding, &serialized) {
+            Ok(bytes) => Some(bytes),
+            Err(_) => return result,
+        }
+    } else {
+        None
+    };
+    result.compression = Some(QueryResultCompression {
+        encoding: compression.encoding,
+        uncompressed_size: serialized.len(),
+        bytes,
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::{DeflateDecoder, GzDecoder};
+    use graphql_parser::query as q;
+    use std::io::Read;
+
+    fn result(text: &str) -> QueryResult {
+        QueryResult::new(Some(q::Value::String(text.to_owned())))
+    }
+
+    fn compression(encoding: QueryResultEncoding, precompress: bool) -> SubscriptionCompression {
+        SubscriptionCompression {
+            encoding,
+            threshold_bytes: 100,
+            precompress,
+        }
+    }
+
+    #[test]
+    fn leaves_results_below_the_threshold_alone() {
+        let small = compress_result(
+            result("small"),
+            &compression(QueryResultEncoding::Gzip, true),
+        );
+
+        assert_eq!(small.compression, None);
+    }
+
+    #[test]
+    fn only_marks_large_results_without_precompression() {
+        let large = result(&"a".repeat(200));
+        let serialized = serde_json::to_vec(&large).unwrap();
+
+        let large = compress_result(large, &compression(QueryResultEncoding::Gzip, false));
+
+        assert_eq!(
+            large.compression,
+            Some(QueryResultCompression {
+                encoding: QueryResultEncoding::Gzip,
+                uncompressed_size: serialized.len(),
+                bytes: None,
+            })
+        );
+    }
+
+    #[test]
+    fn precompresses_large_results() {
+        let large = result(&"a".repeat(200));
+        let serialized = serde_json::to_vec(&large).unwrap();
+
+        let gzip = compress_result(
+            result(&"a".repeat(200)),
+            &compression(QueryResultEncoding::Gzip, true),
+        );
+        let deflate = compress_result(large, &compression(QueryResultEncoding::Deflate, true));
+
+        let mut decompressed = vec![];
+        let gzip_bytes = gzip.compression.unwrap().bytes.unwrap();
+        GzDecoder::new(gzip_bytes.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, serialized);
+        assert!(gzip_bytes.len() < serialized.len());
+
+        let mut decompressed = vec![];
+        let deflate_bytes = deflate.compression.unwrap().bytes.unwrap();
+        DeflateDecoder::new(deflate_bytes.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, serialized);
+    }
+}
@@ -0,0 +1,165 @@
+use graphql_parser::{query as q, schema as s};
+use indexmap::IndexSet;
+use std::collections::HashSet;
+
+use graph::prelude::*;
+
+use crate::execution::{ObjectOrInterface, Query};
+use crate::schema::ast as sast;
+
+/// The entity types that a subscription reads, in the order its query
+/// references them. It is computed once when the subscription is set up, and
+/// shared by everything that needs to know which entity types the query
+/// reads, like the event filter and the limit on referenced entity types.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct EntityTypeSet {
+    entity_types: IndexSet<String>,
+}
+
+impl EntityTypeSet {
+    /// Collects the names of all types that the fields of `query`, which is
+    /// run against `subscription_type`, may resolve to. This includes the
+    /// types of nested fields and of fields in fragments, and for interfaces
+    /// all types implementing them.
+    pub fn new(query: &Query, subscription_type: &s::ObjectType) -> Self {
+        let mut entity_types = IndexSet::new();
+        collect_entity_types(
+            query,
+            subscription_type.into(),
+            &query.selection_set,
+            &mut HashSet::new(),
+            &mut entity_types,
+        );
+        EntityTypeSet { entity_types }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entity_types.len()
+    }
+
+    pub fn contains(&self, entity_type: &str) -> bool {
+        self.entity_types.contains(entity_type)
+    }
+
+    /// The entity types in the order the query references them.
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.entity_types.iter()
+    }
+
+    /// Whether `event` changes at least one entity of one of the types.
+    pub fn is_changed_by(&self, event: &StoreEvent) -> bool {
+        event
+            .changes
+            .iter()
+            .any(|change| self.contains(&change.entity_type))
+    }
+}
+
+fn collect_entity_types<'a>(
+    query: &'a Query,
+    object_type: ObjectOrInterface<'_>,
+    selection_set: &'a q::SelectionSet,
+    visited_fragments: &mut HashSet<&'a q::Name>,
+    entity_types: &mut IndexSet<String>,
+) {
+    let schema = &query.schema.document;
+
+    for selection in selection_set.items.iter() {
+        match selection {
+            q::Selection::Field(field) => {
+                if let Some(type_def) = sast::get_field(object_type, &field.name)
+                    .and_then(|field| sast::get_type_definition_from_field(schema, field))
+                {
+                    collect_entity_types_for_type(
+                        query,
+                        type_def,
+                        &field.selection_set,
+                        visited_fragments,
+                        entity_types,
+                    );
+                }
+            }
+            q::Selection::FragmentSpread(spread) => {
+                // Only visit each fragment once, even if it is spread
+                // several times
+                if !visited_fragments.insert(&spread.fragment_name) {
+                    continue;
+                }
+                if let Some(fragment) = query.get_fragment(&spread.fragment_name) {
+                    let q::TypeCondition::On(name) = &fragment.type_condition;
+                    if let Some(type_def) = sast::get_named_type(schema, name) {
+                        collect_entity_types_for_type(
+                            query,
+                            type_def,
+                            &fragment.selection_set,
+                            visited_fragments,
+                            entity_types,
+                        );
+                    }
+                }
+            }
+            q::Selection::InlineFragment(fragment) => match &fragment.type_condition {
+                Some(q::TypeCondition::On(name)) => {
+                    if let Some(type_def) = sast::get_named_type(schema, name) {
+                        collect_entity_types_for_type(
+                            query,
+                            type_def,
+                            &fragment.selection_set,
+                            visited_fragments,
+                            entity_types,
+                        );
+                    }
+                }
+                None => collect_entity_types(
+                    query,
+                    object_type,
+                    &fragment.selection_set,
+                    visited_fragments,
+                    entity_types,
+                ),
+            },
+        }
+    }
+}
+
+/// Collects the types that `type_def` stands for as well as the types
+/// referenced by the `selection_set` that is applied to it.
+fn collect_entity_types_for_type<'a>(
+    query: &'a Query,
+    type_def: &s::TypeDefinition,
+    selection_set: &'a q::SelectionSet,
+    visited_fragments: &mut HashSet<&'a q::Name>,
+    entity_types: &mut IndexSet<String>,
+) {
+    match type_def {
+        s::TypeDefinition::Object(t) => {
+            entity_types.insert(t.name.clone());
+            collect_entity_types(
+                query,
+                t.into(),
+                selection_set,
+                visited_fragments,
+                entity_types,
+            );
+        }
+        s::TypeDefinition::Interface(t) => {
+            entity_types.extend(
+                query
+                    .schema
+                    .types_for_interface()
+                    .get(&t.name)
+                    .into_iter()
+                    .flatten()
+                    .map(|object_type| object_type.name.clone()),
+            );
+            collect_entity_types(
+                query,
+                t.into(),
+                selection_set,
+                visited_fragments,
+                entity_types,
+            );
+        }
+        _ => (),
+    }
+}
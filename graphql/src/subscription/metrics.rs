@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use graph::prelude::*;
+
+/// Metrics for the queries that subscriptions run in response to store events.
+///
+/// All metrics are labeled with the `query_id` of the subscription query so
+/// that they can be correlated with the subscription's log lines. Identical
+/// subscriptions share a `query_id`, which keeps the number of label values
+/// bounded by the number of distinct queries. The gauges are process-wide.
+pub struct SubscriptionMetrics {
+    query_execution_time: Box<HistogramVec>,
+    query_permit_wait_time: Box<HistogramVec>,
+    query_result_size: Box<HistogramVec>,
+    events_processed: Box<CounterVec>,
+    events_overloaded: Box<CounterVec>,
+    events_dropped: Box<CounterVec>,
+    active_subscriptions: Box<Gauge>,
+    query_permits_in_use: Box<Gauge>,
+    query_permit_waiters: Box<Gauge>,
+}
+
+/// Increments a gauge while it is alive. Since the gauge is decremented when
+/// the guard is dropped, it stays accurate when the stream or future holding
+/// the guard is dropped before it completes.
+pub(crate) struct GaugeGuard(Gauge);
+
+impl GaugeGuard {
+    fn new(gauge: &Gauge) -> Self {
+        gauge.inc();
+        GaugeGuard(gauge.clone())
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+impl fmt::Debug for SubscriptionMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SubscriptionMetrics {{ }}")
+    }
+}
+
+impl SubscriptionMetrics {
+    pub fn new(registry: Arc<impl MetricsRegistry>) -> Self {
+        let query_execution_time = registry
+            .new_histogram_vec(
+                String::from("subscription_query_execution_time"),
+                String::from("Execution time for subscription queries"),
+                HashMap::new(),
+                vec![String::from("query_id")],
+                vec![0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0],
+            )
+            .expect("failed to create `subscription_query_execution_time` histogram");
+        let query_permit_wait_time = registry
+            .new_histogram_vec(
+                String::from("subscription_query_permit_wait_time"),
+                String::from("Time subscription queries wait for a query permit"),
+                HashMap::new(),
+                vec![String::from("query_id")],
+                vec![0.001, 0.01, 0.1, 0.5, 1.0, 5.0, 10.0],
+            )
+            .expect("failed to create `subscription_query_permit_wait_time` histogram");
+        let query_result_size = registry
+            .new_histogram_vec(
+                String::from("subscription_query_result_size"),
+                String::from("Size of serialized subscription query results in bytes"),
+                HashMap::new(),
+                vec![String::from("query_id")],
+                vec![100.0, 1_000.0, 10_000.0, 100_000.0, 1_000_000.0],
+            )
+            .expect("failed to create `subscription_query_result_size` histogram");
+        let events_processed = registry
+            .new_counter_vec(
+                String::from("subscription_events_processed"),
+                String::from("Number of events subscriptions have run their query for"),
+                HashMap::new(),
+                vec![String::from("query_id")],
+            )
+            .expect("failed to create `subscription_events_processed` counter");
+        let events_overloaded = registry
+            .new_counter_vec(
+                String::from("subscription_events_overloaded"),
+                String::from("Number of events that got no query permit in time"),
+                HashMap::new(),
+                vec![String::from("query_id")],
+            )
+            .expect("failed to create `subscription_events_overloaded` counter");
+        let events_dropped = registry
+            .new_counter_vec(
+                String::from("subscription_events_dropped"),
+                String::from("Number of events dropped because the event buffer was full"),
+                HashMap::new(),
+                vec![String::from("query_id"), String::from("policy")],
+            )
+            .expect("failed to create `subscription_events_dropped` counter");
+        let active_subscriptions = registry
+            .new_gauge(
+                String::from("subscriptions_active"),
+                String::from("Number of subscriptions that are currently active"),
+                HashMap::new(),
+            )
+            .expect("failed to create `subscriptions_active` gauge");
+        let query_permits_in_use = registry
+            .new_gauge(
+                String::from("subscription_query_permits_in_use"),
+                String::from("Number of subscription queries that hold a query permit"),
+                HashMap::new(),
+            )
+            .expect("failed to create `subscription_query_permits_in_use` gauge");
+        let query_permit_waiters = registry
+            .new_gauge(
+                String::from("subscription_query_permit_waiters"),
+                String::from("Number of subscription events waiting for a query permit"),
+                HashMap::new(),
+            )
+            .expect("failed to create `subscription_query_permit_waiters` gauge");
+
+        Self {
+            query_execution_time,
+            query_permit_wait_time,
+            query_result_size,
+            events_processed,
+            events_overloaded,
+            events_dropped,
+            active_subscriptions,
+            query_permits_in_use,
+            query_permit_waiters,
+        }
+    }
+
+    pub fn observe_query_execution_time(&self, query_id: &str, duration: f64) {
+        self.query_execution_time
+            .with_label_values(&[query_id])
+            .observe(duration);
+    }
+
+    pub fn observe_query_permit_wait_time(&self, query_id: &str, duration: f64) {
+        self.query_permit_wait_time
+            .with_label_values(&[query_id])
+            .observe(duration);
+    }
+
+    pub fn observe_query_result_size(&self, query_id: &str, bytes: usize) {
+        self.query_result_size
+            .with_label_values(&[query_id])
+            .observe(bytes as f64);
+    }
+
+    pub fn event_processed(&self, query_id: &str) {
+        self.events_processed.with_label_values(&[query_id]).inc();
+    }
+
+    pub fn event_overloaded(&self, query_id: &str) {
+        self.events_overloaded.with_label_values(&[query_id]).inc();
+    }
+
+    pub fn event_dropped(&self, query_id: &str, policy: &str) {
+        self.events_dropped
+            .with_label_values(&[query_id, policy])
+            .inc();
+    }
+
+    /// Counts a subscription as active until the guard is dropped.
+    pub(crate) fn subscription_active(&self) -> GaugeGuard {
+        GaugeGuard::new(&self.active_subscriptions)
+    }
+
+    /// Counts a query as holding a query permit until the guard is dropped.
+    pub(crate) fn query_permit_in_use(&self) -> GaugeGuard {
+        GaugeGuard::new(&self.query_permits_in_use)
+    }
+
+    /// Counts an event as waiting for a query permit until the guard is
+    /// dropped.
+    pub(crate) fn query_permit_waiting(&self) -> GaugeGuard {
+        GaugeGuard::new(&self.query_permit_waiters)
+    }
+}
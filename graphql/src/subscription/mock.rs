@@ -0,0 +1,250 @@
+use graphql_parser::{query as q, schema as s};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+use std::thread::ThreadId;
+use std::time::{Duration, Instant};
+
+use graph::prelude::futures03::channel::mpsc;
+use graph::prelude::*;
+
+use crate::execution::{ExecutionContext, ObjectOrInterface, Resolver};
+use crate::prelude::{Name, ObjectType};
+use crate::query::ext::BlockConstraint;
+
+use super::clock::{ClockDelay, SubscriptionClock};
+
+/// A resolver for testing subscriptions deterministically. Tests decide
+/// which store events the field streams of a subscription emit and when,
+/// and what the fields of the subscription query resolve to.
+///
+/// Fields resolve to the value stubbed for their response key with
+/// `stub_field`; list fields without a stub resolve to an empty list and
+/// other fields to `null`. The resolver never prefetches, and claims to have
+/// indexed up to `BLOCK_NUMBER_MAX`. It declares that it may block unless
+/// a test calls `declare_non_blocking`.
+///
+/// Clones share their field streams and stubs, so a test can keep a clone
+/// around after passing the resolver to `execute_subscription`.
+#[derive(Clone, Default)]
+pub struct MockSubscriptionResolver {
+    streams: Arc<Mutex<Vec<mpsc::UnboundedSender<StoreEvent>>>>,
+    stubs: Arc<Mutex<HashMap<String, q::Value>>>,
+    stream_setup_delay: Arc<Mutex<Option<Duration>>>,
+    unsubscribable_fields: Arc<Mutex<HashSet<String>>>,
+    non_blocking: Arc<AtomicBool>,
+    list_threads: Arc<Mutex<Vec<ThreadId>>>,
+}
+
+impl MockSubscriptionResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes fields with the response key `key` resolve to `value` from now
+    /// on. Queries that are already being executed may or may not see it.
+    pub fn stub_field(&self, key: &str, value: q::Value) {
+        self.stubs.lock().unwrap().insert(key.to_owned(), value);
+    }
+
+    /// Sends `event` on every field stream that is still open. Events that
+    /// are sent before a subscription reads them are buffered.
+    pub fn push_event(&self, event: StoreEvent) {
+        self.streams
+            .lock()
+            .unwrap()
+            .retain(|stream| stream.unbounded_send(event.clone()).is_ok());
+    }
+
+    /// Ends all field streams gracefully, as happens when a subgraph is
+    /// removed.
+    pub fn end_events(&self) {
+        self.streams.lock().unwrap().clear();
+    }
+
+    /// Makes opening a field stream take `delay`, like a slow store would.
+    pub fn delay_stream_setup(&self, delay: Duration) {
+        *self.stream_setup_delay.lock().unwrap() = Some(delay);
+    }
+
+    /// Makes fields named `name` not subscribable from now on.
+    pub fn disable_field_stream(&self, name: &str) {
+        self.unsubscribable_fields
+            .lock()
+            .unwrap()
+            .insert(name.to_owned());
+    }
+
+    /// Makes the resolver declare that it doesn't block, see
+    /// `Resolver::is_blocking`.
+    pub fn declare_non_blocking(&self) {
+        self.non_blocking.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns the threads that list fields were resolved on, in order.
+    pub fn list_threads(&self) -> Vec<ThreadId> {
+        self.list_threads.lock().unwrap().clone()
+    }
+
+    /// Returns the number of field streams that are still open.
+    pub fn open_streams(&self) -> usize {
+        let mut streams = self.streams.lock().unwrap();
+        streams.retain(|stream| !stream.is_closed());
+        streams.len()
+    }
+
+    fn stub(&self, field: &q::Field) -> Option<q::Value> {
+        let key = field.alias.as_ref().unwrap_or(&field.name);
+        self.stubs.lock().unwrap().get(key).cloned()
+    }
+}
+
+impl Resolver for MockSubscriptionResolver {
+    fn prefetch(
+        &self,
+        _: &ExecutionContext<Self>,
+        _: &q::SelectionSet,
+    ) -> Result<Option<q::Value>, Vec<QueryExecutionError>> {
+        Ok(None)
+    }
+
+    fn locate_block(&self, _: &BlockConstraint) -> Result<BlockNumber, QueryExecutionError> {
+        Ok(BLOCK_NUMBER_MAX)
+    }
+
+    fn resolve_objects(
+        &self,
+        _: &Option<q::Value>,
+        field: &q::Field,
+        _: &s::Field,
+        _: ObjectOrInterface<'_>,
+        _: &HashMap<&q::Name, q::Value>,
+        _: &BTreeMap<Name, Vec<ObjectType>>,
+        _: BlockNumber,
+        _: u32,
+    ) -> Result<q::Value, QueryExecutionError> {
+        self.list_threads
+            .lock()
+            .unwrap()
+            .push(std::thread::current().id());
+        Ok(self.stub(field).unwrap_or(q::Value::List(vec![])))
+    }
+
+    fn resolve_object(
+        &self,
+        _: &Option<q::Value>,
+        field: &q::Field,
+        _: &s::Field,
+        _: ObjectOrInterface<'_>,
+        _: &HashMap<&q::Name, q::Value>,
+        _: &BTreeMap<Name, Vec<ObjectType>>,
+        _: BlockNumber,
+    ) -> Result<q::Value, QueryExecutionError> {
+        Ok(self.stub(field).unwrap_or(q::Value::Null))
+    }
+
+    fn resolve_field_stream<'a, 'b>(
+        &self,
+        _: &'a s::Document,
+        _: &'a s::ObjectType,
+        _: &'b q::Field,
+        _: &HashMap<&q::Name, q::Value>,
+    ) -> Result<StoreEventStreamBox, QueryExecutionError> {
+        let delay = *self.stream_setup_delay.lock().unwrap();
+        if let Some(delay) = delay {
+            std::thread::sleep(delay);
+        }
+        let (sender, receiver) = mpsc::unbounded();
+        self.streams.lock().unwrap().push(sender);
+        Ok(StoreEventStream::new(Box::new(
+            receiver.map(Ok::<_, ()>).compat(),
+        )))
+    }
+
+    fn supports_field_stream(&self, _: &s::Document, _: &s::ObjectType, field: &q::Field) -> bool {
+        !self
+            .unsubscribable_fields
+            .lock()
+            .unwrap()
+            .contains(&field.name)
+    }
+
+    fn is_blocking(&self) -> bool {
+        !self.non_blocking.load(Ordering::SeqCst)
+    }
+
+    fn latest_block(
+        &self,
+        _: &SubgraphDeploymentId,
+    ) -> Result<Option<BlockNumber>, QueryExecutionError> {
+        Ok(Some(BLOCK_NUMBER_MAX))
+    }
+}
+
+/// A clock for testing timer-driven subscription behavior deterministically.
+/// Time stands still until a test calls `advance`, which completes all
+/// delays that are due by then.
+///
+/// Clones share their time, so a test can keep a clone around after passing
+/// the clock to `execute_subscription`.
+#[derive(Clone)]
+pub struct MockClock {
+    state: Arc<Mutex<MockClockState>>,
+}
+
+struct MockClockState {
+    now: Instant,
+    /// Tasks waiting for a delay that isn't due yet.
+    waiting: Vec<Waker>,
+}
+
+impl MockClock {
+    /// Creates a clock that starts at the current time.
+    pub fn new() -> Self {
+        MockClock {
+            state: Arc::new(Mutex::new(MockClockState {
+                now: Instant::now(),
+                waiting: vec![],
+            })),
+        }
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let waiting = {
+            let mut state = self.state.lock().unwrap();
+            state.now += duration;
+            std::mem::replace(&mut state.waiting, vec![])
+        };
+        // Delays that are not due yet wait again when they are polled
+        for waker in waiting {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SubscriptionClock for MockClock {
+    fn now(&self) -> Instant {
+        self.state.lock().unwrap().now
+    }
+
+    fn delay_until(&self, deadline: Instant) -> ClockDelay {
+        let state = self.state.clone();
+        Box::pin(futures03::future::poll_fn(move |cx: &mut Context<'_>| {
+            let mut state = state.lock().unwrap();
+            if state.now >= deadline {
+                Poll::Ready(())
+            } else {
+                state.waiting.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }))
+    }
+}
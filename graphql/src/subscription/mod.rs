@@ -1,21 +1,53 @@
 use graphql_parser::{query as q, schema as s, Style};
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::panic::AssertUnwindSafe;
 use std::result::Result;
+use std::sync::Mutex;
+use std::task::Poll;
 use std::time::{Duration, Instant};
-use tokio::sync::Semaphore;
+use uuid::Uuid;
 
 use graph::prelude::*;
 
 use crate::execution::*;
+use crate::introspection::IntrospectionResolver;
+use crate::query::ast as qast;
 use crate::schema::ast as sast;
 
 use lazy_static::lazy_static;
 
+mod buffer;
+mod clock;
+mod compression;
+mod entity_types;
+mod metrics;
+#[cfg(any(test, feature = "test-support"))]
+mod mock;
+mod permits;
+mod shared_stream;
+mod tracing;
+mod unsubscribe;
+
+pub use self::buffer::{SubscriptionBufferPolicy, SubscriptionEventBuffer};
+pub use self::clock::{ClockDelay, SubscriptionClock, SystemClock};
+pub use self::compression::SubscriptionCompression;
+use self::entity_types::EntityTypeSet;
+pub use self::metrics::SubscriptionMetrics;
+#[cfg(any(test, feature = "test-support"))]
+pub use self::mock::{MockClock, MockSubscriptionResolver};
+use self::permits::QueryPermit;
+pub use self::permits::{QueryPermits, SubscriptionPriority};
+pub use self::shared_stream::SharedStreamResolver;
+pub use self::tracing::TracingResolver;
+pub use self::unsubscribe::{UnsubscribeSignal, Unsubscriber};
+
 lazy_static! {
-    static ref SUBSCRIPTION_QUERY_SEMAPHORE: Semaphore = {
+    static ref SUBSCRIPTION_QUERY_PERMITS: Arc<QueryPermits> = {
         // This is duplicating the logic in main.rs to get the connection pool size, which is
         // unfortunate. But because this module has no share state otherwise, it's not simple to
-        // refactor so that the semaphore isn't a global.
+        // refactor so that the permits aren't a global.
         // See also 82d5dad6-b633-4350-86d9-70c8b2e65805
         let db_conn_pool_size = std::env::var("STORE_CONNECTION_POOL_SIZE")
             .unwrap_or("10".into())
@@ -23,11 +55,153 @@ lazy_static! {
             .expect("invalid STORE_CONNECTION_POOL_SIZE");
 
         // Limit the amount of connections that can be taken up by subscription queries.
-        Semaphore::new((0.7 * db_conn_pool_size as f64).ceil() as usize)
+        Arc::new(QueryPermits::new((0.7 * db_conn_pool_size as f64).ceil() as usize))
     };
+
+    // Number of active subscriptions for each connection that identifies itself
+    // through `SubscriptionExecutionOptions::connection_id`.
+    static ref ACTIVE_SUBSCRIPTIONS: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+}
+
+/// How long opening the event stream for a top-level subscription field may
+/// take by default, see `SubscriptionExecutionOptions::stream_setup_timeout`.
+pub const DEFAULT_STREAM_SETUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How much subscription timers are randomly extended by default, see
+/// `SubscriptionExecutionOptions::timer_jitter`.
+pub const DEFAULT_TIMER_JITTER: f64 = 0.1;
+
+/// Returns the query permits that are shared by all subscriptions that don't
+/// set `SubscriptionExecutionOptions::query_permits`.
+pub fn shared_subscription_query_permits() -> Arc<QueryPermits> {
+    SUBSCRIPTION_QUERY_PERMITS.clone()
+}
+
+/// How the results of a subscription are delivered to the client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubscriptionDeliveryMode {
+    /// Every result contains the full query result.
+    Full,
+
+    /// The first result contains the full query result; every later result
+    /// only contains the paths that changed since the previous result.
+    Diff,
+}
+
+/// The blocks a subscription executes its query at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SubscriptionBlockRange {
+    pub from: BlockNumber,
+    pub to: BlockNumber,
+}
+
+/// A function that every result of a subscription query is passed through
+/// before it is sent, see `SubscriptionExecutionOptions::result_transform`.
+pub type SubscriptionResultTransform = Arc<dyn Fn(QueryResult) -> QueryResult + Send + Sync>;
+
+/// Key-value pairs that are added to every log line of a subscription, see
+/// `SubscriptionExecutionOptions::log_context`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SubscriptionLogContext {
+    values: Vec<(&'static str, String)>,
+}
+
+impl SubscriptionLogContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `key` with `value` to the context.
+    pub fn add(mut self, key: &'static str, value: impl Into<String>) -> Self {
+        self.values.push((key, value.into()));
+        self
+    }
+}
+
+impl slog::KV for SubscriptionLogContext {
+    fn serialize(&self, _: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        for (key, value) in self.values.iter() {
+            serializer.emit_str(key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Looks up the current schema of the subgraph a subscription was made for,
+/// see `SubscriptionExecutionOptions::schema_source`. Returns `None` if it
+/// can't be determined.
+pub type SubscriptionSchemaSource = Arc<dyn Fn() -> Option<Arc<Schema>> + Send + Sync>;
+
+/// What an active subscription does when the schema of its subgraph changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubscriptionSchemaChangePolicy {
+    /// Keep executing the query against the schema the subscription was
+    /// validated against.
+    Pin,
+
+    /// Validate the query against the new schema. If it is still valid, keep
+    /// executing it against the original schema; otherwise end the
+    /// subscription with a `QueryResult::schema_changed` result. The events
+    /// of the subscription still come from the original deployment, which is
+    /// why revalidating only decides whether the subscription ends.
+    Revalidate,
+}
+
+/// What an active subscription does after a result with errors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubscriptionErrorPolicy {
+    /// Keep executing the query for later events, which may succeed again.
+    ContinueOnError,
+
+    /// Send the result with errors, and end the subscription after it.
+    TerminateOnError,
+}
+
+/// How a subscription treats an error in one of its results, see
+/// `SubscriptionExecutionOptions::error_classifier`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SubscriptionErrorSeverity {
+    /// Send the error as a warning in `QueryResult::warnings` and keep the
+    /// subscription going, whatever its error policy.
+    Warning,
+
+    /// Send the error in the errors of the result; the error policy of the
+    /// subscription decides whether it ends after the result.
+    Error,
+
+    /// Send the error in the errors of the result, and end the subscription
+    /// after it, whatever its error policy.
+    Fatal,
+}
+
+/// A function that decides how a subscription treats each error in its
+/// results, see `SubscriptionExecutionOptions::error_classifier`.
+pub type SubscriptionErrorClassifier =
+    Arc<dyn Fn(&QueryError) -> SubscriptionErrorSeverity + Send + Sync>;
+
+/// Which top-level fields of the subscription type subscriptions may select.
+/// Fields that aren't permitted can still be queried.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SubscriptionFieldFilter {
+    /// Only these fields may be selected.
+    Allow(HashSet<String>),
+
+    /// All fields except these may be selected.
+    Deny(HashSet<String>),
+}
+
+impl SubscriptionFieldFilter {
+    /// Whether subscriptions may select the field named `field`.
+    pub fn permits(&self, field: &str) -> bool {
+        match self {
+            SubscriptionFieldFilter::Allow(fields) => fields.contains(field),
+            SubscriptionFieldFilter::Deny(fields) => !fields.contains(field),
+        }
+    }
 }
 
-/// Options available for subscription execution.
+/// Options available for subscription execution. Prefer creating them with
+/// `SubscriptionExecutionOptions::builder`.
 pub struct SubscriptionExecutionOptions<R>
 where
     R: Resolver,
@@ -35,12 +209,33 @@ where
     /// The logger to use during subscription execution.
     pub logger: Logger,
 
+    /// If set, these values are added to every log line of the subscription,
+    /// including the ones for the queries it runs for events, e.g. to
+    /// identify the tenant or connection it belongs to.
+    pub log_context: Option<SubscriptionLogContext>,
+
     /// The resolver to use.
     pub resolver: R,
 
     /// Individual timeout for each subscription query.
     pub timeout: Option<Duration>,
 
+    /// How long the queries for all events of the subscription may run in
+    /// total over its lifetime, not counting the time they wait for a query
+    /// permit. Once the queries have run for longer, the next event ends the
+    /// subscription with a `QueryExecutionError::ExecutionBudgetExhausted`
+    /// error instead of running the query again. This keeps a single
+    /// long-lived subscription from using up a shared node, independently of
+    /// `timeout`, which limits the query for each event. If this is `None`,
+    /// the subscription may run its queries for as long as it is active.
+    pub lifetime_execution_budget: Option<Duration>,
+
+    /// Timeouts for individual fields, keyed by `<type name>.<field name>`,
+    /// e.g. `Subscription.tokens`. A field that runs out of time fails with
+    /// a `FieldTimeout` error while the other fields of its result are still
+    /// sent. Fields without an entry only have the whole-query `timeout`.
+    pub field_timeouts: HashMap<String, Duration>,
+
     /// Maximum complexity for a subscription query.
     pub max_complexity: Option<u64>,
 
@@ -49,6 +244,726 @@ where
 
     /// Maximum value for the `first` argument.
     pub max_first: u32,
+
+    /// Maximum size of a serialized subscription result. Results that are
+    /// larger are replaced with an error.
+    pub max_result_size_bytes: Option<usize>,
+
+    /// If set, results whose serialization is larger than its threshold carry
+    /// a `QueryResult::compression` hint, and optionally the compressed
+    /// result, so that transports can compress them without serializing them
+    /// again. This happens after `max_result_size_bytes` has been checked
+    /// and, in diff mode, for the diff that is sent. If this is `None`,
+    /// results are sent uncompressed.
+    pub compression: Option<SubscriptionCompression>,
+
+    /// The query permits that limit how many queries this subscription and
+    /// every other subscription with the same permits run concurrently. The
+    /// caller owns them, and can read how many are available for metrics. If
+    /// this is `None`, queries are limited by the query permits that are
+    /// shared by all subscriptions without permits of their own and sized
+    /// based on `STORE_CONNECTION_POOL_SIZE`, see
+    /// `shared_subscription_query_permits`. Either way, query permits are
+    /// handed out by `priority`, and fairly within a priority, see
+    /// `acquire_query_permit`.
+    pub query_permits: Option<Arc<QueryPermits>>,
+
+    /// How urgently the queries of this subscription get query permits when
+    /// queries of other subscriptions wait for them as well. Events of
+    /// `Interactive` subscriptions get permits ahead of `Normal` ones, and
+    /// those ahead of `Bulk` ones, but events of lower priorities still get
+    /// a permit after a bounded number of events of higher priorities were
+    /// preferred to them. Transports can set this per connection, e.g. to
+    /// let dashboards preempt analytics.
+    pub priority: SubscriptionPriority,
+
+    /// How long the query for an event may wait for a query permit. If no
+    /// permit becomes available in time, the result for that event is a
+    /// `QueryExecutionError::Overloaded` error instead. If this is `None`,
+    /// queries wait for a permit indefinitely.
+    pub query_permit_timeout: Option<Duration>,
+
+    /// How many events the subscription may execute its query for at the
+    /// same time. Results are still sent in the order of the events that
+    /// caused them, so a slow query holds back the results, but not the
+    /// execution, of the queries for later events. Every query still needs a
+    /// query permit. With the default of 1, queries are executed one after
+    /// the other.
+    pub max_in_flight_queries: usize,
+
+    /// Whether to execute the query for each event inline on the async
+    /// runtime if the resolver declares that it doesn't block, see
+    /// `Resolver::is_blocking`. This saves the hop to the blocking thread
+    /// pool for resolvers that are backed by in-memory data. Queries for
+    /// resolvers that may block are always executed on the blocking thread
+    /// pool, and every query still needs a query permit.
+    pub execute_inline: bool,
+
+    /// How long the resolver may take to open the event stream for each
+    /// top-level field when the subscription is set up. If it takes longer,
+    /// `execute_subscription` fails with `SubscriptionError::StreamSetupTimeout`
+    /// instead of waiting for a store that hangs. This is separate from
+    /// `timeout`, which only applies to executing the query for events. If
+    /// this is `None`, or `execute_subscription` isn't called within a tokio
+    /// runtime, setting up the streams isn't limited.
+    pub stream_setup_timeout: Option<Duration>,
+
+    /// How long setting up the subscription may take in total, from
+    /// validating the query through coercing the arguments of its top-level
+    /// fields to opening all of their event streams. If it takes longer,
+    /// `execute_subscription` fails with `SubscriptionError::SetupTimeout`.
+    /// Opening each stream is still limited by `stream_setup_timeout` as
+    /// well. If this is `None`, only the individual streams are limited.
+    pub setup_timeout: Option<Duration>,
+
+    /// If set, events that arrive within this window of each other are
+    /// merged and cause only a single re-execution of the subscription query.
+    pub subscription_debounce: Option<Duration>,
+
+    /// Whether to re-execute the subscription query at most once per block.
+    /// Events for the same block that are waiting to be processed are merged
+    /// into the latest of them. Unlike debouncing, this doesn't delay any
+    /// event; it relies on the store setting `StoreEvent::block`, and events
+    /// without a block are never merged. A revert followed by a different
+    /// block with the same number still causes a re-execution for each.
+    pub once_per_block: bool,
+
+    /// If set, the subscription sends at most one result per interval.
+    /// Events that arrive before the interval since the last result has
+    /// passed are merged, and the merged event is executed as soon as it
+    /// has. Unlike debouncing, a steady stream of events still causes a
+    /// result every interval, and the latest state after a burst is always
+    /// sent. The initial result counts as a result.
+    pub min_update_interval: Option<Duration>,
+
+    /// If set, events are read from the store in the background into a
+    /// buffer of this size while the query for an earlier event is being
+    /// executed. What happens to events once the buffer is full depends on
+    /// its policy. If this is `None`, events are only read when the query
+    /// for the previous event has finished.
+    pub event_buffer: Option<SubscriptionEventBuffer>,
+
+    /// Whether to send full results or only the changes to the previous result.
+    pub delivery_mode: SubscriptionDeliveryMode,
+
+    /// Whether to drop results whose data is the same as that of the last
+    /// result that was sent, e.g. because the event changed a field that
+    /// the query doesn't select. Only results with data and without errors
+    /// or warnings are compared, and the first result is always sent. Data
+    /// is compared before it is turned into a diff, and a hash of it is
+    /// compared before the data itself.
+    pub suppress_duplicate_results: bool,
+
+    /// Whether the subscription ends after a result with errors, including
+    /// partial results where only some fields failed. An error from the store
+    /// event stream always ends the subscription, since no further events
+    /// can be expected from it. Neither sends a `QueryResult::completed`
+    /// result.
+    pub error_policy: SubscriptionErrorPolicy,
+
+    /// If set, decides for every error in a result whether it is only a
+    /// warning, an error that is subject to `error_policy`, or fatal. A
+    /// result with a fatal error ends the subscription. Warnings are moved
+    /// from the errors of the result to `QueryResult::warnings`, so that
+    /// clients that treat every result with errors as failed keep using the
+    /// rest of the result. If this is `None`, all errors are subject to
+    /// `error_policy`. The error from a broken store event stream is not
+    /// classified and always ends the subscription.
+    pub error_classifier: Option<SubscriptionErrorClassifier>,
+
+    /// Identifies the connection the subscription was made on. Subscriptions
+    /// without a connection id are not counted towards any connection.
+    pub connection_id: Option<String>,
+
+    /// Maximum number of subscriptions that may be active on one connection.
+    pub max_subscriptions_per_connection: Option<usize>,
+
+    /// If set, an empty keep-alive result is sent whenever no other result
+    /// has been sent for this long.
+    pub keep_alive_interval: Option<Duration>,
+
+    /// The fraction by which the keep-alive interval, the debounce window and
+    /// the minimum update interval are randomly extended, so that the timers
+    /// of many subscriptions with the same settings don't fire at the same
+    /// time. With a jitter of 0.1, a keep-alive interval of 10s becomes one
+    /// between 10s and 11s. Timers never fire earlier than configured. The
+    /// keep-alive and minimum update intervals are jittered anew every time
+    /// their timer is set, the debounce window once per subscription. A
+    /// jitter of 0 turns this off.
+    pub timer_jitter: f64,
+
+    /// The clock that the keep-alive interval, the debounce window, the
+    /// minimum update interval and the query permit timeout are measured
+    /// with, including how long an event waited for a permit. `timeout` is
+    /// measured in real time regardless. This is `SystemClock` except in
+    /// tests that control time with a `MockClock`.
+    pub clock: Arc<dyn SubscriptionClock>,
+
+    /// Metrics to record for the queries this subscription runs.
+    pub metrics: Option<Arc<SubscriptionMetrics>>,
+
+    /// Whether the subscription may select more than one top-level field.
+    ///
+    /// This is not standard GraphQL: the spec requires subscriptions to have
+    /// exactly one top-level field. When enabled, the subscription listens to
+    /// the events of all of its top-level fields and every event re-executes
+    /// the full selection set.
+    pub allow_multiple_subscription_fields: bool,
+
+    /// If set, subscriptions that select a top-level field the filter
+    /// doesn't permit are rejected with `SubscriptionError::FieldNotPermitted`
+    /// before any event stream is opened.
+    pub field_filter: Option<SubscriptionFieldFilter>,
+
+    /// If set, subscriptions whose query may read more than this many
+    /// distinct entity types are rejected with
+    /// `SubscriptionError::TooManyEntityTypes` before any event stream is
+    /// opened. Each of these types makes the subscription re-execute for
+    /// changes to it.
+    pub max_referenced_entity_types: Option<usize>,
+
+    /// Whether to execute the subscription query right away and send its
+    /// result before any event arrives. The GraphQL over WebSocket protocol
+    /// requires at least one result per subscription; clients that turn this
+    /// off only receive results for actual changes.
+    pub emit_initial_result: bool,
+
+    /// Whether to start executing the query for the initial result in the
+    /// background while the subscription is being set up, instead of when
+    /// the client starts reading results. The initial result is then sent
+    /// as soon as it is requested if that query has finished. It is subject
+    /// to the same timeout, complexity limits and query permits as every
+    /// other result. Has no effect without `emit_initial_result`.
+    pub prewarm_initial_result: bool,
+
+    /// If set, the subscription resumes after the event with this tag: only
+    /// events with a higher tag cause results, and no initial result is sent.
+    ///
+    /// Tags are assigned in increasing order when a `StoreEvent` is created,
+    /// so every event that is created after the event with the given tag has
+    /// a higher tag. Tags are only unique within one node process and start
+    /// over when it restarts.
+    pub resume_from_tag: Option<usize>,
+
+    /// If set, the subscription stops as soon as the corresponding guard is
+    /// canceled or dropped. This also aborts a query that is being executed
+    /// for an event, at the latest before it resolves its next field.
+    pub cancel_handle: Option<CancelHandle>,
+
+    /// If set, the subscription ends with a last `QueryResult::unsubscribed`
+    /// result once the corresponding `Unsubscriber` is used. This is meant
+    /// for clients that unsubscribe deliberately, while `cancel_handle` is
+    /// meant for connections that go away.
+    pub unsubscribe_signal: Option<UnsubscribeSignal>,
+
+    /// Whether the subscription may select the introspection fields
+    /// `__schema` and `__type`. Subscriptions that select them while this is
+    /// off are rejected; `__typename` is always allowed.
+    pub allow_introspection: bool,
+
+    /// How the subscription query is executed. With `ExecutionMode::Verify`,
+    /// the query for every event is also executed with prefetching, and the
+    /// result is an `IncorrectPrefetchResult` error if the two differ.
+    pub execution_mode: ExecutionMode,
+
+    /// If set, the query for an event is executed at the latest block the
+    /// subgraph has been indexed up to, but never past `to`. Events that
+    /// arrive before the subgraph reaches `from` don't cause results, and the
+    /// response stream completes after the result at block `to` has been
+    /// sent. A range where `from` and `to` are the same pins the subscription
+    /// to that block. If this is `None`, queries run at the latest block.
+    pub block_range: Option<SubscriptionBlockRange>,
+
+    /// If set, the result of the query for every event is passed through
+    /// this function before it is sent. The transform sees the result after
+    /// `max_result_size_bytes` has been checked, so it can't be used to get
+    /// around that limit, and before results are turned into diffs with
+    /// `SubscriptionDeliveryMode::Diff`, so it always sees full results.
+    /// Results the subscription generates itself, i.e. keep-alives, the
+    /// completion result and event stream errors, are not transformed.
+    pub result_transform: Option<SubscriptionResultTransform>,
+
+    /// Whether `result_transform` is also applied to the initial result.
+    pub transform_initial_result: bool,
+
+    /// Whether results report the tag of the event that caused them and the
+    /// entity changes in it, see `QueryResultEvent`. This helps with
+    /// debugging why a result was sent, but makes every result larger. The
+    /// initial result reports tag 0 and no changes.
+    pub report_event_details: bool,
+
+    /// Whether to log the query of the subscription in the readable form of
+    /// `normalized_query_text` instead of on a single line. This makes
+    /// complex queries easier to debug, but makes logs much larger.
+    pub log_normalized_query: bool,
+
+    /// If set, the subscription looks up the current schema of its subgraph
+    /// with this function before it executes its query for an event, at
+    /// most once per block; events without a block always look it up. A
+    /// schema of a different deployment than the one the subscription was
+    /// validated against counts as a change and is handled once, according
+    /// to `schema_change_policy`. The source is never consulted under
+    /// `SubscriptionSchemaChangePolicy::Pin`, and without a source,
+    /// subscriptions always use the schema they were validated against.
+    pub schema_source: Option<SubscriptionSchemaSource>,
+
+    /// What to do when `schema_source` reports a new schema.
+    pub schema_change_policy: SubscriptionSchemaChangePolicy,
+}
+
+impl<R> SubscriptionExecutionOptions<R>
+where
+    R: Resolver,
+{
+    /// Creates a builder for subscription options. This is the recommended
+    /// way to create options since call sites only need to set the options
+    /// they care about.
+    pub fn builder(logger: Logger, resolver: R) -> SubscriptionExecutionOptionsBuilder<R> {
+        SubscriptionExecutionOptionsBuilder::new(logger, resolver)
+    }
+}
+
+/// Builds `SubscriptionExecutionOptions`. Options that aren't set keep their
+/// defaults: no timeout, complexity limit or other limits besides the
+/// default `max_first` of 1000 and `DEFAULT_STREAM_SETUP_TIMEOUT`, full
+/// results, an initial result, and `DEFAULT_TIMER_JITTER`.
+pub struct SubscriptionExecutionOptionsBuilder<R>
+where
+    R: Resolver,
+{
+    options: SubscriptionExecutionOptions<R>,
+}
+
+impl<R> SubscriptionExecutionOptionsBuilder<R>
+where
+    R: Resolver,
+{
+    pub fn new(logger: Logger, resolver: R) -> Self {
+        SubscriptionExecutionOptionsBuilder {
+            options: SubscriptionExecutionOptions {
+                logger,
+                log_context: None,
+                resolver,
+                timeout: None,
+                lifetime_execution_budget: None,
+                field_timeouts: HashMap::new(),
+                max_complexity: None,
+                max_depth: u8::max_value(),
+                max_first: 1000,
+                max_result_size_bytes: None,
+                compression: None,
+                query_permits: None,
+                priority: SubscriptionPriority::Normal,
+                query_permit_timeout: None,
+                max_in_flight_queries: 1,
+                execute_inline: false,
+                stream_setup_timeout: Some(DEFAULT_STREAM_SETUP_TIMEOUT),
+                setup_timeout: None,
+                subscription_debounce: None,
+                once_per_block: false,
+                min_update_interval: None,
+                event_buffer: None,
+                delivery_mode: SubscriptionDeliveryMode::Full,
+                suppress_duplicate_results: false,
+                error_policy: SubscriptionErrorPolicy::ContinueOnError,
+                error_classifier: None,
+                connection_id: None,
+                max_subscriptions_per_connection: None,
+                keep_alive_interval: None,
+                timer_jitter: DEFAULT_TIMER_JITTER,
+                clock: Arc::new(SystemClock),
+                metrics: None,
+                allow_multiple_subscription_fields: false,
+                field_filter: None,
+                max_referenced_entity_types: None,
+                emit_initial_result: true,
+                prewarm_initial_result: false,
+                resume_from_tag: None,
+                cancel_handle: None,
+                unsubscribe_signal: None,
+                allow_introspection: true,
+                execution_mode: ExecutionMode::Prefetch,
+                block_range: None,
+                result_transform: None,
+                transform_initial_result: true,
+                report_event_details: false,
+                log_normalized_query: false,
+                schema_source: None,
+                schema_change_policy: SubscriptionSchemaChangePolicy::Pin,
+            },
+        }
+    }
+
+    /// Sets values to add to every log line of the subscription.
+    pub fn log_context(mut self, log_context: SubscriptionLogContext) -> Self {
+        self.options.log_context = Some(log_context);
+        self
+    }
+
+    /// Sets the timeout for each subscription query.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long the queries of the subscription may run in total.
+    pub fn lifetime_execution_budget(mut self, lifetime_execution_budget: Duration) -> Self {
+        self.options.lifetime_execution_budget = Some(lifetime_execution_budget);
+        self
+    }
+
+    /// Sets the timeout for the field `field`, given as
+    /// `<type name>.<field name>`.
+    pub fn field_timeout(mut self, field: String, timeout: Duration) -> Self {
+        self.options.field_timeouts.insert(field, timeout);
+        self
+    }
+
+    /// Sets the maximum complexity of the subscription query.
+    pub fn max_complexity(mut self, max_complexity: u64) -> Self {
+        self.options.max_complexity = Some(max_complexity);
+        self
+    }
+
+    /// Sets the maximum depth of the subscription query.
+    pub fn max_depth(mut self, max_depth: u8) -> Self {
+        self.options.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the maximum value for the `first` argument.
+    pub fn max_first(mut self, max_first: u32) -> Self {
+        self.options.max_first = max_first;
+        self
+    }
+
+    /// Sets the maximum size of a serialized result.
+    pub fn max_result_size_bytes(mut self, max_result_size_bytes: usize) -> Self {
+        self.options.max_result_size_bytes = Some(max_result_size_bytes);
+        self
+    }
+
+    /// Sets how results larger than `threshold_bytes` are compressed, and
+    /// whether they are compressed right away or only marked.
+    pub fn compression(
+        mut self,
+        encoding: QueryResultEncoding,
+        threshold_bytes: usize,
+        precompress: bool,
+    ) -> Self {
+        self.options.compression = Some(SubscriptionCompression {
+            encoding,
+            threshold_bytes,
+            precompress,
+        });
+        self
+    }
+
+    /// Sets the query permits the subscription shares with other
+    /// subscriptions.
+    pub fn query_permits(mut self, query_permits: Arc<QueryPermits>) -> Self {
+        self.options.query_permits = Some(query_permits);
+        self
+    }
+
+    /// Sets how urgently the queries of the subscription get query permits.
+    pub fn priority(mut self, priority: SubscriptionPriority) -> Self {
+        self.options.priority = priority;
+        self
+    }
+
+    /// Sets how long queries may wait for a query permit.
+    pub fn query_permit_timeout(mut self, query_permit_timeout: Duration) -> Self {
+        self.options.query_permit_timeout = Some(query_permit_timeout);
+        self
+    }
+
+    /// Sets how many events the query may be executed for at the same time.
+    pub fn max_in_flight_queries(mut self, max_in_flight_queries: usize) -> Self {
+        self.options.max_in_flight_queries = max_in_flight_queries.max(1);
+        self
+    }
+
+    /// Sets whether queries for non-blocking resolvers are executed inline.
+    pub fn execute_inline(mut self, execute_inline: bool) -> Self {
+        self.options.execute_inline = execute_inline;
+        self
+    }
+
+    /// Sets how long opening the event stream for a field may take.
+    pub fn stream_setup_timeout(mut self, stream_setup_timeout: Duration) -> Self {
+        self.options.stream_setup_timeout = Some(stream_setup_timeout);
+        self
+    }
+
+    /// Lets opening the event stream for a field take as long as it takes,
+    /// instead of `DEFAULT_STREAM_SETUP_TIMEOUT`.
+    pub fn no_stream_setup_timeout(mut self) -> Self {
+        self.options.stream_setup_timeout = None;
+        self
+    }
+
+    /// Sets how long setting up the subscription may take in total.
+    pub fn setup_timeout(mut self, setup_timeout: Duration) -> Self {
+        self.options.setup_timeout = Some(setup_timeout);
+        self
+    }
+
+    /// Sets the window within which events are merged.
+    pub fn subscription_debounce(mut self, subscription_debounce: Duration) -> Self {
+        self.options.subscription_debounce = Some(subscription_debounce);
+        self
+    }
+
+    /// Sets whether to re-execute the query at most once per block.
+    pub fn once_per_block(mut self, once_per_block: bool) -> Self {
+        self.options.once_per_block = once_per_block;
+        self
+    }
+
+    /// Sets the minimum time between two results.
+    pub fn min_update_interval(mut self, min_update_interval: Duration) -> Self {
+        self.options.min_update_interval = Some(min_update_interval);
+        self
+    }
+
+    /// Sets the size of the event buffer and what happens once it is full.
+    pub fn event_buffer(mut self, size: usize, policy: SubscriptionBufferPolicy) -> Self {
+        self.options.event_buffer = Some(SubscriptionEventBuffer { size, policy });
+        self
+    }
+
+    /// Sets how results are delivered.
+    pub fn delivery_mode(mut self, delivery_mode: SubscriptionDeliveryMode) -> Self {
+        self.options.delivery_mode = delivery_mode;
+        self
+    }
+
+    /// Sets whether results with the same data as the last one are dropped.
+    pub fn suppress_duplicate_results(mut self, suppress_duplicate_results: bool) -> Self {
+        self.options.suppress_duplicate_results = suppress_duplicate_results;
+        self
+    }
+
+    /// Sets whether the subscription ends after a result with errors.
+    pub fn error_policy(mut self, error_policy: SubscriptionErrorPolicy) -> Self {
+        self.options.error_policy = error_policy;
+        self
+    }
+
+    /// Sets the function that decides how errors in results are treated.
+    pub fn error_classifier(mut self, error_classifier: SubscriptionErrorClassifier) -> Self {
+        self.options.error_classifier = Some(error_classifier);
+        self
+    }
+
+    /// Sets the connection the subscription was made on.
+    pub fn connection_id(mut self, connection_id: String) -> Self {
+        self.options.connection_id = Some(connection_id);
+        self
+    }
+
+    /// Sets the maximum number of active subscriptions per connection.
+    pub fn max_subscriptions_per_connection(
+        mut self,
+        max_subscriptions_per_connection: usize,
+    ) -> Self {
+        self.options.max_subscriptions_per_connection = Some(max_subscriptions_per_connection);
+        self
+    }
+
+    /// Sets the interval after which idle subscriptions send a keep-alive.
+    pub fn keep_alive_interval(mut self, keep_alive_interval: Duration) -> Self {
+        self.options.keep_alive_interval = Some(keep_alive_interval);
+        self
+    }
+
+    /// Sets the fraction by which timers are randomly extended.
+    pub fn timer_jitter(mut self, timer_jitter: f64) -> Self {
+        self.options.timer_jitter = timer_jitter;
+        self
+    }
+
+    /// Sets the clock that the timers of the subscription use.
+    pub fn clock(mut self, clock: Arc<dyn SubscriptionClock>) -> Self {
+        self.options.clock = clock;
+        self
+    }
+
+    /// Sets the metrics to record.
+    pub fn metrics(mut self, metrics: Arc<SubscriptionMetrics>) -> Self {
+        self.options.metrics = Some(metrics);
+        self
+    }
+
+    /// Sets whether multiple top-level fields are allowed.
+    pub fn allow_multiple_subscription_fields(
+        mut self,
+        allow_multiple_subscription_fields: bool,
+    ) -> Self {
+        self.options.allow_multiple_subscription_fields = allow_multiple_subscription_fields;
+        self
+    }
+
+    /// Sets which top-level fields subscriptions may select.
+    pub fn field_filter(mut self, field_filter: SubscriptionFieldFilter) -> Self {
+        self.options.field_filter = Some(field_filter);
+        self
+    }
+
+    /// Sets the maximum number of entity types a subscription may read.
+    pub fn max_referenced_entity_types(mut self, max_referenced_entity_types: usize) -> Self {
+        self.options.max_referenced_entity_types = Some(max_referenced_entity_types);
+        self
+    }
+
+    /// Sets whether to send a result before any event arrives.
+    pub fn emit_initial_result(mut self, emit_initial_result: bool) -> Self {
+        self.options.emit_initial_result = emit_initial_result;
+        self
+    }
+
+    /// Sets whether the query for the initial result starts during setup.
+    pub fn prewarm_initial_result(mut self, prewarm_initial_result: bool) -> Self {
+        self.options.prewarm_initial_result = prewarm_initial_result;
+        self
+    }
+
+    /// Sets the tag of the last event the client has seen.
+    pub fn resume_from_tag(mut self, resume_from_tag: usize) -> Self {
+        self.options.resume_from_tag = Some(resume_from_tag);
+        self
+    }
+
+    /// Sets the handle through which the subscription can be canceled.
+    pub fn cancel_handle(mut self, cancel_handle: CancelHandle) -> Self {
+        self.options.cancel_handle = Some(cancel_handle);
+        self
+    }
+
+    /// Sets the signal through which the client can unsubscribe.
+    pub fn unsubscribe_signal(mut self, unsubscribe_signal: UnsubscribeSignal) -> Self {
+        self.options.unsubscribe_signal = Some(unsubscribe_signal);
+        self
+    }
+
+    /// Sets whether introspection fields may be selected.
+    pub fn allow_introspection(mut self, allow_introspection: bool) -> Self {
+        self.options.allow_introspection = allow_introspection;
+        self
+    }
+
+    /// Sets how the subscription query is executed.
+    pub fn execution_mode(mut self, execution_mode: ExecutionMode) -> Self {
+        self.options.execution_mode = execution_mode;
+        self
+    }
+
+    /// Sets the range of blocks the subscription executes its query at.
+    pub fn block_range(mut self, from: BlockNumber, to: BlockNumber) -> Self {
+        self.options.block_range = Some(SubscriptionBlockRange { from, to });
+        self
+    }
+
+    /// Pins the subscription to a single block.
+    pub fn pin_block(self, block: BlockNumber) -> Self {
+        self.block_range(block, block)
+    }
+
+    /// Sets the function results are passed through before they are sent.
+    pub fn result_transform(mut self, result_transform: SubscriptionResultTransform) -> Self {
+        self.options.result_transform = Some(result_transform);
+        self
+    }
+
+    /// Sets whether the initial result is also transformed.
+    pub fn transform_initial_result(mut self, transform_initial_result: bool) -> Self {
+        self.options.transform_initial_result = transform_initial_result;
+        self
+    }
+
+    /// Sets whether results report the event that caused them.
+    pub fn report_event_details(mut self, report_event_details: bool) -> Self {
+        self.options.report_event_details = report_event_details;
+        self
+    }
+
+    /// Sets whether to log the query in its readable, normalized form.
+    pub fn log_normalized_query(mut self, log_normalized_query: bool) -> Self {
+        self.options.log_normalized_query = log_normalized_query;
+        self
+    }
+
+    /// Sets how the subscription looks up the current schema of its subgraph.
+    pub fn schema_source(mut self, schema_source: SubscriptionSchemaSource) -> Self {
+        self.options.schema_source = Some(schema_source);
+        self
+    }
+
+    /// Sets what to do when the schema of the subgraph changes.
+    pub fn schema_change_policy(
+        mut self,
+        schema_change_policy: SubscriptionSchemaChangePolicy,
+    ) -> Self {
+        self.options.schema_change_policy = schema_change_policy;
+        self
+    }
+
+    pub fn build(self) -> SubscriptionExecutionOptions<R> {
+        self.options
+    }
+}
+
+/// Counts a subscription as active on its connection for as long as the
+/// guard is alive.
+struct ActiveSubscriptionGuard {
+    connection_id: String,
+}
+
+impl ActiveSubscriptionGuard {
+    fn new(connection_id: String, limit: Option<usize>) -> Result<Self, SubscriptionError> {
+        let mut active_subscriptions = ACTIVE_SUBSCRIPTIONS.lock().unwrap();
+        let active = active_subscriptions
+            .get(&connection_id)
+            .cloned()
+            .unwrap_or(0);
+
+        if let Some(limit) = limit {
+            if active >= limit {
+                return Err(SubscriptionError::TooManySubscriptions {
+                    connection_id,
+                    limit,
+                });
+            }
+        }
+
+        active_subscriptions.insert(connection_id.clone(), active + 1);
+        Ok(ActiveSubscriptionGuard { connection_id })
+    }
+}
+
+impl Drop for ActiveSubscriptionGuard {
+    fn drop(&mut self) {
+        let mut active_subscriptions = ACTIVE_SUBSCRIPTIONS.lock().unwrap();
+        if let Some(active) = active_subscriptions.get_mut(&self.connection_id) {
+            *active -= 1;
+            if *active == 0 {
+                active_subscriptions.remove(&self.connection_id);
+            }
+        }
+    }
+}
+
+/// Returns `true` if `document` is a subscription, i.e. it has a single
+/// operation and that is a subscription. This only looks at the operation
+/// type, so that transports can decide how to handle a document before
+/// doing any other work; `execute_subscription` still validates the
+/// document in full and rejects anything that isn't a valid subscription.
+pub fn is_subscription_document(document: &q::Document) -> bool {
+    match qast::get_operations(document).as_slice() {
+        [q::OperationDefinition::Subscription(_)] => true,
+        _ => false,
+    }
 }
 
 pub fn execute_subscription<R>(
@@ -58,35 +973,42 @@ pub fn execute_subscription<R>(
 where
     R: Resolver + 'static,
 {
-    let query_text = subscription
-        .query
-        .document
-        .format(&Style::default().indent(0))
-        .replace('\n', " ");
+    if let Some(SubscriptionBlockRange { from, to }) = options.block_range {
+        if from > to {
+            return Err(SubscriptionError::InvalidBlockRange { from, to });
+        }
+    }
 
-    let query = crate::execution::Query::new(
-        subscription.query,
-        options.max_complexity,
-        options.max_depth,
-    )?;
+    // The query text is only logged once; every other log line has the
+    // short `query_id` to correlate it with this one
+    let query_text = if options.log_normalized_query {
+        normalized_query_text(&subscription.query.document)
+    } else {
+        subscription
+            .query
+            .document
+            .format(&Style::default().indent(0))
+            .replace('\n', " ")
+    };
 
-    // Create a fresh execution context
-    let ctx = ExecutionContext {
-        logger: options.logger,
-        resolver: Arc::new(options.resolver),
-        query: query.clone(),
-        fields: vec![],
-        deadline: None,
-        max_first: options.max_first,
-        block: BLOCK_NUMBER_MAX,
-        mode: ExecutionMode::Prefetch,
+    // Keep the query as it was submitted to validate it against new schemas;
+    // a pinned subscription never looks at them
+    let schema_check = match options.schema_change_policy {
+        SubscriptionSchemaChangePolicy::Pin => None,
+        SubscriptionSchemaChangePolicy::Revalidate => {
+            options.schema_source.clone().map(|source| SchemaCheck {
+                source,
+                query: subscription.query.clone(),
+                checked_block: Mutex::new(None),
+                handled: Mutex::new(None),
+            })
+        }
     };
 
-    if !query.is_subscription() {
-        return Err(SubscriptionError::from(QueryExecutionError::NotSupported(
-            "Only subscriptions are supported".to_string(),
-        )));
-    }
+    // The setup deadline bounds everything up to opening the event streams
+    let setup_deadline = options.setup_timeout.map(|t| Instant::now() + t);
+    let ctx = subscription_context(subscription, &options, setup_deadline)?;
+    check_setup_deadline(&ctx, options.setup_timeout)?;
 
     info!(
         ctx.logger,
@@ -94,124 +1016,4473 @@ where
         "query" => query_text,
     );
 
-    let source_stream = create_source_event_stream(&ctx)?;
-    let response_stream = map_source_to_response_stream(&ctx, source_stream, options.timeout);
-    Ok(response_stream)
-}
+    // Reject the subscription before doing any work if its connection
+    // already has too many active subscriptions
+    let active_subscription_guard = match options.connection_id {
+        Some(connection_id) => Some(ActiveSubscriptionGuard::new(
+            connection_id,
+            options.max_subscriptions_per_connection,
+        )?),
+        None => None,
+    };
 
-fn create_source_event_stream(
-    ctx: &ExecutionContext<impl Resolver>,
-) -> Result<StoreEventStreamBox, SubscriptionError> {
-    let subscription_type = sast::get_root_subscription_type(&ctx.query.schema.document)
-        .ok_or(QueryExecutionError::NoRootSubscriptionObjectType)?;
+    // Look up the subscription type once; every event uses the same one. It
+    // exists since `subscription_context` checked that
+    let subscription_type = Arc::new(
+        sast::get_root_subscription_type(&ctx.query.schema.document)
+            .unwrap()
+            .clone(),
+    );
 
-    let grouped_field_set = collect_fields(ctx, &subscription_type, &ctx.query.selection_set, None);
+    // Determine once which entity types the subscription reads
+    let entity_types = Arc::new(EntityTypeSet::new(&ctx.query, &subscription_type));
 
-    if grouped_field_set.is_empty() {
-        return Err(SubscriptionError::from(QueryExecutionError::EmptyQuery));
-    } else if grouped_field_set.len() > 1 {
-        return Err(SubscriptionError::from(
-            QueryExecutionError::MultipleSubscriptionFields,
-        ));
-    }
+    let source_stream =
+        create_source_event_stream(&ctx, &subscription_type, entity_types, &options)?;
+    let source_stream = match options.resume_from_tag {
+        Some(tag) => events_after_tag(source_stream, tag),
+        None => source_stream,
+    };
+    let source_stream = match options.subscription_debounce {
+        Some(window) => events_debounced(
+            source_stream,
+            jittered(window, options.timer_jitter),
+            options.clock.clone(),
+        ),
+        None => source_stream,
+    };
+    let source_stream = if options.once_per_block {
+        events_once_per_block(source_stream)
+    } else {
+        source_stream
+    };
+    let emit_initial_result = options.emit_initial_result && options.resume_from_tag.is_none();
+    let source_stream = match options.min_update_interval {
+        Some(interval) => events_at_min_interval(
+            source_stream,
+            interval,
+            emit_initial_result,
+            options.timer_jitter,
+            options.clock.clone(),
+        ),
+        None => source_stream,
+    };
 
-    let fields = grouped_field_set.get_index(0).unwrap();
-    let field = fields.1[0];
-    let argument_values = coerce_argument_values(&ctx, subscription_type, field)?;
+    // From here on, the subscription can't fail anymore
+    let active_gauge = options
+        .metrics
+        .as_ref()
+        .map(|metrics| metrics.subscription_active());
 
-    resolve_field_stream(ctx, subscription_type, field, argument_values)
-}
+    let event_ctx = subscription_event_context(&ctx, subscription_type, &options, schema_check);
+    let response_options = ResponseStreamOptions {
+        emit_initial_result,
+        prewarm_initial_result: options.prewarm_initial_result,
+        event_buffer: options.event_buffer,
+        max_in_flight_queries: options.max_in_flight_queries,
+        delivery_mode: options.delivery_mode,
+        suppress_duplicate_results: options.suppress_duplicate_results,
+        error_policy: options.error_policy,
+        error_classifier: options.error_classifier,
+        compression: options.compression,
+        keep_alive_interval: options.keep_alive_interval,
+        timer_jitter: options.timer_jitter,
+    };
+    let response_stream = map_source_to_response_stream(event_ctx, source_stream, response_options);
 
-fn resolve_field_stream(
-    ctx: &ExecutionContext<impl Resolver>,
-    object_type: &s::ObjectType,
-    field: &q::Field,
-    _argument_values: HashMap<&q::Name, q::Value>,
-) -> Result<StoreEventStreamBox, SubscriptionError> {
-    ctx.resolver
-        .resolve_field_stream(&ctx.query.schema.document, object_type, field)
-        .map_err(SubscriptionError::from)
+    let response_stream = match options.cancel_handle {
+        Some(cancel_handle) => cancelable_response_stream(response_stream, &cancel_handle),
+        None => response_stream,
+    };
+
+    let response_stream = match options.unsubscribe_signal {
+        Some(signal) => unsubscribe::unsubscribable_response_stream(response_stream, signal),
+        None => response_stream,
+    };
+
+    // Keep the subscription counted as active until the response stream is dropped
+    Ok(Box::new(response_stream.map(move |result| {
+        let _ = (&active_subscription_guard, &active_gauge);
+        result
+    })))
 }
 
-fn map_source_to_response_stream(
-    ctx: &ExecutionContext<impl Resolver + 'static>,
-    source_stream: StoreEventStreamBox,
-    timeout: Option<Duration>,
+/// Executes the query of a subscription once against the latest block and
+/// returns its result, without listening to any store events.
+///
+/// The query is executed exactly like the query for an event of a
+/// subscription with the same options: it needs a query permit from the
+/// same query permits, runs on the blocking thread pool unless it may be
+/// executed inline, and reports hitting `timeout` as a
+/// `QueryExecutionError::SubscriptionTimeout`. A snapshot therefore can't
+/// take more database connections than subscriptions may.
+pub async fn execute_subscription_snapshot<R>(
+    subscription: Subscription,
+    options: SubscriptionExecutionOptions<R>,
+) -> Result<QueryResult, SubscriptionError>
+where
+    R: Resolver + 'static,
+{
+    let ctx = subscription_context(subscription, &options, None)?;
+
+    info!(ctx.logger, "Execute subscription snapshot");
+
+    // We have established that this exists in `subscription_context`
+    let subscription_type = Arc::new(
+        sast::get_root_subscription_type(&ctx.query.schema.document)
+            .unwrap()
+            .clone(),
+    );
+
+    let event_ctx = subscription_event_context(&ctx, subscription_type, &options, None);
+    let event = StoreEvent {
+        tag: 0,
+        changes: Default::default(),
+        block: None,
+    };
+    Ok(execute_subscription_event(Arc::new(event_ctx), event, BLOCK_NUMBER_MAX).await)
+}
+
+/// Validates `subscription` and creates the execution context for it.
+fn subscription_context<R>(
+    subscription: Subscription,
+    options: &SubscriptionExecutionOptions<R>,
+    deadline: Option<Instant>,
+) -> Result<ExecutionContext<R>, SubscriptionError>
+where
+    R: Resolver,
+{
+    let subscription_id = Uuid::new_v4().to_string();
+    let subgraph_id = (*subscription.query.schema.id).clone();
+    let operation_name = subscription.query.operation_name.clone();
+
+    let query = crate::execution::Query::new(
+        subscription.query,
+        options.max_complexity,
+        options.max_depth,
+    )?;
+
+    let logger = match &options.log_context {
+        Some(log_context) => options.logger.new(slog::OwnedKV(log_context.clone())),
+        None => options.logger.clone(),
+    };
+    let subscription_logger = logger.new(o!(
+        "subgraph_id" => subgraph_id,
+        "subscription_id" => subscription_id,
+        "query_id" => query.query_id.clone(),
+    ));
+
+    if !query.is_subscription() {
+        return Err(SubscriptionError::from(not_a_subscription(
+            operation_name.as_deref(),
+        )));
+    }
+
+    sast::get_root_subscription_type(&query.schema.document)
+        .ok_or_else(|| no_root_subscription_type(&query))?;
+
+    if !options.allow_introspection {
+        let errors = introspection_errors(&query);
+        if !errors.is_empty() {
+            return Err(SubscriptionError::from(errors));
+        }
+    }
+
+    // Create a fresh execution context
+    Ok(ExecutionContext {
+        logger: subscription_logger,
+        resolver: Arc::new(options.resolver.clone()),
+        query,
+        fields: vec![],
+        deadline,
+        field_timeouts: Arc::new(options.field_timeouts.clone()),
+        cancel_handle: options.cancel_handle.clone(),
+        max_first: options.max_first,
+        block: BLOCK_NUMBER_MAX,
+        mode: options.execution_mode,
+    })
+}
+
+/// Creates the context that the query of the subscription in `ctx` is
+/// executed with for every event.
+fn subscription_event_context<R>(
+    ctx: &ExecutionContext<R>,
+    subscription_type: Arc<s::ObjectType>,
+    options: &SubscriptionExecutionOptions<R>,
+    schema_check: Option<SchemaCheck>,
+) -> SubscriptionEventContext<R>
+where
+    R: Resolver,
+{
+    SubscriptionEventContext {
+        logger: ctx.logger.clone(),
+        resolver: ctx.resolver.clone(),
+        query: ctx.query.cheap_clone(),
+        subscription_type,
+        timeout: options.timeout,
+        field_timeouts: ctx.field_timeouts.clone(),
+        max_complexity: options.max_complexity,
+        max_depth: options.max_depth,
+        max_first: ctx.max_first,
+        mode: ctx.mode,
+        block_range: options.block_range,
+        max_result_size_bytes: options.max_result_size_bytes,
+        query_permits: options
+            .query_permits
+            .clone()
+            .unwrap_or_else(shared_subscription_query_permits),
+        priority: options.priority,
+        query_permit_timeout: options.query_permit_timeout,
+        execute_inline: options.execute_inline,
+        metrics: options.metrics.clone(),
+        cancel_handle: options.cancel_handle.clone(),
+        result_transform: options.result_transform.clone(),
+        transform_initial_result: options.transform_initial_result,
+        report_event_details: options.report_event_details,
+        execution_budget: options.lifetime_execution_budget,
+        execution_spent: Mutex::new(Duration::from_secs(0)),
+        schema_check,
+        clock: options.clock.clone(),
+    }
+}
+
+/// Limits that `validate_subscription` checks a subscription document
+/// against. They mean the same as the options of the same name in
+/// `SubscriptionExecutionOptions`, and default to the same values.
+#[derive(Clone, Debug)]
+pub struct SubscriptionValidationLimits {
+    pub max_complexity: Option<u64>,
+    pub max_depth: u8,
+    pub allow_multiple_subscription_fields: bool,
+    pub allow_introspection: bool,
+}
+
+impl Default for SubscriptionValidationLimits {
+    fn default() -> Self {
+        SubscriptionValidationLimits {
+            max_complexity: None,
+            max_depth: u8::max_value(),
+            allow_multiple_subscription_fields: false,
+            allow_introspection: true,
+        }
+    }
+}
+
+/// Checks that `document` is a subscription that `execute_subscription`
+/// would accept for `schema` under `limits`, without executing it or opening
+/// any event streams, e.g. to check subscriptions in CI before shipping them.
+///
+/// Returns all problems that were found. Documents that fail the validation
+/// of `Query::new`, including its complexity and depth limits, are only
+/// reported with those errors, since the other checks rely on a valid query.
+/// Checks that depend on the resolver or the state of the node, like field
+/// filters and connection limits, are not run.
+pub fn validate_subscription(
+    document: q::Document,
+    schema: Arc<Schema>,
+    limits: &SubscriptionValidationLimits,
+) -> Result<(), Vec<QueryExecutionError>> {
+    let query = crate::execution::Query::new(
+        graph::prelude::Query::new(schema, document, None),
+        limits.max_complexity,
+        limits.max_depth,
+    )?;
+
+    let mut errors = vec![];
+    if !query.is_subscription() {
+        errors.push(not_a_subscription(None));
+    } else {
+        match sast::get_root_subscription_type(&query.schema.document) {
+            None => errors.push(no_root_subscription_type(&query)),
+            Some(subscription_type) => {
+                // Collecting fields only looks at the query and its schema,
+                // the resolver is never used
+                let logger = Logger::root(slog::Discard, o!());
+                let ctx = ExecutionContext {
+                    resolver: Arc::new(IntrospectionResolver::new(&logger, &query.schema)),
+                    logger,
+                    query: query.cheap_clone(),
+                    fields: vec![],
+                    deadline: None,
+                    field_timeouts: Arc::new(HashMap::new()),
+                    cancel_handle: None,
+                    max_first: std::u32::MAX,
+                    block: BLOCK_NUMBER_MAX,
+                    mode: ExecutionMode::Prefetch,
+                };
+                let grouped_field_set =
+                    collect_fields(&ctx, subscription_type, &query.selection_set, None);
+                if let Err(e) = check_subscription_fields(
+                    grouped_field_set.len(),
+                    limits.allow_multiple_subscription_fields,
+                ) {
+                    errors.push(e);
+                }
+            }
+        }
+    }
+    if !limits.allow_introspection {
+        errors.extend(introspection_errors(&query));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// The error for documents whose operation, called `operation_name` if it
+/// was selected by name, is not a subscription.
+fn not_a_subscription(operation_name: Option<&str>) -> QueryExecutionError {
+    let message = match operation_name {
+        Some(name) => format!(
+            "Operation `{}` is not a subscription, only subscriptions are supported",
+            name
+        ),
+        None => "Only subscriptions are supported".to_string(),
+    };
+    QueryExecutionError::NotSupported(message)
+}
+
+fn no_root_subscription_type(query: &crate::execution::Query) -> QueryExecutionError {
+    QueryExecutionError::NoRootSubscriptionObjectType {
+        deployment: query.schema.id.clone(),
+        schema_version: query.schema.version(),
+    }
+}
+
+/// Checks the number of top-level fields of a subscription.
+fn check_subscription_fields(
+    fields: usize,
+    allow_multiple_fields: bool,
+) -> Result<(), QueryExecutionError> {
+    if fields == 0 {
+        Err(QueryExecutionError::EmptyQuery)
+    } else if fields > 1 && !allow_multiple_fields {
+        Err(QueryExecutionError::MultipleSubscriptionFields)
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns an error for each introspection field that `query` selects.
+fn introspection_errors(query: &crate::execution::Query) -> Vec<QueryExecutionError> {
+    introspection_fields(query, &query.selection_set, &mut HashSet::new())
+        .into_iter()
+        .map(|field| QueryExecutionError::IntrospectionDisabled(field.position, field.name.clone()))
+        .collect()
+}
+
+/// Collects the `__schema` and `__type` fields anywhere in `selection_set`,
+/// including in fragments.
+fn introspection_fields<'a>(
+    query: &'a crate::execution::Query,
+    selection_set: &'a q::SelectionSet,
+    visited_fragments: &mut HashSet<&'a q::Name>,
+) -> Vec<&'a q::Field> {
+    let mut fields = vec![];
+    for selection in &selection_set.items {
+        match selection {
+            q::Selection::Field(field) => {
+                if field.name == "__schema" || field.name == "__type" {
+                    fields.push(field);
+                }
+                fields.extend(introspection_fields(
+                    query,
+                    &field.selection_set,
+                    visited_fragments,
+                ));
+            }
+            q::Selection::FragmentSpread(spread) => {
+                if visited_fragments.insert(&spread.fragment_name) {
+                    if let Some(fragment) = query.get_fragment(&spread.fragment_name) {
+                        fields.extend(introspection_fields(
+                            query,
+                            &fragment.selection_set,
+                            visited_fragments,
+                        ));
+                    }
+                }
+            }
+            q::Selection::InlineFragment(fragment) => {
+                fields.extend(introspection_fields(
+                    query,
+                    &fragment.selection_set,
+                    visited_fragments,
+                ));
+            }
+        }
+    }
+    fields
+}
+
+/// Ends `response_stream` as soon as the guard behind `cancel_handle` is
+/// canceled, without waiting for the next result. Dropping the stream drops
+/// an in-flight query execution along with its query permit.
+fn cancelable_response_stream(
+    response_stream: QueryResultStream,
+    cancel_handle: &CancelHandle,
 ) -> QueryResultStream {
-    let logger = ctx.logger.clone();
+    Box::new(
+        response_stream
+            .map(Ok::<_, ()>)
+            .compat()
+            .cancelable(cancel_handle, || ())
+            .compat()
+            .take_while(|result| futures03::future::ready(result.is_ok()))
+            .map(|result| result.unwrap()),
+    )
+}
+
+fn create_source_event_stream<R>(
+    ctx: &ExecutionContext<R>,
+    subscription_type: &s::ObjectType,
+    entity_types: Arc<EntityTypeSet>,
+    options: &SubscriptionExecutionOptions<R>,
+) -> Result<StoreEventStreamBox, SubscriptionError>
+where
+    R: Resolver + 'static,
+{
+    let setup_timeout = options.setup_timeout;
+    let grouped_field_set = collect_fields(ctx, &subscription_type, &ctx.query.selection_set, None);
+
+    check_subscription_fields(
+        grouped_field_set.len(),
+        options.allow_multiple_subscription_fields,
+    )?;
+
+    if let Some(field_filter) = options.field_filter.as_ref() {
+        if let Some(fields) = grouped_field_set
+            .values()
+            .find(|fields| !field_filter.permits(&fields[0].name))
+        {
+            return Err(SubscriptionError::FieldNotPermitted {
+                field: fields[0].name.clone(),
+            });
+        }
+    }
+
+    if let Some(fields) = grouped_field_set.values().find(|fields| {
+        !ctx.resolver.supports_field_stream(
+            &ctx.query.schema.document,
+            subscription_type,
+            fields[0],
+        )
+    }) {
+        return Err(SubscriptionError::FieldNotSubscribable {
+            field: fields[0].name.clone(),
+        });
+    }
+
+    // Types are collected in the order the query references them, so the
+    // ones past the limit are the ones that pushed the query over it
+    if let Some(limit) = options.max_referenced_entity_types {
+        if entity_types.len() > limit {
+            return Err(SubscriptionError::TooManyEntityTypes {
+                limit,
+                entity_types: entity_types.iter().skip(limit).cloned().collect(),
+            });
+        }
+    }
+
+    let source_streams = grouped_field_set
+        .values()
+        .map(|fields| {
+            let field = fields[0];
+            let argument_values = coerce_argument_values(&ctx, subscription_type, field)?;
+            check_setup_deadline(ctx, setup_timeout)?;
+
+            // Opening the stream may take neither longer than the stream
+            // setup timeout nor past the setup deadline
+            let remaining = ctx
+                .deadline
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let timeout = match (options.stream_setup_timeout, remaining) {
+                (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+                (timeout, remaining) => timeout.or(remaining),
+            };
+            match timeout {
+                Some(timeout) => resolve_field_stream_with_timeout(
+                    ctx,
+                    subscription_type,
+                    field,
+                    argument_values,
+                    timeout,
+                )
+                .map_err(|e| match e {
+                    SubscriptionError::StreamSetupTimeout { .. } => {
+                        check_setup_deadline(ctx, setup_timeout).err().unwrap_or(e)
+                    }
+                    e => e,
+                }),
+                None => resolve_field_stream(ctx, subscription_type, field, argument_values),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let source_stream = merge_source_streams(source_streams);
+    // Only re-execute the subscription for events that change any of the
+    // entity types it reads
+    Ok(filter_by_entity_types(source_stream, entity_types))
+}
+
+/// Fails with `SubscriptionError::SetupTimeout` if the deadline of the setup
+/// context `ctx`, which `setup_timeout` gave it, has passed.
+fn check_setup_deadline<R>(
+    ctx: &ExecutionContext<R>,
+    setup_timeout: Option<Duration>,
+) -> Result<(), SubscriptionError>
+where
+    R: Resolver,
+{
+    match (ctx.deadline, setup_timeout) {
+        (Some(deadline), Some(timeout)) if deadline <= Instant::now() => {
+            Err(SubscriptionError::SetupTimeout { timeout })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Drops all events from `source_stream` whose tag is not higher than `tag`.
+fn events_after_tag(source_stream: StoreEventStreamBox, tag: usize) -> StoreEventStreamBox {
+    StoreEventStream::new(Box::new(source_stream.filter(move |event| event.tag > tag)))
+}
+
+/// Coalesces bursts of events from `source_stream`. When an event arrives,
+/// all events that arrive within `window` after it are merged into it, and
+/// the result is passed on once `window` has passed, measured with `clock`.
+/// The merged event has the changes and the highest tag and block of all
+/// events it was built from. Errors are passed on after the pending event.
+fn events_debounced(
+    source_stream: StoreEventStreamBox,
+    window: Duration,
+    clock: Arc<dyn SubscriptionClock>,
+) -> StoreEventStreamBox {
+    let mut source_stream = source_stream.fuse();
+    let mut pending: Option<StoreEvent> = None;
+    let mut had_err = false;
+    let mut delay = None;
+
+    StoreEventStream::new(Box::new(stream::poll_fn(
+        move || -> Result<Async<Option<StoreEvent>>, ()> {
+            if had_err {
+                // We had an error the last time through, but passed on the
+                // pending event first. Indicate the error now
+                had_err = false;
+                return Err(());
+            }
+
+            loop {
+                match source_stream.poll() {
+                    Ok(Async::Ready(Some(event))) => {
+                        pending = Some(match pending.take() {
+                            Some(previous) => {
+                                let tag = previous.tag.max(event.tag);
+                                StoreEvent {
+                                    tag,
+                                    ..previous.extend(event)
+                                }
+                            }
+                            None => {
+                                // Start the window with the first event of a burst
+                                delay = Some(
+                                    clock
+                                        .delay_until(clock.now() + window)
+                                        .unit_error()
+                                        .compat(),
+                                );
+                                event
+                            }
+                        })
+                    }
+                    Ok(Async::Ready(None)) => {
+                        delay = None;
+                        return Ok(Async::Ready(pending.take()));
+                    }
+                    Ok(Async::NotReady) => break,
+                    Err(()) => {
+                        delay = None;
+                        return match pending.take() {
+                            Some(event) => {
+                                had_err = true;
+                                Ok(Async::Ready(Some(event)))
+                            }
+                            None => Err(()),
+                        };
+                    }
+                }
+            }
+
+            // Pass on the pending event once its window has passed. Timer
+            // errors are harmless. Treat them as if the timer had become
+            // ready.
+            match delay.as_mut().map(|delay| delay.poll()) {
+                None | Some(Ok(Async::NotReady)) => Ok(Async::NotReady),
+                Some(Ok(Async::Ready(()))) | Some(Err(())) => {
+                    delay = None;
+                    Ok(Async::Ready(pending.take()))
+                }
+            }
+        },
+    )))
+}
+
+/// Merges consecutive events in `source_stream` that are for the same block
+/// and are ready at the same time. The merged event has the tag of the
+/// latest of them and the changes of all of them.
+fn events_once_per_block(mut source_stream: StoreEventStreamBox) -> StoreEventStreamBox {
+    let mut pending: Option<StoreEvent> = None;
+    let mut ended = false;
+
+    StoreEventStream::new(Box::new(stream::poll_fn(
+        move || -> Result<Async<Option<StoreEvent>>, ()> {
+            loop {
+                if ended {
+                    return Ok(Async::Ready(pending.take()));
+                }
+                match source_stream.poll()? {
+                    Async::Ready(Some(event)) => match pending.take() {
+                        Some(previous)
+                            if previous.block.is_some() && previous.block == event.block =>
+                        {
+                            pending = Some(event.extend(previous))
+                        }
+                        Some(previous) => {
+                            pending = Some(event);
+                            return Ok(Async::Ready(Some(previous)));
+                        }
+                        None => pending = Some(event),
+                    },
+                    Async::Ready(None) => ended = true,
+                    // Nothing else is ready; send what we have
+                    Async::NotReady => {
+                        return Ok(match pending.take() {
+                            Some(event) => Async::Ready(Some(event)),
+                            None => Async::NotReady,
+                        })
+                    }
+                }
+            }
+        },
+    )))
+}
+
+/// Passes on at most one event from `source_stream` per `interval`. Events
+/// that arrive before the interval since the last event has passed are
+/// merged into the latest of them, which is passed on once it has, even if
+/// the source stream ends in the meantime. If `delay_first` is set, the
+/// first interval starts right away, e.g. because a result is about to be
+/// sent; otherwise the first event is passed on without delay. Every
+/// interval is extended by up to `jitter`, see `jittered`, and measured with
+/// `clock`.
+fn events_at_min_interval(
+    mut source_stream: StoreEventStreamBox,
+    interval: Duration,
+    delay_first: bool,
+    jitter: f64,
+    clock: Arc<dyn SubscriptionClock>,
+) -> StoreEventStreamBox {
+    let mut pending: Option<StoreEvent> = None;
+    let mut ended = false;
+    let mut next_allowed = if delay_first {
+        Some(clock.now() + jittered(interval, jitter))
+    } else {
+        None
+    };
+    let mut delay = None;
+
+    StoreEventStream::new(Box::new(stream::poll_fn(
+        move || -> Result<Async<Option<StoreEvent>>, ()> {
+            // Errors are passed on right away; the pending event is kept
+            while !ended {
+                match source_stream.poll()? {
+                    Async::Ready(Some(event)) => {
+                        pending = Some(match pending.take() {
+                            Some(previous) => event.extend(previous),
+                            None => event,
+                        })
+                    }
+                    Async::Ready(None) => ended = true,
+                    Async::NotReady => break,
+                }
+            }
+
+            if pending.is_none() {
+                return Ok(if ended {
+                    Async::Ready(None)
+                } else {
+                    Async::NotReady
+                });
+            }
+
+            // Hold the pending event back until the interval has passed
+            if let Some(next_allowed) = next_allowed {
+                if clock.now() < next_allowed {
+                    let delay = delay.get_or_insert_with(|| {
+                        clock.delay_until(next_allowed).unit_error().compat()
+                    });
+                    // Timer errors are harmless. Treat them as if the timer
+                    // had become ready.
+                    if let Ok(Async::NotReady) = delay.poll() {
+                        return Ok(Async::NotReady);
+                    }
+                }
+            }
+            delay = None;
+            next_allowed = Some(clock.now() + jittered(interval, jitter));
+            Ok(Async::Ready(pending.take()))
+        },
+    )))
+}
+
+/// Merges the event streams of several top-level subscription fields into one.
+///
+/// The store sends the same event to every field that listens to it. Since
+/// each event re-executes the whole selection set, an event is dropped if an
+/// event with the same or a higher tag has already been passed on: the query
+/// that ran for that event already observed its changes.
+fn merge_source_streams(mut source_streams: Vec<StoreEventStreamBox>) -> StoreEventStreamBox {
+    if source_streams.len() == 1 {
+        return source_streams.pop().unwrap();
+    }
+
+    let merged = source_streams.into_iter().fold(
+        Box::new(stream::empty()) as Box<dyn Stream<Item = StoreEvent, Error = ()> + Send>,
+        |merged, source_stream| Box::new(merged.select(source_stream)),
+    );
+
+    let mut last_tag = None;
+    StoreEventStream::new(Box::new(merged.filter(move |event| {
+        if last_tag.map_or(false, |tag| event.tag <= tag) {
+            return false;
+        }
+        last_tag = Some(event.tag);
+        true
+    })))
+}
+
+/// Only pass on events from `source_stream` that change at least one
+/// entity of one of the given types.
+fn filter_by_entity_types(
+    source_stream: StoreEventStreamBox,
+    entity_types: Arc<EntityTypeSet>,
+) -> StoreEventStreamBox {
+    StoreEventStream::new(Box::new(
+        source_stream.filter(move |event| entity_types.is_changed_by(event)),
+    ))
+}
+
+fn resolve_field_stream(
+    ctx: &ExecutionContext<impl Resolver>,
+    object_type: &s::ObjectType,
+    field: &q::Field,
+    argument_values: HashMap<&q::Name, q::Value>,
+) -> Result<StoreEventStreamBox, SubscriptionError> {
+    ctx.resolver
+        .resolve_field_stream(
+            &ctx.query.schema.document,
+            object_type,
+            field,
+            &argument_values,
+        )
+        .map_err(SubscriptionError::from)
+}
+
+/// Like `resolve_field_stream`, but gives up if the resolver hasn't opened
+/// the stream after `timeout`. The resolver blocks, so it is called on the
+/// blocking pool; if it gives up, the stream is dropped once the resolver
+/// returns it. Outside of a tokio runtime, there is no blocking pool and the
+/// stream is opened without a timeout.
+///
+/// The caller waits for the stream without yielding, and should therefore
+/// not run on a core thread of the runtime; `GraphQlRunner` sets up
+/// subscriptions on the blocking pool for that reason.
+fn resolve_field_stream_with_timeout(
+    ctx: &ExecutionContext<impl Resolver + 'static>,
+    object_type: &s::ObjectType,
+    field: &q::Field,
+    argument_values: HashMap<&q::Name, q::Value>,
+    timeout: Duration,
+) -> Result<StoreEventStreamBox, SubscriptionError> {
+    if tokio::runtime::Handle::try_current().is_err() {
+        return resolve_field_stream(ctx, object_type, field, argument_values);
+    }
+
     let resolver = ctx.resolver.clone();
     let query = ctx.query.cheap_clone();
-    let max_first = ctx.max_first;
+    let object_type = object_type.clone();
+    let owned_field = field.clone();
+    let argument_values = argument_values
+        .into_iter()
+        .map(|(name, value)| (name.clone(), value))
+        .collect::<Vec<_>>();
+
+    let open_stream = graph::spawn_blocking_allow_panic(async move {
+        let argument_values: HashMap<_, _> = argument_values
+            .iter()
+            .map(|(name, value)| (name, value.clone()))
+            .collect();
+        resolver.resolve_field_stream(
+            &query.schema.document,
+            &object_type,
+            &owned_field,
+            &argument_values,
+        )
+    });
+
+    match graph::block_on_allow_panic(tokio::time::timeout(timeout, open_stream)) {
+        Ok(Ok(stream)) => stream.map_err(SubscriptionError::from),
+        Ok(Err(_)) => Err(SubscriptionError::from(QueryExecutionError::Panic(
+            format!(
+                "resolver panicked opening the event stream for field `{}`",
+                field.name
+            ),
+        ))),
+        Err(_) => Err(SubscriptionError::StreamSetupTimeout {
+            field: field.name.clone(),
+            timeout,
+        }),
+    }
+}
+
+/// Everything needed to execute the query of a subscription for an event.
+struct SubscriptionEventContext<R> {
+    logger: Logger,
+    resolver: Arc<R>,
+    query: Arc<crate::execution::Query>,
+    subscription_type: Arc<s::ObjectType>,
+    timeout: Option<Duration>,
+    field_timeouts: Arc<HashMap<String, Duration>>,
+    max_complexity: Option<u64>,
+    max_depth: u8,
+    max_first: u32,
+    mode: ExecutionMode,
+    block_range: Option<SubscriptionBlockRange>,
+    max_result_size_bytes: Option<usize>,
+    query_permits: Arc<QueryPermits>,
+    priority: SubscriptionPriority,
+    query_permit_timeout: Option<Duration>,
+    execute_inline: bool,
+    metrics: Option<Arc<SubscriptionMetrics>>,
+    cancel_handle: Option<CancelHandle>,
+    result_transform: Option<SubscriptionResultTransform>,
+    transform_initial_result: bool,
+    report_event_details: bool,
+    schema_check: Option<SchemaCheck>,
+    execution_budget: Option<Duration>,
+    /// How long the queries for events have run so far; only tracked if
+    /// there is an execution budget
+    execution_spent: Mutex<Duration>,
+    clock: Arc<dyn SubscriptionClock>,
+}
+
+/// Lets a subscription notice that the schema of its subgraph changed.
+struct SchemaCheck {
+    source: SubscriptionSchemaSource,
+    /// The query as it was submitted
+    query: graph::prelude::Query,
+    /// The block of the last event the schema was looked up for
+    checked_block: Mutex<Option<BlockNumber>>,
+    /// The deployment of the last new schema that was handled
+    handled: Mutex<Option<SubgraphDeploymentId>>,
+}
+
+/// The settings from `SubscriptionExecutionOptions` that decide how
+/// `map_source_to_response_stream` turns events into results.
+struct ResponseStreamOptions {
+    emit_initial_result: bool,
+    prewarm_initial_result: bool,
+    event_buffer: Option<SubscriptionEventBuffer>,
+    max_in_flight_queries: usize,
+    delivery_mode: SubscriptionDeliveryMode,
+    suppress_duplicate_results: bool,
+    error_policy: SubscriptionErrorPolicy,
+    error_classifier: Option<SubscriptionErrorClassifier>,
+    compression: Option<SubscriptionCompression>,
+    keep_alive_interval: Option<Duration>,
+    timer_jitter: f64,
+}
+
+fn map_source_to_response_stream<R>(
+    event_ctx: SubscriptionEventContext<R>,
+    source_stream: StoreEventStreamBox,
+    options: ResponseStreamOptions,
+) -> QueryResultStream
+where
+    R: Resolver + 'static,
+{
+    let ResponseStreamOptions {
+        emit_initial_result,
+        prewarm_initial_result,
+        event_buffer,
+        max_in_flight_queries,
+        delivery_mode,
+        suppress_duplicate_results,
+        error_policy,
+        error_classifier,
+        compression,
+        keep_alive_interval,
+        timer_jitter,
+    } = options;
+
+    // Keep reading events while queries are being executed
+    let source_stream = match event_buffer {
+        Some(event_buffer) => buffer::buffered_events(
+            source_stream,
+            event_buffer,
+            event_ctx.metrics.clone(),
+            event_ctx.query.query_id.clone(),
+        ),
+        None => source_stream,
+    };
+
+    let clock = event_ctx.clock.clone();
+    let event_ctx = Arc::new(event_ctx);
 
     // Create a stream with a single empty event. By chaining this in front
     // of the real events, we trick the subscription into executing its query
     // at least once. This satisfies the GraphQL over Websocket protocol
     // requirement of "respond[ing] with at least one GQL_DATA message", see
     // https://github.com/apollographql/subscriptions-transport-ws/blob/master/PROTOCOL.md#gql_data
-    let trigger_stream = futures03::stream::iter(vec![Ok(StoreEvent {
+    //
+    // Clients that turn off `emit_initial_result` take care of that
+    // requirement themselves. Their first result is the first one caused by
+    // a real event, which is also sent in full in diff mode.
+    //
+    // Every event is paired with whether it is the trigger event.
+    let trigger_event = || StoreEvent {
         tag: 0,
         changes: Default::default(),
-    })]);
+        block: None,
+    };
+    let trigger_events = if emit_initial_result {
+        vec![Ok((trigger_event(), true))]
+    } else {
+        vec![]
+    };
+    let trigger_stream = futures03::stream::iter(trigger_events);
 
-    Box::new(
-        trigger_stream
-            .chain(source_stream.compat())
-            .then(move |res| match res {
-                Err(()) => {
-                    futures03::future::ready(QueryExecutionError::EventStreamError.into()).boxed()
-                }
-                Ok(event) => execute_subscription_event(
-                    logger.clone(),
-                    resolver.clone(),
-                    query.clone(),
-                    event,
-                    timeout.clone(),
-                    max_first,
+    // Start on the query for the initial result right away; the trigger
+    // event picks up its result instead of running the query itself
+    let block_range = event_ctx.block_range;
+    let mut prewarmed = if emit_initial_result && prewarm_initial_result {
+        Some(graph::spawn(execute_event(
+            event_ctx.clone(),
+            trigger_event(),
+            block_range,
+        )))
+    } else {
+        None
+    };
+
+    // `None` marks the end of the source stream
+    let events = trigger_stream
+        .chain(source_stream.compat().map_ok(|event| (event, false)))
+        .map(Some)
+        .chain(futures03::stream::once(futures03::future::ready(None)));
+
+    // Every event leads to either no result or to a result and whether it is
+    // the last one; only subscriptions with a block range or that terminate
+    // on errors skip events or end before the source stream does. Outcomes
+    // are passed on in the order of the events, even if queries for later
+    // events finish first
+    let response_stream = events
+        .map(move |res| match res {
+            // A broken source stream ends the subscription regardless of the
+            // error policy
+            Some(Err(())) => futures03::future::ready(Some((
+                QueryResult::from(QueryExecutionError::EventStreamError),
+                true,
+            )))
+            .boxed(),
+            // Tell the client that no more results will follow; this is only
+            // reached if the source stream ended gracefully, e.g. because the
+            // subgraph was removed
+            None => futures03::future::ready(Some((QueryResult::completed(), true))).boxed(),
+            Some(Ok((event, is_trigger))) => {
+                let transform = match &event_ctx.result_transform {
+                    Some(_) if is_trigger && !event_ctx.transform_initial_result => None,
+                    transform => transform.clone(),
+                };
+                let prewarmed = if is_trigger { prewarmed.take() } else { None };
+                let error_classifier = error_classifier.clone();
+                let outcome = match prewarmed {
+                    Some(prewarmed) => prewarmed
+                        .map(|outcome| {
+                            outcome.unwrap_or_else(|e| {
+                                let e = QueryExecutionError::Panic(e.to_string());
+                                Some((QueryResult::from(e), false))
+                            })
+                        })
+                        .boxed(),
+                    None => execute_event(event_ctx.clone(), event, block_range).boxed(),
+                };
+                outcome
+                    .map(move |outcome| match (outcome, transform) {
+                        (Some((result, last)), Some(transform)) => Some((transform(result), last)),
+                        (outcome, _) => outcome,
+                    })
+                    .map(move |outcome| {
+                        outcome.map(|(mut result, last)| {
+                            let terminate =
+                                match classify_errors(&mut result, error_classifier.as_ref()) {
+                                    Some(SubscriptionErrorSeverity::Fatal) => true,
+                                    Some(SubscriptionErrorSeverity::Error) => {
+                                        error_policy == SubscriptionErrorPolicy::TerminateOnError
+                                    }
+                                    Some(SubscriptionErrorSeverity::Warning) | None => false,
+                                };
+                            (result, last || terminate)
+                        })
+                    })
+                    .boxed()
+            }
+        })
+        .buffered(max_in_flight_queries.max(1))
+        .scan(false, |done, outcome: Option<(QueryResult, bool)>| {
+            futures03::future::ready(if *done {
+                None
+            } else {
+                if let Some((_, last)) = &outcome {
+                    *done = *last;
+                }
+                Some(outcome)
+            })
+        })
+        .filter_map(|outcome| futures03::future::ready(outcome.map(|(result, _)| result)));
+
+    // Results without anything new for the client are dropped before the
+    // diffs are computed, which would be empty for them
+    let response_stream: QueryResultStream = if suppress_duplicate_results {
+        Box::new(
+            response_stream
+                .scan(
+                    None,
+                    |last_sent: &mut Option<(u64, q::Value)>, result: QueryResult| {
+                        futures03::future::ready(Some(if is_duplicate_result(last_sent, &result) {
+                            None
+                        } else {
+                            Some(result)
+                        }))
+                    },
                 )
-                .boxed(),
+                .filter_map(futures03::future::ready),
+        )
+    } else {
+        Box::new(response_stream)
+    };
+
+    let response_stream: QueryResultStream = match delivery_mode {
+        SubscriptionDeliveryMode::Full => Box::new(response_stream),
+        SubscriptionDeliveryMode::Diff => Box::new(response_stream.scan(
+            None,
+            |previous: &mut Option<q::Value>, mut result: QueryResult| {
+                // Results without data, e.g. errors, are passed on as they are
+                // and don't replace the value later results are compared to
+                if let Some(data) = result.data.take() {
+                    result.data = Some(match previous.as_ref() {
+                        Some(previous) => diff_values(previous, &data)
+                            .unwrap_or_else(|| q::Value::Object(BTreeMap::new())),
+                        None => data.clone(),
+                    });
+                    *previous = Some(data);
+                }
+                futures03::future::ready(Some(result))
+            },
+        )),
+    };
+
+    // Compress what is actually sent, i.e. the diff in diff mode
+    let response_stream: QueryResultStream = match compression {
+        Some(compression) => Box::new(
+            response_stream.map(move |result| compression::compress_result(result, &compression)),
+        ),
+        None => response_stream,
+    };
+
+    match keep_alive_interval {
+        Some(interval) => with_keep_alive(response_stream, interval, timer_jitter, clock),
+        None => response_stream,
+    }
+}
+
+/// Moves the errors of `result` that `classifier` considers warnings to the
+/// warnings of the result, and returns the highest severity of its errors,
+/// or `None` if it has none. Without a classifier, all errors are subject
+/// to the error policy.
+fn classify_errors(
+    result: &mut QueryResult,
+    classifier: Option<&SubscriptionErrorClassifier>,
+) -> Option<SubscriptionErrorSeverity> {
+    let classifier = match (&result.errors, classifier) {
+        (None, _) => return None,
+        (Some(_), None) => return Some(SubscriptionErrorSeverity::Error),
+        (Some(_), Some(classifier)) => classifier,
+    };
+
+    let mut severity = None;
+    let mut errors = vec![];
+    let mut warnings = result.warnings.take().unwrap_or_default();
+    for error in result.errors.take().unwrap_or_default() {
+        let error_severity = classifier(&error);
+        severity = severity.max(Some(error_severity));
+        match error_severity {
+            SubscriptionErrorSeverity::Warning => warnings.push(error),
+            _ => errors.push(error),
+        }
+    }
+    result.errors = Some(errors).filter(|errors| !errors.is_empty());
+    result.warnings = Some(warnings).filter(|warnings| !warnings.is_empty());
+    severity
+}
+
+/// Interleaves keep-alive results into `response_stream` whenever it hasn't
+/// produced a result for `interval`. Keep-alive results have neither data nor
+/// errors, see `QueryResult::is_keep_alive`. They are generated locally and
+/// never cause the subscription query to be executed. Every interval is
+/// extended by up to `jitter`, see `jittered`, and measured with `clock`.
+fn with_keep_alive(
+    mut response_stream: QueryResultStream,
+    interval: Duration,
+    jitter: f64,
+    clock: Arc<dyn SubscriptionClock>,
+) -> QueryResultStream {
+    let next_keep_alive = move || clock.delay_until(clock.now() + jittered(interval, jitter));
+    let mut delay = next_keep_alive();
+
+    Box::new(futures03::stream::poll_fn(move |cx| {
+        // Results, and the end of the response stream, take priority over
+        // keep-alives; every result restarts the keep-alive timer
+        if let Poll::Ready(result) = response_stream.poll_next_unpin(cx) {
+            delay = next_keep_alive();
+            return Poll::Ready(result);
+        }
+
+        match delay.poll_unpin(cx) {
+            Poll::Ready(()) => {
+                delay = next_keep_alive();
+                Poll::Ready(Some(QueryResult::keep_alive()))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }))
+}
+
+/// Extends `duration` by a random fraction of itself between 0 and `jitter`,
+/// so that timers that are set at the same time with the same duration are
+/// spread out instead of firing together.
+fn jittered(duration: Duration, jitter: f64) -> Duration {
+    if jitter > 0.0 {
+        duration.mul_f64(1.0 + rand::random::<f64>() * jitter)
+    } else {
+        duration
+    }
+}
+
+/// Checks the result of a subscription query against the complexity and
+/// depth limits of the subscription.
+///
+/// The complexity of a result is measured the same way `Query` estimates the
+/// complexity of a query, except that it uses the number of entries that
+/// lists in the result actually have instead of their `first` argument.
+fn check_result_complexity(
+    value: &q::Value,
+    max_complexity: Option<u64>,
+    max_depth: u8,
+) -> Result<(), QueryExecutionError> {
+    fn complexity(value: &q::Value, max_depth: u8, depth: u8) -> Result<u64, QueryExecutionError> {
+        match value {
+            q::Value::Object(fields) => {
+                if depth >= max_depth {
+                    return Err(QueryExecutionError::TooDeep(max_depth));
+                }
+                fields.values().try_fold(0u64, |total, value| {
+                    Ok(total.saturating_add(complexity(value, max_depth, depth + 1)?))
+                })
+            }
+            q::Value::List(values) => values.iter().try_fold(0u64, |total, value| {
+                let value_complexity = complexity(value, max_depth, depth)?;
+                Ok(total.saturating_add(value_complexity.saturating_add(1)))
             }),
-    )
+            _ => Ok(0),
+        }
+    }
+
+    let result_complexity = complexity(value, max_depth, 0)?;
+    match max_complexity {
+        Some(max_complexity) if result_complexity > max_complexity => Err(
+            QueryExecutionError::TooComplex(result_complexity, max_complexity),
+        ),
+        _ => Ok(()),
+    }
 }
 
-async fn execute_subscription_event(
-    logger: Logger,
-    resolver: Arc<impl Resolver + 'static>,
-    query: Arc<crate::execution::Query>,
+/// Whether `result` has the same data as the last result that was sent,
+/// whose data `last_sent` holds together with its hash. Only results with
+/// data and without errors or warnings count; any other result that is sent
+/// starts the comparison over, so the data after it is always sent.
+fn is_duplicate_result(last_sent: &mut Option<(u64, q::Value)>, result: &QueryResult) -> bool {
+    let data = match (&result.data, &result.errors, &result.warnings) {
+        (Some(data), None, None) => data,
+        _ => {
+            *last_sent = None;
+            return false;
+        }
+    };
+
+    let mut hasher = DefaultHasher::new();
+    hash_value(data, &mut hasher);
+    let hash = hasher.finish();
+    match last_sent {
+        Some((last_hash, last_data)) if *last_hash == hash && last_data == data => true,
+        _ => {
+            *last_sent = Some((hash, data.clone()));
+            false
+        }
+    }
+}
+
+/// Feeds `value` into `hasher`. Values that are equal hash the same, except
+/// for floats that only differ in the sign of zero, which is harmless for
+/// telling results apart.
+fn hash_value(value: &q::Value, hasher: &mut impl Hasher) {
+    std::mem::discriminant(value).hash(hasher);
+    match value {
+        q::Value::Variable(name) | q::Value::Enum(name) | q::Value::String(name) => {
+            name.hash(hasher)
+        }
+        q::Value::Int(n) => n.as_i64().hash(hasher),
+        q::Value::Float(f) => f.to_bits().hash(hasher),
+        q::Value::Boolean(b) => b.hash(hasher),
+        q::Value::Null => (),
+        q::Value::List(values) => {
+            values.len().hash(hasher);
+            for value in values {
+                hash_value(value, hasher);
+            }
+        }
+        q::Value::Object(fields) => {
+            fields.len().hash(hasher);
+            for (key, value) in fields {
+                key.hash(hasher);
+                hash_value(value, hasher);
+            }
+        }
+    }
+}
+
+/// Computes the changes from `previous` to `current`. Object values are
+/// compared field by field, and only fields whose values changed are
+/// included; fields that were removed are reported as `null`. All other
+/// values are reported in full if they changed. Returns `None` if the two
+/// values are the same.
+fn diff_values(previous: &q::Value, current: &q::Value) -> Option<q::Value> {
+    match (previous, current) {
+        (q::Value::Object(previous), q::Value::Object(current)) => {
+            let mut changes = BTreeMap::new();
+            for (key, value) in current.iter() {
+                match previous.get(key) {
+                    Some(previous_value) => {
+                        if let Some(change) = diff_values(previous_value, value) {
+                            changes.insert(key.clone(), change);
+                        }
+                    }
+                    None => {
+                        changes.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            for key in previous.keys() {
+                if !current.contains_key(key) {
+                    changes.insert(key.clone(), q::Value::Null);
+                }
+            }
+            if changes.is_empty() {
+                None
+            } else {
+                Some(q::Value::Object(changes))
+            }
+        }
+        (previous, current) if previous == current => None,
+        (_, current) => Some(current.clone()),
+    }
+}
+
+/// Executes the query for `event`, within `block_range` if it is set. Returns
+/// the result, if any, together with whether it is the last result.
+async fn execute_event(
+    event_ctx: Arc<SubscriptionEventContext<impl Resolver + 'static>>,
     event: StoreEvent,
-    timeout: Option<Duration>,
-    max_first: u32,
-) -> QueryResult {
-    debug!(logger, "Execute subscription event"; "event" => format!("{:?}", event));
+    block_range: Option<SubscriptionBlockRange>,
+) -> Option<(QueryResult, bool)> {
+    if let Some(result) = check_execution_budget(&event_ctx) {
+        return Some((result, true));
+    }
 
-    // Create a fresh execution context with deadline.
-    let ctx = ExecutionContext {
-        logger,
-        resolver,
-        query,
-        fields: vec![],
-        deadline: timeout.map(|t| Instant::now() + t),
-        max_first,
-        block: BLOCK_NUMBER_MAX,
-        mode: ExecutionMode::Prefetch,
-    };
+    if let Some(result) = check_schema(&event_ctx, event.block).await {
+        return Some((result, true));
+    }
 
-    // We have established that this exists earlier in the subscription execution
-    let subscription_type = sast::get_root_subscription_type(&ctx.query.schema.document)
-        .unwrap()
-        .clone();
+    match block_range {
+        Some(range) => execute_subscription_event_in_range(event_ctx, event, range).await,
+        None => Some((
+            execute_subscription_event(event_ctx, event, BLOCK_NUMBER_MAX).await,
+            false,
+        )),
+    }
+}
 
-    // Use a semaphore to prevent subscription queries, which can be numerous and might query all at
-    // once, from flooding the blocking thread pool and the DB connection pool.
-    let _permit = SUBSCRIPTION_QUERY_SEMAPHORE.acquire();
-    let result = graph::spawn_blocking_allow_panic(async move {
-        execute_selection_set(&ctx, &ctx.query.selection_set, &subscription_type, &None)
-    })
-    .await
-    .map_err(|e| vec![QueryExecutionError::Panic(e.to_string())])
-    .and_then(|x| x);
+/// Returns the last result of the subscription if its queries have run for
+/// longer than its execution budget allows.
+fn check_execution_budget(
+    event_ctx: &SubscriptionEventContext<impl Resolver + 'static>,
+) -> Option<QueryResult> {
+    let budget = event_ctx.execution_budget?;
+    let spent = *event_ctx.execution_spent.lock().unwrap();
+    if spent <= budget {
+        return None;
+    }
+
+    info!(
+        event_ctx.logger,
+        "Subscription exhausted its execution budget";
+        "spent_ms" => spent.as_millis(),
+        "budget_ms" => budget.as_millis(),
+    );
+    Some(QueryResult::from(
+        QueryExecutionError::ExecutionBudgetExhausted { spent, budget },
+    ))
+}
+
+/// Checks whether the schema of the subgraph has changed since the
+/// subscription was validated, and revalidates the query against a new
+/// schema. Returns the last result of the subscription if it has to end
+/// because of the change. The schema is looked up once per `block`.
+async fn check_schema(
+    event_ctx: &SubscriptionEventContext<impl Resolver + 'static>,
+    block: Option<BlockNumber>,
+) -> Option<QueryResult> {
+    let check = event_ctx.schema_check.as_ref()?;
+    if let Some(block) = block {
+        let mut checked_block = check.checked_block.lock().unwrap();
+        if *checked_block == Some(block) {
+            return None;
+        }
+        *checked_block = Some(block);
+    }
+
+    // Looking up the schema may have to go to the store
+    let source = check.source.clone();
+    let schema = graph::spawn_blocking_allow_panic(async move { source() })
+        .await
+        .ok()
+        .flatten()?;
+
+    let pinned = &event_ctx.query.schema;
+    if Arc::ptr_eq(&schema, pinned) || schema.id == pinned.id {
+        return None;
+    }
+    {
+        let mut handled = check.handled.lock().unwrap();
+        if handled.as_ref() == Some(&schema.id) {
+            return None;
+        }
+        *handled = Some(schema.id.clone());
+    }
+
+    let deployment = schema.id.to_string();
+    let query = graph::prelude::Query::new(
+        schema.clone(),
+        check.query.document.clone(),
+        check.query.variables.clone(),
+    )
+    .with_operation_name(check.query.operation_name.clone());
+    let mut errors =
+        match crate::execution::Query::new(query, event_ctx.max_complexity, event_ctx.max_depth) {
+            Ok(_) if sast::get_root_subscription_type(&schema.document).is_none() => {
+                vec![QueryExecutionError::NoRootSubscriptionObjectType {
+                    deployment: schema.id.clone(),
+                    schema_version: schema.version(),
+                }]
+            }
+            Ok(_) => vec![],
+            Err(errors) => errors,
+        };
+
+    if errors.is_empty() {
+        info!(
+            event_ctx.logger,
+            "Subgraph schema changed, subscription is still valid and keeps its schema";
+            "deployment" => &deployment,
+        );
+        return None;
+    }
+
+    info!(
+        event_ctx.logger,
+        "Subgraph schema changed, end subscription that is no longer valid";
+        "deployment" => &deployment,
+    );
+    errors.insert(
+        0,
+        QueryExecutionError::SubscriptionSchemaChanged(deployment),
+    );
+    Some(QueryResult::schema_changed(errors))
+}
+
+/// Executes the query for `event` at the latest block the subgraph has been
+/// indexed up to, but at most at the end of `range`. Returns `None` if the
+/// subgraph hasn't reached the start of the range yet, and otherwise the
+/// result together with whether it is the result at the end of the range.
+async fn execute_subscription_event_in_range(
+    event_ctx: Arc<SubscriptionEventContext<impl Resolver + 'static>>,
+    event: StoreEvent,
+    range: SubscriptionBlockRange,
+) -> Option<(QueryResult, bool)> {
+    let resolver = event_ctx.resolver.clone();
+    let subgraph_id = event_ctx.query.schema.id.clone();
+    let latest_block =
+        graph::spawn_blocking_allow_panic(async move { resolver.latest_block(&subgraph_id) })
+            .await
+            .unwrap_or_else(|e| Err(QueryExecutionError::Panic(e.to_string())));
+
+    let latest_block = match latest_block {
+        Ok(Some(latest_block)) if latest_block >= range.from => latest_block,
+        Ok(_) => return None,
+        Err(e) => return Some((QueryResult::from(e), false)),
+    };
+
+    let block = latest_block.min(range.to);
+    let result = execute_subscription_event(event_ctx, event, block).await;
+    Some((result, block == range.to))
+}
+
+async fn execute_subscription_event(
+    event_ctx: Arc<SubscriptionEventContext<impl Resolver + 'static>>,
+    event: StoreEvent,
+    block: BlockNumber,
+) -> QueryResult {
+    let mut entity_types = BTreeMap::new();
+    for change in event.changes.iter() {
+        *entity_types.entry(change.entity_type.clone()).or_insert(0) += 1;
+    }
+    debug!(
+        event_ctx.logger,
+        "Execute subscription event";
+        "changes" => event.changes.len(),
+        "entity_types" => entity_types.keys().cloned().collect::<Vec<_>>().join(", "),
+    );
+    let event_details = if event_ctx.report_event_details {
+        Some(QueryResultEvent {
+            tag: event.tag,
+            changes: event.changes.len(),
+            entity_types,
+        })
+    } else {
+        None
+    };
+    debug!(
+        event_ctx.logger,
+        #"subscription_event",
+        "Subscription event";
+        "event" => format!("{:?}", event),
+    );
+
+    // Create a fresh execution context with deadline. Resolvers that trace
+    // store operations keep a separate trace for each event
+    let start = Instant::now();
+    let permit_wait_start = event_ctx.clock.now();
+    let resolver = Arc::new(event_ctx.resolver.for_query());
+    let ctx = ExecutionContext {
+        logger: event_ctx.logger.clone(),
+        resolver: resolver.cheap_clone(),
+        query: event_ctx.query.cheap_clone(),
+        fields: vec![],
+        deadline: event_ctx.timeout.map(|t| start + t),
+        field_timeouts: event_ctx.field_timeouts.clone(),
+        cancel_handle: event_ctx.cancel_handle.clone(),
+        max_first: event_ctx.max_first,
+        block,
+        mode: event_ctx.mode,
+    };
+
+    let subscription_type = event_ctx.subscription_type.clone();
+
+    // Use query permits to prevent subscription queries, which can be numerous and might query all
+    // at once, from flooding the blocking thread pool and the DB connection pool.
+    // Rather than letting events queue up for a permit without bound, tell
+    // the client to back off if there's no permit in time
+    let waiting = event_ctx
+        .metrics
+        .as_ref()
+        .map(|metrics| metrics.query_permit_waiting());
+    let _permit = match acquire_query_permit(
+        &event_ctx.query_permits,
+        event_ctx.priority,
+        event_ctx.query_permit_timeout,
+        event_ctx.clock.as_ref(),
+    )
+    .await
+    {
+        Some(permit) => permit,
+        None => {
+            let waited = event_ctx
+                .clock
+                .now()
+                .saturating_duration_since(permit_wait_start);
+            warn!(
+                event_ctx.logger,
+                "No query permit available for subscription event";
+                "waited_ms" => waited.as_millis(),
+            );
+            if let Some(metrics) = event_ctx.metrics.as_ref() {
+                metrics.event_overloaded(&event_ctx.query.query_id);
+            }
+            let mut result = QueryResult::from(QueryExecutionError::Overloaded { waited });
+            result.event = event_details;
+            return result;
+        }
+    };
+    drop(waiting);
+    let _permit_in_use = event_ctx
+        .metrics
+        .as_ref()
+        .map(|metrics| metrics.query_permit_in_use());
+    let permit_wait_time = event_ctx
+        .clock
+        .now()
+        .saturating_duration_since(permit_wait_start);
+
+    let execution_start = Instant::now();
+
+    // Fields that fail don't keep the other fields from being sent; only a
+    // panic loses the whole result
+    let execute_inline = event_ctx.execute_inline && !resolver.is_blocking();
+    let execute = move || {
+        // Panics are caught on the thread that executes the query, since that
+        // is where we can find out which field was being executed
+        std::panic::catch_unwind(AssertUnwindSafe(|| {
+            let (value, errors) = execute_selection_set_partially(
+                &ctx,
+                &ctx.query.selection_set,
+                &subscription_type,
+                &None,
+            );
+            if ctx.mode == ExecutionMode::Verify && errors.is_empty() {
+                let errors = verify_prefetched_result(&ctx, &subscription_type, &value);
+                if !errors.is_empty() {
+                    return (None, errors);
+                }
+            }
+            (value, errors)
+        }))
+        .unwrap_or_else(|payload| {
+            let message = panic_message(payload.as_ref());
+            let e = match current_root_field() {
+                Some(field) => QueryExecutionError::FieldPanic(field, message),
+                None => QueryExecutionError::Panic(message),
+            };
+            (None, vec![e])
+        })
+    };
+    let (value, errors) = if execute_inline {
+        execute()
+    } else {
+        graph::spawn_blocking_allow_panic(async move { execute() })
+            .await
+            .unwrap_or_else(|e| (None, vec![QueryExecutionError::Panic(e.to_string())]))
+    };
+
+    // Report hitting the deadline as a subscription timeout so that clients
+    // can tell it apart from other errors and retry
+    let errors = errors
+        .into_iter()
+        .map(|e| match e {
+            QueryExecutionError::Timeout => QueryExecutionError::SubscriptionTimeout {
+                elapsed: start.elapsed(),
+            },
+            e => e,
+        })
+        .collect::<Vec<_>>();
+
+    // The complexity of the query is only estimated when the subscription is
+    // set up; check that what was actually loaded stays within bounds. The
+    // errors of the fields that failed are reported either way
+    let (value, errors) = match value {
+        Some(value) => {
+            match check_result_complexity(&value, event_ctx.max_complexity, event_ctx.max_depth) {
+                Ok(()) => (Some(value), errors),
+                Err(e) => {
+                    let mut errors = errors;
+                    errors.push(e);
+                    (None, errors)
+                }
+            }
+        }
+        None => (None, errors),
+    };
+
+    let execution_time = execution_start.elapsed();
+    if event_ctx.execution_budget.is_some() {
+        *event_ctx.execution_spent.lock().unwrap() += execution_time;
+    }
+
+    let result = QueryResult {
+        data: value,
+        errors: if errors.is_empty() {
+            None
+        } else {
+            Some(errors.into_iter().map(QueryError::from).collect())
+        },
+        warnings: None,
+        completed: false,
+        schema_changed: false,
+        unsubscribed: false,
+        event: event_details,
+        trace: resolver.trace(),
+        compression: None,
+    };
+
+    // Serializing the result is expensive, only do it if we need its size
+    let result_size = if event_ctx.metrics.is_some() || event_ctx.max_result_size_bytes.is_some() {
+        serde_json::to_vec(&result).ok().map(|bytes| bytes.len())
+    } else {
+        None
+    };
+
+    if let Some(metrics) = event_ctx.metrics.as_ref() {
+        let query_id = &event_ctx.query.query_id;
+        metrics.event_processed(query_id);
+        metrics.observe_query_permit_wait_time(query_id, permit_wait_time.as_secs_f64());
+        metrics.observe_query_execution_time(query_id, execution_time.as_secs_f64());
+        if let Some(result_size) = result_size {
+            metrics.observe_query_result_size(query_id, result_size);
+        }
+    }
+
+    match (result_size, event_ctx.max_result_size_bytes) {
+        (Some(size), Some(max_size)) if size > max_size => {
+            let mut too_large =
+                QueryResult::from(QueryExecutionError::ResultTooLarge(size, max_size));
+            too_large.event = result.event;
+            too_large.trace = result.trace;
+            too_large
+        }
+        _ => result,
+    }
+}
+
+/// Waits for a query permit from `permits` for an event of a subscription
+/// with `priority`, for at most `timeout` if it is set. Returns `None` if no
+/// permit became available in time.
+///
+/// Permits are handed out by priority, and fairly within a priority: events
+/// of the same priority get them in the order in which they started
+/// waiting, no matter which subscription they belong to, so an event that
+/// starts waiting later can't overtake one that is already waiting. Events
+/// whose wait times out leave the queue without changing the order of the
+/// others. A chatty subscription can therefore delay a quiet one of the same
+/// priority by at most the queries for the events that were already
+/// waiting, but never starve it. See `QueryPermits` for how lower
+/// priorities still make progress.
+async fn acquire_query_permit<'a>(
+    permits: &'a QueryPermits,
+    priority: SubscriptionPriority,
+    timeout: Option<Duration>,
+    clock: &dyn SubscriptionClock,
+) -> Option<QueryPermit<'a>> {
+    match timeout {
+        Some(timeout) => {
+            let deadline = clock.delay_until(clock.now() + timeout);
+            match futures03::future::select(Box::pin(permits.acquire(priority)), deadline).await {
+                futures03::future::Either::Left((permit, _)) => Some(permit),
+                futures03::future::Either::Right(((), _)) => None,
+            }
+        }
+        None => Some(permits.acquire(priority).await),
+    }
+}
+
+/// The message of a panic, if it has the usual `&str` or `String` payload.
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => String::from("unknown panic"),
+        },
+    }
+}
+
+/// Executes the subscription query again, this time with prefetching, and
+/// checks that the result is the same as `value`.
+fn verify_prefetched_result<R>(
+    ctx: &ExecutionContext<R>,
+    subscription_type: &s::ObjectType,
+    value: &Option<q::Value>,
+) -> Vec<QueryExecutionError>
+where
+    R: Resolver,
+{
+    let initial_data = match ctx.resolver.prefetch(ctx, &ctx.query.selection_set) {
+        Ok(initial_data) => initial_data,
+        Err(errors) => return errors,
+    };
+    let (prefetched, errors) = execute_selection_set_partially(
+        ctx,
+        &ctx.query.selection_set,
+        subscription_type,
+        &initial_data,
+    );
+    if !errors.is_empty() {
+        return errors;
+    }
+
+    if &prefetched != value {
+        vec![QueryExecutionError::IncorrectPrefetchResult {
+            slow: value.clone().unwrap_or(q::Value::Null),
+            prefetch: prefetched.unwrap_or(q::Value::Null),
+        }]
+    } else {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::{Query as GraphDataQuery, *};
+    use graphql_parser::{parse_query, query as q, schema as s};
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::object;
+    use crate::prelude::{Name, ObjectType};
+    use crate::query::ext::BlockConstraint;
+
+    const SCHEMA: &str = "
+        type Query {
+            musicians(first: Int): [Musician!]!
+            named: [Named!]!
+        }
+
+        type Subscription {
+            musicians(first: Int): [Musician!]!
+            named: [Named!]!
+        }
+
+        interface Named {
+            name: String!
+        }
+
+        type Musician implements Named @entity {
+            id: ID!
+            name: String!
+            bands: [Band!]!
+        }
+
+        type Band implements Named @entity {
+            id: ID!
+            name: String!
+            songs: [Song!]!
+        }
+
+        type Song @entity {
+            id: ID!
+            title: String!
+        }
+    ";
+
+    /// A resolver that takes a long time to resolve lists of objects.
+    #[derive(Clone)]
+    struct SlowResolver {
+        musicians: usize,
+
+        /// The response key of a field that fails to resolve.
+        failing_field: Option<&'static str>,
+
+        /// The response key of a field that panics while it is resolved.
+        panicking_field: Option<&'static str>,
+
+        /// If set, prefetching is supported and lists resolved from
+        /// prefetched data have this many musicians.
+        prefetched_musicians: Option<usize>,
+    }
+
+    impl Resolver for SlowResolver {
+        fn prefetch(
+            &self,
+            _: &ExecutionContext<Self>,
+            _: &q::SelectionSet,
+        ) -> Result<Option<q::Value>, Vec<QueryExecutionError>> {
+            Ok(self
+                .prefetched_musicians
+                .map(|_| q::Value::Object(BTreeMap::new())))
+        }
+
+        fn locate_block(&self, _: &BlockConstraint) -> Result<BlockNumber, QueryExecutionError> {
+            Ok(BLOCK_NUMBER_MAX)
+        }
+
+        fn resolve_objects(
+            &self,
+            parent: &Option<q::Value>,
+            field: &q::Field,
+            _: &s::Field,
+            _: ObjectOrInterface<'_>,
+            _: &HashMap<&q::Name, q::Value>,
+            _: &BTreeMap<Name, Vec<ObjectType>>,
+            _: BlockNumber,
+            _: u32,
+        ) -> Result<q::Value, QueryExecutionError> {
+            let alias = field.alias.as_ref().map(String::as_str);
+            if self.panicking_field.is_some() && alias == self.panicking_field {
+                panic!("broken resolver");
+            }
+            if self.failing_field.is_some() && alias == self.failing_field {
+                return Err(QueryExecutionError::ResolveEntitiesError(
+                    "broken field".to_owned(),
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+            let musicians = match parent {
+                Some(_) => self.prefetched_musicians.unwrap_or(self.musicians),
+                None => self.musicians,
+            };
+            Ok(q::Value::List(vec![object! { name: "John" }; musicians]))
+        }
+
+        fn resolve_object(
+            &self,
+            _: &Option<q::Value>,
+            _: &q::Field,
+            _: &s::Field,
+            _: ObjectOrInterface<'_>,
+            _: &HashMap<&q::Name, q::Value>,
+            _: &BTreeMap<Name, Vec<ObjectType>>,
+            _: BlockNumber,
+        ) -> Result<q::Value, QueryExecutionError> {
+            Ok(q::Value::Null)
+        }
+
+        fn resolve_field_stream<'a, 'b>(
+            &self,
+            _: &'a s::Document,
+            _: &'a s::ObjectType,
+            _: &'b q::Field,
+            _: &HashMap<&q::Name, q::Value>,
+        ) -> Result<StoreEventStreamBox, QueryExecutionError> {
+            let events: Box<dyn Stream<Item = StoreEvent, Error = ()> + Send> =
+                Box::new(stream::empty());
+            Ok(StoreEventStream::new(events))
+        }
+
+        fn latest_block(
+            &self,
+            _: &SubgraphDeploymentId,
+        ) -> Result<Option<BlockNumber>, QueryExecutionError> {
+            Ok(Some(LATEST_BLOCK))
+        }
+    }
+
+    /// The block `SlowResolver` claims to have indexed up to.
+    const LATEST_BLOCK: BlockNumber = 5;
+
+    fn schema() -> Arc<Schema> {
+        Arc::new(
+            Schema::parse(SCHEMA, SubgraphDeploymentId::new("subscription").unwrap())
+                .expect("invalid test schema"),
+        )
+    }
+
+    fn options(timeout: Option<Duration>) -> SubscriptionExecutionOptions<SlowResolver> {
+        let builder = SubscriptionExecutionOptions::builder(
+            Logger::root(slog::Discard, o!()),
+            SlowResolver {
+                musicians: 1,
+                failing_field: None,
+                panicking_field: None,
+                prefetched_musicians: None,
+            },
+        )
+        .max_depth(100)
+        .max_first(std::u32::MAX);
+        match timeout {
+            Some(timeout) => builder.timeout(timeout).build(),
+            None => builder.build(),
+        }
+    }
+
+    fn entity_type_set(query: &str) -> EntityTypeSet {
+        let query = crate::execution::Query::new(
+            GraphDataQuery::new(
+                schema(),
+                parse_query(query).expect("invalid test query"),
+                None,
+            ),
+            None,
+            std::u8::MAX,
+        )
+        .expect("invalid test query");
+        let subscription_type = sast::get_root_subscription_type(&query.schema.document).unwrap();
+
+        EntityTypeSet::new(&query, subscription_type)
+    }
+
+    fn entity_types(query: &str) -> HashSet<String> {
+        entity_type_set(query).iter().cloned().collect()
+    }
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn query_id_ignores_formatting() {
+        let id = |query: &str| crate::execution::query_id(&parse_query(query).unwrap());
+
+        let compact = id("subscription { musicians(first: 10) { name bands { name } } }");
+        let formatted = id("
+            # All musicians and their bands
+            subscription {
+              musicians(first: 10) {
+                name,
+                bands { name }
+              }
+            }");
+        assert_eq!(compact, formatted);
+        assert_eq!(compact.len(), 16);
+
+        assert_ne!(
+            compact,
+            id("subscription { musicians(first: 10) { name } }")
+        );
+    }
+
+    #[test]
+    fn normalized_query_text_ignores_formatting_and_order() {
+        let normalized =
+            |query: &str| crate::execution::normalized_query_text(&parse_query(query).unwrap());
+
+        let text = normalized(
+            "subscription { musicians(first: 10, skip: 0) { name ...bands } }
+             fragment bands on Musician { bands { name id } }",
+        );
+        assert_eq!(
+            normalized(
+                "fragment bands on Musician { bands { id, name } }
+                 subscription {
+                   musicians(skip: 0, first: 10) { ...bands name }
+                 }"
+            ),
+            text
+        );
+        assert_eq!(
+            text,
+            "subscription {
+  musicians(first: 10, skip: 0) {
+    name
+    ...bands
+  }
+}
+
+fragment bands on Musician {
+  bands {
+    id
+    name
+  }
+}
+"
+        );
+    }
+
+    #[test]
+    fn collects_types_of_nested_fields() {
+        assert_eq!(
+            entity_types("subscription { musicians { name bands { name } } }"),
+            names(&["Musician", "Band"])
+        );
+    }
+
+    #[test]
+    fn collects_types_from_fragments() {
+        assert_eq!(
+            entity_types(
+                "subscription { musicians { ...bands } }
+                 fragment bands on Musician { bands { songs { title } } }"
+            ),
+            names(&["Musician", "Band", "Song"])
+        );
+    }
+
+    #[test]
+    fn entity_type_set_covers_fragments_and_nested_selections() {
+        let entity_types = entity_type_set(
+            "subscription {
+               musicians {
+                 ... on Musician { bands { ...songs } }
+                 bands { name ...songs }
+               }
+             }
+             fragment songs on Band { songs { title } }",
+        );
+
+        // Each type is only listed once, where the query first references it
+        assert_eq!(
+            entity_types.iter().collect::<Vec<_>>(),
+            vec!["Musician", "Band", "Song"]
+        );
+        assert!(entity_types.contains("Song"));
+        assert!(!entity_types.contains("Named"));
+
+        let change = |entity_type: &str| EntityChange {
+            subgraph_id: SubgraphDeploymentId::new("subscription").unwrap(),
+            entity_type: entity_type.to_owned(),
+            entity_id: "1".to_owned(),
+            operation: EntityChangeOperation::Set,
+        };
+        assert!(entity_types.is_changed_by(&StoreEvent::new(vec![change("Song")])));
+        assert!(!entity_types.is_changed_by(&StoreEvent::new(vec![change("Other")])));
+    }
+
+    #[test]
+    fn collects_implementations_of_interfaces() {
+        assert_eq!(
+            entity_types("subscription { named { name } }"),
+            names(&["Musician", "Band"])
+        );
+    }
+
+    fn ids(ids: &[&str]) -> q::Value {
+        q::Value::List(
+            ids.iter()
+                .map(|id| q::Value::String(id.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn diff_of_equal_values_is_empty() {
+        let value = object! { musician: object! { name: "John" } };
+        assert_eq!(diff_values(&value, &value), None);
+    }
+
+    #[test]
+    fn diff_only_contains_changed_paths() {
+        let previous = object! {
+            musician: object! { name: "John", mainBand: "b1", bands: ids(&["b1"]) },
+            song: object! { title: "Cheesy Tune" },
+        };
+        let current = object! {
+            musician: object! { name: "John", bands: ids(&["b1", "b2"]) },
+            song: object! { title: "Cheesy Tune" },
+        };
+
+        assert_eq!(
+            diff_values(&previous, &current),
+            Some(object! {
+                musician: object! { mainBand: q::Value::Null, bands: ids(&["b1", "b2"]) },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_subscription_query_reports_subscription_timeout() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        let mut stream = execute_subscription(
+            Subscription { query },
+            options(Some(Duration::from_millis(1))),
+        )
+        .unwrap();
+        let result = stream.next().await.unwrap();
+
+        match result.errors.as_ref().map(|errors| errors.as_slice()) {
+            Some([QueryError::ExecutionError(QueryExecutionError::SubscriptionTimeout { .. })]) => {
+                ()
+            }
+            _ => panic!("expected a subscription timeout, got {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_overload_when_no_query_permit_is_available() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        // Without any permits, the query can never run
+        let mut stream = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                query_permits: Some(Arc::new(QueryPermits::new(0))),
+                query_permit_timeout: Some(Duration::from_millis(10)),
+                ..options(None)
+            },
+        )
+        .unwrap();
+        let result = stream.next().await.unwrap();
+
+        assert!(result.data.is_none());
+        match result.errors.as_ref().map(|errors| errors.as_slice()) {
+            Some([QueryError::ExecutionError(QueryExecutionError::Overloaded { .. })]) => (),
+            _ => panic!("expected an overloaded error, got {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn query_permits_are_handed_out_in_the_order_events_wait() {
+        let permits = Arc::new(QueryPermits::new(1));
+        let granted = Arc::new(Mutex::new(vec![]));
+
+        // Starts waiting for a permit and gives it back right away
+        let wait = |name: &'static str, timeout: Option<Duration>| {
+            let permits = permits.clone();
+            let granted = granted.clone();
+            tokio::spawn(async move {
+                let priority = SubscriptionPriority::Normal;
+                if acquire_query_permit(&permits, priority, timeout, &SystemClock)
+                    .await
+                    .is_some()
+                {
+                    granted.lock().unwrap().push(name);
+                }
+            });
+        };
+        let let_waiters_queue_up = || tokio::time::delay_for(Duration::from_millis(5));
+
+        // While all permits are taken, a chatty subscription keeps producing
+        // events around one event of a quiet subscription. Another event
+        // gives up waiting in between
+        let held = permits.acquire(SubscriptionPriority::Normal).await;
+        for name in &["chatty-1", "chatty-2"] {
+            wait(*name, None);
+            let_waiters_queue_up().await;
+        }
+        wait("impatient", Some(Duration::from_millis(1)));
+        let_waiters_queue_up().await;
+        wait("quiet", None);
+        let_waiters_queue_up().await;
+        for name in &["chatty-3", "chatty-4"] {
+            wait(*name, None);
+            let_waiters_queue_up().await;
+        }
+        drop(held);
+        let_waiters_queue_up().await;
+
+        assert_eq!(
+            *granted.lock().unwrap(),
+            vec!["chatty-1", "chatty-2", "quiet", "chatty-3", "chatty-4"]
+        );
+    }
+
+    #[tokio::test]
+    async fn query_permits_go_to_higher_priorities_first() {
+        let permits = Arc::new(QueryPermits::new(1));
+        let granted = Arc::new(Mutex::new(vec![]));
+
+        let wait = |name: String, priority| {
+            let permits = permits.clone();
+            let granted = granted.clone();
+            tokio::spawn(async move {
+                let _permit = permits.acquire(priority).await;
+                granted.lock().unwrap().push(name);
+            });
+        };
+        let let_waiters_queue_up = || tokio::time::delay_for(Duration::from_millis(5));
+
+        // Bulk events that start waiting first are overtaken by interactive
+        // ones, but not indefinitely
+        let held = permits.acquire(SubscriptionPriority::Normal).await;
+        for i in 0..2 {
+            wait(format!("bulk-{}", i), SubscriptionPriority::Bulk);
+            let_waiters_queue_up().await;
+        }
+        wait("normal".to_owned(), SubscriptionPriority::Normal);
+        let_waiters_queue_up().await;
+        for i in 0..10 {
+            wait(
+                format!("interactive-{}", i),
+                SubscriptionPriority::Interactive,
+            );
+            let_waiters_queue_up().await;
+        }
+        drop(held);
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+
+        let granted = granted.lock().unwrap();
+        let mut expected = (0..8)
+            .map(|i| format!("interactive-{}", i))
+            .collect::<Vec<_>>();
+        // Both lower levels have been passed over 8 times; the lowest goes
+        // first, then the other one, and the interactive events after that
+        expected.extend(vec!["bulk-0".to_owned(), "normal".to_owned()]);
+        expected.extend(vec!["interactive-8".to_owned(), "interactive-9".to_owned()]);
+        expected.push("bulk-1".to_owned());
+        assert_eq!(*granted, expected);
+    }
+
+    #[tokio::test]
+    async fn verify_mode_reports_differences_between_execution_modes() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        let mut stream = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                resolver: SlowResolver {
+                    musicians: 1,
+                    failing_field: None,
+                    panicking_field: None,
+                    prefetched_musicians: Some(2),
+                },
+                execution_mode: ExecutionMode::Verify,
+                ..options(None)
+            },
+        )
+        .unwrap();
+        let result = stream.next().await.unwrap();
+
+        assert!(result.data.is_none());
+        match result.errors.as_ref().map(|errors| errors.as_slice()) {
+            Some(
+                [QueryError::ExecutionError(QueryExecutionError::IncorrectPrefetchResult { .. })],
+            ) => (),
+            _ => panic!("expected an incorrect prefetch result, got {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn block_range_limits_blocks_and_completes_the_stream() {
+        let results = |from, to| {
+            let query = GraphDataQuery::new(
+                schema(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            execute_subscription(
+                Subscription { query },
+                SubscriptionExecutionOptions {
+                    block_range: Some(SubscriptionBlockRange { from, to }),
+                    ..options(None)
+                },
+            )
+            .unwrap()
+            .collect::<Vec<_>>()
+        };
+
+        // The subgraph is past the end of the range, so the initial result is
+        // the last one
+        let within = results(1, 3).await;
+        assert_eq!(within.len(), 1);
+        assert!(within[0].data.is_some());
+
+        // The subgraph hasn't reached the range yet, and there are no events
+        // anymore
+        let before = results(LATEST_BLOCK + 1, 10).await;
+        assert_eq!(before.len(), 1);
+        assert!(before[0].completed);
+    }
+
+    #[tokio::test]
+    async fn graceful_end_of_events_completes_the_subscription() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        let results = execute_subscription(Subscription { query }, options(None))
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results[0].data.is_some());
+        assert!(!results[0].completed);
+        assert!(results[1].completed);
+        assert!(!results[1].is_keep_alive());
+        assert_eq!(
+            serde_json::to_string(&results[1]).unwrap(),
+            r#"{"extensions":{"completed":true}}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn initial_result_reports_the_trigger_event() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        let results = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                report_event_details: true,
+                ..options(None)
+            },
+        )
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+
+        assert_eq!(
+            results[0].event,
+            Some(QueryResultEvent {
+                tag: 0,
+                changes: 0,
+                entity_types: BTreeMap::new(),
+            })
+        );
+        assert_eq!(
+            serde_json::to_string(&results[0]).unwrap(),
+            r#"{"data":{"musicians":[{"name":"John"}]},"extensions":{"event":{"tag":0,"changes":0,"entityTypes":{}}}}"#
+        );
+
+        // Results the subscription generates itself don't report an event
+        assert!(results[1].completed);
+        assert_eq!(results[1].event, None);
+    }
+
+    #[tokio::test]
+    async fn subscriptions_with_a_shared_schema_get_the_same_results() {
+        let schema = schema();
+        let subscribe = || {
+            let query = GraphDataQuery::new(
+                schema.clone(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            execute_subscription(Subscription { query }, options(None)).unwrap()
+        };
+
+        let mut first = subscribe();
+        let mut second = subscribe();
+        let first = first.next().await.unwrap();
+        let second = second.next().await.unwrap();
+
+        assert!(first.errors.is_none());
+        assert!(first.data.is_some());
+        assert_eq!(first.data, second.data);
+    }
+
+    #[tokio::test]
+    async fn source_stream_errors_end_the_subscription() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        let ctx = subscription_context(Subscription { query }, &options(None), None).unwrap();
+        let event_ctx = SubscriptionEventContext {
+            logger: ctx.logger.clone(),
+            resolver: ctx.resolver.clone(),
+            query: ctx.query.cheap_clone(),
+            subscription_type: Arc::new(
+                sast::get_root_subscription_type(&ctx.query.schema.document)
+                    .unwrap()
+                    .clone(),
+            ),
+            timeout: None,
+            field_timeouts: Arc::new(HashMap::new()),
+            max_complexity: None,
+            max_depth: 100,
+            max_first: ctx.max_first,
+            mode: ctx.mode,
+            block_range: None,
+            max_result_size_bytes: None,
+            query_permits: Arc::new(QueryPermits::new(1)),
+            priority: SubscriptionPriority::Normal,
+            query_permit_timeout: None,
+            execute_inline: false,
+            metrics: None,
+            cancel_handle: None,
+            result_transform: None,
+            transform_initial_result: true,
+            report_event_details: false,
+            schema_check: None,
+            execution_budget: None,
+            execution_spent: Mutex::new(Duration::from_secs(0)),
+            clock: Arc::new(SystemClock),
+        };
+        let failing: Box<dyn Stream<Item = StoreEvent, Error = ()> + Send> =
+            Box::new(stream::iter_result(vec![
+                Err(()),
+                Ok(StoreEvent::new(vec![])),
+            ]));
+
+        // The event after the error doesn't cause a result, even though
+        // errors of queries don't end the subscription
+        let options = ResponseStreamOptions {
+            emit_initial_result: false,
+            prewarm_initial_result: false,
+            event_buffer: None,
+            max_in_flight_queries: 1,
+            delivery_mode: SubscriptionDeliveryMode::Full,
+            suppress_duplicate_results: false,
+            error_policy: SubscriptionErrorPolicy::ContinueOnError,
+            error_classifier: None,
+            compression: None,
+            keep_alive_interval: None,
+            timer_jitter: 0.0,
+        };
+        let results =
+            map_source_to_response_stream(event_ctx, StoreEventStream::new(failing), options)
+                .collect::<Vec<_>>()
+                .await;
+
+        assert_eq!(results.len(), 1);
+        assert!(results[0].errors.is_some());
+        assert!(!results[0].completed);
+    }
+
+    #[test]
+    fn recognizes_subscription_documents() {
+        let is_subscription = |query| is_subscription_document(&parse_query(query).unwrap());
+
+        assert!(is_subscription("subscription { musicians { name } }"));
+        assert!(is_subscription(
+            "subscription Named { musicians { ...Name } } fragment Name on Musician { name }"
+        ));
+        assert!(!is_subscription("{ musicians { name } }"));
+        assert!(!is_subscription("query { musicians { name } }"));
+        assert!(!is_subscription(
+            "subscription A { musicians { name } } subscription B { named { name } }"
+        ));
+    }
+
+    #[test]
+    fn rejects_fields_the_filter_does_not_permit() {
+        let subscribe = |filter| {
+            let query = GraphDataQuery::new(
+                schema(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            execute_subscription(
+                Subscription { query },
+                SubscriptionExecutionOptions {
+                    field_filter: Some(filter),
+                    ..options(None)
+                },
+            )
+        };
+        let fields = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+
+        assert!(subscribe(SubscriptionFieldFilter::Allow(fields(&["musicians"]))).is_ok());
+        assert!(subscribe(SubscriptionFieldFilter::Deny(fields(&["named"]))).is_ok());
+        for filter in vec![
+            SubscriptionFieldFilter::Allow(fields(&["named"])),
+            SubscriptionFieldFilter::Deny(fields(&["musicians"])),
+        ] {
+            match subscribe(filter) {
+                Err(SubscriptionError::FieldNotPermitted { field }) => {
+                    assert_eq!(field, "musicians")
+                }
+                Err(e) => panic!("expected a field that is not permitted, got {:?}", e),
+                Ok(_) => panic!("expected the subscription to be rejected"),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_queries_that_reference_too_many_entity_types() {
+        let subscribe = |query: &str, limit| {
+            let query = GraphDataQuery::new(schema(), parse_query(query).unwrap(), None);
+            execute_subscription(
+                Subscription { query },
+                SubscriptionExecutionOptions {
+                    max_referenced_entity_types: Some(limit),
+                    ..options(None)
+                },
+            )
+        };
+        let query = "subscription { musicians { name bands { name songs { title } } } }";
+
+        assert!(subscribe(query, 3).is_ok());
+        match subscribe(query, 1) {
+            Err(SubscriptionError::TooManyEntityTypes {
+                limit,
+                entity_types,
+            }) => {
+                assert_eq!(limit, 1);
+                assert_eq!(entity_types, vec!["Band", "Song"]);
+            }
+            Err(e) => panic!("expected too many entity types, got {:?}", e),
+            Ok(_) => panic!("expected the subscription to be rejected"),
+        }
+    }
+
+    #[test]
+    fn rejects_mutually_recursive_fragments() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query(
+                "subscription { musicians { ...a } }
+                 fragment a on Musician { name ...b }
+                 fragment b on Musician { bands { name } ...a }",
+            )
+            .unwrap(),
+            None,
+        );
+
+        match execute_subscription(Subscription { query }, options(None)) {
+            Err(SubscriptionError::GraphQLError(ref errors)) => match errors.as_slice() {
+                [QueryExecutionError::FragmentCycle(path)] => assert_eq!(path, &["a", "b", "a"]),
+                errors => panic!("expected a fragment cycle, got {:?}", errors),
+            },
+            Err(e) => panic!("expected a fragment cycle, got {:?}", e),
+            Ok(_) => panic!("expected the subscription to be rejected"),
+        }
+    }
+
+    #[test]
+    fn rejects_inverted_block_ranges() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        match execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                block_range: Some(SubscriptionBlockRange { from: 3, to: 1 }),
+                ..options(None)
+            },
+        ) {
+            Err(SubscriptionError::InvalidBlockRange { from: 3, to: 1 }) => (),
+            Err(e) => panic!("expected an invalid block range, got {:?}", e),
+            Ok(_) => panic!("expected the subscription to be rejected"),
+        }
+    }
+
+    #[tokio::test]
+    async fn result_transform_applies_to_query_results_only() {
+        let results = |transform_initial_result| {
+            let query = GraphDataQuery::new(
+                schema(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            let calls = Arc::new(AtomicUsize::new(0));
+            let counter = calls.clone();
+            let transform: SubscriptionResultTransform =
+                Arc::new(move |mut result: QueryResult| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    result.data = Some(object! { transformed: true });
+                    result
+                });
+            let results = execute_subscription(
+                Subscription { query },
+                SubscriptionExecutionOptions {
+                    result_transform: Some(transform),
+                    transform_initial_result,
+                    ..options(None)
+                },
+            )
+            .unwrap()
+            .collect::<Vec<_>>();
+            async move { (results.await, calls.load(Ordering::SeqCst)) }
+        };
+
+        // The completion result is not transformed
+        let (transformed, calls) = results(true).await;
+        assert_eq!(calls, 1);
+        assert_eq!(transformed.len(), 2);
+        assert_eq!(transformed[0].data, Some(object! { transformed: true }));
+        assert!(transformed[1].completed);
+
+        let (untransformed, calls) = results(false).await;
+        assert_eq!(calls, 0);
+        assert_ne!(untransformed[0].data, Some(object! { transformed: true }));
+    }
+
+    #[tokio::test]
+    async fn mock_resolver_drives_scripted_events() {
+        let resolver = MockSubscriptionResolver::new();
+        resolver.stub_field("musicians", q::Value::List(vec![object! { name: "John" }]));
+
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        let options = SubscriptionExecutionOptions::builder(
+            Logger::root(slog::Discard, o!()),
+            resolver.clone(),
+        )
+        .report_event_details(true)
+        .build();
+        let mut results = execute_subscription(Subscription { query }, options).unwrap();
+
+        let initial = results.next().await.unwrap();
+        assert_eq!(
+            initial.data,
+            Some(object! { musicians: vec![object! { name: "John" }] })
+        );
+        assert_eq!(resolver.open_streams(), 1);
+
+        resolver.stub_field(
+            "musicians",
+            q::Value::List(vec![object! { name: "John" }, object! { name: "Paul" }]),
+        );
+        resolver.push_event(StoreEvent::new(vec![EntityChange {
+            subgraph_id: SubgraphDeploymentId::new("subscription").unwrap(),
+            entity_type: "Musician".to_owned(),
+            entity_id: "m2".to_owned(),
+            operation: EntityChangeOperation::Set,
+        }]));
+
+        let changed = results.next().await.unwrap();
+        assert_eq!(
+            changed.data,
+            Some(object! {
+                musicians: vec![object! { name: "John" }, object! { name: "Paul" }]
+            })
+        );
+        assert_eq!(changed.event.unwrap().changes, 1);
+
+        resolver.end_events();
+        assert!(results.next().await.unwrap().completed);
+        assert!(results.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn tracing_resolver_attaches_a_trace_for_each_event() {
+        let traced_results = |enabled| async move {
+            let resolver = MockSubscriptionResolver::new();
+            let query = GraphDataQuery::new(
+                schema(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            let options = SubscriptionExecutionOptions::builder(
+                Logger::root(slog::Discard, o!()),
+                TracingResolver::new(resolver.clone(), enabled),
+            )
+            .build();
+            let results = execute_subscription(Subscription { query }, options).unwrap();
+
+            resolver.push_event(StoreEvent::new(vec![]));
+            resolver.end_events();
+            results.collect::<Vec<_>>().await
+        };
+
+        let results = traced_results(true).await;
+        assert_eq!(results.len(), 3);
+        for result in &results[..2] {
+            let trace = result.trace.as_ref().unwrap();
+            assert_eq!(trace.len(), 1);
+            assert_eq!(trace[0].operation, "resolve_objects");
+            assert_eq!(trace[0].field, Some("musicians".to_owned()));
+        }
+        assert!(results[2].completed);
+        assert!(results[2].trace.is_none());
+
+        let results = traced_results(false).await;
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|result| result.trace.is_none()));
+    }
+
+    /// Pushes three events at once into a subscription with an event buffer
+    /// of size one and returns the tags of the events that caused results.
+    async fn buffered_event_tags(policy: SubscriptionBufferPolicy) -> (Vec<usize>, Vec<usize>) {
+        let resolver = MockSubscriptionResolver::new();
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        let options = SubscriptionExecutionOptions::builder(
+            Logger::root(slog::Discard, o!()),
+            resolver.clone(),
+        )
+        .emit_initial_result(false)
+        .report_event_details(true)
+        .event_buffer(1, policy)
+        .build();
+        let results = execute_subscription(Subscription { query }, options).unwrap();
+
+        let events = (0..3).map(|_| StoreEvent::new(vec![])).collect::<Vec<_>>();
+        let tags = events.iter().map(|event| event.tag).collect();
+        for event in events {
+            resolver.push_event(event);
+        }
+        resolver.end_events();
+
+        let results = results.collect::<Vec<_>>().await;
+        let result_tags = results
+            .iter()
+            .filter_map(|result| result.event.as_ref().map(|event| event.tag))
+            .collect();
+        (tags, result_tags)
+    }
+
+    #[tokio::test]
+    async fn event_buffer_applies_its_policy() {
+        let (tags, result_tags) = buffered_event_tags(SubscriptionBufferPolicy::Block).await;
+        assert_eq!(result_tags, tags);
+
+        let (tags, result_tags) = buffered_event_tags(SubscriptionBufferPolicy::DropOldest).await;
+        assert_eq!(result_tags, vec![tags[2]]);
+
+        let (tags, result_tags) = buffered_event_tags(SubscriptionBufferPolicy::DropNewest).await;
+        assert_eq!(result_tags, vec![tags[0]]);
+    }
+
+    #[tokio::test]
+    async fn error_policy_decides_whether_errors_end_the_subscription() {
+        let results = |error_policy| async move {
+            let resolver = MockSubscriptionResolver::new();
+            let query = GraphDataQuery::new(
+                schema(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            // Every result is too large and therefore an error
+            let options = SubscriptionExecutionOptions::builder(
+                Logger::root(slog::Discard, o!()),
+                resolver.clone(),
+            )
+            .max_result_size_bytes(1)
+            .error_policy(error_policy)
+            .build();
+            let results = execute_subscription(Subscription { query }, options).unwrap();
+
+            resolver.push_event(StoreEvent::new(vec![]));
+            resolver.end_events();
+            results.collect::<Vec<_>>().await
+        };
+
+        let continued = results(SubscriptionErrorPolicy::ContinueOnError).await;
+        assert_eq!(continued.len(), 3);
+        assert!(continued[..2].iter().all(|result| result.errors.is_some()));
+        assert!(continued[2].completed);
+
+        let terminated = results(SubscriptionErrorPolicy::TerminateOnError).await;
+        assert_eq!(terminated.len(), 1);
+        assert!(terminated[0].errors.is_some());
+        assert!(!terminated[0].completed);
+    }
+
+    #[tokio::test]
+    async fn suppresses_results_with_the_same_data_as_the_last_one() {
+        let resolver = MockSubscriptionResolver::new();
+        let john = || q::Value::List(vec![object! { name: "John" }]);
+        let paul = || q::Value::List(vec![object! { name: "Paul" }]);
+        resolver.stub_field("musicians", john());
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        let options = SubscriptionExecutionOptions::builder(
+            Logger::root(slog::Discard, o!()),
+            resolver.clone(),
+        )
+        .suppress_duplicate_results(true)
+        .build();
+        let mut results = execute_subscription(Subscription { query }, options).unwrap();
+
+        // The initial result is sent, and the one for the first event is
+        // dropped since nothing the query selects changed
+        assert!(results.next().await.unwrap().data.is_some());
+        resolver.push_event(StoreEvent::new(vec![]));
+
+        resolver.stub_field("musicians", paul());
+        resolver.push_event(StoreEvent::new(vec![]));
+        assert_eq!(
+            results.next().await.unwrap().data,
+            Some(object! { musicians: paul() })
+        );
+
+        // Going back to earlier data is a change, too
+        resolver.push_event(StoreEvent::new(vec![]));
+        resolver.stub_field("musicians", john());
+        resolver.push_event(StoreEvent::new(vec![]));
+        resolver.end_events();
+        assert_eq!(
+            results.next().await.unwrap().data,
+            Some(object! { musicians: john() })
+        );
+        assert!(results.next().await.unwrap().completed);
+    }
+
+    #[tokio::test]
+    async fn non_blocking_resolvers_can_be_executed_inline() {
+        let list_threads = |execute_inline, non_blocking| async move {
+            let resolver = MockSubscriptionResolver::new();
+            if non_blocking {
+                resolver.declare_non_blocking();
+            }
+            let query = GraphDataQuery::new(
+                schema(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            let options = SubscriptionExecutionOptions::builder(
+                Logger::root(slog::Discard, o!()),
+                resolver.clone(),
+            )
+            .execute_inline(execute_inline)
+            .build();
+            let mut results = execute_subscription(Subscription { query }, options).unwrap();
+
+            assert!(results.next().await.unwrap().data.is_some());
+            resolver.list_threads()
+        };
+
+        // Only queries for resolvers that don't block run on the test thread
+        let test_thread = std::thread::current().id();
+        assert_eq!(list_threads(true, true).await, vec![test_thread]);
+        assert_ne!(list_threads(true, false).await, vec![test_thread]);
+        assert_ne!(list_threads(false, true).await, vec![test_thread]);
+    }
+
+    #[tokio::test]
+    async fn error_classifier_decides_how_errors_are_treated() {
+        let results = |error_policy, severity| async move {
+            let resolver = MockSubscriptionResolver::new();
+            let query = GraphDataQuery::new(
+                schema(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            // Every result is too large and therefore an error
+            let classifier: SubscriptionErrorClassifier =
+                Arc::new(move |error: &QueryError| match error {
+                    QueryError::ExecutionError(QueryExecutionError::ResultTooLarge(..)) => severity,
+                    _ => SubscriptionErrorSeverity::Error,
+                });
+            let options = SubscriptionExecutionOptions::builder(
+                Logger::root(slog::Discard, o!()),
+                resolver.clone(),
+            )
+            .max_result_size_bytes(1)
+            .error_policy(error_policy)
+            .error_classifier(classifier)
+            .build();
+            let results = execute_subscription(Subscription { query }, options).unwrap();
+
+            resolver.push_event(StoreEvent::new(vec![]));
+            resolver.end_events();
+            results.collect::<Vec<_>>().await
+        };
+
+        // Warnings keep even subscriptions going that terminate on errors
+        let warned = results(
+            SubscriptionErrorPolicy::TerminateOnError,
+            SubscriptionErrorSeverity::Warning,
+        )
+        .await;
+        assert_eq!(warned.len(), 3);
+        for result in &warned[..2] {
+            assert!(result.errors.is_none());
+            assert_eq!(result.warnings.as_ref().unwrap().len(), 1);
+            assert!(!result.is_keep_alive());
+            let serialized = serde_json::to_value(result).unwrap();
+            assert!(serialized["extensions"]["warnings"][0]["message"].is_string());
+        }
+        assert!(warned[2].completed);
+
+        // Fatal errors end even subscriptions that continue on errors
+        let fatal = results(
+            SubscriptionErrorPolicy::ContinueOnError,
+            SubscriptionErrorSeverity::Fatal,
+        )
+        .await;
+        assert_eq!(fatal.len(), 1);
+        assert!(fatal[0].errors.is_some());
+        assert!(!fatal[0].completed);
+    }
+
+    #[tokio::test]
+    async fn compresses_results_above_the_threshold() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let resolver = MockSubscriptionResolver::new();
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        let options = SubscriptionExecutionOptions::builder(
+            Logger::root(slog::Discard, o!()),
+            resolver.clone(),
+        )
+        .compression(QueryResultEncoding::Gzip, 100, true)
+        .build();
+        let mut results = execute_subscription(Subscription { query }, options).unwrap();
+
+        let initial = results.next().await.unwrap();
+        assert_eq!(initial.compression, None);
+
+        let musicians = (0..10)
+            .map(|i| object! { name: format!("Musician {}", i) })
+            .collect();
+        resolver.stub_field("musicians", q::Value::List(musicians));
+        resolver.push_event(StoreEvent::new(vec![]));
+        let large = results.next().await.unwrap();
+        let compression = large.compression.clone().unwrap();
+        let serialized = serde_json::to_vec(&large).unwrap();
+        assert_eq!(compression.encoding, QueryResultEncoding::Gzip);
+        assert_eq!(compression.uncompressed_size, serialized.len());
+
+        let mut decompressed = vec![];
+        GzDecoder::new(compression.bytes.unwrap().as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, serialized);
+
+        resolver.end_events();
+        let completed = results.next().await.unwrap();
+        assert!(completed.completed);
+        assert_eq!(completed.compression, None);
+    }
+
+    #[tokio::test]
+    async fn min_update_interval_merges_events_and_sends_the_latest() {
+        let interval = Duration::from_millis(100);
+        let resolver = MockSubscriptionResolver::new();
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        let options = SubscriptionExecutionOptions::builder(
+            Logger::root(slog::Discard, o!()),
+            resolver.clone(),
+        )
+        .report_event_details(true)
+        .min_update_interval(interval)
+        .build();
+        let start = Instant::now();
+        let mut results = execute_subscription(Subscription { query }, options).unwrap();
+
+        // The burst is merged and only sent once the interval after the
+        // initial result has passed
+        let burst = (0..3).map(|_| StoreEvent::new(vec![])).collect::<Vec<_>>();
+        let last_tag = burst[2].tag;
+        for event in burst {
+            resolver.push_event(event);
+        }
+        assert_eq!(results.next().await.unwrap().event.unwrap().tag, 0);
+        let merged = results.next().await.unwrap();
+        assert_eq!(merged.event.unwrap().tag, last_tag);
+        assert!(start.elapsed() >= interval);
+
+        // The last event is still sent after the events end
+        let event = StoreEvent::new(vec![]);
+        let tag = event.tag;
+        resolver.push_event(event);
+        resolver.end_events();
+        assert_eq!(results.next().await.unwrap().event.unwrap().tag, tag);
+        assert!(start.elapsed() >= interval * 2);
+        assert!(results.next().await.unwrap().completed);
+        assert!(results.next().await.is_none());
+    }
+
+    /// Delegates to a `MockSubscriptionResolver`, but resolving the first
+    /// list takes much longer than resolving later ones. Records how many
+    /// lists are resolved at the same time.
+    #[derive(Clone, Default)]
+    struct FirstSlowResolver {
+        inner: MockSubscriptionResolver,
+        calls: Arc<AtomicUsize>,
+        /// The number of lists that are being resolved, and the maximum of
+        /// that so far.
+        running: Arc<Mutex<(usize, usize)>>,
+    }
+
+    impl Resolver for FirstSlowResolver {
+        fn prefetch(
+            &self,
+            _: &ExecutionContext<Self>,
+            _: &q::SelectionSet,
+        ) -> Result<Option<q::Value>, Vec<QueryExecutionError>> {
+            Ok(None)
+        }
+
+        fn locate_block(
+            &self,
+            block_constraint: &BlockConstraint,
+        ) -> Result<BlockNumber, QueryExecutionError> {
+            self.inner.locate_block(block_constraint)
+        }
+
+        fn resolve_objects(
+            &self,
+            parent: &Option<q::Value>,
+            field: &q::Field,
+            field_definition: &s::Field,
+            object_type: ObjectOrInterface<'_>,
+            arguments: &HashMap<&q::Name, q::Value>,
+            types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
+            block: BlockNumber,
+            max_first: u32,
+        ) -> Result<q::Value, QueryExecutionError> {
+            let first = self.calls.fetch_add(1, Ordering::SeqCst) == 0;
+            {
+                let mut running = self.running.lock().unwrap();
+                running.0 += 1;
+                running.1 = running.1.max(running.0);
+            }
+            std::thread::sleep(Duration::from_millis(if first { 300 } else { 10 }));
+            self.running.lock().unwrap().0 -= 1;
+            self.inner.resolve_objects(
+                parent,
+                field,
+                field_definition,
+                object_type,
+                arguments,
+                types_for_interface,
+                block,
+                max_first,
+            )
+        }
+
+        fn resolve_object(
+            &self,
+            parent: &Option<q::Value>,
+            field: &q::Field,
+            field_definition: &s::Field,
+            object_type: ObjectOrInterface<'_>,
+            arguments: &HashMap<&q::Name, q::Value>,
+            types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
+            block: BlockNumber,
+        ) -> Result<q::Value, QueryExecutionError> {
+            self.inner.resolve_object(
+                parent,
+                field,
+                field_definition,
+                object_type,
+                arguments,
+                types_for_interface,
+                block,
+            )
+        }
+
+        fn resolve_field_stream<'a, 'b>(
+            &self,
+            schema: &'a s::Document,
+            object_type: &'a s::ObjectType,
+            field: &'b q::Field,
+            arguments: &HashMap<&q::Name, q::Value>,
+        ) -> Result<StoreEventStreamBox, QueryExecutionError> {
+            self.inner
+                .resolve_field_stream(schema, object_type, field, arguments)
+        }
+
+        fn latest_block(
+            &self,
+            subgraph: &SubgraphDeploymentId,
+        ) -> Result<Option<BlockNumber>, QueryExecutionError> {
+            self.inner.latest_block(subgraph)
+        }
+    }
+
+    /// Pushes three events into a subscription that may execute
+    /// `max_in_flight_queries` queries at once and returns the tags of the
+    /// events, the tags of the events that caused results, and the maximum
+    /// number of queries that were executed at the same time.
+    async fn in_flight_event_tags(max_in_flight_queries: usize) -> (Vec<usize>, Vec<usize>, usize) {
+        let resolver = FirstSlowResolver::default();
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        let options = SubscriptionExecutionOptions::builder(
+            Logger::root(slog::Discard, o!()),
+            resolver.clone(),
+        )
+        .emit_initial_result(false)
+        .report_event_details(true)
+        .query_permits(Arc::new(QueryPermits::new(3)))
+        .max_in_flight_queries(max_in_flight_queries)
+        .build();
+        let results = execute_subscription(Subscription { query }, options).unwrap();
+
+        let events = (0..3).map(|_| StoreEvent::new(vec![])).collect::<Vec<_>>();
+        let tags = events.iter().map(|event| event.tag).collect();
+        for event in events {
+            resolver.inner.push_event(event);
+        }
+        resolver.inner.end_events();
+
+        let results = results.collect::<Vec<_>>().await;
+        let result_tags = results
+            .iter()
+            .filter_map(|result| result.event.as_ref().map(|event| event.tag))
+            .collect();
+        let max_running = resolver.running.lock().unwrap().1;
+        (tags, result_tags, max_running)
+    }
+
+    #[tokio::test]
+    async fn in_flight_queries_keep_the_order_of_events() {
+        let (tags, result_tags, max_running) = in_flight_event_tags(1).await;
+        assert_eq!(result_tags, tags);
+        assert_eq!(max_running, 1);
+
+        // The queries for later events finish before the one for the first
+        // event, but their results are sent after it
+        let (tags, result_tags, max_running) = in_flight_event_tags(3).await;
+        assert_eq!(result_tags, tags);
+        assert!(max_running > 1);
+    }
+
+    #[tokio::test]
+    async fn exhausted_execution_budget_ends_the_subscription() {
+        let budget = Duration::from_millis(100);
+        let resolver = FirstSlowResolver::default();
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        let options = SubscriptionExecutionOptions::builder(
+            Logger::root(slog::Discard, o!()),
+            resolver.clone(),
+        )
+        .lifetime_execution_budget(budget)
+        .build();
+        let mut results = execute_subscription(Subscription { query }, options).unwrap();
+
+        // The initial query alone takes longer than the budget allows
+        assert!(results.next().await.unwrap().errors.is_none());
+
+        resolver.inner.push_event(StoreEvent::new(vec![]));
+        let last = results.next().await.unwrap();
+        match last.errors.as_ref().map(|errors| errors.as_slice()) {
+            Some(
+                [QueryError::ExecutionError(QueryExecutionError::ExecutionBudgetExhausted {
+                    spent,
+                    budget: exhausted,
+                })],
+            ) => {
+                assert!(*spent > budget);
+                assert_eq!(*exhausted, budget);
+            }
+            errors => panic!("unexpected errors: {:?}", errors),
+        }
+        assert!(results.next().await.is_none());
+
+        // The query didn't run again for the event
+        assert_eq!(resolver.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn prewarmed_initial_result_is_computed_during_setup() {
+        let initial_result = |prewarm| async move {
+            let resolver = MockSubscriptionResolver::new();
+            resolver.stub_field("musicians", q::Value::List(vec![object! { name: "John" }]));
+
+            let query = GraphDataQuery::new(
+                schema(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            let options = SubscriptionExecutionOptions::builder(
+                Logger::root(slog::Discard, o!()),
+                resolver.clone(),
+            )
+            .prewarm_initial_result(prewarm)
+            .build();
+            let mut results = execute_subscription(Subscription { query }, options).unwrap();
+
+            // Give the prewarmed query time to finish before the data changes
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+            resolver.stub_field("musicians", q::Value::List(vec![]));
+
+            results.next().await.unwrap().data
+        };
+
+        assert_eq!(
+            initial_result(true).await,
+            Some(object! { musicians: vec![object! { name: "John" }] })
+        );
+        assert_eq!(
+            initial_result(false).await,
+            Some(object! { musicians: q::Value::List(vec![]) })
+        );
+    }
+
+    #[tokio::test]
+    async fn schema_changes_are_handled_according_to_the_policy() {
+        let musician_changed = || {
+            StoreEvent::new(vec![EntityChange {
+                subgraph_id: SubgraphDeploymentId::new("subscription").unwrap(),
+                entity_type: "Musician".to_owned(),
+                entity_id: "m1".to_owned(),
+                operation: EntityChangeOperation::Set,
+            }])
+        };
+        let results_after_schema_change = |policy| async move {
+            let resolver = MockSubscriptionResolver::new();
+            let current_schema = Arc::new(Mutex::new(schema()));
+
+            let query = GraphDataQuery::new(
+                current_schema.lock().unwrap().clone(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            let source = current_schema.clone();
+            let options = SubscriptionExecutionOptions::builder(
+                Logger::root(slog::Discard, o!()),
+                resolver.clone(),
+            )
+            .schema_source(Arc::new(move || Some(source.lock().unwrap().clone())))
+            .schema_change_policy(policy)
+            .build();
+            let mut results = execute_subscription(Subscription { query }, options).unwrap();
+            assert!(results.next().await.unwrap().data.is_some());
+
+            // Deploy a schema without the `musicians` field
+            *current_schema.lock().unwrap() = Arc::new(
+                Schema::parse(
+                    &SCHEMA.replace("musicians", "artists"),
+                    SubgraphDeploymentId::new("redeployed").unwrap(),
+                )
+                .unwrap(),
+            );
+            resolver.push_event(musician_changed());
+            let result = results.next().await.unwrap();
+
+            // Find out whether the subscription is still running
+            resolver.push_event(musician_changed());
+            (result, results.next().await.is_some())
+        };
+
+        let (result, running) =
+            results_after_schema_change(SubscriptionSchemaChangePolicy::Pin).await;
+        assert!(result.data.is_some());
+        assert!(!result.schema_changed);
+        assert!(running);
+
+        let (result, running) =
+            results_after_schema_change(SubscriptionSchemaChangePolicy::Revalidate).await;
+        assert!(result.data.is_none());
+        assert!(result.schema_changed);
+        match result.errors.as_ref().map(|errors| errors.first()) {
+            Some(Some(QueryError::ExecutionError(
+                QueryExecutionError::SubscriptionSchemaChanged(deployment),
+            ))) => assert_eq!(deployment, "redeployed"),
+            errors => panic!("expected a schema change error, got {:?}", errors),
+        }
+        assert!(!running);
+    }
+
+    #[tokio::test]
+    async fn schema_is_looked_up_once_per_block_unless_pinned() {
+        let lookups = |policy| async move {
+            let resolver = MockSubscriptionResolver::new();
+            let lookups = Arc::new(AtomicUsize::new(0));
+
+            let query = GraphDataQuery::new(
+                schema(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            let counter = lookups.clone();
+            let options = SubscriptionExecutionOptions::builder(
+                Logger::root(slog::Discard, o!()),
+                resolver.clone(),
+            )
+            .schema_source(Arc::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Some(schema())
+            }))
+            .schema_change_policy(policy)
+            .build();
+            let mut results = execute_subscription(Subscription { query }, options).unwrap();
+            assert!(results.next().await.unwrap().data.is_some());
+
+            for block in vec![1, 1, 2] {
+                let mut event = StoreEvent::new(vec![]);
+                event.block = Some(block);
+                resolver.push_event(event);
+                assert!(results.next().await.unwrap().data.is_some());
+            }
+            lookups.load(Ordering::SeqCst)
+        };
+
+        // The initial result has no block, the events look it up for blocks 1 and 2
+        assert_eq!(lookups(SubscriptionSchemaChangePolicy::Revalidate).await, 3);
+        assert_eq!(lookups(SubscriptionSchemaChangePolicy::Pin).await, 0);
+    }
+
+    /// Sets up `subscription` on the blocking pool, like `GraphQlRunner`
+    /// does, and returns how that went.
+    async fn set_up_on_blocking_pool(
+        subscription: Subscription,
+        options: SubscriptionExecutionOptions<MockSubscriptionResolver>,
+    ) -> Result<(), SubscriptionError> {
+        graph::spawn_blocking_allow_panic(async move {
+            execute_subscription(subscription, options).map(|_| ())
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn slow_stream_setup_times_out() {
+        let subscribe = |timeout: Option<Duration>| {
+            let resolver = MockSubscriptionResolver::new();
+            resolver.delay_stream_setup(Duration::from_millis(200));
+            let query = GraphDataQuery::new(
+                schema(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            let builder =
+                SubscriptionExecutionOptions::builder(Logger::root(slog::Discard, o!()), resolver);
+            let options = match timeout {
+                Some(timeout) => builder.stream_setup_timeout(timeout),
+                None => builder.no_stream_setup_timeout(),
+            }
+            .build();
+            set_up_on_blocking_pool(Subscription { query }, options)
+        };
+
+        assert!(subscribe(Some(Duration::from_secs(5))).await.is_ok());
+        assert!(subscribe(None).await.is_ok());
+        match subscribe(Some(Duration::from_millis(10))).await {
+            Err(SubscriptionError::StreamSetupTimeout { field, timeout }) => {
+                assert_eq!(field, "musicians");
+                assert_eq!(timeout, Duration::from_millis(10));
+            }
+            Err(e) => panic!("expected a stream setup timeout, got {:?}", e),
+            Ok(_) => panic!("expected the subscription to be rejected"),
+        }
+    }
+
+    #[tokio::test]
+    async fn timed_out_stream_setup_does_not_block_the_executor() {
+        let resolver = MockSubscriptionResolver::new();
+        resolver.delay_stream_setup(Duration::from_millis(500));
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        let options =
+            SubscriptionExecutionOptions::builder(Logger::root(slog::Discard, o!()), resolver)
+                .stream_setup_timeout(Duration::from_millis(50))
+                .build();
+
+        // Other tasks keep running while the setup waits for the stream
+        let ticks = Arc::new(AtomicUsize::new(0));
+        {
+            let ticks = ticks.clone();
+            graph::spawn(async move {
+                loop {
+                    tokio::time::delay_for(Duration::from_millis(5)).await;
+                    ticks.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+        }
+
+        let started = Instant::now();
+        let result = set_up_on_blocking_pool(Subscription { query }, options).await;
+        assert!(
+            matches!(result, Err(SubscriptionError::StreamSetupTimeout { .. })),
+            "expected a stream setup timeout, got {:?}",
+            result
+        );
+        // The setup gave up without waiting for the resolver
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(ticks.load(Ordering::SeqCst) > 0);
+    }
+
+    #[tokio::test]
+    async fn slow_setup_times_out_as_a_whole() {
+        let subscribe = |setup_timeout| {
+            let resolver = MockSubscriptionResolver::new();
+            resolver.delay_stream_setup(Duration::from_millis(60));
+            let query = GraphDataQuery::new(
+                schema(),
+                parse_query("subscription { musicians { name } named { name } }").unwrap(),
+                None,
+            );
+            let options =
+                SubscriptionExecutionOptions::builder(Logger::root(slog::Discard, o!()), resolver)
+                    .allow_multiple_subscription_fields(true)
+                    .setup_timeout(setup_timeout)
+                    .build();
+            set_up_on_blocking_pool(Subscription { query }, options)
+        };
+
+        // Each stream opens well within the stream setup timeout, but both
+        // together take longer than the setup timeout
+        assert!(subscribe(Duration::from_secs(5)).await.is_ok());
+        match subscribe(Duration::from_millis(100)).await {
+            Err(SubscriptionError::SetupTimeout { timeout }) => {
+                assert_eq!(timeout, Duration::from_millis(100));
+            }
+            Err(e) => panic!("expected a setup timeout, got {:?}", e),
+            Ok(_) => panic!("expected the subscription to be rejected"),
+        }
+    }
+
+    /// Collects the keys and values of every log line.
+    #[derive(Clone, Default)]
+    struct CapturingDrain(Arc<Mutex<Vec<(String, HashMap<String, String>)>>>);
+
+    impl slog::Drain for CapturingDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(
+            &self,
+            record: &slog::Record,
+            values: &slog::OwnedKVList,
+        ) -> Result<(), slog::Never> {
+            struct Collect(HashMap<String, String>);
+
+            impl slog::Serializer for Collect {
+                fn emit_arguments(
+                    &mut self,
+                    key: slog::Key,
+                    val: &std::fmt::Arguments,
+                ) -> slog::Result {
+                    self.0.insert(key.to_string(), val.to_string());
+                    Ok(())
+                }
+            }
+
+            let mut collect = Collect(HashMap::new());
+            slog::KV::serialize(values, record, &mut collect).unwrap();
+            slog::KV::serialize(&record.kv(), record, &mut collect).unwrap();
+            self.0
+                .lock()
+                .unwrap()
+                .push((record.msg().to_string(), collect.0));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn log_context_is_added_to_every_log_line() {
+        let drain = CapturingDrain::default();
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        let mut results = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                logger: Logger::root(drain.clone(), o!()),
+                log_context: Some(SubscriptionLogContext::new().add("tenant", "acme")),
+                ..options(None)
+            },
+        )
+        .unwrap();
+        results.next().await.unwrap();
+
+        let lines = drain.0.lock().unwrap();
+        assert!(lines
+            .iter()
+            .any(|(msg, _)| msg == "Execute subscription event"));
+        for (msg, values) in lines.iter() {
+            assert_eq!(
+                values.get("tenant").map(String::as_str),
+                Some("acme"),
+                "log line `{}` is missing the context",
+                msg
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn applies_variable_defaults() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription ($first: Int = 1) { musicians(first: $first) { name } }")
+                .unwrap(),
+            None,
+        );
+
+        let results = execute_subscription(Subscription { query }, options(None))
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert!(results[0].data.is_some());
+        assert!(results[0].errors.is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_variables_before_creating_the_stream() {
+        let variables = QueryVariables::new(
+            vec![
+                ("first".to_owned(), q::Value::String("ten".to_owned())),
+                ("skip".to_owned(), q::Value::Boolean(true)),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query(
+                "subscription ($first: Int, $skip: Int) { musicians(first: $first) { name } }",
+            )
+            .unwrap(),
+            Some(variables),
+        );
+
+        match execute_subscription(Subscription { query }, options(None)) {
+            Err(SubscriptionError::GraphQLError(errors)) => {
+                let mut names = errors
+                    .iter()
+                    .map(|e| match e {
+                        QueryExecutionError::InvalidVariableError(_, name, ty, _) => {
+                            assert_eq!(ty, "Int");
+                            name.clone()
+                        }
+                        e => panic!("expected an invalid variable, got {:?}", e),
+                    })
+                    .collect::<Vec<_>>();
+                names.sort();
+                assert_eq!(names, vec!["first", "skip"]);
+            }
+            Err(e) => panic!("expected invalid variables, got {:?}", e),
+            Ok(_) => panic!("expected the subscription to be rejected"),
+        }
+    }
+
+    fn subscribe_to_operation(
+        document: &str,
+        operation_name: Option<&str>,
+    ) -> Result<SubscriptionResult, SubscriptionError> {
+        let query = GraphDataQuery::new(schema(), parse_query(document).unwrap(), None)
+            .with_operation_name(operation_name.map(str::to_owned));
+        execute_subscription(Subscription { query }, options(None))
+    }
+
+    #[test]
+    fn selects_the_subscription_operation_by_name() {
+        const DOCUMENT: &str = "
+            subscription Musicians { musicians { name } }
+            subscription Named { named { name } }
+            query AllMusicians { musicians { name } }
+        ";
+
+        assert!(subscribe_to_operation("subscription { musicians { name } }", None).is_ok());
+        assert!(subscribe_to_operation(DOCUMENT, Some("Named")).is_ok());
+
+        match subscribe_to_operation(DOCUMENT, None) {
+            Err(SubscriptionError::GraphQLError(errors)) => match errors.as_slice() {
+                [QueryExecutionError::OperationNameRequired] => (),
+                errors => panic!("expected a missing operation name, got {:?}", errors),
+            },
+            _ => panic!("expected a missing operation name"),
+        }
+        match subscribe_to_operation(DOCUMENT, Some("Bands")) {
+            Err(SubscriptionError::GraphQLError(errors)) => match errors.as_slice() {
+                [QueryExecutionError::OperationNotFound(name)] => assert_eq!(name, "Bands"),
+                errors => panic!("expected an unknown operation, got {:?}", errors),
+            },
+            _ => panic!("expected an unknown operation"),
+        }
+        match subscribe_to_operation(DOCUMENT, Some("AllMusicians")) {
+            Err(SubscriptionError::GraphQLError(errors)) => match errors.as_slice() {
+                [QueryExecutionError::NotSupported(message)] => {
+                    assert!(message.contains("`AllMusicians` is not a subscription"))
+                }
+                errors => panic!("expected a query to be rejected, got {:?}", errors),
+            },
+            _ => panic!("expected a query to be rejected"),
+        }
+    }
+
+    #[test]
+    fn reports_the_deployment_whose_schema_has_no_subscription_type() {
+        let schema = Arc::new(
+            Schema::parse(
+                "
+                type Query { musicians: [Musician!]! }
+                type Musician @entity { id: ID!, name: String! }
+                ",
+                SubgraphDeploymentId::new("noSubscriptions").unwrap(),
+            )
+            .expect("invalid test schema"),
+        );
+        let version = schema.version();
+        let query = GraphDataQuery::new(
+            schema,
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        match execute_subscription(Subscription { query }, options(None)) {
+            Err(SubscriptionError::GraphQLError(errors)) => match errors.as_slice() {
+                [QueryExecutionError::NoRootSubscriptionObjectType {
+                    deployment,
+                    schema_version,
+                }] => {
+                    assert_eq!(deployment.to_string(), "noSubscriptions");
+                    assert_eq!(schema_version, &version);
+                }
+                errors => panic!("expected a missing Subscription type, got {:?}", errors),
+            },
+            Err(e) => panic!("expected a missing Subscription type, got {:?}", e),
+            Ok(_) => panic!("expected the subscription to be rejected"),
+        }
+    }
+
+    #[test]
+    fn rejects_subscriptions_past_connection_limit() {
+        let subscribe = || {
+            let query = GraphDataQuery::new(
+                schema(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            execute_subscription(
+                Subscription { query },
+                SubscriptionExecutionOptions {
+                    connection_id: Some("connection-limit-test".to_owned()),
+                    max_subscriptions_per_connection: Some(1),
+                    ..options(None)
+                },
+            )
+        };
+
+        let first = subscribe().ok().expect("first subscription is accepted");
+        match subscribe() {
+            Err(SubscriptionError::TooManySubscriptions { limit: 1, .. }) => (),
+            Err(e) => panic!("expected too many subscriptions, got {:?}", e),
+            Ok(_) => panic!("expected the second subscription to be rejected"),
+        }
+
+        // Dropping a subscription makes room for a new one
+        drop(first);
+        assert!(subscribe().is_ok());
+    }
+
+    #[tokio::test]
+    async fn idle_subscription_sends_keep_alives() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        // The initial query takes 50ms, leaving time for keep-alives before
+        // its result arrives
+        let results = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                keep_alive_interval: Some(Duration::from_millis(10)),
+                ..options(None)
+            },
+        )
+        .ok()
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+
+        assert!(results[0].is_keep_alive());
+        assert!(results.iter().any(|result| result.data.is_some()));
+
+        // The stream ends with the result of the query and the completion
+        // of the subscription, not with a keep-alive
+        assert!(results[results.len() - 2].data.is_some());
+        assert!(results.last().unwrap().completed);
+    }
+
+    #[tokio::test]
+    async fn keep_alives_follow_the_injected_clock() {
+        let clock = MockClock::new();
+        let resolver = MockSubscriptionResolver::new();
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        let options = SubscriptionExecutionOptions::builder(
+            Logger::root(slog::Discard, o!()),
+            resolver.clone(),
+        )
+        .keep_alive_interval(Duration::from_secs(10))
+        .timer_jitter(0.0)
+        .clock(Arc::new(clock.clone()))
+        .build();
+        let mut results = execute_subscription(Subscription { query }, options).unwrap();
+
+        assert!(results.next().await.unwrap().data.is_some());
+
+        // No real time needs to pass for a keep-alive, and none is sent
+        // before the clock reaches the end of the interval
+        clock.advance(Duration::from_secs(9));
+        assert!(results.next().now_or_never().is_none());
+        clock.advance(Duration::from_secs(1));
+        assert!(results.next().await.unwrap().is_keep_alive());
+
+        resolver.end_events();
+        assert!(results.next().await.unwrap().completed);
+    }
+
+    #[tokio::test]
+    async fn permit_waits_are_measured_with_the_injected_clock() {
+        let clock = MockClock::new();
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        let options = SubscriptionExecutionOptions::builder(
+            Logger::root(slog::Discard, o!()),
+            MockSubscriptionResolver::new(),
+        )
+        .query_permits(Arc::new(QueryPermits::new(0)))
+        .query_permit_timeout(Duration::from_secs(60))
+        .clock(Arc::new(clock.clone()))
+        .build();
+        let mut results = execute_subscription(Subscription { query }, options).unwrap();
+
+        // The initial query gives up on a permit once the clock says so, and
+        // reports the time that passed on the clock
+        assert!(results.next().now_or_never().is_none());
+        clock.advance(Duration::from_secs(60));
+        let result = results.next().await.unwrap();
+        match result.errors.as_ref().map(|errors| errors.as_slice()) {
+            Some([QueryError::ExecutionError(QueryExecutionError::Overloaded { waited })]) => {
+                assert_eq!(*waited, Duration::from_secs(60))
+            }
+            _ => panic!("expected an overloaded error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn jitter_extends_timers_by_up_to_the_fraction() {
+        let interval = Duration::from_millis(100);
+        assert_eq!(jittered(interval, 0.0), interval);
+
+        let jittered = (0..100)
+            .map(|_| jittered(interval, 0.5))
+            .collect::<HashSet<_>>();
+        assert!(jittered
+            .iter()
+            .all(|jittered| *jittered >= interval && *jittered <= interval.mul_f64(1.5)));
+        assert!(jittered.len() > 1);
+    }
+
+    fn event_stream(tags: &[usize]) -> StoreEventStreamBox {
+        let events = tags
+            .iter()
+            .map(|tag| StoreEvent {
+                tag: *tag,
+                changes: HashSet::new(),
+                block: None,
+            })
+            .collect::<Vec<_>>();
+        let source: Box<dyn Stream<Item = StoreEvent, Error = ()> + Send> =
+            Box::new(stream::iter_ok(events));
+        StoreEventStream::new(source)
+    }
+
+    #[test]
+    fn merged_source_streams_pass_on_each_event_once() {
+        let merged = merge_source_streams(vec![event_stream(&[1, 2, 4]), event_stream(&[1, 3, 4])]);
+        let tags = merged
+            .collect()
+            .wait()
+            .unwrap()
+            .into_iter()
+            .map(|event| event.tag)
+            .collect::<Vec<_>>();
+
+        // Every tag arrives once; events that arrive after an event with a
+        // higher tag are dropped
+        let mut deduplicated = tags.clone();
+        deduplicated.dedup();
+        assert_eq!(tags, deduplicated);
+        assert!(tags.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(tags.first(), Some(&1));
+        assert_eq!(tags.last(), Some(&4));
+    }
+
+    #[test]
+    fn events_for_the_same_block_are_merged() {
+        let change = |entity_id: &str| EntityChange {
+            subgraph_id: SubgraphDeploymentId::new("blocks").unwrap(),
+            entity_type: "Musician".to_owned(),
+            entity_id: entity_id.to_owned(),
+            operation: EntityChangeOperation::Set,
+        };
+        let event = |tag, block, entity_id| StoreEvent {
+            tag,
+            changes: vec![change(entity_id)].into_iter().collect(),
+            block,
+        };
+        let events = vec![
+            event(1, Some(1), "a"),
+            event(2, Some(1), "b"),
+            event(3, Some(2), "c"),
+            event(4, None, "d"),
+            event(5, None, "e"),
+            event(6, Some(2), "f"),
+        ];
+        let source: Box<dyn Stream<Item = StoreEvent, Error = ()> + Send> =
+            Box::new(stream::iter_ok(events));
+
+        let merged = events_once_per_block(StoreEventStream::new(source))
+            .collect()
+            .wait()
+            .unwrap();
+
+        let tags = merged.iter().map(|event| event.tag).collect::<Vec<_>>();
+        assert_eq!(tags, vec![2, 3, 4, 5, 6]);
+        assert_eq!(merged[0].changes.len(), 2);
+        assert_eq!(merged[0].block, Some(1));
+    }
+
+    #[tokio::test]
+    async fn debounced_events_keep_the_latest_block() {
+        let change = |entity_id: &str| EntityChange {
+            subgraph_id: SubgraphDeploymentId::new("blocks").unwrap(),
+            entity_type: "Musician".to_owned(),
+            entity_id: entity_id.to_owned(),
+            operation: EntityChangeOperation::Set,
+        };
+        let event = |tag, block, entity_id| StoreEvent {
+            tag,
+            changes: vec![change(entity_id)].into_iter().collect(),
+            block,
+        };
+        let events = vec![
+            event(1, Some(1), "a"),
+            event(2, Some(2), "b"),
+            event(3, Some(2), "c"),
+        ];
+        let source: Box<dyn Stream<Item = StoreEvent, Error = ()> + Send> =
+            Box::new(stream::iter_ok(events));
+
+        // The burst is debounced into one event, which is for the latest
+        // block of the burst, not the first
+        let merged = events_once_per_block(events_debounced(
+            StoreEventStream::new(source),
+            Duration::from_secs(60),
+            Arc::new(SystemClock),
+        ))
+        .collect()
+        .compat()
+        .await
+        .unwrap();
+
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].tag, 3);
+        assert_eq!(merged[0].block, Some(2));
+        assert_eq!(merged[0].changes.len(), 3);
+    }
+
+    #[test]
+    fn debounce_follows_the_injected_clock() {
+        let clock = MockClock::new();
+        let (sender, receiver) = futures03::channel::mpsc::unbounded();
+        let source: Box<dyn Stream<Item = StoreEvent, Error = ()> + Send> =
+            Box::new(receiver.map(Ok::<_, ()>).compat());
+        let mut debounced = events_debounced(
+            StoreEventStream::new(source),
+            Duration::from_secs(10),
+            Arc::new(clock.clone()),
+        )
+        .compat();
+
+        let first = StoreEvent::new(vec![]);
+        let second = StoreEvent::new(vec![]);
+        let last_tag = second.tag;
+
+        // Events are held back until the clock reaches the end of the window
+        // that the first of them started, and are then passed on as one
+        sender.unbounded_send(first).unwrap();
+        assert!(debounced.next().now_or_never().is_none());
+        clock.advance(Duration::from_secs(9));
+        sender.unbounded_send(second).unwrap();
+        assert!(debounced.next().now_or_never().is_none());
+        clock.advance(Duration::from_secs(1));
+        let event = debounced.next().now_or_never().unwrap().unwrap().unwrap();
+        assert_eq!(event.tag, last_tag);
+
+        drop(sender);
+        assert!(debounced.next().now_or_never().unwrap().is_none());
+    }
+
+    #[test]
+    fn multiple_subscription_fields_are_rejected_by_default() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { a: musicians { name } b: musicians { id } }").unwrap(),
+            None,
+        );
+
+        match execute_subscription(Subscription { query }, options(None)) {
+            Err(SubscriptionError::GraphQLError(ref errors)) => match errors.as_slice() {
+                [QueryExecutionError::MultipleSubscriptionFields] => (),
+                errors => panic!("expected multiple subscription fields, got {:?}", errors),
+            },
+            Err(e) => panic!("expected multiple subscription fields, got {:?}", e),
+            Ok(_) => panic!("expected the subscription to be rejected"),
+        }
+    }
+
+    #[tokio::test]
+    async fn multiple_subscription_fields_are_executed_together_when_allowed() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { a: musicians { name } b: musicians { name } }").unwrap(),
+            None,
+        );
+
+        let mut stream = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                allow_multiple_subscription_fields: true,
+                ..options(None)
+            },
+        )
+        .ok()
+        .unwrap();
+        let result = stream.next().await.unwrap();
+
+        let john = || q::Value::List(vec![object! { name: "John" }]);
+        assert_eq!(result.data, Some(object! { a: john(), b: john() }));
+    }
+
+    #[tokio::test]
+    async fn failing_fields_do_not_discard_other_fields() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query(
+                "subscription { a: musicians { name } b: musicians { name } c: musicians { name } }",
+            )
+            .unwrap(),
+            None,
+        );
+
+        let mut stream = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                allow_multiple_subscription_fields: true,
+                resolver: SlowResolver {
+                    musicians: 1,
+                    failing_field: Some("b"),
+                    panicking_field: None,
+                    prefetched_musicians: None,
+                },
+                ..options(None)
+            },
+        )
+        .ok()
+        .unwrap();
+        let result = stream.next().await.unwrap();
+
+        let john = || q::Value::List(vec![object! { name: "John" }]);
+        assert_eq!(result.data, Some(object! { a: john(), c: john() }));
+        match result.errors.as_ref().map(|errors| errors.as_slice()) {
+            Some([QueryError::ExecutionError(QueryExecutionError::ResolveEntitiesError(_))]) => (),
+            _ => panic!("expected the error of the failing field, got {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_fields_time_out_without_discarding_other_fields() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query(
+                "subscription { a: musicians { name } b: musicians { name bands { name } } }",
+            )
+            .unwrap(),
+            None,
+        );
+
+        // Resolving `bands` takes longer than its timeout; the rest of the
+        // query has no timeout
+        let mut stream = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                allow_multiple_subscription_fields: true,
+                field_timeouts: vec![("Musician.bands".to_owned(), Duration::from_millis(10))]
+                    .into_iter()
+                    .collect(),
+                ..options(None)
+            },
+        )
+        .ok()
+        .unwrap();
+        let result = stream.next().await.unwrap();
+
+        let john = || q::Value::List(vec![object! { name: "John" }]);
+        assert_eq!(result.data, Some(object! { a: john() }));
+        match result.errors.as_ref().map(|errors| errors.as_slice()) {
+            Some([QueryError::ExecutionError(QueryExecutionError::FieldTimeout(_, field, _))]) => {
+                assert_eq!(field, "bands")
+            }
+            _ => panic!("expected a field timeout, got {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn panics_report_the_field_that_was_executing() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { a: musicians { name } b: musicians { name } }").unwrap(),
+            None,
+        );
+
+        let mut stream = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                allow_multiple_subscription_fields: true,
+                resolver: SlowResolver {
+                    musicians: 1,
+                    failing_field: None,
+                    panicking_field: Some("b"),
+                    prefetched_musicians: None,
+                },
+                ..options(None)
+            },
+        )
+        .ok()
+        .unwrap();
+        let result = stream.next().await.unwrap();
+
+        assert_eq!(result.data, None);
+        match result.errors.as_ref().map(|errors| errors.as_slice()) {
+            Some([QueryError::ExecutionError(QueryExecutionError::FieldPanic(field, message))]) => {
+                assert_eq!(field, "b");
+                assert_eq!(message, "broken resolver");
+            }
+            _ => panic!("expected a panic in field `b`, got {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn initial_result_can_be_skipped() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        // Without events, a subscription that skips the initial result
+        // produces no results at all
+        let results = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                emit_initial_result: false,
+                ..options(None)
+            },
+        )
+        .ok()
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+
+        // The only result marks the end of the (empty) event stream
+        assert_eq!(results.len(), 1);
+        assert!(results[0].completed);
+    }
+
+    #[tokio::test]
+    async fn canceled_subscription_stops_in_flight_query() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        let guard = CancelGuard::new();
+        let stream = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                cancel_handle: Some(guard.handle()),
+                ..options(None)
+            },
+        )
+        .ok()
+        .unwrap();
+
+        // Cancel while the initial query, which takes 50ms, is running
+        graph::spawn(async move {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+            guard.cancel();
+        });
+
+        let results = stream.collect::<Vec<_>>().await;
+        assert!(results.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unsubscribing_ends_the_subscription_with_a_single_result() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        let (unsubscriber, signal) = Unsubscriber::new();
+        let stream = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                unsubscribe_signal: Some(signal),
+                ..options(None)
+            },
+        )
+        .ok()
+        .unwrap();
+
+        // Unsubscribe while the initial query, which takes 50ms, is running
+        graph::spawn(async move {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+            unsubscriber.unsubscribe();
+        });
+
+        let results = stream.collect::<Vec<_>>().await;
+        assert_eq!(results.len(), 1);
+        assert!(results[0].unsubscribed);
+        assert!(!results[0].completed);
+    }
+
+    #[tokio::test]
+    async fn dropping_the_unsubscriber_keeps_the_subscription_going() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        let (unsubscriber, signal) = Unsubscriber::new();
+        drop(unsubscriber);
+        let mut stream = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                unsubscribe_signal: Some(signal),
+                ..options(None)
+            },
+        )
+        .ok()
+        .unwrap();
+
+        let initial = stream.next().await.unwrap();
+        assert!(initial.data.is_some());
+        assert!(!initial.unsubscribed);
+    }
+
+    #[tokio::test]
+    async fn snapshot_contains_current_result() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        let result = execute_subscription_snapshot(Subscription { query }, options(None))
+            .await
+            .ok()
+            .unwrap();
+
+        assert_eq!(
+            result.data,
+            Some(object! { musicians: q::Value::List(vec![object! { name: "John" }]) })
+        );
+    }
+
+    #[tokio::test]
+    async fn snapshot_needs_a_query_permit() {
+        let permits = Arc::new(QueryPermits::new(1));
+        let snapshot = || {
+            let query = GraphDataQuery::new(
+                schema(),
+                parse_query("subscription { musicians { name } }").unwrap(),
+                None,
+            );
+            execute_subscription_snapshot(
+                Subscription { query },
+                SubscriptionExecutionOptions {
+                    query_permits: Some(permits.clone()),
+                    query_permit_timeout: Some(Duration::from_millis(10)),
+                    ..options(None)
+                },
+            )
+        };
+
+        // While a subscription sharing the permits holds the only one, the
+        // snapshot is rejected like the query for an event would be
+        let held = permits.acquire(SubscriptionPriority::Normal).await;
+        let result = snapshot().await.ok().unwrap();
+        assert!(result.data.is_none());
+        match result.errors.as_ref().map(|errors| errors.as_slice()) {
+            Some([QueryError::ExecutionError(QueryExecutionError::Overloaded { .. })]) => (),
+            _ => panic!("expected an overloaded error, got {:?}", result),
+        }
+
+        drop(held);
+        let result = snapshot().await.ok().unwrap();
+        assert!(result.errors.is_none());
+        assert_eq!(permits.available_permits(), 1);
+    }
+
+    #[test]
+    fn introspection_is_rejected_when_disabled() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query(
+                "subscription { musicians { ...details } } \
+                 fragment details on Musician { name __type(name: \"Band\") { name } }",
+            )
+            .unwrap(),
+            None,
+        );
+        let options = SubscriptionExecutionOptions {
+            allow_introspection: false,
+            ..options(None)
+        };
+
+        match execute_subscription(Subscription { query }, options) {
+            Err(SubscriptionError::GraphQLError(ref errors)) => match errors.as_slice() {
+                [QueryExecutionError::IntrospectionDisabled(_, field)] => {
+                    assert_eq!(field, "__type")
+                }
+                errors => panic!("expected introspection to be disabled, got {:?}", errors),
+            },
+            Err(e) => panic!("expected introspection to be disabled, got {:?}", e),
+            Ok(_) => panic!("expected the subscription to be rejected"),
+        }
+    }
+
+    #[test]
+    fn rejects_fields_that_are_not_subscribable() {
+        let resolver = MockSubscriptionResolver::new();
+        resolver.disable_field_stream("musicians");
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        let options = SubscriptionExecutionOptions::builder(
+            Logger::root(slog::Discard, o!()),
+            resolver.clone(),
+        )
+        .build();
+
+        match execute_subscription(Subscription { query }, options) {
+            Err(SubscriptionError::FieldNotSubscribable { field }) => {
+                assert_eq!(field, "musicians")
+            }
+            Err(e) => panic!("expected the field to not be subscribable, got {:?}", e),
+            Ok(_) => panic!("expected the subscription to be rejected"),
+        }
+        assert_eq!(resolver.open_streams(), 0);
+    }
+
+    #[test]
+    fn validation_reports_all_problems_of_a_subscription() {
+        let validate = |query: &str, limits: SubscriptionValidationLimits| {
+            validate_subscription(parse_query(query).unwrap(), schema(), &limits)
+        };
+
+        assert!(validate(
+            "subscription { musicians { name } }",
+            SubscriptionValidationLimits::default()
+        )
+        .is_ok());
+
+        let errors = validate(
+            "subscription { musicians { name __type(name: \"Band\") { name } } named { name } }",
+            SubscriptionValidationLimits {
+                allow_introspection: false,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        match errors.as_slice() {
+            [QueryExecutionError::MultipleSubscriptionFields, QueryExecutionError::IntrospectionDisabled(_, field)] =>
+            {
+                assert_eq!(field, "__type")
+            }
+            errors => panic!("unexpected errors: {:?}", errors),
+        }
+
+        let errors = validate(
+            "query { musicians { name } }",
+            SubscriptionValidationLimits::default(),
+        )
+        .unwrap_err();
+        match errors.as_slice() {
+            [QueryExecutionError::NotSupported(_)] => {}
+            errors => panic!("unexpected errors: {:?}", errors),
+        }
+    }
+
+    #[tokio::test]
+    async fn data_fields_are_allowed_when_introspection_is_disabled() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+        let options = SubscriptionExecutionOptions {
+            allow_introspection: false,
+            ..options(None)
+        };
+
+        let result = execute_subscription_snapshot(Subscription { query }, options)
+            .await
+            .ok()
+            .unwrap();
+
+        assert_eq!(
+            result.data,
+            Some(object! { musicians: q::Value::List(vec![object! { name: "John" }]) })
+        );
+    }
+
+    #[tokio::test]
+    async fn snapshot_rejects_queries() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("query { musicians { name } }").unwrap(),
+            None,
+        );
+
+        match execute_subscription_snapshot(Subscription { query }, options(None)).await {
+            Err(SubscriptionError::GraphQLError(ref errors)) => match errors.as_slice() {
+                [QueryExecutionError::NotSupported(_)] => (),
+                errors => panic!("expected not supported, got {:?}", errors),
+            },
+            result => panic!("expected not supported, got {:?}", result.ok()),
+        }
+    }
+
+    #[tokio::test]
+    async fn results_that_exceed_max_complexity_are_rejected() {
+        // The query is estimated to load a single musician, which is within
+        // the limit, but the resolver loads two
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians(first: 1) { name } }").unwrap(),
+            None,
+        );
+
+        let mut stream = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                resolver: SlowResolver {
+                    musicians: 2,
+                    failing_field: None,
+                    panicking_field: None,
+                    prefetched_musicians: None,
+                },
+                max_complexity: Some(1),
+                ..options(None)
+            },
+        )
+        .ok()
+        .unwrap();
+        let result = stream.next().await.unwrap();
+
+        match result.errors.as_ref().map(|errors| errors.as_slice()) {
+            Some([QueryError::ExecutionError(QueryExecutionError::TooComplex(2, 1))]) => (),
+            _ => panic!("expected the result to be too complex, got {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn results_that_exceed_max_complexity_keep_the_errors_of_failing_fields() {
+        // Both fields are estimated to load a single musician, but the one
+        // that doesn't fail loads three
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query(
+                "subscription { a: musicians(first: 1) { name } b: musicians(first: 1) { name } }",
+            )
+            .unwrap(),
+            None,
+        );
+
+        let mut stream = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                allow_multiple_subscription_fields: true,
+                resolver: SlowResolver {
+                    musicians: 3,
+                    failing_field: Some("b"),
+                    panicking_field: None,
+                    prefetched_musicians: None,
+                },
+                max_complexity: Some(2),
+                ..options(None)
+            },
+        )
+        .ok()
+        .unwrap();
+        let result = stream.next().await.unwrap();
+
+        assert!(result.data.is_none());
+        let errors = result.errors.unwrap();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(
+            errors[0],
+            QueryError::ExecutionError(QueryExecutionError::ResolveEntitiesError(_))
+        ));
+        assert!(matches!(
+            errors[1],
+            QueryError::ExecutionError(QueryExecutionError::TooComplex(3, 2))
+        ));
+    }
+
+    #[tokio::test]
+    async fn results_that_exceed_max_size_are_rejected() {
+        let query = GraphDataQuery::new(
+            schema(),
+            parse_query("subscription { musicians { name } }").unwrap(),
+            None,
+        );
+
+        let mut stream = execute_subscription(
+            Subscription { query },
+            SubscriptionExecutionOptions {
+                max_result_size_bytes: Some(10),
+                ..options(None)
+            },
+        )
+        .ok()
+        .unwrap();
+        let result = stream.next().await.unwrap();
+
+        match result.errors.as_ref().map(|errors| errors.as_slice()) {
+            Some([QueryError::ExecutionError(QueryExecutionError::ResultTooLarge(_, 10))]) => (),
+            _ => panic!("expected the result to be too large, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn resumed_subscriptions_skip_seen_events() {
+        let tags = events_after_tag(event_stream(&[1, 2, 3]), 1)
+            .collect()
+            .wait()
+            .unwrap()
+            .into_iter()
+            .map(|event| event.tag)
+            .collect::<Vec<_>>();
 
-    match result {
-        Ok(value) => QueryResult::new(Some(value)),
-        Err(e) => QueryResult::from(e),
+        assert_eq!(tags, vec![2, 3]);
     }
 }
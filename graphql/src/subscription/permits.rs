@@ -0,0 +1,216 @@
+use std::collections::{HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+/// How urgently the queries of a subscription need a query permit when
+/// queries of several subscriptions wait for one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SubscriptionPriority {
+    /// For bulk subscriptions, e.g. analytics, whose results may be late.
+    /// They only get a permit when no other events are waiting for one, or
+    /// when they have been passed over too often.
+    Bulk,
+
+    /// The default.
+    Normal,
+
+    /// For subscriptions that users are waiting on, e.g. dashboards. Their
+    /// events get permits ahead of the events of all other subscriptions.
+    Interactive,
+}
+
+impl Default for SubscriptionPriority {
+    fn default() -> Self {
+        SubscriptionPriority::Normal
+    }
+}
+
+impl SubscriptionPriority {
+    const LEVELS: usize = 3;
+
+    fn level(&self) -> usize {
+        match self {
+            SubscriptionPriority::Bulk => 0,
+            SubscriptionPriority::Normal => 1,
+            SubscriptionPriority::Interactive => 2,
+        }
+    }
+}
+
+/// How many permits may go to events of higher priorities while events of a
+/// lower priority are waiting before one of those gets the next permit.
+const MAX_PASSED_OVER: usize = 8;
+
+#[derive(Default)]
+struct State {
+    available: usize,
+    next_id: u64,
+    /// The waiting events of each priority level, in the order they started to
+    /// wait, with the waker of the task to notify when they get a permit.
+    waiting: [VecDeque<(u64, Waker)>; SubscriptionPriority::LEVELS],
+    /// Waiters that got a permit but haven't noticed yet.
+    granted: HashSet<u64>,
+    /// How many permits have gone to higher levels while a level had waiters.
+    passed_over: [usize; SubscriptionPriority::LEVELS],
+}
+
+impl State {
+    /// Picks the level whose longest-waiting event gets the next permit: the
+    /// highest level with waiters, unless a lower level with waiters has
+    /// been passed over too often.
+    fn next_level(&mut self) -> Option<usize> {
+        let waiting = &self.waiting;
+        let passed_over = &self.passed_over;
+        let starving = (0..SubscriptionPriority::LEVELS)
+            .find(|level| !waiting[*level].is_empty() && passed_over[*level] >= MAX_PASSED_OVER);
+        let level = starving.or_else(|| {
+            (0..SubscriptionPriority::LEVELS)
+                .rev()
+                .find(|level| !waiting[*level].is_empty())
+        })?;
+
+        for lower in 0..level {
+            if !self.waiting[lower].is_empty() {
+                self.passed_over[lower] += 1;
+            }
+        }
+        self.passed_over[level] = 0;
+        Some(level)
+    }
+
+    /// Hands the available permits to waiting events.
+    fn grant(&mut self) {
+        while self.available > 0 {
+            let level = match self.next_level() {
+                Some(level) => level,
+                None => return,
+            };
+            let (id, waker) = self.waiting[level].pop_front().unwrap();
+            self.available -= 1;
+            self.granted.insert(id);
+            waker.wake();
+        }
+    }
+}
+
+/// Limits how many subscription queries run at the same time, and decides
+/// which waiting event gets a permit when one becomes available.
+///
+/// Events of higher priorities get permits first. Within a priority, events
+/// get permits in the order in which they started waiting, no matter which
+/// subscription they belong to, so an event that starts waiting later can't
+/// overtake one that is already waiting. Lower priorities still make
+/// progress under contention: once `MAX_PASSED_OVER` permits have gone to
+/// higher priorities while events of a lower priority were waiting, the
+/// longest-waiting of those gets the next permit.
+///
+/// Permits only limit the subscriptions that share them, so callers create
+/// them once, e.g. per node or per store, and hand the same `Arc` to every
+/// subscription through `SubscriptionExecutionOptions::query_permits`.
+pub struct QueryPermits {
+    state: Mutex<State>,
+}
+
+/// A query permit; it is given back when it is dropped.
+pub struct QueryPermit<'a> {
+    permits: &'a QueryPermits,
+}
+
+impl Drop for QueryPermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.permits.state.lock().unwrap();
+        state.available += 1;
+        state.grant();
+    }
+}
+
+impl QueryPermits {
+    pub fn new(permits: usize) -> Self {
+        QueryPermits {
+            state: Mutex::new(State {
+                available: permits,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// The number of permits that are not in use.
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().available
+    }
+
+    /// Waits for a permit. Dropping the returned future before it completes
+    /// gives up waiting without changing the order of the other waiters.
+    pub fn acquire(&self, priority: SubscriptionPriority) -> Acquire<'_> {
+        Acquire {
+            permits: self,
+            level: priority.level(),
+            id: None,
+        }
+    }
+}
+
+pub struct Acquire<'a> {
+    permits: &'a QueryPermits,
+    level: usize,
+    /// Set once the event waits in the queue of its level.
+    id: Option<u64>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = QueryPermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let permits = self.permits;
+        let mut state = permits.state.lock().unwrap();
+
+        match self.id {
+            // Permits are only available if nobody is waiting
+            None if state.available > 0 => {
+                state.available -= 1;
+                Poll::Ready(QueryPermit { permits })
+            }
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.waiting[self.level].push_back((id, cx.waker().clone()));
+                self.id = Some(id);
+                Poll::Pending
+            }
+            Some(id) if state.granted.remove(&id) => {
+                self.id = None;
+                Poll::Ready(QueryPermit { permits })
+            }
+            Some(id) => {
+                if let Some((_, waker)) = state.waiting[self.level]
+                    .iter_mut()
+                    .find(|(waiter, _)| *waiter == id)
+                {
+                    *waker = cx.waker().clone();
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let id = match self.id {
+            Some(id) => id,
+            None => return,
+        };
+
+        let mut state = self.permits.state.lock().unwrap();
+        if state.granted.remove(&id) {
+            // The permit was granted after the event stopped waiting, pass
+            // it on to the next one
+            state.available += 1;
+            state.grant();
+        } else {
+            state.waiting[self.level].retain(|(waiter, _)| *waiter != id);
+        }
+    }
+}
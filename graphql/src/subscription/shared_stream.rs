@@ -0,0 +1,513 @@
+use graphql_parser::{query as q, schema as s};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, Weak};
+
+use graph::prelude::futures03::channel::mpsc;
+use graph::prelude::*;
+
+use crate::execution::{ExecutionContext, ObjectOrInterface, Resolver};
+use crate::prelude::{Name, ObjectType};
+use crate::query::ext::BlockConstraint;
+
+/// Identifies identical field stream requests. Two requests share one
+/// underlying stream if all of the following are equal:
+///
+/// - `schema`: a hash of the formatted schema document
+/// - `object_type`: the name of the object type the field belongs to
+/// - `field`: the formatted query field, including its alias, arguments,
+///   directives and selection set
+/// - `arguments`: the formatted coerced argument values, sorted by name
+///
+/// These are all the inputs of `Resolver::resolve_field_stream`, which
+/// means that requests with the same key get the same stream from the
+/// wrapped resolver.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct StreamKey {
+    schema: u64,
+    object_type: String,
+    field: String,
+    arguments: Vec<(String, String)>,
+}
+
+impl StreamKey {
+    fn new(
+        schema: &s::Document,
+        object_type: &s::ObjectType,
+        field: &q::Field,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Self {
+        let mut hasher = DefaultHasher::new();
+        schema.to_string().hash(&mut hasher);
+
+        let mut arguments = arguments
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+        arguments.sort();
+
+        StreamKey {
+            schema: hasher.finish(),
+            object_type: object_type.name.clone(),
+            field: field.to_string(),
+            arguments,
+        }
+    }
+}
+
+type SharedStreams = Arc<Mutex<HashMap<StreamKey, Weak<SharedStream>>>>;
+
+/// One underlying stream and the subscribers it is fanned out to. The
+/// stream is closed when the last subscriber drops its `Arc`.
+struct SharedStream {
+    key: StreamKey,
+    streams: SharedStreams,
+    subscribers: Mutex<Vec<mpsc::UnboundedSender<StoreEvent>>>,
+    /// Stops forwarding events from the underlying stream, and drops it,
+    /// when the shared stream is dropped.
+    _guard: CancelGuard,
+}
+
+impl SharedStream {
+    fn subscribe(self: &Arc<Self>) -> StoreEventStreamBox {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push(sender);
+
+        // Every subscriber keeps the shared stream alive
+        let shared = self.clone();
+        let stream = receiver
+            .map(move |event| {
+                let _ = &shared;
+                Ok::<_, ()>(event)
+            })
+            .compat();
+        StoreEventStream::new(Box::new(stream))
+    }
+
+    fn forward(&self, event: StoreEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+    }
+}
+
+impl Drop for SharedStream {
+    fn drop(&mut self) {
+        let mut streams = self.streams.lock().unwrap();
+
+        // A new shared stream for the same key may have been registered
+        // since the last subscriber went away
+        let replaced = streams
+            .get(&self.key)
+            .map_or(false, |stream| stream.upgrade().is_some());
+        if !replaced {
+            streams.remove(&self.key);
+        }
+    }
+}
+
+/// A resolver that shares field streams between identical subscriptions.
+///
+/// When many clients subscribe with the same query, every subscription
+/// would otherwise open its own store event stream. This wrapper opens one
+/// stream with the wrapped resolver per `StreamKey` and fans its events out
+/// to all subscriptions. The underlying stream is closed once the last of
+/// them is dropped. All other methods are passed through to the wrapped
+/// resolver.
+#[derive(Clone)]
+pub struct SharedStreamResolver<R> {
+    inner: R,
+    streams: SharedStreams,
+}
+
+impl<R> SharedStreamResolver<R>
+where
+    R: Resolver,
+{
+    pub fn new(inner: R) -> Self {
+        SharedStreamResolver {
+            inner,
+            streams: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The number of underlying streams that are currently open.
+    pub fn open_streams(&self) -> usize {
+        self.streams.lock().unwrap().len()
+    }
+
+    /// An execution context for the wrapped resolver that is otherwise the
+    /// same as `ctx`.
+    fn inner_context(&self, ctx: &ExecutionContext<Self>) -> ExecutionContext<R> {
+        ExecutionContext {
+            logger: ctx.logger.clone(),
+            query: ctx.query.cheap_clone(),
+            resolver: Arc::new(self.inner.clone()),
+            fields: ctx.fields.clone(),
+            deadline: ctx.deadline,
+            field_timeouts: ctx.field_timeouts.clone(),
+            cancel_handle: ctx.cancel_handle.clone(),
+            max_first: ctx.max_first,
+            block: ctx.block,
+            mode: ctx.mode,
+        }
+    }
+}
+
+impl<R> Resolver for SharedStreamResolver<R>
+where
+    R: Resolver,
+{
+    fn prefetch(
+        &self,
+        ctx: &ExecutionContext<Self>,
+        selection_set: &q::SelectionSet,
+    ) -> Result<Option<q::Value>, Vec<QueryExecutionError>> {
+        self.inner.prefetch(&self.inner_context(ctx), selection_set)
+    }
+
+    fn locate_block(
+        &self,
+        block_constraint: &BlockConstraint,
+    ) -> Result<BlockNumber, QueryExecutionError> {
+        self.inner.locate_block(block_constraint)
+    }
+
+    fn resolve_objects(
+        &self,
+        parent: &Option<q::Value>,
+        field: &q::Field,
+        field_definition: &s::Field,
+        object_type: ObjectOrInterface<'_>,
+        arguments: &HashMap<&q::Name, q::Value>,
+        types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
+        block: BlockNumber,
+        max_first: u32,
+    ) -> Result<q::Value, QueryExecutionError> {
+        self.inner.resolve_objects(
+            parent,
+            field,
+            field_definition,
+            object_type,
+            arguments,
+            types_for_interface,
+            block,
+            max_first,
+        )
+    }
+
+    fn resolve_object(
+        &self,
+        parent: &Option<q::Value>,
+        field: &q::Field,
+        field_definition: &s::Field,
+        object_type: ObjectOrInterface<'_>,
+        arguments: &HashMap<&q::Name, q::Value>,
+        types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
+        block: BlockNumber,
+    ) -> Result<q::Value, QueryExecutionError> {
+        self.inner.resolve_object(
+            parent,
+            field,
+            field_definition,
+            object_type,
+            arguments,
+            types_for_interface,
+            block,
+        )
+    }
+
+    fn resolve_enum_value(
+        &self,
+        field: &q::Field,
+        enum_type: &s::EnumType,
+        value: Option<&q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        self.inner.resolve_enum_value(field, enum_type, value)
+    }
+
+    fn resolve_scalar_value(
+        &self,
+        parent_object_type: &s::ObjectType,
+        parent: &BTreeMap<String, q::Value>,
+        field: &q::Field,
+        scalar_type: &s::ScalarType,
+        value: Option<&q::Value>,
+        argument_values: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        self.inner.resolve_scalar_value(
+            parent_object_type,
+            parent,
+            field,
+            scalar_type,
+            value,
+            argument_values,
+        )
+    }
+
+    fn resolve_enum_values(
+        &self,
+        field: &q::Field,
+        enum_type: &s::EnumType,
+        value: Option<&q::Value>,
+    ) -> Result<q::Value, Vec<QueryExecutionError>> {
+        self.inner.resolve_enum_values(field, enum_type, value)
+    }
+
+    fn resolve_scalar_values(
+        &self,
+        field: &q::Field,
+        scalar_type: &s::ScalarType,
+        value: Option<&q::Value>,
+    ) -> Result<q::Value, Vec<QueryExecutionError>> {
+        self.inner.resolve_scalar_values(field, scalar_type, value)
+    }
+
+    fn resolve_abstract_type<'a>(
+        &self,
+        schema: &'a s::Document,
+        abstract_type: &s::TypeDefinition,
+        object_value: &q::Value,
+    ) -> Option<&'a s::ObjectType> {
+        self.inner
+            .resolve_abstract_type(schema, abstract_type, object_value)
+    }
+
+    fn supports_field_stream(
+        &self,
+        schema: &s::Document,
+        object_type: &s::ObjectType,
+        field: &q::Field,
+    ) -> bool {
+        self.inner.supports_field_stream(schema, object_type, field)
+    }
+
+    fn is_blocking(&self) -> bool {
+        self.inner.is_blocking()
+    }
+
+    fn latest_block(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+    ) -> Result<Option<BlockNumber>, QueryExecutionError> {
+        self.inner.latest_block(subgraph)
+    }
+
+    fn for_query(&self) -> Self {
+        SharedStreamResolver {
+            inner: self.inner.for_query(),
+            streams: self.streams.clone(),
+        }
+    }
+
+    fn trace(&self) -> Option<Vec<QueryTraceEntry>> {
+        self.inner.trace()
+    }
+
+    fn resolve_field_stream<'a, 'b>(
+        &self,
+        schema: &'a s::Document,
+        object_type: &'a s::ObjectType,
+        field: &'b q::Field,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<StoreEventStreamBox, QueryExecutionError> {
+        let key = StreamKey::new(schema, object_type, field, arguments);
+
+        // Hold the lock while opening the stream so that concurrent
+        // identical requests don't open more than one
+        let mut streams = self.streams.lock().unwrap();
+        if let Some(shared) = streams.get(&key).and_then(|stream| stream.upgrade()) {
+            return Ok(shared.subscribe());
+        }
+
+        let source = self
+            .inner
+            .resolve_field_stream(schema, object_type, field, arguments)?;
+
+        let guard = CancelGuard::new();
+        let source = source.cancelable(&guard, || ());
+        let shared = Arc::new(SharedStream {
+            key: key.clone(),
+            streams: self.streams.clone(),
+            subscribers: Mutex::new(vec![]),
+            _guard: guard,
+        });
+        streams.insert(key, Arc::downgrade(&shared));
+
+        // The forwarding task only holds a weak reference so that it doesn't
+        // keep the shared stream alive
+        let weak_shared = Arc::downgrade(&shared);
+        graph::spawn(async move {
+            let mut source = source.compat();
+            while let Some(Ok(event)) = source.next().await {
+                match weak_shared.upgrade() {
+                    Some(shared) => shared.forward(event),
+                    None => break,
+                }
+            }
+        });
+
+        Ok(shared.subscribe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graphql_parser::{parse_query, parse_schema};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use crate::schema::ast as sast;
+
+    /// Returns a stream that is fed through the last sender in `sources` and
+    /// counts the calls to `resolve_field_stream`.
+    #[derive(Clone, Default)]
+    struct StreamingResolver {
+        calls: Arc<AtomicUsize>,
+        sources: Arc<Mutex<Vec<mpsc::UnboundedSender<StoreEvent>>>>,
+    }
+
+    impl Resolver for StreamingResolver {
+        fn prefetch(
+            &self,
+            _: &ExecutionContext<Self>,
+            _: &q::SelectionSet,
+        ) -> Result<Option<q::Value>, Vec<QueryExecutionError>> {
+            Ok(None)
+        }
+
+        fn locate_block(&self, _: &BlockConstraint) -> Result<BlockNumber, QueryExecutionError> {
+            Ok(BLOCK_NUMBER_MAX)
+        }
+
+        fn resolve_objects(
+            &self,
+            _: &Option<q::Value>,
+            _: &q::Field,
+            _: &s::Field,
+            _: ObjectOrInterface<'_>,
+            _: &HashMap<&q::Name, q::Value>,
+            _: &BTreeMap<Name, Vec<ObjectType>>,
+            _: BlockNumber,
+            _: u32,
+        ) -> Result<q::Value, QueryExecutionError> {
+            Ok(q::Value::Null)
+        }
+
+        fn resolve_object(
+            &self,
+            _: &Option<q::Value>,
+            _: &q::Field,
+            _: &s::Field,
+            _: ObjectOrInterface<'_>,
+            _: &HashMap<&q::Name, q::Value>,
+            _: &BTreeMap<Name, Vec<ObjectType>>,
+            _: BlockNumber,
+        ) -> Result<q::Value, QueryExecutionError> {
+            Ok(q::Value::Null)
+        }
+
+        fn resolve_field_stream<'a, 'b>(
+            &self,
+            _: &'a s::Document,
+            _: &'a s::ObjectType,
+            _: &'b q::Field,
+            _: &HashMap<&q::Name, q::Value>,
+        ) -> Result<StoreEventStreamBox, QueryExecutionError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let (sender, receiver) = mpsc::unbounded();
+            self.sources.lock().unwrap().push(sender);
+            Ok(StoreEventStream::new(Box::new(
+                receiver.map(Ok::<_, ()>).compat(),
+            )))
+        }
+    }
+
+    const SCHEMA: &str = "
+        type Subscription {
+            musicians: [Musician!]!
+        }
+
+        type Musician {
+            id: ID!
+            name: String!
+        }
+    ";
+
+    fn subscribe(resolver: &SharedStreamResolver<StreamingResolver>) -> StoreEventStreamBox {
+        let schema = parse_schema(SCHEMA).unwrap();
+        let subscription_type = sast::get_root_subscription_type(&schema).unwrap();
+        let query = parse_query("subscription { musicians { name } }").unwrap();
+        let field = match &query.definitions[0] {
+            q::Definition::Operation(q::OperationDefinition::Subscription(subscription)) => {
+                match &subscription.selection_set.items[0] {
+                    q::Selection::Field(field) => field.clone(),
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        };
+        resolver
+            .resolve_field_stream(&schema, subscription_type, &field, &HashMap::new())
+            .unwrap()
+    }
+
+    fn event(entity_id: &str) -> StoreEvent {
+        StoreEvent::new(vec![EntityChange {
+            subgraph_id: SubgraphDeploymentId::new("shared").unwrap(),
+            entity_type: "Musician".to_owned(),
+            entity_id: entity_id.to_owned(),
+            operation: EntityChangeOperation::Set,
+        }])
+    }
+
+    #[tokio::test]
+    async fn identical_subscriptions_share_one_stream() {
+        let inner = StreamingResolver::default();
+        let resolver = SharedStreamResolver::new(inner.clone());
+
+        let mut first = subscribe(&resolver).compat();
+        let mut second = subscribe(&resolver).compat();
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(resolver.open_streams(), 1);
+
+        let sent = event("m1");
+        inner.sources.lock().unwrap()[0]
+            .unbounded_send(sent.clone())
+            .unwrap();
+        assert_eq!(first.next().await, Some(Ok(sent.clone())));
+        assert_eq!(second.next().await, Some(Ok(sent)));
+    }
+
+    #[tokio::test]
+    async fn stream_is_closed_when_last_subscriber_drops() {
+        let inner = StreamingResolver::default();
+        let resolver = SharedStreamResolver::new(inner.clone());
+
+        let first = subscribe(&resolver);
+        let second = subscribe(&resolver);
+
+        drop(first);
+        assert_eq!(resolver.open_streams(), 1);
+        drop(second);
+        assert_eq!(resolver.open_streams(), 0);
+
+        // The forwarding task lets go of the underlying stream once it
+        // notices that it was canceled
+        let source = inner.sources.lock().unwrap()[0].clone();
+        for _ in 0..100 {
+            if source.is_closed() {
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+        assert!(source.is_closed());
+
+        // A new subscription opens a new stream
+        let _third = subscribe(&resolver);
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,252 @@
+use graphql_parser::{query as q, schema as s};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use graph::prelude::*;
+
+use crate::execution::{ExecutionContext, ObjectOrInterface, Resolver};
+use crate::prelude::{Name, ObjectType};
+use crate::query::ext::BlockConstraint;
+
+/// A resolver that records the store operations of a query, and how long
+/// each of them took. Subscriptions attach the trace of the query they run
+/// for an event to its result, serialized as `"extensions": { "trace": [...] }`.
+///
+/// Whether to trace is decided when the resolver is created. A resolver that
+/// doesn't trace passes every call straight through to the wrapped resolver
+/// and never allocates a trace. Clones share their trace; use
+/// `Resolver::for_query` to get a resolver with an empty trace for each query.
+#[derive(Clone)]
+pub struct TracingResolver<R> {
+    inner: R,
+    trace: Option<Arc<Mutex<Vec<QueryTraceEntry>>>>,
+}
+
+impl<R> TracingResolver<R>
+where
+    R: Resolver,
+{
+    pub fn new(inner: R, enabled: bool) -> Self {
+        TracingResolver {
+            inner,
+            trace: if enabled {
+                Some(Arc::new(Mutex::new(vec![])))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Runs `operation` and records it in the trace, if there is one.
+    fn traced<T>(
+        &self,
+        name: &'static str,
+        field: Option<&q::Field>,
+        operation: impl FnOnce() -> T,
+    ) -> T {
+        let trace = match &self.trace {
+            Some(trace) => trace,
+            None => return operation(),
+        };
+
+        let start = Instant::now();
+        let result = operation();
+        let duration_us = start.elapsed().as_micros() as u64;
+        trace.lock().unwrap().push(QueryTraceEntry {
+            operation: name,
+            field: field.map(|field| field.alias.as_ref().unwrap_or(&field.name).clone()),
+            duration_us,
+        });
+        result
+    }
+
+    /// An execution context for the wrapped resolver that is otherwise the
+    /// same as `ctx`.
+    fn inner_context(&self, ctx: &ExecutionContext<Self>) -> ExecutionContext<R> {
+        ExecutionContext {
+            logger: ctx.logger.clone(),
+            query: ctx.query.cheap_clone(),
+            resolver: Arc::new(self.inner.clone()),
+            fields: ctx.fields.clone(),
+            deadline: ctx.deadline,
+            field_timeouts: ctx.field_timeouts.clone(),
+            cancel_handle: ctx.cancel_handle.clone(),
+            max_first: ctx.max_first,
+            block: ctx.block,
+            mode: ctx.mode,
+        }
+    }
+}
+
+impl<R> Resolver for TracingResolver<R>
+where
+    R: Resolver,
+{
+    fn prefetch(
+        &self,
+        ctx: &ExecutionContext<Self>,
+        selection_set: &q::SelectionSet,
+    ) -> Result<Option<q::Value>, Vec<QueryExecutionError>> {
+        self.traced("prefetch", None, || {
+            self.inner.prefetch(&self.inner_context(ctx), selection_set)
+        })
+    }
+
+    fn locate_block(
+        &self,
+        block_constraint: &BlockConstraint,
+    ) -> Result<BlockNumber, QueryExecutionError> {
+        self.traced("locate_block", None, || {
+            self.inner.locate_block(block_constraint)
+        })
+    }
+
+    fn resolve_objects(
+        &self,
+        parent: &Option<q::Value>,
+        field: &q::Field,
+        field_definition: &s::Field,
+        object_type: ObjectOrInterface<'_>,
+        arguments: &HashMap<&q::Name, q::Value>,
+        types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
+        block: BlockNumber,
+        max_first: u32,
+    ) -> Result<q::Value, QueryExecutionError> {
+        self.traced("resolve_objects", Some(field), || {
+            self.inner.resolve_objects(
+                parent,
+                field,
+                field_definition,
+                object_type,
+                arguments,
+                types_for_interface,
+                block,
+                max_first,
+            )
+        })
+    }
+
+    fn resolve_object(
+        &self,
+        parent: &Option<q::Value>,
+        field: &q::Field,
+        field_definition: &s::Field,
+        object_type: ObjectOrInterface<'_>,
+        arguments: &HashMap<&q::Name, q::Value>,
+        types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
+        block: BlockNumber,
+    ) -> Result<q::Value, QueryExecutionError> {
+        self.traced("resolve_object", Some(field), || {
+            self.inner.resolve_object(
+                parent,
+                field,
+                field_definition,
+                object_type,
+                arguments,
+                types_for_interface,
+                block,
+            )
+        })
+    }
+
+    fn resolve_enum_value(
+        &self,
+        field: &q::Field,
+        enum_type: &s::EnumType,
+        value: Option<&q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        self.inner.resolve_enum_value(field, enum_type, value)
+    }
+
+    fn resolve_scalar_value(
+        &self,
+        parent_object_type: &s::ObjectType,
+        parent: &BTreeMap<String, q::Value>,
+        field: &q::Field,
+        scalar_type: &s::ScalarType,
+        value: Option<&q::Value>,
+        argument_values: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        self.inner.resolve_scalar_value(
+            parent_object_type,
+            parent,
+            field,
+            scalar_type,
+            value,
+            argument_values,
+        )
+    }
+
+    fn resolve_enum_values(
+        &self,
+        field: &q::Field,
+        enum_type: &s::EnumType,
+        value: Option<&q::Value>,
+    ) -> Result<q::Value, Vec<QueryExecutionError>> {
+        self.inner.resolve_enum_values(field, enum_type, value)
+    }
+
+    fn resolve_scalar_values(
+        &self,
+        field: &q::Field,
+        scalar_type: &s::ScalarType,
+        value: Option<&q::Value>,
+    ) -> Result<q::Value, Vec<QueryExecutionError>> {
+        self.inner.resolve_scalar_values(field, scalar_type, value)
+    }
+
+    fn resolve_abstract_type<'a>(
+        &self,
+        schema: &'a s::Document,
+        abstract_type: &s::TypeDefinition,
+        object_value: &q::Value,
+    ) -> Option<&'a s::ObjectType> {
+        self.inner
+            .resolve_abstract_type(schema, abstract_type, object_value)
+    }
+
+    fn resolve_field_stream<'a, 'b>(
+        &self,
+        schema: &'a s::Document,
+        object_type: &'a s::ObjectType,
+        field: &'b q::Field,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<StoreEventStreamBox, QueryExecutionError> {
+        self.inner
+            .resolve_field_stream(schema, object_type, field, arguments)
+    }
+
+    fn supports_field_stream(
+        &self,
+        schema: &s::Document,
+        object_type: &s::ObjectType,
+        field: &q::Field,
+    ) -> bool {
+        self.inner.supports_field_stream(schema, object_type, field)
+    }
+
+    fn is_blocking(&self) -> bool {
+        self.inner.is_blocking()
+    }
+
+    fn latest_block(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+    ) -> Result<Option<BlockNumber>, QueryExecutionError> {
+        self.inner.latest_block(subgraph)
+    }
+
+    fn for_query(&self) -> Self {
+        TracingResolver {
+            inner: self.inner.for_query(),
+            trace: self.trace.as_ref().map(|_| Arc::new(Mutex::new(vec![]))),
+        }
+    }
+
+    fn trace(&self) -> Option<Vec<QueryTraceEntry>> {
+        self.trace
+            .as_ref()
+            .map(|trace| trace.lock().unwrap().clone())
+    }
+}
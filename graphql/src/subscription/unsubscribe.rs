@@ -0,0 +1,68 @@
+use std::task::Poll;
+
+use graph::prelude::futures03::channel::oneshot;
+use graph::prelude::*;
+
+/// Lets the transport end a subscription because its client unsubscribed,
+/// e.g. with a `GQL_STOP` message. The subscription then drops the query
+/// that is being executed, if any, and sends `QueryResult::unsubscribed` as
+/// its last result, which the transport can translate into `GQL_COMPLETE`.
+///
+/// Dropping an `Unsubscriber` without calling `unsubscribe` has no effect.
+/// A subscription whose response stream is dropped, or that is canceled
+/// through its `cancel_handle`, e.g. because the connection went away, ends
+/// without that result.
+#[derive(Debug)]
+pub struct Unsubscriber(oneshot::Sender<()>);
+
+/// The receiving end of an `Unsubscriber`, which is passed to the
+/// subscription in its options.
+#[derive(Debug)]
+pub struct UnsubscribeSignal(oneshot::Receiver<()>);
+
+impl Unsubscriber {
+    pub fn new() -> (Self, UnsubscribeSignal) {
+        let (sender, receiver) = oneshot::channel();
+        (Unsubscriber(sender), UnsubscribeSignal(receiver))
+    }
+
+    /// Ends the subscription. Does nothing if it has ended already.
+    pub fn unsubscribe(self) {
+        let _ = self.0.send(());
+    }
+}
+
+/// Ends `response_stream` with a single `QueryResult::unsubscribed` result
+/// as soon as `signal` fires, without waiting for the next result.
+pub(crate) fn unsubscribable_response_stream(
+    mut response_stream: QueryResultStream,
+    signal: UnsubscribeSignal,
+) -> QueryResultStream {
+    let mut signal = Some(signal.0);
+    let mut done = false;
+
+    Box::new(futures03::stream::poll_fn(move |cx| {
+        if done {
+            return Poll::Ready(None);
+        }
+
+        if let Some(receiver) = signal.as_mut() {
+            match receiver.poll_unpin(cx) {
+                Poll::Ready(Ok(())) => {
+                    done = true;
+                    return Poll::Ready(Some(QueryResult::unsubscribed()));
+                }
+                // The `Unsubscriber` was dropped, so the client can't
+                // unsubscribe anymore
+                Poll::Ready(Err(_)) => signal = None,
+                Poll::Pending => {}
+            }
+        }
+
+        let result = response_stream.poll_next_unpin(cx);
+        if let Poll::Ready(None) = result {
+            done = true;
+        }
+        result
+    }))
+}
@@ -51,7 +51,7 @@ fn test_schema(id: SubgraphDeploymentId) -> Schema {
                 band: Band @derivedFrom(field: \"originalSongs\")
             }
 
-            type SongStat @entity {
+            type SongStat @entity @maxFirst(value: 100) {
                 id: ID!
                 song: Song @derivedFrom(field: \"id\")
                 played: Int!
@@ -63,9 +63,13 @@ fn test_schema(id: SubgraphDeploymentId) -> Schema {
 }
 
 fn api_test_schema() -> Schema {
-    let mut schema = test_schema(TEST_SUBGRAPH_ID.clone());
+    api_test_schema_for(TEST_SUBGRAPH_ID.clone())
+}
+
+fn api_test_schema_for(id: SubgraphDeploymentId) -> Schema {
+    let mut schema = test_schema(id.clone());
     schema.document = api_schema(&schema.document).expect("Failed to derive API schema");
-    schema.add_subgraph_id_directives(TEST_SUBGRAPH_ID.clone());
+    schema.add_subgraph_id_directives(id);
     schema
 }
 
@@ -795,15 +799,12 @@ async fn query_complexity_subscriptions() {
         .unwrap(),
         None,
     );
-    let max_complexity = Some(1_010_100);
-    let options = SubscriptionExecutionOptions {
-        logger: logger.clone(),
-        resolver: store_resolver.clone(),
-        timeout: None,
-        max_complexity,
-        max_depth: 100,
-        max_first: std::u32::MAX,
-    };
+    let max_complexity = 1_010_100;
+    let options = SubscriptionExecutionOptions::builder(logger.clone(), store_resolver.clone())
+        .max_complexity(max_complexity)
+        .max_depth(100)
+        .max_first(std::u32::MAX)
+        .build();
 
     // This query is exactly at the maximum complexity.
     // FIXME: Not collecting the stream because that will hang the test.
@@ -833,14 +834,11 @@ async fn query_complexity_subscriptions() {
         None,
     );
 
-    let options = SubscriptionExecutionOptions {
-        logger,
-        resolver: store_resolver,
-        timeout: None,
-        max_complexity,
-        max_depth: 100,
-        max_first: std::u32::MAX,
-    };
+    let options = SubscriptionExecutionOptions::builder(logger, store_resolver)
+        .max_complexity(max_complexity)
+        .max_depth(100)
+        .max_first(std::u32::MAX)
+        .build();
 
     // The extra introspection causes the complexity to go over.
     let result = execute_subscription(Subscription { query }, options);
@@ -1150,6 +1148,31 @@ fn cannot_filter_by_derved_relationship_fields() {
     };
 }
 
+#[test]
+fn max_first_directive_limits_first() {
+    // The global limit in these tests is `u32::MAX`, but `SongStat` is
+    // limited to 100 with `@maxFirst`
+    let result = execute_query_document(
+        graphql_parser::parse_query(
+            "
+        query {
+            songStats(first: 101, orderBy: id) { id }
+        }
+        ",
+        )
+        .expect("invalid test query"),
+    );
+
+    assert!(result.errors.is_some());
+    match &result.errors.unwrap()[0] {
+        QueryError::ExecutionError(QueryExecutionError::RangeArgumentsError(args, limit)) => {
+            assert_eq!(args, &vec!["first"]);
+            assert_eq!(*limit, 100);
+        }
+        e => panic!(format!("expected RangeArgumentsError, got {}", e)),
+    };
+}
+
 #[tokio::test]
 async fn subscription_gets_result_even_without_events() {
     let logger = Logger::root(slog::Discard, o!());
@@ -1168,14 +1191,10 @@ async fn subscription_gets_result_even_without_events() {
         None,
     );
 
-    let options = SubscriptionExecutionOptions {
-        logger: logger.clone(),
-        resolver: store_resolver.clone(),
-        timeout: None,
-        max_complexity: None,
-        max_depth: 100,
-        max_first: std::u32::MAX,
-    };
+    let options = SubscriptionExecutionOptions::builder(logger.clone(), store_resolver.clone())
+        .max_depth(100)
+        .max_first(std::u32::MAX)
+        .build();
 
     // Execute the subscription and expect at least one result to be
     // available in the result stream
@@ -1206,6 +1225,201 @@ async fn subscription_gets_result_even_without_events() {
     );
 }
 
+#[tokio::test]
+async fn subscription_drops_events_for_other_ids() {
+    use test_store::block_store::{FakeBlock, BLOCK_THREE, BLOCK_TWO};
+
+    // The test changes entities, so it gets a deployment of its own. The
+    // blocks are inserted along with the main test deployment
+    let id = SubgraphDeploymentId::new("graphqlTestsQuerySubscriptionIds").unwrap();
+    let _ = &*TEST_SUBGRAPH_ID;
+    if !STORE.is_deployed(&id).unwrap() {
+        insert_test_entities(&**STORE, id.clone());
+    }
+
+    let logger = Logger::root(slog::Discard, o!());
+    let store_resolver = StoreResolver::new(&logger, STORE.clone());
+
+    let query = Query::new(
+        Arc::new(api_test_schema_for(id.clone())),
+        graphql_parser::parse_query(
+            "subscription {
+              musicians(where: { id_in: [\"m1\"] }) {
+                name
+              }
+            }",
+        )
+        .unwrap(),
+        None,
+    );
+
+    let options = SubscriptionExecutionOptions::builder(logger.clone(), store_resolver.clone())
+        .max_depth(100)
+        .max_first(std::u32::MAX)
+        .report_event_details(true)
+        .build();
+    let mut stream = execute_subscription(Subscription { query }, options).unwrap();
+
+    let initial = tokio::time::timeout(Duration::from_secs(3), stream.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(initial.errors.is_none());
+
+    let rename = |entity_id: &str, name: &str, block: &FakeBlock| {
+        let data = Entity::from(vec![
+            ("__typename", Value::from("Musician")),
+            ("id", Value::from(entity_id)),
+            ("name", Value::from(name)),
+        ]);
+        transact_entity_operations(
+            &STORE,
+            id.clone(),
+            EthereumBlockPointer::from((block.block_hash(), block.number)),
+            vec![EntityOperation::Set {
+                key: EntityKey {
+                    subgraph_id: id.clone(),
+                    entity_type: "Musician".to_owned(),
+                    entity_id: entity_id.to_owned(),
+                },
+                data,
+            }],
+        )
+        .unwrap();
+    };
+    rename("m2", "Lisa Marie", &*BLOCK_TWO);
+    rename("m1", "Johnny", &*BLOCK_THREE);
+
+    // Only the change of `m1` reaches the subscription; the change of `m2`
+    // neither triggers a result of its own nor is merged into this one
+    let result = tokio::time::timeout(Duration::from_secs(3), stream.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(result.errors.is_none());
+    assert_eq!(result.event.unwrap().changes, 1);
+    assert_eq!(
+        result.data,
+        Some(object_value(vec![(
+            "musicians",
+            q::Value::List(vec![object_value(vec![(
+                "name",
+                q::Value::String(String::from("Johnny"))
+            )])])
+        )])),
+    );
+    assert!(tokio::time::timeout(Duration::from_secs(2), stream.next())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn subscription_runs_with_own_query_semaphore() {
+    let logger = Logger::root(slog::Discard, o!());
+    let store_resolver = StoreResolver::new(&logger, STORE.clone());
+
+    let query = Query::new(
+        Arc::new(api_test_schema()),
+        graphql_parser::parse_query(
+            "subscription {
+              musicians(orderBy: id, first: 1) {
+                name
+              }
+            }",
+        )
+        .unwrap(),
+        None,
+    );
+
+    let options = SubscriptionExecutionOptions::builder(logger.clone(), store_resolver.clone())
+        .max_depth(100)
+        .max_first(std::u32::MAX)
+        .query_permits(Arc::new(QueryPermits::new(1)))
+        .build();
+
+    // A subscription with a single permit of its own must still be able
+    // to execute its initial query
+    let stream = execute_subscription(Subscription { query }, options).unwrap();
+    let results: Vec<_> = stream
+        .take(1)
+        .collect()
+        .map(Result::<_, ()>::Ok)
+        .compat()
+        .timeout(Duration::from_secs(3))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert!(results[0].errors.is_none());
+    assert_eq!(
+        results[0].data,
+        Some(object_value(vec![(
+            "musicians",
+            q::Value::List(vec![object_value(vec![(
+                "name",
+                q::Value::String(String::from("John"))
+            )])])
+        )])),
+    );
+}
+
+#[tokio::test]
+async fn subscription_queries_wait_for_a_held_query_permit() {
+    let logger = Logger::root(slog::Discard, o!());
+    let store_resolver = StoreResolver::new(&logger, STORE.clone());
+    let permits = Arc::new(QueryPermits::new(1));
+
+    let query = Query::new(
+        Arc::new(api_test_schema()),
+        graphql_parser::parse_query(
+            "subscription {
+              musicians(orderBy: id, first: 1) {
+                name
+              }
+            }",
+        )
+        .unwrap(),
+        None,
+    );
+
+    let options = SubscriptionExecutionOptions::builder(logger.clone(), store_resolver.clone())
+        .max_depth(100)
+        .max_first(std::u32::MAX)
+        .query_permits(permits.clone())
+        .build();
+
+    // While the only permit is held, e.g. by the query of another
+    // subscription with the same permits, the initial query has to wait
+    let held = permits.acquire(SubscriptionPriority::Normal).await;
+    assert_eq!(permits.available_permits(), 0);
+    let mut stream = execute_subscription(Subscription { query }, options).unwrap();
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), stream.next())
+            .await
+            .is_err()
+    );
+
+    // Once the permit is given back, the query runs
+    drop(held);
+    let result = tokio::time::timeout(Duration::from_secs(3), stream.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(result.errors.is_none());
+    assert_eq!(
+        result.data,
+        Some(object_value(vec![(
+            "musicians",
+            q::Value::List(vec![object_value(vec![(
+                "name",
+                q::Value::String(String::from("John"))
+            )])])
+        )])),
+    );
+    assert_eq!(permits.available_permits(), 1);
+}
+
 #[test]
 fn can_use_nested_filter() {
     let result = execute_query_document(
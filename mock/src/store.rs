@@ -3,7 +3,7 @@ use mockall::*;
 use std::collections::BTreeMap;
 
 use graph::components::store::*;
-use graph::components::subgraph::ProofOfIndexingDigest;
+use graph::components::subgraph::{ProofOfIndexingDigest, ProofOfIndexingHealth};
 use graph::data::subgraph::schema::*;
 use graph::prelude::*;
 use graph_graphql::prelude::api_schema;
@@ -92,6 +92,13 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn check_proof_of_indexing<'a>(
+        &'a self,
+        _subgraph_id: &'a SubgraphDeploymentId,
+    ) -> DynTryFuture<'a, ProofOfIndexingHealth> {
+        unimplemented!()
+    }
+
     fn find(&self, _query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
         unimplemented!()
     }
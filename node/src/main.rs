@@ -18,7 +18,7 @@ use graph::util::security::SafeDisplay;
 use graph_chain_arweave::adapter::ArweaveAdapter;
 use graph_chain_ethereum::{network_indexer, BlockIngestor, BlockStreamBuilder, Transport};
 use graph_core::{
-    three_box::ThreeBoxAdapter, LinkResolver, MetricsRegistry,
+    three_box::ThreeBoxAdapter, DataSourceFileCache, LinkResolver, LruFileCache, MetricsRegistry,
     SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
     SubgraphRegistrar as IpfsSubgraphRegistrar,
 };
@@ -536,6 +536,7 @@ async fn main() {
             let graphql_runner = Arc::new(graph_core::GraphQlRunner::new(
                 &logger,
                 generic_store.clone(),
+                graphql_metrics_registry.clone(),
             ));
             let mut graphql_server = GraphQLQueryServer::new(
                 &logger_factory,
@@ -655,7 +656,11 @@ async fn main() {
                 link_resolver.clone(),
                 generic_store.clone(),
                 graphql_runner.clone(),
-            );
+            )
+            .with_file_cache(Arc::new(DataSourceFileCache::new(
+                Arc::new(LruFileCache::default()),
+                metrics_registry.clone(),
+            )));
 
             // Forward subgraph events from the subgraph provider to the subgraph instance manager
             graph::spawn(
@@ -22,10 +22,14 @@ use graph::prelude::{
 use graph::util;
 use web3::types::{Log, Transaction};
 
-use crate::host_exports::HostExports;
+use crate::host_exports::{HostExportOverrides, HostExports};
 use crate::mapping::{MappingContext, MappingRequest, MappingTrigger};
 
 pub(crate) const TIMEOUT_ENV_VAR: &str = "GRAPH_MAPPING_HANDLER_TIMEOUT";
+pub(crate) const NON_DETERMINISTIC_TIMEOUT_ENV_VAR: &str =
+    "GRAPH_MAPPING_HANDLER_TIMEOUT_NON_DETERMINISTIC";
+pub(crate) const ERROR_BUDGET_ENV_VAR: &str = "GRAPH_MAPPING_HANDLER_ERROR_BUDGET";
+pub(crate) const ERROR_BUDGET_WINDOW_ENV_VAR: &str = "GRAPH_MAPPING_HANDLER_ERROR_BUDGET_WINDOW";
 
 struct RuntimeHostConfig {
     subgraph_id: SubgraphDeploymentId,
@@ -35,6 +39,10 @@ struct RuntimeHostConfig {
     data_source_context: Option<DataSourceContext>,
     contract: Source,
     templates: Arc<Vec<DataSourceTemplate>>,
+    handler_timeout: Option<Duration>,
+    handler_timeout_is_deterministic: bool,
+    handler_error_budget: Option<HandlerErrorBudget>,
+    host_export_overrides: HostExportOverrides,
 }
 
 pub struct RuntimeHostBuilder<S> {
@@ -43,6 +51,10 @@ pub struct RuntimeHostBuilder<S> {
     stores: HashMap<String, Arc<S>>,
     arweave_adapter: Arc<dyn ArweaveAdapter>,
     three_box_adapter: Arc<dyn ThreeBoxAdapter>,
+    handler_timeout: Option<Duration>,
+    handler_timeout_is_deterministic: bool,
+    handler_error_budget: Option<HandlerErrorBudget>,
+    host_export_overrides: HostExportOverrides,
 }
 
 impl<S> Clone for RuntimeHostBuilder<S>
@@ -56,6 +68,10 @@ where
             stores: self.stores.clone(),
             arweave_adapter: self.arweave_adapter.cheap_clone(),
             three_box_adapter: self.three_box_adapter.cheap_clone(),
+            handler_timeout: self.handler_timeout,
+            handler_timeout_is_deterministic: self.handler_timeout_is_deterministic,
+            handler_error_budget: self.handler_error_budget,
+            host_export_overrides: self.host_export_overrides.clone(),
         }
     }
 }
@@ -77,8 +93,59 @@ where
             stores,
             arweave_adapter,
             three_box_adapter,
+            handler_timeout: std::env::var(TIMEOUT_ENV_VAR)
+                .ok()
+                .and_then(|s| u64::from_str(&s).ok())
+                .map(Duration::from_secs),
+            handler_timeout_is_deterministic: std::env::var(NON_DETERMINISTIC_TIMEOUT_ENV_VAR)
+                .map_or(true, |s| s != "true"),
+            handler_error_budget: std::env::var(ERROR_BUDGET_ENV_VAR).ok().map(|s| {
+                HandlerErrorBudget {
+                    failures: usize::from_str(&s)
+                        .expect("invalid GRAPH_MAPPING_HANDLER_ERROR_BUDGET"),
+                    window: Duration::from_secs(
+                        std::env::var(ERROR_BUDGET_WINDOW_ENV_VAR)
+                            .unwrap_or("3600".into())
+                            .parse::<u64>()
+                            .expect("invalid GRAPH_MAPPING_HANDLER_ERROR_BUDGET_WINDOW"),
+                    ),
+                }
+            }),
+            host_export_overrides: HostExportOverrides::default(),
         }
     }
+
+    /// Limits how long each invocation of a mapping handler may run. Defaults
+    /// to `GRAPH_MAPPING_HANDLER_TIMEOUT`, in seconds, or no limit.
+    pub fn with_handler_timeout(mut self, handler_timeout: Option<Duration>) -> Self {
+        self.handler_timeout = handler_timeout;
+        self
+    }
+
+    /// Whether a handler that times out fails the subgraph permanently, which
+    /// is the default, or whether the subgraph is restarted as it would be
+    /// after any other non-deterministic error.
+    pub fn with_deterministic_handler_timeout(mut self, deterministic: bool) -> Self {
+        self.handler_timeout_is_deterministic = deterministic;
+        self
+    }
+
+    /// Limits how often each handler may fail non-deterministically before
+    /// the subgraph is marked as failed. Defaults to
+    /// `GRAPH_MAPPING_HANDLER_ERROR_BUDGET` failures within
+    /// `GRAPH_MAPPING_HANDLER_ERROR_BUDGET_WINDOW`, in seconds, or no limit.
+    pub fn with_handler_error_budget(mut self, budget: Option<HandlerErrorBudget>) -> Self {
+        self.handler_error_budget = budget;
+        self
+    }
+
+    /// Replaces host functions of all hosts with stubs, for testing; see
+    /// `HostExportOverrides` for which functions can be replaced. Without
+    /// overrides, hosts use the standard host functions.
+    pub fn with_host_export_overrides(mut self, overrides: HostExportOverrides) -> Self {
+        self.host_export_overrides = overrides;
+        self
+    }
 }
 
 impl<S> RuntimeHostBuilderTrait for RuntimeHostBuilder<S>
@@ -146,6 +213,10 @@ where
                 data_source_context: data_source.context,
                 contract: data_source.source,
                 templates,
+                handler_timeout: self.handler_timeout,
+                handler_timeout_is_deterministic: self.handler_timeout_is_deterministic,
+                handler_error_budget: self.handler_error_budget,
+                host_export_overrides: self.host_export_overrides.clone(),
             },
             mapping_request_sender,
             metrics,
@@ -166,6 +237,7 @@ pub struct RuntimeHost {
     mapping_request_sender: Sender<MappingRequest>,
     host_exports: Arc<HostExports>,
     metrics: Arc<HostMetrics>,
+    handler_error_budget: Option<HandlerErrorBudget>,
 }
 
 impl RuntimeHost {
@@ -204,10 +276,6 @@ impl RuntimeHost {
             .clone();
 
         let data_source_name = config.data_source_name;
-        let timeout = std::env::var(TIMEOUT_ENV_VAR)
-            .ok()
-            .and_then(|s| u64::from_str(&s).ok())
-            .map(Duration::from_secs);
 
         // Create new instance of externally hosted functions invoker. The `Arc` is simply to avoid
         // implementing `Clone` for `HostExports`.
@@ -224,9 +292,11 @@ impl RuntimeHost {
             link_resolver,
             store,
             call_cache,
-            timeout,
+            config.handler_timeout,
+            config.handler_timeout_is_deterministic,
             arweave_adapter,
             three_box_adapter,
+            config.host_export_overrides,
         ));
 
         Ok(RuntimeHost {
@@ -239,6 +309,7 @@ impl RuntimeHost {
             mapping_request_sender,
             host_exports,
             metrics,
+            handler_error_budget: config.handler_error_budget,
         })
     }
 
@@ -424,7 +495,13 @@ impl RuntimeHost {
             .map_err(|_| format_err!("Mapping terminated before handling trigger"))?;
 
         let elapsed = start_time.elapsed();
-        metrics.observe_handler_execution_time(elapsed.as_secs_f64(), handler);
+        metrics.observe_handler_invocation(handler, &self.data_source_name);
+        metrics.observe_handler_execution_time(
+            elapsed.as_secs_f64(),
+            handler,
+            &self.data_source_name,
+        );
+        let result = result.map_err(|e| self.check_error_budget(handler, e));
 
         info!(
             logger, "Done processing Ethereum trigger";
@@ -446,6 +523,42 @@ impl RuntimeHost {
 
         result
     }
+
+    /// Counts handler failures, and turns a non-deterministic failure into a
+    /// `HandlerErrorBudgetExceeded` error if the handler has failed too often.
+    fn check_error_budget(&self, handler: &str, e: Error) -> Error {
+        if e.downcast_ref::<HandlerTimeoutError>().is_some() {
+            self.metrics
+                .observe_handler_timeout(handler, &self.data_source_name);
+        }
+
+        // Deterministic failures fail the subgraph anyway
+        if is_deterministic_handler_error(&e) {
+            return e;
+        }
+        self.metrics
+            .observe_handler_failure(handler, &self.data_source_name);
+        let budget = match self.handler_error_budget {
+            Some(budget) => budget,
+            None => return e,
+        };
+        let failures = self.metrics.record_recent_handler_failure(
+            handler,
+            &self.data_source_name,
+            budget.window,
+        );
+        if failures <= budget.failures {
+            return e;
+        }
+        HandlerErrorBudgetExceeded {
+            handler: handler.to_owned(),
+            data_source: self.data_source_name.clone(),
+            failures,
+            window: budget.window,
+            error: e.to_string(),
+        }
+        .into()
+    }
 }
 
 #[async_trait]
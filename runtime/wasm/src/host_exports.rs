@@ -24,7 +24,11 @@ pub(crate) trait ExportError: fmt::Debug + fmt::Display + Send + Sync + 'static
 
 impl<E> ExportError for E where E: fmt::Debug + fmt::Display + Send + Sync + 'static {}
 
-/// Error raised in host functions.
+/// Error raised in host functions. Host functions that can fail for reasons
+/// that have nothing to do with the mapping, e.g. because the Ethereum node
+/// or the store is unavailable, raise a `HostExportError<Error>` that wraps
+/// a `NonDeterministicHostError` for those failures, so that the handler
+/// fails with a `NonDeterministicHostError` as well.
 #[derive(Debug)]
 pub(crate) struct HostExportError<E>(pub(crate) E);
 
@@ -40,10 +44,66 @@ impl From<graph::prelude::Error> for HostExportError<String> {
     }
 }
 
+type EthereumCallOverride =
+    dyn Fn(&EthereumContractCall) -> Result<Vec<Token>, EthereumContractCallError> + Send + Sync;
+type IpfsCatOverride = dyn Fn(&str) -> Result<Vec<u8>, Error> + Send + Sync;
+type EnsNameByHashOverride = dyn Fn(&str) -> Result<Option<String>, Error> + Send + Sync;
+
+/// Replaces some host functions with stubs, so that tests can check how
+/// mappings respond to controlled host results. The functions that can be
+/// replaced are:
+///
+/// - `ethereum.call`, which receives the call after its ABI was resolved;
+///   returning `EthereumContractCallError::Revert` makes the call revert
+/// - `ipfs.cat`, which receives the IPFS link
+/// - `ens.nameByHash`, which receives the hash
+///
+/// All other host functions, and any function without a stub, behave as
+/// they do in production.
+#[derive(Clone, Default)]
+pub struct HostExportOverrides {
+    ethereum_call: Option<Arc<EthereumCallOverride>>,
+    ipfs_cat: Option<Arc<IpfsCatOverride>>,
+    ens_name_by_hash: Option<Arc<EnsNameByHashOverride>>,
+}
+
+impl HostExportOverrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ethereum_call(
+        mut self,
+        ethereum_call: impl Fn(&EthereumContractCall) -> Result<Vec<Token>, EthereumContractCallError>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.ethereum_call = Some(Arc::new(ethereum_call));
+        self
+    }
+
+    pub fn ipfs_cat(
+        mut self,
+        ipfs_cat: impl Fn(&str) -> Result<Vec<u8>, Error> + Send + Sync + 'static,
+    ) -> Self {
+        self.ipfs_cat = Some(Arc::new(ipfs_cat));
+        self
+    }
+
+    pub fn ens_name_by_hash(
+        mut self,
+        ens_name_by_hash: impl Fn(&str) -> Result<Option<String>, Error> + Send + Sync + 'static,
+    ) -> Self {
+        self.ens_name_by_hash = Some(Arc::new(ens_name_by_hash));
+        self
+    }
+}
+
 pub(crate) struct HostExports {
     subgraph_id: SubgraphDeploymentId,
     pub(crate) api_version: Version,
-    data_source_name: String,
+    pub(crate) data_source_name: String,
     data_source_address: Option<Address>,
     data_source_network: String,
     data_source_context: Option<DataSourceContext>,
@@ -59,8 +119,10 @@ pub(crate) struct HostExports {
     call_cache: Arc<dyn EthereumCallCache>,
     store: Arc<dyn crate::RuntimeStore>,
     handler_timeout: Option<Duration>,
+    handler_timeout_is_deterministic: bool,
     arweave_adapter: Arc<dyn ArweaveAdapter>,
     three_box_adapter: Arc<dyn ThreeBoxAdapter>,
+    overrides: HostExportOverrides,
 }
 
 // Not meant to be useful, only to allow deriving.
@@ -85,8 +147,10 @@ impl HostExports {
         store: Arc<dyn crate::RuntimeStore>,
         call_cache: Arc<dyn EthereumCallCache>,
         handler_timeout: Option<Duration>,
+        handler_timeout_is_deterministic: bool,
         arweave_adapter: Arc<dyn ArweaveAdapter>,
         three_box_adapter: Arc<dyn ThreeBoxAdapter>,
+        overrides: HostExportOverrides,
     ) -> Self {
         let causality_region = format!("ethereum/{}", data_source_network);
 
@@ -105,8 +169,10 @@ impl HostExports {
             call_cache,
             store,
             handler_timeout,
+            handler_timeout_is_deterministic,
             arweave_adapter,
             three_box_adapter,
+            overrides,
         }
     }
 
@@ -215,7 +281,7 @@ impl HostExports {
         state: &mut BlockState,
         entity_type: String,
         entity_id: String,
-    ) -> Result<Option<Entity>, HostExportError<impl ExportError>> {
+    ) -> Result<Option<Entity>, HostExportError<Error>> {
         let store_key = EntityKey {
             subgraph_id: self.subgraph_id.clone(),
             entity_type: entity_type.clone(),
@@ -225,7 +291,14 @@ impl HostExports {
         let result = state
             .entity_cache
             .get(self.store.as_ref(), &store_key)
-            .map_err(HostExportError)
+            .map_err(|e| {
+                HostExportError(
+                    NonDeterministicHostError {
+                        message: format!("Failed to load entity from the store: {}", e),
+                    }
+                    .into(),
+                )
+            })
             .map(|ok| ok.to_owned());
 
         result
@@ -237,7 +310,7 @@ impl HostExports {
         logger: &Logger,
         block: &LightEthereumBlock,
         unresolved_call: UnresolvedContractCall,
-    ) -> Result<Option<Vec<Token>>, HostExportError<impl ExportError>> {
+    ) -> Result<Option<Vec<Token>>, HostExportError<Error>> {
         let start_time = Instant::now();
 
         // Obtain the path to the contract ABI
@@ -246,7 +319,7 @@ impl HostExports {
             .iter()
             .find(|abi| abi.name == unresolved_call.contract_name)
             .ok_or_else(|| {
-                HostExportError(format!(
+                HostExportError(format_err!(
                     "Could not find ABI for contract \"{}\", try adding it to the 'abis' section \
                      of the subgraph manifest",
                     unresolved_call.contract_name
@@ -262,9 +335,11 @@ impl HostExports {
             None => contract
                 .function(unresolved_call.function_name.as_str())
                 .map_err(|e| {
-                    HostExportError(format!(
+                    HostExportError(format_err!(
                         "Unknown function \"{}::{}\" called from WASM runtime: {}",
-                        unresolved_call.contract_name, unresolved_call.function_name, e
+                        unresolved_call.contract_name,
+                        unresolved_call.function_name,
+                        e
                     ))
                 })?,
 
@@ -274,15 +349,17 @@ impl HostExports {
             Some(ref function_signature) => contract
                 .functions_by_name(unresolved_call.function_name.as_str())
                 .map_err(|e| {
-                    HostExportError(format!(
+                    HostExportError(format_err!(
                         "Unknown function \"{}::{}\" called from WASM runtime: {}",
-                        unresolved_call.contract_name, unresolved_call.function_name, e
+                        unresolved_call.contract_name,
+                        unresolved_call.function_name,
+                        e
                     ))
                 })?
                 .iter()
                 .find(|f| function_signature == &f.signature())
                 .ok_or_else(|| {
-                    HostExportError(format!(
+                    HostExportError(format_err!(
                         "Unknown function \"{}::{}\" with signature `{}` \
                          called from WASM runtime",
                         unresolved_call.contract_name,
@@ -299,22 +376,38 @@ impl HostExports {
             args: unresolved_call.function_args.clone(),
         };
 
-        // Run Ethereum call in tokio runtime
-        let eth_adapter = self.ethereum_adapter.clone();
-        let logger1 = logger.clone();
-        let call_cache = self.call_cache.clone();
-        let result = match block_on(future::lazy(move || {
-            eth_adapter.contract_call(&logger1, call, call_cache)
-        })) {
+        let call_result = match &self.overrides.ethereum_call {
+            Some(ethereum_call) => ethereum_call(&call),
+            None => {
+                // Run Ethereum call in tokio runtime
+                let eth_adapter = self.ethereum_adapter.clone();
+                let logger1 = logger.clone();
+                let call_cache = self.call_cache.clone();
+                block_on(future::lazy(move || {
+                    eth_adapter.contract_call(&logger1, call, call_cache)
+                }))
+            }
+        };
+        let result = match call_result {
             Ok(tokens) => Ok(Some(tokens)),
             Err(EthereumContractCallError::Revert(reason)) => {
                 info!(logger, "Contract call reverted"; "reason" => reason);
                 Ok(None)
             }
-            Err(e) => Err(HostExportError(format!(
-                "Failed to call function \"{}\" of contract \"{}\": {}",
-                unresolved_call.function_name, unresolved_call.contract_name, e
-            ))),
+            Err(e) => {
+                let message = format!(
+                    "Failed to call function \"{}\" of contract \"{}\": {}",
+                    unresolved_call.function_name, unresolved_call.contract_name, e
+                );
+                Err(HostExportError(match e {
+                    // The Ethereum node failed, not the call
+                    EthereumContractCallError::Web3Error(_)
+                    | EthereumContractCallError::Timeout => {
+                        NonDeterministicHostError { message }.into()
+                    }
+                    _ => format_err!("{}", message),
+                }))
+            }
         };
 
         debug!(logger, "Contract call finished";
@@ -385,6 +478,9 @@ impl HostExports {
         logger: &Logger,
         link: String,
     ) -> Result<Vec<u8>, HostExportError<impl ExportError>> {
+        if let Some(ipfs_cat) = &self.overrides.ipfs_cat {
+            return ipfs_cat(&link).map_err(HostExportError);
+        }
         block_on03(
             self.link_resolver
                 .cat(logger, &Link { link })
@@ -406,10 +502,10 @@ impl HostExports {
         callback: &str,
         user_data: store::Value,
         flags: Vec<String>,
-    ) -> Result<Vec<BlockState>, HostExportError<impl ExportError>> {
+    ) -> Result<Vec<BlockState>, HostExportError<Error>> {
         const JSON_FLAG: &str = "json";
         if !flags.contains(&JSON_FLAG.to_string()) {
-            return Err(HostExportError(format!("Flags must contain 'json'")));
+            return Err(HostExportError(format_err!("Flags must contain 'json'")));
         }
 
         let host_metrics = module.host_metrics.clone();
@@ -426,14 +522,22 @@ impl HostExports {
         let mut last_log = start;
         let logger = ctx.logger.new(o!("ipfs_map" => link.clone()));
 
+        // Failing to fetch the file has nothing to do with the mapping
+        let fetch_error = |e: Error| -> Error {
+            NonDeterministicHostError {
+                message: e.to_string(),
+            }
+            .into()
+        };
         let result = block_on03(async move {
             let mut stream: JsonValueStream = self
                 .link_resolver
                 .json_stream(&logger, &Link { link })
-                .await?;
+                .await
+                .map_err(fetch_error)?;
             let mut v = Vec::new();
             while let Some(sv) = stream.next().await {
-                let sv = sv?;
+                let sv = sv.map_err(fetch_error)?;
                 let module = WasmiModule::from_valid_module_with_ctx(
                     valid_module.clone(),
                     ctx.clone_with_empty_block_state(),
@@ -454,7 +558,14 @@ impl HostExports {
             }
             Ok(v)
         });
-        result.map_err(move |e: Error| HostExportError(format!("{}: {}", errmsg, e.to_string())))
+        result.map_err(move |e: Error| {
+            let message = format!("{}: {}", errmsg, e.to_string());
+            HostExportError(if is_deterministic_handler_error(&e) {
+                format_err!("{}", message)
+            } else {
+                NonDeterministicHostError { message }.into()
+            })
+        })
     }
 
     /// Expects a decimal string.
@@ -545,6 +656,26 @@ impl HostExports {
         Ok(())
     }
 
+    /// Returns the error to report for a handler that started at `start_time`
+    /// and failed, if the failure was caused by the handler running out of
+    /// time.
+    pub(crate) fn handler_timeout_error(
+        &self,
+        handler: &str,
+        block: &LightEthereumBlock,
+        start_time: Instant,
+    ) -> Option<HandlerTimeoutError> {
+        self.handler_timeout
+            .filter(|timeout| start_time.elapsed() > *timeout)
+            .map(|timeout| HandlerTimeoutError {
+                handler: handler.to_owned(),
+                data_source: self.data_source_name.clone(),
+                block: EthereumBlockPointer::from(block),
+                timeout,
+                deterministic: self.handler_timeout_is_deterministic,
+            })
+    }
+
     /// Useful for IPFS hashes stored as bytes
     pub(crate) fn bytes_to_base58(&self, bytes: Vec<u8>) -> String {
         ::bs58::encode(&bytes).into_string()
@@ -645,7 +776,12 @@ impl HostExports {
         &self,
         hash: &str,
     ) -> Result<Option<String>, HostExportError<impl ExportError>> {
-        self.store.find_ens_name(hash).map_err(HostExportError)
+        if let Some(ens_name_by_hash) = &self.overrides.ens_name_by_hash {
+            return ens_name_by_hash(hash).map_err(|e| HostExportError(e.to_string()));
+        }
+        self.store
+            .find_ens_name(hash)
+            .map_err(|e| HostExportError(e.to_string()))
     }
 
     pub(crate) fn log_log(&self, logger: &Logger, level: slog::Level, msg: String) {
@@ -13,6 +13,7 @@ mod module;
 
 /// Runtime-agnostic implementation of exports to WASM.
 mod host_exports;
+pub use host_exports::HostExportOverrides;
 
 use graph::prelude::web3::types::Address;
 use graph::prelude::{Store, SubgraphDeploymentStore};
@@ -98,6 +98,33 @@ fn format_wasmi_error(e: Error) -> String {
     }
 }
 
+/// Returns the `NonDeterministicHostError` that a host function trapped
+/// with, if any, see `HostExportError`.
+fn non_deterministic_host_error(e: &Error) -> Option<&NonDeterministicHostError> {
+    match e {
+        Error::Trap(trap) => match trap.kind() {
+            wasmi::TrapKind::Host(host_error) => host_error
+                .downcast_ref::<HostExportError<FailureError>>()
+                .and_then(|e| e.0.downcast_ref::<NonDeterministicHostError>()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The error for a handler that failed with `e`, described by `message`.
+/// Handlers that failed because a host function failed non-deterministically
+/// fail with a `NonDeterministicHostError` as well.
+fn handler_error(message: String, e: Error) -> FailureError {
+    let deterministic = non_deterministic_host_error(&e).is_none();
+    let message = format!("{}: {}", message, format_wasmi_error(e));
+    if deterministic {
+        format_err!("{}", message)
+    } else {
+        NonDeterministicHostError { message }.into()
+    }
+}
+
 /// A WASM module based on wasmi that powers a subgraph runtime.
 pub(crate) struct WasmiModule {
     pub module: ModuleRef,
@@ -122,6 +149,9 @@ pub(crate) struct WasmiModule {
 
     // How many times we've passed a timeout checkpoint during execution.
     timeout_checkpoint_count: u64,
+
+    // Gas used by the current handler, as metered by the injected gas counter.
+    gas_used: u64,
 }
 
 impl WasmiModule {
@@ -168,6 +198,7 @@ impl WasmiModule {
             arena_free_size: 0,
             arena_start_ptr: 0,
             timeout_checkpoint_count: 0,
+            gas_used: 0,
         };
 
         this.module = module
@@ -186,6 +217,7 @@ impl WasmiModule {
         params: Vec<LogParam>,
     ) -> Result<BlockState, FailureError> {
         self.start_time = Instant::now();
+        self.gas_used = 0;
 
         let block = self.ctx.block.clone();
 
@@ -225,15 +257,22 @@ impl WasmiModule {
             .module
             .clone()
             .invoke_export(handler_name, &[event], &mut self);
+        let timeout_error = self.handler_timeout_error(handler_name);
+        self.observe_handler_gas_used(handler_name);
 
         // Return either the output state (collected entity operations etc.) or an error
-        result.map(|_| self.ctx.state).map_err(|e| {
-            format_err!(
-                "Failed to handle Ethereum event with handler \"{}\": {}",
-                handler_name,
-                format_wasmi_error(e)
-            )
-        })
+        result
+            .map(|_| self.ctx.state)
+            .map_err(|e| match timeout_error {
+                Some(timeout_error) => timeout_error.into(),
+                None => handler_error(
+                    format!(
+                        "Failed to handle Ethereum event with handler \"{}\"",
+                        handler_name
+                    ),
+                    e,
+                ),
+            })
     }
 
     pub(crate) fn handle_json_callback(
@@ -253,10 +292,12 @@ impl WasmiModule {
 
         // Return either the collected entity operations or an error
         result.map(|_| self.ctx.state).map_err(|e| {
-            format_err!(
-                "Failed to handle callback with handler \"{}\": {}",
-                handler_name,
-                format_wasmi_error(e),
+            handler_error(
+                format!(
+                    "Failed to handle callback with handler \"{}\"",
+                    handler_name
+                ),
+                e,
             )
         })
     }
@@ -270,6 +311,7 @@ impl WasmiModule {
         outputs: Vec<LogParam>,
     ) -> Result<BlockState, FailureError> {
         self.start_time = Instant::now();
+        self.gas_used = 0;
 
         let call = EthereumCallData {
             to: call.to,
@@ -289,14 +331,21 @@ impl WasmiModule {
             .module
             .clone()
             .invoke_export(handler_name, &[arg], &mut self);
+        let timeout_error = self.handler_timeout_error(handler_name);
+        self.observe_handler_gas_used(handler_name);
 
-        result.map(|_| self.ctx.state).map_err(|err| {
-            format_err!(
-                "Failed to handle Ethereum call with handler \"{}\": {}",
-                handler_name,
-                format_wasmi_error(err),
-            )
-        })
+        result
+            .map(|_| self.ctx.state)
+            .map_err(|err| match timeout_error {
+                Some(timeout_error) => timeout_error.into(),
+                None => handler_error(
+                    format!(
+                        "Failed to handle Ethereum call with handler \"{}\"",
+                        handler_name
+                    ),
+                    err,
+                ),
+            })
     }
 
     pub(crate) fn handle_ethereum_block(
@@ -304,6 +353,7 @@ impl WasmiModule {
         handler_name: &str,
     ) -> Result<BlockState, FailureError> {
         self.start_time = Instant::now();
+        self.gas_used = 0;
 
         // Prepare an EthereumBlock for the WASM runtime
         let arg = EthereumBlockData::from(self.ctx.block.as_ref());
@@ -313,14 +363,37 @@ impl WasmiModule {
             &[RuntimeValue::from(self.asc_new(&arg))],
             &mut self,
         );
+        let timeout_error = self.handler_timeout_error(handler_name);
+        self.observe_handler_gas_used(handler_name);
 
-        result.map(|_| self.ctx.state).map_err(|err| {
-            format_err!(
-                "Failed to handle Ethereum block with handler \"{}\": {}",
-                handler_name,
-                format_wasmi_error(err)
-            )
-        })
+        result
+            .map(|_| self.ctx.state)
+            .map_err(|err| match timeout_error {
+                Some(timeout_error) => timeout_error.into(),
+                None => handler_error(
+                    format!(
+                        "Failed to handle Ethereum block with handler \"{}\"",
+                        handler_name
+                    ),
+                    err,
+                ),
+            })
+    }
+
+    fn observe_handler_gas_used(&self, handler_name: &str) {
+        self.host_metrics.observe_handler_gas_used(
+            self.gas_used,
+            handler_name,
+            &self.ctx.host_exports.data_source_name,
+        );
+    }
+
+    /// Returns a `HandlerTimeoutError` if the current handler has run for
+    /// longer than the handler timeout.
+    pub(crate) fn handler_timeout_error(&self, handler_name: &str) -> Option<HandlerTimeoutError> {
+        self.ctx
+            .host_exports
+            .handler_timeout_error(handler_name, &self.ctx.block, self.start_time)
     }
 }
 
@@ -365,7 +438,9 @@ impl<E> HostError for HostExportError<E> where E: fmt::Debug + fmt::Display + Se
 
 // Implementation of externals.
 impl WasmiModule {
-    fn gas(&mut self) -> Result<Option<RuntimeValue>, Trap> {
+    fn gas(&mut self, gas: u32) -> Result<Option<RuntimeValue>, Trap> {
+        self.gas_used += gas as u64;
+
         // This function is called so often that the overhead of calling `Instant::now()` every
         // time would be significant, so we spread out the checks.
         if self.timeout_checkpoint_count % 100 == 0 {
@@ -1010,7 +1085,7 @@ impl Externals for WasmiModule {
     ) -> Result<Option<RuntimeValue>, Trap> {
         // This function is hot, so avoid the cost of registering metrics.
         if index == GAS_FUNC_INDEX {
-            return self.gas();
+            return self.gas(args.nth_checked(0)?);
         }
 
         // Start a catch-all section for exports that don't have their own section.
@@ -6,7 +6,7 @@ use std::io::Cursor;
 use std::str::FromStr;
 use wasmi::nan_preserving_float::F64;
 
-use crate::host_exports::HostExports;
+use crate::host_exports::{HostExportOverrides, HostExports};
 use graph::components::store::*;
 use graph::data::store::scalar;
 use graph::data::subgraph::*;
@@ -30,6 +30,21 @@ fn test_valid_module_and_store(
 ) -> (
     WasmiModule,
     Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
+) {
+    test_valid_module_and_store_with_overrides(
+        subgraph_id,
+        data_source,
+        HostExportOverrides::default(),
+    )
+}
+
+fn test_valid_module_and_store_with_overrides(
+    subgraph_id: &str,
+    data_source: DataSource,
+    overrides: HostExportOverrides,
+) -> (
+    WasmiModule,
+    Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
 ) {
     let store = STORE.clone();
     let metrics_registry = Arc::new(MockMetricsRegistry::new());
@@ -60,7 +75,7 @@ fn test_valid_module_and_store(
 
     let module = WasmiModule::from_valid_module_with_ctx(
         Arc::new(ValidModule::new(data_source.mapping.runtime.as_ref().clone()).unwrap()),
-        mock_context(deployment_id, data_source, store.clone()),
+        mock_context(deployment_id, data_source, store.clone(), overrides),
         host_metrics,
     )
     .unwrap();
@@ -128,6 +143,7 @@ fn mock_host_exports(
     subgraph_id: SubgraphDeploymentId,
     data_source: DataSource,
     store: Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
+    overrides: HostExportOverrides,
 ) -> HostExports {
     let mock_ethereum_adapter = Arc::new(MockEthereumAdapter::default());
     let arweave_adapter = Arc::new(ArweaveAdapter::new("https://arweave.net".to_string()));
@@ -152,8 +168,10 @@ fn mock_host_exports(
             .ok()
             .and_then(|s| u64::from_str(&s).ok())
             .map(std::time::Duration::from_secs),
+        true,
         arweave_adapter,
         three_box_adapter,
+        overrides,
     )
 }
 
@@ -161,11 +179,17 @@ fn mock_context(
     subgraph_id: SubgraphDeploymentId,
     data_source: DataSource,
     store: Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
+    overrides: HostExportOverrides,
 ) -> MappingContext {
     MappingContext {
         logger: test_store::LOGGER.clone(),
         block: Default::default(),
-        host_exports: Arc::new(mock_host_exports(subgraph_id, data_source, store)),
+        host_exports: Arc::new(mock_host_exports(
+            subgraph_id,
+            data_source,
+            store,
+            overrides,
+        )),
         state: BlockState::default(),
     }
 }
@@ -836,6 +860,33 @@ fn ens_name_by_hash() {
         .is_null());
 }
 
+#[test]
+fn host_export_overrides_replace_host_functions() {
+    let overrides = HostExportOverrides::new()
+        .ipfs_cat(|link| Ok(format!("stubbed {}", link).into_bytes()))
+        .ens_name_by_hash(|hash| Ok(Some(format!("name of {}", hash))));
+
+    let (mut module, _) = test_valid_module_and_store_with_overrides(
+        "ipfsCatOverride",
+        mock_data_source("wasm_test/ipfs_cat.wasm"),
+        overrides.clone(),
+    );
+    let link = module.asc_new("QmNotOnIpfs");
+    let data = module.takes_ptr_returns_ptr::<_, AscString>("ipfsCatString", link);
+    let data: String = module.asc_get(data);
+    assert_eq!(data, "stubbed QmNotOnIpfs");
+
+    let (mut module, _) = test_valid_module_and_store_with_overrides(
+        "ensNameByHashOverride",
+        mock_data_source("wasm_test/ens_name_by_hash.wasm"),
+        overrides,
+    );
+    let hash = module.asc_new("0x1234");
+    let name = module.takes_ptr_returns_ptr::<_, AscString>("nameByHash", hash);
+    let name: String = module.asc_get(name);
+    assert_eq!(name, "name of 0x1234");
+}
+
 #[test]
 fn entity_store() {
     let (mut module, store) =
@@ -1,4 +1,5 @@
 use super::*;
+use web3::types::H256;
 
 #[test]
 fn unbounded_loop() {
@@ -20,6 +21,35 @@ fn unbounded_loop() {
     );
 }
 
+#[test]
+fn unbounded_loop_reports_handler_timeout() {
+    // Set handler timeout to 3 seconds.
+    env::set_var(crate::host::TIMEOUT_ENV_VAR, "3");
+    let mut module = test_module(
+        "unboundedLoopTimeout",
+        mock_data_source("wasm_test/non_terminating.wasm"),
+    );
+    module.ctx.block = Arc::new(LightEthereumBlock {
+        hash: Some(H256::zero()),
+        number: Some(1u64.into()),
+        ..Default::default()
+    });
+    module.start_time = Instant::now();
+    module
+        .module
+        .clone()
+        .invoke_export("loop", &[], &mut module)
+        .unwrap_err();
+
+    let err = module
+        .handler_timeout_error("loop")
+        .expect("handler did not time out");
+    assert_eq!(err.handler, "loop");
+    assert_eq!(err.data_source, "example data source");
+    assert_eq!(err.block.number, 1);
+    assert!(err.deterministic);
+}
+
 #[test]
 fn unbounded_recursion() {
     let mut module = test_module(
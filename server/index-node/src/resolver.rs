@@ -95,6 +95,10 @@ struct IndexingStatusWithoutNode {
     synced: bool,
     /// Whether or not the subgraph has failed syncing.
     failed: bool,
+    /// Whether the failure will happen again when the failed block is
+    /// processed again; `false` if the subgraph has not failed or the cause
+    /// of the failure is unknown.
+    deterministic: bool,
     /// If it has failed, an optional error.
     error: Option<String>,
     /// Indexing status on different chains involved in the subgraph's data sources.
@@ -108,6 +112,10 @@ struct IndexingStatus {
     synced: bool,
     /// Whether or not the subgraph has failed syncing.
     failed: bool,
+    /// Whether the failure will happen again when the failed block is
+    /// processed again; `false` if the subgraph has not failed or the cause
+    /// of the failure is unknown.
+    deterministic: bool,
     /// If it has failed, an optional error.
     error: Option<String>,
     /// Indexing status on different chains involved in the subgraph's data sources.
@@ -123,6 +131,7 @@ impl IndexingStatusWithoutNode {
             subgraph: self.subgraph,
             synced: self.synced,
             failed: self.failed,
+            deterministic: self.deterministic,
             error: self.error,
             chains: self.chains,
             node,
@@ -159,6 +168,7 @@ impl TryFromValue for IndexingStatusWithoutNode {
             subgraph: value.get_required("id")?,
             synced: value.get_required("synced")?,
             failed: value.get_required("failed")?,
+            deterministic: value.get_optional("deterministicFailure")?.unwrap_or(false),
             error: None,
             chains: vec![ChainIndexingStatus::Ethereum(EthereumIndexingStatus {
                 network: value
@@ -181,6 +191,7 @@ impl From<IndexingStatus> for q::Value {
             subgraph: status.subgraph,
             synced: status.synced,
             failed: status.failed,
+            deterministic: status.deterministic,
             error: status.error,
             chains: status.chains.into_iter().map(q::Value::from).collect::<Vec<_>>(),
             node: status.node,
@@ -274,6 +285,7 @@ where
                     id
                     synced
                     failed
+                    deterministicFailure
                     ethereumHeadBlockNumber
                     ethereumHeadBlockHash
                     earliestEthereumBlockHash
@@ -364,6 +376,7 @@ where
                         id
                         synced
                         failed
+                        deterministicFailure
                         ethereumHeadBlockNumber
                         ethereumHeadBlockHash
                         earliestEthereumBlockHash
@@ -6,6 +6,7 @@ use std::task::Poll;
 use std::time::Instant;
 
 use graph::components::server::query::GraphQLServerError;
+use graph::components::subgraph::ProofOfIndexingHealth;
 use graph::prelude::*;
 use graph_graphql::prelude::{execute_query, QueryExecutionOptions};
 
@@ -147,6 +148,67 @@ where
             .boxed()
     }
 
+    /// Checks the proof of indexing of a subgraph for its head block. Responds
+    /// with 503 if it is inconsistent, and with 200 otherwise, including for
+    /// subgraphs that have not started indexing.
+    fn handle_poi_health(&self, subgraph: &str) -> IndexNodeServiceResponse {
+        let store = self.store.clone();
+        let deployment_id = match SubgraphDeploymentId::new(subgraph) {
+            Ok(deployment_id) => deployment_id,
+            Err(_) => {
+                return Box::pin(futures03::future::err(GraphQLServerError::ClientError(
+                    format!("invalid subgraph deployment ID `{}`", subgraph),
+                )))
+            }
+        };
+
+        async move {
+            let health = store
+                .check_proof_of_indexing(&deployment_id)
+                .await
+                .map_err(|e| GraphQLServerError::InternalError(e.to_string()))?;
+
+            let mut body = serde_json::json!({
+                "subgraph": deployment_id.to_string(),
+                "status": health.as_str(),
+            });
+            match &health {
+                ProofOfIndexingHealth::NotStarted | ProofOfIndexingHealth::Unsupported => (),
+                ProofOfIndexingHealth::Healthy {
+                    block_number,
+                    digest,
+                } => {
+                    body["blockNumber"] = (*block_number).into();
+                    body["digest"] = digest.0.clone().into();
+                }
+                ProofOfIndexingHealth::Mismatch {
+                    block_number,
+                    stored,
+                    computed,
+                } => {
+                    body["blockNumber"] = (*block_number).into();
+                    body["stored"] = stored.0.clone().into();
+                    body["computed"] = computed.0.clone().into();
+                }
+                ProofOfIndexingHealth::Advancing { block_number } => {
+                    body["blockNumber"] = (*block_number).into();
+                }
+            }
+
+            let status = if health.is_healthy() {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            Ok(Response::builder()
+                .status(status)
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap())
+        }
+        .boxed()
+    }
+
     // Handles OPTIONS requests
     fn handle_graphql_options(&self, _request: Request<Body>) -> IndexNodeServiceResponse {
         Box::pin(async {
@@ -216,6 +278,8 @@ where
             (Method::POST, ["graphql"]) => self.handle_graphql_query(req.into_body()),
             (Method::OPTIONS, ["graphql"]) => self.handle_graphql_options(req),
 
+            (Method::GET, ["health", "poi", subgraph]) => self.handle_poi_health(subgraph),
+
             _ => self.handle_not_found(),
         }
     }
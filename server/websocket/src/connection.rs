@@ -292,7 +292,8 @@ where
 
                     // Construct a subscription
                     let subscription = Subscription {
-                        query: Query::new(schema.clone(), query, variables),
+                        query: Query::new(schema.clone(), query, variables)
+                            .with_operation_name(payload.operation_name),
                     };
 
                     debug!(logger, "Start operation";
@@ -323,6 +324,13 @@ where
                                         OutgoingMessage::from_query_result(err_id.clone(), result);
                                     error_sink.unbounded_send(msg.into()).unwrap();
                                 }
+                                e => {
+                                    let msg = OutgoingMessage::from_error_string(
+                                        err_id.clone(),
+                                        e.to_string(),
+                                    );
+                                    error_sink.unbounded_send(msg.into()).unwrap();
+                                }
                             };
                         })
                         .and_then(move |result_stream| {
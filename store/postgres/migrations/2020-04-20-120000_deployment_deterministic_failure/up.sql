@@ -0,0 +1,4 @@
+-- Whether the failure of a failed subgraph will happen again when the
+-- block is processed again; null if unknown or the subgraph has not failed
+alter table subgraphs.subgraph_deployment
+  add column deterministic_failure boolean;
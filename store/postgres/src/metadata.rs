@@ -20,6 +20,7 @@ table! {
         id -> Text,
         manifest -> Text,
         failed -> Bool,
+        deterministic_failure -> Nullable<Bool>,
         synced -> Bool,
         earliest_ethereum_block_hash -> Nullable<Binary>,
         earliest_ethereum_block_number -> Nullable<Numeric>,
@@ -9,7 +9,6 @@ use graph::prelude::{CancelGuard, CancelHandle, CancelToken, CancelableError};
 use graph::spawn_blocking_async_allow_panic;
 use lazy_static::lazy_static;
 use lru_time_cache::LruCache;
-use stable_hash::utils::stable_hash_with_hasher;
 use std::collections::{BTreeMap, HashMap};
 use std::convert::{TryFrom, TryInto};
 use std::iter::FromIterator;
@@ -20,7 +19,9 @@ use tokio::sync::Semaphore;
 use uuid::Uuid;
 
 use graph::components::store::{EntityCollection, Store as StoreTrait};
-use graph::components::subgraph::ProofOfIndexingDigest;
+use graph::components::subgraph::{
+    combine_proofs_of_indexing, ProofOfIndexingDigest, ProofOfIndexingHealth,
+};
 use graph::data::subgraph::schema::{
     SubgraphDeploymentEntity, TypedEntity as _, POI_OBJECT, SUBGRAPHS_ID,
 };
@@ -31,8 +32,8 @@ use graph::prelude::{
     EntityModification, EntityOrder, EntityQuery, EntityRange, Error, EthereumBlock,
     EthereumBlockPointer, EthereumCallCache, EthereumNetworkIdentifier, EventProducer as _, Future,
     Future01CompatExt, LightEthereumBlock, Logger, MetadataOperation, MetricsRegistry,
-    QueryExecutionError, Schema, Sink as _, StopwatchMetrics, StoreError, StoreEvent,
-    StoreEventStream, StoreEventStreamBox, Stream, SubgraphAssignmentProviderError,
+    ProofOfIndexingError, QueryExecutionError, Schema, Sink as _, StopwatchMetrics, StoreError,
+    StoreEvent, StoreEventStream, StoreEventStreamBox, Stream, SubgraphAssignmentProviderError,
     SubgraphDeploymentId, SubgraphDeploymentStore, SubgraphEntityPair, TransactionAbortError,
     Value, BLOCK_NUMBER_MAX,
 };
@@ -959,6 +960,70 @@ impl Store {
     }
 }
 
+/// How often `check_proof_of_indexing` reads the digests before it gives up
+/// on a subgraph that keeps processing blocks.
+const CHECK_PROOF_OF_INDEXING_ATTEMPTS: usize = 3;
+
+/// Reads a block number attribute, e.g. `latestEthereumBlockNumber`, from
+/// the deployment entity of the subgraph.
+fn deployment_block_number(
+    conn: &e::Connection,
+    subgraph_id: &SubgraphDeploymentId,
+    attribute: &str,
+) -> Result<Option<u64>, Error> {
+    let key = SubgraphDeploymentEntity::key(subgraph_id.clone());
+    let subgraph_entity = conn
+        .find_metadata(&key.entity_type, &key.entity_id)
+        .map_err(|e| format_err!("error reading subgraph entity: {}", e))?
+        .ok_or_else(|| format_err!("subgraph {} does not exist", subgraph_id))?;
+
+    let number: Option<BigInt> = match subgraph_entity.get(attribute) {
+        None => None,
+        Some(value) => value.clone().try_into()?,
+    };
+    Ok(number.map(|number| number.to_u64()))
+}
+
+/// Combines the digests of all causality regions of the subgraph as of
+/// `block` into its proof of indexing.
+fn proof_of_indexing_as_of(
+    conn: &e::Connection,
+    logger: &Logger,
+    block: BlockNumber,
+) -> Result<ProofOfIndexingDigest, Error> {
+    let entities = conn
+        .query(
+            logger,
+            EntityCollection::All(vec![POI_OBJECT.to_owned()]),
+            None,
+            None,
+            EntityRange {
+                first: None,
+                skip: 0,
+            },
+            block,
+        )
+        .map_err(Error::from)?;
+
+    let by_causality_region = entities
+        .into_iter()
+        .map(|e| {
+            let causality_region = e.id()?;
+            let digest = match e.get("digest") {
+                None => Err(format_err!("Entity is missing a digest attribute")),
+                Some(Value::String(s)) => Ok(s.to_owned()),
+                _ => Err(format_err!("Entity has non-string digest attribute")),
+            }?;
+
+            let poi = ProofOfIndexingDigest(digest);
+
+            Ok((causality_region, poi))
+        })
+        .collect::<Result<HashMap<_, _>, Error>>()?;
+
+    Ok(combine_proofs_of_indexing(&by_causality_region))
+}
+
 impl StoreTrait for Store {
     fn block_ptr(
         &self,
@@ -989,6 +1054,7 @@ impl StoreTrait for Store {
         block_number: u64,
     ) -> DynTryFuture<'a, Option<ProofOfIndexingDigest>> {
         let logger = self.logger.cheap_clone();
+        let subgraph_id_for_block = subgraph_id.clone();
 
         self.with_entity_conn(subgraph_id, move |conn, cancel| {
             cancel.check_cancel()?;
@@ -997,40 +1063,92 @@ impl StoreTrait for Store {
                 return Ok(None);
             }
 
-            let entities = conn
-                .query(
-                    &logger,
-                    EntityCollection::All(vec![POI_OBJECT.to_owned()]),
-                    None,
-                    None,
-                    EntityRange {
-                        first: None,
-                        skip: 0,
-                    },
-                    block_number.try_into().unwrap(),
-                )
-                .map_err(Error::from)?;
+            // Only blocks for which the store has the history of the PoI
+            // entities have a well-defined proof of indexing
+            let subgraph_id = subgraph_id_for_block;
+            let latest_block =
+                deployment_block_number(conn, &subgraph_id, "latestEthereumBlockNumber")?;
+            if latest_block.map_or(true, |latest_block| block_number > latest_block) {
+                return Err(Error::from(ProofOfIndexingError::BlockNotIndexed {
+                    subgraph_id,
+                    block_number,
+                    latest_block,
+                })
+                .into());
+            }
+            let earliest_block =
+                deployment_block_number(conn, &subgraph_id, "earliestEthereumBlockNumber")?;
+            if let Some(earliest_block) = earliest_block {
+                if block_number < earliest_block {
+                    return Err(Error::from(ProofOfIndexingError::BlockPruned {
+                        subgraph_id,
+                        block_number,
+                        earliest_block,
+                    })
+                    .into());
+                }
+            }
             cancel.check_cancel()?;
 
-            let by_causality_region = entities
-                .into_iter()
-                .map(|e| {
-                    let causality_region = e.id()?;
-                    let digest = match e.get("digest") {
-                        None => Err(format_err!("Entity is missing a digest attribute")),
-                        Some(Value::String(s)) => Ok(s.to_owned()),
-                        _ => Err(format_err!("Entity has non-string digest attribute")),
-                    }?;
+            let digest = proof_of_indexing_as_of(conn, &logger, block_number.try_into().unwrap())?;
+            Ok(Some(digest))
+        })
+        .boxed()
+    }
 
-                    let poi = ProofOfIndexingDigest(digest);
+    fn check_proof_of_indexing<'a>(
+        &'a self,
+        subgraph_id: &'a SubgraphDeploymentId,
+    ) -> DynTryFuture<'a, ProofOfIndexingHealth> {
+        let logger = self.logger.cheap_clone();
+        let subgraph_id_for_head = subgraph_id.clone();
 
-                    Ok((causality_region, poi))
-                })
-                .collect::<Result<HashMap<_, _>, Error>>()?;
+        self.with_entity_conn(subgraph_id, move |conn, cancel| {
+            let subgraph_id = subgraph_id_for_head;
+            if !conn.supports_proof_of_indexing() {
+                return Ok(ProofOfIndexingHealth::Unsupported);
+            }
+
+            // The subgraph may process blocks while the digests are
+            // computed, in which case the stored digest is newer than the
+            // head block that was read; retry a few times for the head to
+            // stay put
+            let mut block_number =
+                match deployment_block_number(conn, &subgraph_id, "latestEthereumBlockNumber")? {
+                    Some(block_number) => block_number,
+                    None => return Ok(ProofOfIndexingHealth::NotStarted),
+                };
+            for _ in 0..CHECK_PROOF_OF_INDEXING_ATTEMPTS {
+                cancel.check_cancel()?;
+
+                let stored = proof_of_indexing_as_of(conn, &logger, BLOCK_NUMBER_MAX)?;
+                cancel.check_cancel()?;
+                let computed =
+                    proof_of_indexing_as_of(conn, &logger, block_number.try_into().unwrap())?;
+
+                match deployment_block_number(conn, &subgraph_id, "latestEthereumBlockNumber")? {
+                    Some(head) if head == block_number => (),
+                    Some(head) => {
+                        block_number = head;
+                        continue;
+                    }
+                    None => return Ok(ProofOfIndexingHealth::NotStarted),
+                }
 
-            let hash = stable_hash_with_hasher::<twox_hash::XxHash64, _>(&by_causality_region);
-            let hash = format!("{:x}", hash);
-            Ok(Some(ProofOfIndexingDigest(hash)))
+                return Ok(if stored == computed {
+                    ProofOfIndexingHealth::Healthy {
+                        block_number,
+                        digest: computed,
+                    }
+                } else {
+                    ProofOfIndexingHealth::Mismatch {
+                        block_number,
+                        stored,
+                        computed,
+                    }
+                });
+            }
+            Ok(ProofOfIndexingHealth::Advancing { block_number })
         })
         .boxed()
     }
@@ -1137,6 +1255,7 @@ impl StoreTrait for Store {
                 // so that we do not hold a lock on the notification queue
                 // for longer than we have to
                 let event: StoreEvent = mods.iter().collect();
+                let event = event.with_block(block_ptr_to.number as BlockNumber);
 
                 // Make the changes
                 let section = stopwatch.start_section("apply_entity_modifications");
@@ -1227,6 +1346,7 @@ impl StoreTrait for Store {
 
             let (event, count) = econn.revert_block(&block_ptr_from)?;
             econn.update_entity_count(count)?;
+            let event = event.with_block(block_ptr_to.number as BlockNumber);
             Ok((event, metadata_event))
         })?;
 
@@ -10,6 +10,7 @@ use std::time::Duration;
 use test_store::*;
 
 use graph::components::store::{EntityFilter, EntityKey, EntityOrder, EntityQuery};
+use graph::components::subgraph::ProofOfIndexingHealth;
 use graph::data::store::scalar;
 use graph::data::subgraph::schema::*;
 use graph::data::subgraph::*;
@@ -1195,6 +1196,7 @@ fn subscribe_and_consume(
                 Some(StoreEvent {
                     tag: event.tag,
                     changes,
+                    block: event.block,
                 })
             }
         });
@@ -1588,6 +1590,7 @@ fn revert_block_with_dynamic_data_source_operations() {
                 ]
                 .into_iter(),
             ),
+            block: None,
         }];
         check_events(subscription, expected_events)
     })
@@ -2209,3 +2212,53 @@ fn find_at_block() {
         shaqueeena_at_block(7000, "teeko@email.com");
     }
 }
+
+#[test]
+fn proof_of_indexing_for_unindexed_block_fails() {
+    run_test(|store| {
+        Box::pin(async move {
+            let next_block = TEST_BLOCK_2_PTR.number + 1;
+            let err = store
+                .get_proof_of_indexing(&TEST_SUBGRAPH_ID, next_block)
+                .await
+                .expect_err("expected the proof of indexing of an unindexed block to fail");
+
+            match err.downcast_ref::<ProofOfIndexingError>() {
+                Some(ProofOfIndexingError::BlockNotIndexed {
+                    block_number,
+                    latest_block,
+                    ..
+                }) => {
+                    assert_eq!(*block_number, next_block);
+                    assert_eq!(*latest_block, Some(TEST_BLOCK_2_PTR.number));
+                }
+                _ => panic!("unexpected error: {}", err),
+            }
+
+            // Blocks that have been indexed have a proof of indexing
+            store
+                .get_proof_of_indexing(&TEST_SUBGRAPH_ID, TEST_BLOCK_1_PTR.number)
+                .await?;
+
+            Result::<(), Error>::Ok(())
+        })
+        .compat()
+    })
+}
+
+#[test]
+fn proof_of_indexing_of_head_block_is_healthy() {
+    run_test(|store| {
+        Box::pin(async move {
+            let health = store.check_proof_of_indexing(&TEST_SUBGRAPH_ID).await?;
+
+            assert!(health.is_healthy(), "unexpected health: {:?}", health);
+            if let ProofOfIndexingHealth::Healthy { block_number, .. } = health {
+                assert_eq!(block_number, TEST_BLOCK_2_PTR.number);
+            }
+
+            Result::<(), Error>::Ok(())
+        })
+        .compat()
+    })
+}